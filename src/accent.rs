@@ -0,0 +1,158 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The pitch accent of a word, with the reading it belongs to
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Accent {
+    reading: String,
+    /// The mora after which the pitch drops, 0 if it doesn't drop
+    downstep: usize,
+}
+
+impl Accent {
+    /// The name of the accent pattern, used as the class of the span around the word
+    fn pattern(&self) -> &'static str {
+        match self.downstep {
+            0 => "heiban",
+            1 => "atamadaka",
+            downstep if downstep >= morae(&self.reading) => "odaka",
+            _ => "nakadaka",
+        }
+    }
+}
+
+/// Counts the morae of a reading in kana. Small kana are part of the mora before them.
+fn morae(reading: &str) -> usize {
+    reading
+        .chars()
+        .filter(|ch| !"ゃゅょぁぃぅぇぉゎャュョァィゥェォヮ".contains(*ch))
+        .count()
+}
+
+/// Pitch accents by word, read from a dictionary with `WORD<TAB>READING<TAB>ACCENT` lines like
+/// kanjium's accents.txt
+#[derive(Debug, Default)]
+pub struct AccentDictionary {
+    words: HashMap<String, Accent>,
+    /// The length in characters of the longest word
+    longest: usize,
+}
+
+impl AccentDictionary {
+    fn parse(data: &str) -> Result<Self> {
+        let mut dict = Self::default();
+        for (idx, line) in data.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [word, reading, accent] = fields[..] else {
+                bail!("line {}: expected WORD, READING and ACCENT", idx + 1);
+            };
+            // Words with more than one accent list all of them, the first is the most common
+            let downstep = accent
+                .split(',')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .parse()
+                .with_context(|| {
+                    format!("line {}: \"{}\" is not a valid accent", idx + 1, accent)
+                })?;
+
+            // The first entry of a word is kept, later ones are usually rarer readings
+            if !dict.words.contains_key(word) {
+                // Words in kana have no reading of their own
+                let reading = if reading.is_empty() { word } else { reading };
+                dict.longest = dict.longest.max(word.chars().count());
+                dict.words.insert(
+                    word.to_string(),
+                    Accent {
+                        reading: reading.to_string(),
+                        downstep,
+                    },
+                );
+            }
+        }
+        Ok(dict)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data =
+            std::fs::read_to_string(path).context("Failed to read pitch accent dictionary")?;
+        Self::parse(&data).context("Failed to parse pitch accent dictionary")
+    }
+
+    /// Wraps the words of `text` that are in the dictionary in a span with the class of their
+    /// accent pattern (`accent-heiban`, `accent-atamadaka`, `accent-nakadaka` or `accent-odaka`)
+    /// and the reading and downstep as title. Words are found by taking the longest word in the
+    /// dictionary at every position.
+    pub fn annotate(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut res = String::new();
+        let mut idx = 0;
+        while idx < chars.len() {
+            let found = (1..=self.longest.min(chars.len() - idx))
+                .rev()
+                .find_map(|len| {
+                    let word: String = chars[idx..idx + len].iter().collect();
+                    self.words.get(&word).map(|accent| (word, accent))
+                });
+            match found {
+                Some((word, accent)) => {
+                    res.push_str(&format!(
+                        "<span class=\"accent-{}\" title=\"{} [{}]\">{}</span>",
+                        accent.pattern(),
+                        accent.reading,
+                        accent.downstep,
+                        word
+                    ));
+                    idx += word.chars().count();
+                }
+                None => {
+                    res.push(chars[idx]);
+                    idx += 1;
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accent_patterns() {
+        let accent = |reading: &str, downstep| Accent {
+            reading: reading.to_string(),
+            downstep,
+        };
+        assert_eq!(accent("さくら", 0).pattern(), "heiban");
+        assert_eq!(accent("いのち", 1).pattern(), "atamadaka");
+        assert_eq!(accent("ひこうき", 2).pattern(), "nakadaka");
+        assert_eq!(accent("いもうと", 4).pattern(), "odaka");
+        assert_eq!(accent("きょう", 2).pattern(), "odaka");
+    }
+
+    #[test]
+    fn annotate_longest_words() {
+        let dict = AccentDictionary::parse(
+            "日本\tにほん\t2\n日本語\tにほんご\t0\n語\tご\t1\n今日\tきょう\t1,0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            dict.annotate("今日も日本語"),
+            "<span class=\"accent-atamadaka\" title=\"きょう [1]\">今日</span>も<span class=\"accent-heiban\" title=\"にほんご [0]\">日本語</span>"
+        );
+    }
+
+    #[test]
+    fn parse_invalid_dictionary() {
+        assert!(AccentDictionary::parse("日本\tにほん\n").is_err());
+        assert!(AccentDictionary::parse("日本\tにほん\tx\n").is_err());
+    }
+}
@@ -1,38 +1,557 @@
 use super::SubtitleBundle;
-use crate::subtitle::Dialogue;
-use anyhow::{Context, Result};
-use genanki_rs::{Field, Model, Note, Template};
+use crate::apkg::ExistingPackage;
+use crate::args::{Args, ModelPreset};
+use crate::korean::romanize;
+use crate::subtitle::{is_rtl, Dialogue};
+use crate::time::Timestamp;
+use crate::util::{fnv1a, media_name, today, FNV_OFFSET};
+use anyhow::{bail, Context, Result};
+use genanki_rs::{Field, Model, ModelType, Note, Template};
+use log::info;
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeMap, HashSet};
+
+const MODEL_ID: i64 = 8815489913192057416;
+/// The id of the note type of the `--info-note`, which appending to a package skips
+pub const INFO_MODEL_ID: i64 = 8815489913192057417;
+const BASE_FIELDS: &[&str] = &["Sequence indicator", "Image", "Audio", "Text"];
+const WORD_TEMPLATE: &str = "Word card";
+/// The tag of notes of which the subtitle is laid out vertically
+const VERTICAL_TAG: &str = "vertical";
+/// The prefix of the tag with the language of a note, a hierarchical tag in Anki
+const LANG_TAG_PREFIX: &str = "lang::";
+/// The prefix of the tag with the difficulty of the words of a note
+const FREQ_TAG_PREFIX: &str = "freq::";
+/// The tag of notes that match one of the `--boost` regexes
+const BOOST_TAG: &str = "boosted";
+/// The prefix of the tag with the date of the run that made a note
+const RUN_TAG_PREFIX: &str = "stos::run-";
+/// The tag of notes of which some media could not be generated, with `--notes-without-media`
+const MISSING_MEDIA_TAG: &str = "stos::missing-media";
+const SPEECH_MISMATCH_TAG: &str = "stos::speech-mismatch";
+/// The tag of the `--info-note`
+const INFO_TAG: &str = "stos::info";
+/// The colors of the text of the speakers with `--color-speakers`, readable on light and dark
+/// backgrounds
+const SPEAKER_COLORS: &[&str] = &[
+    "#e6194b", "#3cb44b", "#4363d8", "#f58231", "#911eb4", "#42a4b4", "#f032e6", "#9a6324",
+];
 
 fn to_audio<S: AsRef<str>>(path: S) -> String {
-    format!("[sound:{}]", path.as_ref())
+    format!("[sound:{}]", media_name(path.as_ref()))
 }
 
 fn to_image<S: AsRef<str>>(path: S) -> String {
-    format!("<img src=\"{}\">", path.as_ref())
+    format!("<img src=\"{}\">", media_name(path.as_ref()))
+}
+
+/// Marks right-to-left text as such, Anki would lay it out left-to-right otherwise
+fn with_direction(text: String) -> String {
+    if is_rtl(&text) {
+        format!("<span dir=\"rtl\">{}</span>", text)
+    } else {
+        text
+    }
+}
+
+/// The speaker of a subtitle with the text that is left without it. The speaker is the actor of an
+/// ASS subtitle, or else the first capture group (or the whole match) of `speaker_re`, which is
+/// removed from the text.
+fn split_speaker(
+    actor: Option<&str>,
+    speaker_re: Option<&Regex>,
+    text: String,
+) -> (Option<String>, String) {
+    if let Some(actor) = actor {
+        return (Some(actor.to_string()), text);
+    }
+    let Some(caps) = speaker_re.and_then(|re| re.captures(&text)) else {
+        return (None, text);
+    };
+    let whole = caps.get(0).unwrap();
+    let speaker = caps.get(1).unwrap_or(whole).as_str().trim();
+    if speaker.is_empty() {
+        return (None, text);
+    }
+    let rest = format!("{}{}", &text[..whole.start()], &text[whole.end()..]);
+    (Some(speaker.to_string()), rest.trim().to_string())
+}
+
+/// The color of the text of `speaker`, the same in every deck
+fn speaker_color(speaker: &str) -> &'static str {
+    let hash = fnv1a(FNV_OFFSET, speaker.as_bytes());
+    SPEAKER_COLORS[(hash % SPEAKER_COLORS.len() as u64) as usize]
+}
+
+fn is_boosted(args: &Args, sub: &SubtitleBundle) -> bool {
+    sub.sub()
+        .text()
+        .is_some_and(|text| args.boost().iter().any(|re| re.is_match(text)))
+}
+
+/// Puts the subtitles that match one of the `--boost` regexes first. New cards are shown in the
+/// order of the notes, so the notes are made in this order. The sort is stable, so both keep
+/// their order otherwise.
+pub fn boosted_first<'a>(
+    args: &Args,
+    subs: impl Iterator<Item = &'a SubtitleBundle>,
+) -> Vec<&'a SubtitleBundle> {
+    let mut subs: Vec<&SubtitleBundle> = subs.collect();
+    if !args.boost().is_empty() {
+        subs.sort_by_key(|sub| !is_boosted(args, sub));
+    }
+    subs
+}
+
+fn note_tags(sub: &SubtitleBundle, boosted: bool, run_tag: Option<&str>) -> Vec<String> {
+    let mut tags = Vec::new();
+    if boosted {
+        tags.push(BOOST_TAG.to_string());
+    }
+    // Vertical text is mostly used for signs, the tag lets them be found or styled in Anki
+    if let Dialogue::Ass(ass) = sub.sub().dialogue() {
+        if ass.text.is_vertical() {
+            tags.push(VERTICAL_TAG.to_string());
+        }
+    }
+    if let Some(lang) = sub.lang() {
+        tags.push(format!("{}{}", LANG_TAG_PREFIX, lang));
+    }
+    if let Some(difficulty) = sub.difficulty() {
+        tags.push(format!("{}{}", FREQ_TAG_PREFIX, difficulty));
+    }
+    if sub.media_failed() {
+        tags.push(MISSING_MEDIA_TAG.to_string());
+    }
+    if sub.speech_mismatch() {
+        tags.push(SPEECH_MISMATCH_TAG.to_string());
+    }
+    tags.extend(run_tag.map(str::to_string));
+    tags
+}
+
+fn field_names(args: &Args) -> Vec<String> {
+    let mut names: Vec<String> = BASE_FIELDS.iter().map(|name| name.to_string()).collect();
+    if args.timestamp_field() {
+        names.push("Timestamp".to_string());
+    }
+    if args.render_ass().is_some() {
+        names.push("Subtitle image".to_string());
+    }
+    if args.speaker_field() {
+        names.push("Speaker".to_string());
+    }
+    if args.color_speakers() {
+        names.push("Speaker color".to_string());
+    }
+    if args.index_fields() {
+        names.push("File index".to_string());
+        names.push("Subtitle index".to_string());
+    }
+    if args.furigana_field() {
+        names.push("Furigana".to_string());
+    }
+    if args.reading_field() {
+        names.push("Reading".to_string());
+    }
+    if args.pitch_accent().is_some() {
+        names.push("Pitch accent".to_string());
+    }
+    if args.chinese_fields() {
+        names.push("Segmented".to_string());
+        names.push("Pinyin".to_string());
+    }
+    if args.romanization_field() {
+        names.push("Romanization".to_string());
+    }
+    if args.pair().is_some() {
+        names.push("Translation".to_string());
+    }
+    for idx in 0..args.extra_sub_stream_selectors().len() {
+        names.push(format!("Text {}", idx + 2));
+    }
+    if args.target_words().is_some() {
+        names.push("Target words".to_string());
+    }
+    if args.run_metadata() {
+        names.push("Generator".to_string());
+    }
+    if args.model_preset() == ModelPreset::ClozeJp {
+        names.push("Cloze".to_string());
+    }
+    names
+}
+
+// Anki identifies note types by id, so a model with a different set of fields must not reuse the
+// id of the default model.
+fn model_id(fields: &[&str]) -> i64 {
+    if fields == BASE_FIELDS {
+        return MODEL_ID;
+    }
+
+    let hash = fnv1a(FNV_OFFSET, fields.join("\x1f").as_bytes());
+    (MODEL_ID ^ hash as i64) & i64::MAX
+}
+
+fn template_name(preset: ModelPreset) -> &'static str {
+    match preset {
+        ModelPreset::Sentence => "Card 1",
+        ModelPreset::Vocab => "Vocab",
+        ModelPreset::Listening => "Listening",
+        ModelPreset::ClozeJp => "Cloze",
+    }
 }
 
-pub fn create_notes<'a, I>(subs: I) -> Result<Vec<Note>>
+/// The CSS genanki gives models without CSS of their own
+const SENTENCE_CSS: &str = ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n";
+
+fn preset_css(preset: ModelPreset) -> &'static str {
+    match preset {
+        ModelPreset::Sentence => "",
+        ModelPreset::Vocab | ModelPreset::Listening => {
+            ".card { font-family: sans-serif; font-size: 20px; text-align: center; }\n\
+             .text { font-size: 32px; }\n\
+             .target { color: #2a7ae2; }\n"
+        }
+        ModelPreset::ClozeJp => {
+            ".card { font-family: \"Noto Sans JP\", \"Hiragino Kaku Gothic ProN\", \"Yu Gothic\", sans-serif; font-size: 20px; text-align: center; }\n\
+             .text { font-size: 36px; }\n\
+             .cloze { font-weight: bold; color: #2a7ae2; }\n"
+        }
+    }
+}
+
+/// The CSS of the model of `preset` with the CSS of `--style-css` after it, so it wins. `None` for
+/// the sentence preset without style, which keeps the CSS genanki gives every model.
+fn model_css(preset: ModelPreset, style_css: Option<&str>) -> Option<String> {
+    let css = match (preset, style_css) {
+        (ModelPreset::Sentence, None) => return None,
+        (ModelPreset::Sentence, Some(_)) => SENTENCE_CSS,
+        (preset, _) => preset_css(preset),
+    };
+    Some(format!("{}{}", css, style_css.unwrap_or_default()))
+}
+
+/// The Text field in the templates. With a Speaker color field the text is shown in that color,
+/// so the field itself stays plain text.
+fn text_field(names: &[&str]) -> &'static str {
+    if names.contains(&"Speaker color") {
+        "<span style=\"color: {{Speaker color}}\">{{Text}}</span>"
+    } else {
+        "{{Text}}"
+    }
+}
+
+/// The front and back templates of the card of `preset`. The fields in `names` that are meant for
+/// the back, like translations and readings, are shown below the answer.
+fn templates(preset: ModelPreset, names: &[&str]) -> (String, String) {
+    let mut back = String::new();
+    for name in names.iter().copied().filter(|name| {
+        *name == "Furigana"
+            || *name == "Reading"
+            || *name == "Pitch accent"
+            || *name == "Segmented"
+            || *name == "Pinyin"
+            || *name == "Romanization"
+            || *name == "Translation"
+            || name.starts_with("Text ")
+    }) {
+        // Anki's furigana filter shows the readings above the text
+        let filter = if name == "Furigana" { "furigana:" } else { "" };
+        back.push_str(&format!(
+            "<p style=\"text-align: center\">{{{{{}{}}}}}</p>",
+            filter, name
+        ));
+    }
+
+    let text = text_field(names);
+    match preset {
+        ModelPreset::Sentence => {
+            let mut qfmt = if names.contains(&"Subtitle image") {
+                "{{Image}}<br>{{Subtitle image}}<br>{{Audio}}<br>".to_string()
+            } else {
+                "{{Image}}<br>{{Audio}}<br>".to_string()
+            };
+            if names.contains(&"Speaker") {
+                qfmt.push_str(
+                    "{{#Speaker}}<p style=\"text-align: center\">{{Speaker}}</p>{{/Speaker}}",
+                );
+            }
+            qfmt.push_str("<h1 style=\"text-align: center\">");
+            qfmt.push_str(text);
+            let mut afmt = qfmt.clone();
+            if !back.is_empty() {
+                afmt.push_str("</h1>");
+                afmt.push_str(&back);
+            }
+            (qfmt, afmt)
+        }
+        ModelPreset::Vocab => {
+            let mut qfmt = format!("<h1 class=\"text\">{}</h1>", text);
+            if names.contains(&"Target words") {
+                qfmt.push_str(
+                    "{{#Target words}}<p class=\"target\">{{Target words}}</p>{{/Target words}}",
+                );
+            }
+            let afmt = format!(
+                "{{{{FrontSide}}}}<hr id=answer>{{{{Image}}}}<br>{{{{Audio}}}}{}",
+                back
+            );
+            (qfmt, afmt)
+        }
+        ModelPreset::Listening => (
+            "{{Audio}}".to_string(),
+            format!(
+                "{{{{FrontSide}}}}<hr id=answer>{{{{Image}}}}<h1 class=\"text\">{}</h1>{}",
+                text, back
+            ),
+        ),
+        ModelPreset::ClozeJp => (
+            "{{Image}}<h1 class=\"text\">{{cloze:Cloze}}</h1>".to_string(),
+            format!(
+                "{{{{Image}}}}<br>{{{{Audio}}}}<h1 class=\"text\">{{{{cloze:Cloze}}}}</h1>{}",
+                back
+            ),
+        ),
+    }
+}
+
+/// Hides the occurrences of `words` in `text` behind the first cloze deletion
+fn cloze(text: &str, words: &[String]) -> String {
+    let mut words: Vec<&String> = words.iter().collect();
+    // The longest words first, so a word is not clozed in part because a shorter one matched
+    words.sort_by_key(|word| std::cmp::Reverse(word.chars().count()));
+    let pattern = words
+        .iter()
+        .map(|word| regex::escape(word))
+        .collect::<Vec<_>>()
+        .join("|");
+    if pattern.is_empty() {
+        return text.to_string();
+    }
+    match RegexBuilder::new(&pattern).case_insensitive(true).build() {
+        Ok(re) => re.replace_all(text, "{{c1::$0}}").into_owned(),
+        Err(_) => text.to_string(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The rows of the table of files of an info note, without the `<tr>` tags
+fn info_rows(info: &str) -> Vec<&str> {
+    info.split("<tr>")
+        .filter(|row| row.starts_with("<td>"))
+        .filter_map(|row| row.split("</tr>").next())
+        .collect()
+}
+
+/// The contents of the `--info-note`: the source files with the time range their notes come from,
+/// the date, the number of notes and the options stos was run with. The files of the `earlier`
+/// info note of the package that is appended to are kept before the new ones.
+fn info_text<'a, I>(
+    args: &Args,
+    deck_name: &str,
+    date: &str,
+    note_count: usize,
+    earlier: Option<&str>,
+    subs: I,
+) -> String
 where
     I: Iterator<Item = &'a SubtitleBundle>,
 {
+    let media_files = if args.media_files().is_empty() {
+        args.sub_files()
+    } else {
+        args.media_files()
+    };
+    // The number of notes, the first start and the last end of every file
+    let mut files: BTreeMap<usize, (usize, Timestamp, Timestamp)> = BTreeMap::new();
+    for sub in subs {
+        let span = sub.sub().timespan();
+        let (count, start, end) =
+            files
+                .entry(sub.file_idx())
+                .or_insert((0, span.start(), span.end()));
+        *count += 1;
+        *start = (*start).min(span.start());
+        *end = (*end).max(span.end());
+    }
+
+    let mut text = format!(
+        "<h2>{}</h2><p>Made by {} {} on {} with {} note(s)</p>",
+        escape_html(deck_name),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        date,
+        note_count
+    );
+    text.push_str("<table><tr><th>File</th><th>Notes</th><th>From</th><th>To</th></tr>");
+    for row in earlier.map(info_rows).unwrap_or_default() {
+        text.push_str(&format!("<tr>{}</tr>", row));
+    }
+    for (file_idx, (count, start, end)) in files {
+        let name = media_files
+            .get(file_idx)
+            .and_then(|file| file.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        text.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&name),
+            count,
+            start,
+            end
+        ));
+    }
+    text.push_str("</table>");
+
+    let options: Vec<String> = args
+        .raw_args()
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    text.push_str(&format!(
+        "<p>Options: <code>{}</code></p>",
+        escape_html(&options.join(" "))
+    ));
+    text
+}
+
+/// Creates the note that describes where the notes of the deck with `deck_id` came from. It has a
+/// note type of its own and the same guid every time, so importing a rebuilt deck updates it.
+/// `earlier` is the info note of the package that is appended to.
+pub fn info_note<'a, I>(
+    args: &Args,
+    deck_id: i64,
+    deck_name: &str,
+    note_count: usize,
+    earlier: Option<&str>,
+    subs: I,
+) -> Result<Note>
+where
+    I: Iterator<Item = &'a SubtitleBundle>,
+{
+    let today = today();
+    let date = format!("{}-{}-{}", &today[..4], &today[4..6], &today[6..]);
     let model = Model::new(
-        8815489913192057416,
-        "stos anki model",
-        vec![
-            Field::new("Sequence indicator"),
-            Field::new("Image"),
-            Field::new("Audio"),
-            Field::new("Text"),
-        ],
-        vec![Template::new("Card 1")
-            .qfmt("{{Image}}<br>{{Audio}}<br><h1 style=\"text-align: center\">{{Text}}")
-            .afmt("{{Image}}<br>{{Audio}}<br><h1 style=\"text-align: center\">{{Text}}")],
+        INFO_MODEL_ID,
+        "stos info model",
+        vec![Field::new("Info")],
+        vec![Template::new("Info").qfmt("{{Info}}").afmt("{{FrontSide}}")],
     );
+    let text = info_text(args, deck_name, &date, note_count, earlier, subs);
+    let guid = format!("stos-info-{}", deck_id);
+    Note::new_with_options(
+        model,
+        vec![text.as_str()],
+        None,
+        Some(vec![INFO_TAG]),
+        Some(guid.as_str()),
+    )
+    .context("Failed to create info note")
+}
+
+/// Creates the notes for `subs`. If `existing` is given, its notes are recreated first and new
+/// notes that are identical to an existing note (apart from the sequence indicator) are skipped.
+pub fn create_notes<'a, I>(
+    args: &Args,
+    existing: Option<&ExistingPackage>,
+    style_css: Option<&str>,
+    subs: I,
+) -> Result<Vec<Note>>
+where
+    I: Iterator<Item = &'a SubtitleBundle>,
+{
+    let names = field_names(args);
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let model = if args.word_list().is_some() {
+        // Word cards only play the clip on the front, so they need a note type of their own
+        let mut id_fields = names.clone();
+        id_fields.push(WORD_TEMPLATE);
+        Model::new(
+            existing
+                .map(ExistingPackage::model_id)
+                .unwrap_or_else(|| model_id(&id_fields)),
+            "stos word model",
+            names.iter().map(|name| Field::new(name)).collect(),
+            vec![Template::new(WORD_TEMPLATE).qfmt("{{Audio}}").afmt(
+                "{{FrontSide}}<hr id=answer><h1 style=\"text-align: center\">{{Text}}</h1>{{Image}}",
+            )],
+        )
+    } else {
+        let preset = args.model_preset();
+        // The other presets have different templates, so they must not share the id of the
+        // sentence model with the same fields
+        let mut id_fields = names.clone();
+        if preset != ModelPreset::Sentence {
+            id_fields.push(template_name(preset));
+        }
+        let id = existing
+            .map(ExistingPackage::model_id)
+            .unwrap_or_else(|| model_id(&id_fields));
+        let (qfmt, afmt) = templates(preset, &names);
+        let fields = names.iter().map(|name| Field::new(name)).collect();
+        let templates = vec![Template::new(template_name(preset)).qfmt(&qfmt).afmt(&afmt)];
+        let name = match preset {
+            ModelPreset::Sentence => "stos anki model".to_string(),
+            _ => format!("stos {} model", template_name(preset).to_lowercase()),
+        };
+        match model_css(preset, style_css) {
+            None => Model::new(id, &name, fields, templates),
+            Some(css) => Model::new_with_options(
+                id,
+                &name,
+                fields,
+                templates,
+                Some(&css),
+                (preset == ModelPreset::ClozeJp).then_some(ModelType::Cloze),
+                None,
+                None,
+                None,
+            ),
+        }
+    };
 
     let mut res = Vec::new();
+    let mut seen: HashSet<Vec<String>> = HashSet::new();
+
+    if let Some(existing) = existing {
+        for note in existing.notes() {
+            if note.fields.len() != names.len() {
+                bail!(
+                    "The existing package has notes with {} fields, but {} fields would be generated",
+                    note.fields.len(),
+                    names.len()
+                );
+            }
+
+            seen.insert(note.fields[1..].to_vec());
+            res.push(
+                Note::new_with_options(
+                    model.clone(),
+                    note.fields.iter().map(String::as_str).collect(),
+                    None,
+                    Some(note.tags.iter().map(String::as_str).collect()),
+                    Some(&note.guid),
+                )
+                .context("Failed to recreate existing note")?,
+            );
+        }
+    }
 
-    for (model, (idx, sub)) in std::iter::repeat(model).zip(subs.enumerate()) {
-        let idx = format!("{}", idx);
+    let offset = res.len();
+    let mut skipped = 0usize;
+    let run_tag = args
+        .run_metadata()
+        .then(|| format!("{}{}", RUN_TAG_PREFIX, today()));
+
+    for (idx, sub) in subs.enumerate() {
+        let idx = format!("{}", offset + idx - skipped);
         let image = sub.image().map(to_image).unwrap_or("".to_string());
         let audio = sub.audio().map(to_audio).unwrap_or("".to_string());
         let diag = match sub.sub().dialogue() {
@@ -41,9 +560,276 @@ where
             Dialogue::Bitmap(_) => sub.sub_image().map(to_image).unwrap_or("".to_string()),
         };
 
+        let (speaker, diag) = if args.speaker_field() {
+            split_speaker(sub.sub().speaker(), args.speaker_regex(), diag)
+        } else {
+            (None, diag)
+        };
+        let mut fields = vec![idx, image, audio, with_direction(diag)];
+        if args.timestamp_field() {
+            fields.push(sub.sub().timespan().start().format(args.timestamp_format()));
+        }
+        if args.render_ass().is_some() {
+            let rendered = match sub.sub().dialogue() {
+                Dialogue::Ass(_) => sub.sub_image().map(to_image),
+                _ => None,
+            };
+            fields.push(rendered.unwrap_or_default());
+        }
+        if args.speaker_field() {
+            fields.push(speaker.clone().unwrap_or_default());
+        }
+        if args.color_speakers() {
+            let color = speaker.as_deref().map(speaker_color).unwrap_or_default();
+            fields.push(color.to_string());
+        }
+        if args.index_fields() {
+            fields.push(sub.file_idx().to_string());
+            fields.push(sub.sub_idx().to_string());
+        }
+        if args.furigana_field() {
+            let furigana = match sub.sub().dialogue() {
+                Dialogue::Ass(ass) => ass.text.furigana(),
+                _ => None,
+            };
+            fields.push(furigana.unwrap_or_default().to_string());
+        }
+        if args.reading_field() {
+            fields.push(sub.reading().unwrap_or_default().to_string());
+        }
+        if args.pitch_accent().is_some() {
+            fields.push(sub.pitch_accent().unwrap_or_default().to_string());
+        }
+        if args.chinese_fields() {
+            fields.push(sub.segmented().unwrap_or_default().to_string());
+            fields.push(sub.pinyin().unwrap_or_default().to_string());
+        }
+        if args.romanization_field() {
+            let romanization = sub.sub().text().map(romanize);
+            fields.push(romanization.unwrap_or_default());
+        }
+        if args.pair().is_some() {
+            fields.push(with_direction(sub.translation().unwrap_or("").to_string()));
+        }
+        fields.extend(sub.aligned().iter().cloned().map(with_direction));
+        if args.target_words().is_some() {
+            fields.push(sub.target_words().join(", "));
+        }
+        if args.run_metadata() {
+            fields.push(format!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        if args.model_preset() == ModelPreset::ClozeJp {
+            let text = sub.sub().text().unwrap_or_default();
+            fields.push(cloze(text, sub.target_words()));
+        }
+
+        if seen.contains(&fields[1..]) {
+            skipped += 1;
+            continue;
+        }
+
+        let tags = note_tags(sub, is_boosted(args, sub), run_tag.as_deref());
         res.push(
-            Note::new(model, vec![&idx, &image, &audio, &diag]).context("Failed to create note")?,
+            Note::new_with_options(
+                model.clone(),
+                fields.iter().map(String::as_str).collect(),
+                None,
+                (!tags.is_empty()).then(|| tags.iter().map(String::as_str).collect()),
+                None,
+            )
+            .context("Failed to create note")?,
         )
     }
+
+    if skipped > 0 {
+        info!(
+            "Skipped {} note(s) that already exist in the package",
+            skipped
+        );
+    }
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtl_fields() {
+        assert_eq!(
+            with_direction("שלום".to_string()),
+            "<span dir=\"rtl\">שלום</span>"
+        );
+        assert_eq!(with_direction("Hello".to_string()), "Hello");
+    }
+
+    #[test]
+    fn language_tag() {
+        let mut bundle = SubtitleBundle::from(crate::subtitle::Subtitle::new(
+            Default::default(),
+            Dialogue::Text("こんにちは".to_string()),
+        ));
+        assert!(note_tags(&bundle, false, None).is_empty());
+        bundle.set_lang("ja");
+        assert_eq!(note_tags(&bundle, false, None), vec!["lang::ja"]);
+        assert_eq!(
+            note_tags(&bundle, true, Some("stos::run-20261016")),
+            vec!["boosted", "lang::ja", "stos::run-20261016"]
+        );
+        bundle.set_audio("/nonexistent/stos-audio.mka");
+        bundle.keep_without_missing_media();
+        assert_eq!(bundle.audio(), None);
+        assert_eq!(
+            note_tags(&bundle, false, None),
+            vec!["lang::ja", "stos::missing-media"]
+        );
+        bundle.set_transcription("こんばんは", true);
+        assert_eq!(
+            note_tags(&bundle, false, None),
+            vec!["lang::ja", "stos::missing-media", "stos::speech-mismatch"]
+        );
+    }
+
+    #[test]
+    fn speakers() {
+        let re = Regex::new(r"^\(?([A-Z]+)\)?:").unwrap();
+        assert_eq!(
+            split_speaker(Some("Alice"), Some(&re), "BOB: Hi".to_string()),
+            (Some("Alice".to_string()), "BOB: Hi".to_string())
+        );
+        assert_eq!(
+            split_speaker(None, Some(&re), "(BOB): Hi".to_string()),
+            (Some("BOB".to_string()), "Hi".to_string())
+        );
+        assert_eq!(
+            split_speaker(None, Some(&re), "Hi: there".to_string()),
+            (None, "Hi: there".to_string())
+        );
+        assert_eq!(
+            split_speaker(None, None, "BOB: Hi".to_string()),
+            (None, "BOB: Hi".to_string())
+        );
+        assert_eq!(speaker_color("Alice"), speaker_color("Alice"));
+    }
+
+    #[test]
+    fn speaker_in_sentence_template() {
+        let (qfmt, _) = templates(ModelPreset::Sentence, &["Text", "Speaker"]);
+        assert_eq!(
+            qfmt,
+            "{{Image}}<br>{{Audio}}<br>{{#Speaker}}<p style=\"text-align: center\">{{Speaker}}</p>{{/Speaker}}<h1 style=\"text-align: center\">{{Text}}"
+        );
+
+        let (qfmt, _) = templates(ModelPreset::Vocab, &["Text", "Speaker", "Speaker color"]);
+        assert_eq!(
+            qfmt,
+            "<h1 class=\"text\"><span style=\"color: {{Speaker color}}\">{{Text}}</span></h1>"
+        );
+    }
+
+    #[test]
+    fn style_css_after_preset_css() {
+        let style = "h1 { color: #ffffff; }\n";
+        assert_eq!(model_css(ModelPreset::Sentence, None), None);
+        assert_eq!(
+            model_css(ModelPreset::Sentence, Some(style)),
+            Some(format!("{}{}", SENTENCE_CSS, style))
+        );
+        assert!(model_css(ModelPreset::Vocab, Some(style))
+            .unwrap()
+            .ends_with(&format!(".target {{ color: #2a7ae2; }}\n{}", style)));
+    }
+
+    #[test]
+    fn cloze_longest_words() {
+        let words = vec!["日本".to_string(), "日本語".to_string(), "cat".to_string()];
+        assert_eq!(
+            cloze("日本語と日本", &words),
+            "{{c1::日本語}}と{{c1::日本}}"
+        );
+        assert_eq!(cloze("A Cat", &words), "A {{c1::Cat}}");
+        assert_eq!(cloze("犬", &[]), "犬");
+    }
+
+    #[test]
+    fn vocab_template_without_target_words() {
+        let (qfmt, afmt) = templates(ModelPreset::Vocab, &["Text", "Translation"]);
+        assert_eq!(qfmt, "<h1 class=\"text\">{{Text}}</h1>");
+        assert!(afmt.ends_with("<p style=\"text-align: center\">{{Translation}}</p>"));
+    }
+
+    #[test]
+    fn escape_info() {
+        assert_eq!(
+            escape_html("<b>\"Tom & Jerry\"</b>"),
+            "&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn info_keeps_earlier_files() {
+        let bundle = |start: u32, end: u32| {
+            SubtitleBundle::from(crate::subtitle::Subtitle::new(
+                crate::time::Timespan::new(
+                    Timestamp::from_millis(start),
+                    Timestamp::from_millis(end),
+                ),
+                Dialogue::Text("Hi".to_string()),
+            ))
+        };
+        let subs = [bundle(1000, 2000), bundle(5000, 6000)];
+        let earlier = info_text(
+            &Args::default(),
+            "Old",
+            "2026-10-01",
+            3,
+            None,
+            subs[..1].iter(),
+        );
+        let text = info_text(
+            &Args::default(),
+            "Tom & Jerry",
+            "2026-10-16",
+            5,
+            Some(&earlier),
+            subs.iter(),
+        );
+
+        assert!(text.starts_with("<h2>Tom &amp; Jerry</h2><p>Made by stos "));
+        assert!(text.contains("on 2026-10-16 with 5 note(s)"));
+        assert_eq!(
+            info_rows(&text),
+            vec![
+                format!(
+                    "<td></td><td>1</td><td>{}</td><td>{}</td>",
+                    Timestamp::from_millis(1000),
+                    Timestamp::from_millis(2000)
+                ),
+                format!(
+                    "<td></td><td>2</td><td>{}</td><td>{}</td>",
+                    Timestamp::from_millis(1000),
+                    Timestamp::from_millis(6000)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_model_id() {
+        assert_eq!(model_id(BASE_FIELDS), MODEL_ID);
+    }
+
+    #[test]
+    fn extended_model_id() {
+        let mut fields = BASE_FIELDS.to_vec();
+        fields.push("Timestamp");
+        let id = model_id(&fields);
+        assert_ne!(id, MODEL_ID);
+        assert!(id >= 0);
+        assert_eq!(id, model_id(&fields));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::anki::INFO_MODEL_ID;
+use anyhow::{bail, Context, Result};
+use log::trace;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Anki uses this id for the "Default" deck that every collection has
+const DEFAULT_DECK_ID: i64 = 1;
+
+#[derive(Debug, Clone)]
+pub struct ExistingNote {
+    pub guid: String,
+    pub fields: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// The contents of a previously generated package that new notes can be appended to
+#[derive(Debug)]
+pub struct ExistingPackage {
+    deck_id: i64,
+    deck_name: String,
+    deck_desc: String,
+    model_id: i64,
+    notes: Vec<ExistingNote>,
+    info: Option<String>,
+    media: Vec<PathBuf>,
+    // The media files are extracted into this directory, so it has to live as long as the package
+    _dir: TempDir,
+}
+
+impl ExistingPackage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).context("Failed to open file")?;
+        let mut archive = zip::ZipArchive::new(file).context("Failed to read package")?;
+        let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+
+        let collection = dir.path().join("collection.anki2");
+        {
+            // Packages exported by newer versions of anki contain both, in which case the old
+            // collection is just a stub
+            let name = if archive.file_names().any(|name| name == "collection.anki21") {
+                "collection.anki21"
+            } else {
+                "collection.anki2"
+            };
+            let mut entry = archive
+                .by_name(name)
+                .context("Package does not contain a collection")?;
+            let mut out = File::create(&collection)?;
+            std::io::copy(&mut entry, &mut out).context("Failed to extract collection")?;
+        }
+
+        let media_map: HashMap<String, String> = match archive.by_name("media") {
+            Ok(entry) => serde_json::from_reader(entry).context("Failed to parse media list")?,
+            Err(_) => HashMap::new(),
+        };
+
+        let mut media = Vec::new();
+        for (idx, name) in media_map {
+            if Path::new(&name).file_name() != Some(OsStr::new(&name)) {
+                bail!("Package contains invalid media file name \"{}\"", name);
+            }
+
+            let out_path = dir.path().join(&name);
+            let mut entry = archive
+                .by_name(&idx)
+                .with_context(|| format!("Package is missing media file \"{}\"", name))?;
+            let mut out = File::create(&out_path)?;
+            std::io::copy(&mut entry, &mut out)
+                .with_context(|| format!("Failed to extract media file \"{}\"", name))?;
+            media.push(out_path);
+        }
+        trace!("extracted {} media files", media.len());
+
+        let conn = Connection::open(&collection).context("Failed to open collection")?;
+        let (deck_id, deck_name, deck_desc) = Self::read_deck(&conn)?;
+        let notes = Self::read_notes(&conn)?;
+        let info = Self::read_info(&conn)?;
+        let model_id = Self::read_model_id(&conn)?;
+        trace!(
+            "read {} notes of deck \"{}\" from \"{}\"",
+            notes.len(),
+            deck_name,
+            path.display()
+        );
+
+        Ok(Self {
+            deck_id,
+            deck_name,
+            deck_desc,
+            model_id,
+            notes,
+            info,
+            media,
+            _dir: dir,
+        })
+    }
+
+    fn read_deck(conn: &Connection) -> Result<(i64, String, String)> {
+        let decks: String = conn.query_row("SELECT decks FROM col", [], |row| row.get(0))?;
+        let decks: HashMap<String, serde_json::Value> =
+            serde_json::from_str(&decks).context("Failed to parse decks")?;
+
+        let used: Option<i64> = conn
+            .query_row(
+                "SELECT did FROM cards GROUP BY did ORDER BY COUNT(*) DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let (id, deck) = decks
+            .iter()
+            .filter_map(|(id, deck)| id.parse::<i64>().ok().map(|id| (id, deck)))
+            .find(|(id, _)| match used {
+                Some(used) => *id == used,
+                None => *id != DEFAULT_DECK_ID,
+            })
+            .context("Package does not contain a deck")?;
+
+        let name = deck["name"].as_str().unwrap_or_default().to_string();
+        let desc = deck["desc"].as_str().unwrap_or_default().to_string();
+        Ok((id, name, desc))
+    }
+
+    fn read_model_id(conn: &Connection) -> Result<i64> {
+        if let Some(mid) = conn
+            .query_row(
+                "SELECT mid FROM notes WHERE mid != ?1 LIMIT 1",
+                [INFO_MODEL_ID],
+                |row| row.get(0),
+            )
+            .optional()?
+        {
+            return Ok(mid);
+        }
+
+        let models: String = conn.query_row("SELECT models FROM col", [], |row| row.get(0))?;
+        let models: HashMap<String, serde_json::Value> =
+            serde_json::from_str(&models).context("Failed to parse models")?;
+        match models.keys().find_map(|id| id.parse().ok()) {
+            Some(id) => Ok(id),
+            None => bail!("Package does not contain a note type"),
+        }
+    }
+
+    fn read_notes(conn: &Connection) -> Result<Vec<ExistingNote>> {
+        // The info note is made again for the new notes, instead of being kept
+        let mut stmt =
+            conn.prepare("SELECT guid, tags, flds FROM notes WHERE mid != ?1 ORDER BY id")?;
+        let notes = stmt
+            .query_map([INFO_MODEL_ID], |row| {
+                let tags: String = row.get(1)?;
+                let fields: String = row.get(2)?;
+                Ok(ExistingNote {
+                    guid: row.get(0)?,
+                    fields: fields.split('\x1f').map(str::to_string).collect(),
+                    tags: tags.split_whitespace().map(str::to_string).collect(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(notes)
+    }
+
+    fn read_info(conn: &Connection) -> Result<Option<String>> {
+        Ok(conn
+            .query_row(
+                "SELECT flds FROM notes WHERE mid = ?1 LIMIT 1",
+                [INFO_MODEL_ID],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn deck_id(&self) -> i64 {
+        self.deck_id
+    }
+
+    pub fn deck_name(&self) -> &str {
+        &self.deck_name
+    }
+
+    pub fn deck_desc(&self) -> &str {
+        &self.deck_desc
+    }
+
+    pub fn model_id(&self) -> i64 {
+        self.model_id
+    }
+
+    pub fn notes(&self) -> &[ExistingNote] {
+        &self.notes
+    }
+
+    /// The contents of the info note of the package, if it has one
+    pub fn info(&self) -> Option<&str> {
+        self.info.as_deref()
+    }
+
+    pub fn media(&self) -> &[PathBuf] {
+        &self.media
+    }
+
+    /// Whether the package already contains a media file called `name`
+    pub fn has_media(&self, name: &str) -> bool {
+        self.media
+            .iter()
+            .any(|path| path.file_name().is_some_and(|file| file == name))
+    }
+}
@@ -1,16 +1,328 @@
-use crate::time::{Duration, Timestamp};
-use crate::util::StreamSelector;
+use crate::manifest::{read_manifest, ManifestRow};
+use crate::playlist::PlaylistFormat;
+use crate::recipe::Recipe;
+use crate::subtitle::{NewlineMode, ReadOptions, TextNormalization};
+use crate::time::{Duration, SyncPoints, Timestamp, TimestampFormat};
+use crate::util::{SelectorSpec, StreamSelector};
 use anyhow::{bail, Context, Result};
+use encoding_rs::Encoding;
 use log::LevelFilter;
 use rand::random;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 use std::ffi::OsString;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 const DEFAULT_DECK_FILE: &str = "deck.apkg";
+const DEFAULT_DECK_FILE_FORMAT: &str = "%n.apkg";
 const DEFAULT_DECK_NAME: &str = "Stos Deck";
 const DEFAULT_DECK_DESC: &str = "A deck generated by stos";
 const DEFAULT_MERGE_DIST: i64 = 250;
+const DEFAULT_KNOWN_SIMILARITY: f64 = 0.9;
+const DEFAULT_UNKNOWN_MAX: usize = 1;
+const DEFAULT_VERIFY_SIMILARITY: f64 = 0.3;
+const DEFAULT_AUDIO_SEPARATOR_LENGTH: i64 = 300;
+const DEFAULT_SUBTITLE_REGION: f64 = 1.0 / 3.0;
+const DEFAULT_OCR_LANG: &str = "eng";
+const DEFAULT_RENDER_SIZE: (u32, u32) = (1280, 720);
+const DEFAULT_PLAYER: &str = "ffplay -nodisp -autoexit -loglevel quiet";
+
+/// What to do with notes that refer to media files that could not be generated
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MissingMediaPolicy {
+    /// Clear the fields that refer to the missing files
+    #[default]
+    DropField,
+    /// Leave the whole note out of the deck
+    DropNote,
+    /// Do not write the deck
+    Error,
+}
+
+impl FromStr for MissingMediaPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "drop-field" => Ok(MissingMediaPolicy::DropField),
+            "drop-note" => Ok(MissingMediaPolicy::DropNote),
+            "error" => Ok(MissingMediaPolicy::Error),
+            _ => bail!(
+                "\"{}\" is not a valid missing media policy, expected drop-field, drop-note or error",
+                s
+            ),
+        }
+    }
+}
+
+/// The language the notes are tagged with
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NoteLang {
+    Code(String),
+    /// The language of the subtitle stream of each file
+    Auto,
+}
+
+impl FromStr for NoteLang {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(NoteLang::Auto),
+            // Anki separates tags by spaces
+            _ if s.is_empty() || s.contains(char::is_whitespace) => {
+                bail!("\"{}\" is not a valid language code", s)
+            }
+            _ => Ok(NoteLang::Code(s.to_string())),
+        }
+    }
+}
+
+/// What `--merge` compares to decide whether two subtitles are the same
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MergeKey {
+    /// The whole dialogue, including styling or the image of bitmap subtitles
+    #[default]
+    Dialogue,
+    /// The normalized text, so restyled repeats of a line are merged as well
+    Text,
+}
+
+impl FromStr for MergeKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dialogue" => Ok(MergeKey::Dialogue),
+            "text" => Ok(MergeKey::Text),
+            _ => bail!(
+                "\"{}\" is not a valid merge key, expected dialogue or text",
+                s
+            ),
+        }
+    }
+}
+
+/// Which subtitles `--max-cards` keeps
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum CardSelection {
+    /// The first subtitles
+    First,
+    /// The subtitles nearest to times spread evenly over the runtime, so the cards cover all of it
+    #[default]
+    Spread,
+    /// Randomly chosen subtitles
+    Random,
+}
+
+impl FromStr for CardSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(CardSelection::First),
+            "spread" => Ok(CardSelection::Spread),
+            "random" => Ok(CardSelection::Random),
+            _ => bail!(
+                "\"{}\" is not a valid card selection, expected first, spread or random",
+                s
+            ),
+        }
+    }
+}
+
+/// How `--blacklist` and `--whitelist` are combined
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum FilterMode {
+    /// Keep the subtitles that match any whitelist regex and no blacklist regex
+    #[default]
+    And,
+    /// Keep the subtitles that match no blacklist regex, and those that match any whitelist regex
+    WhitelistOverrides,
+}
+
+impl FromStr for FilterMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "and" => Ok(FilterMode::And),
+            "whitelist-overrides" => Ok(FilterMode::WhitelistOverrides),
+            _ => bail!(
+                "\"{}\" is not a valid filter mode, expected and or whitelist-overrides",
+                s
+            ),
+        }
+    }
+}
+
+/// How many subtitles of each file `--sample` keeps
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sample {
+    /// A fraction of the subtitles, between 0 and 1
+    Fraction(f64),
+    /// A number of subtitles
+    Count(usize),
+}
+
+impl Sample {
+    /// The number of subtitles to keep of `total`
+    pub fn count(&self, total: usize) -> usize {
+        match *self {
+            Sample::Fraction(fraction) => (total as f64 * fraction).round() as usize,
+            Sample::Count(count) => count.min(total),
+        }
+    }
+}
+
+impl FromStr for Sample {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(count) = s.parse() {
+            return Ok(Sample::Count(count));
+        }
+        match s.parse::<f64>() {
+            Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(Sample::Fraction(fraction)),
+            _ => bail!(
+                "\"{}\" is not a valid sample, expected a fraction between 0 and 1 or a number of subtitles",
+                s
+            ),
+        }
+    }
+}
+
+/// What is put between the lines of the audio clip of a `--context` card
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AudioSeparator {
+    /// A short beep
+    Tone,
+    Silence,
+}
+
+impl FromStr for AudioSeparator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tone" => Ok(AudioSeparator::Tone),
+            "silence" => Ok(AudioSeparator::Silence),
+            _ => bail!(
+                "\"{}\" is not a valid audio separator, expected tone or silence",
+                s
+            ),
+        }
+    }
+}
+
+/// The built-in note type the cards are made with
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ModelPreset {
+    /// The image, audio and text on the front and the extra fields on the back
+    #[default]
+    Sentence,
+    /// The text on the front, to practice reading the words
+    Vocab,
+    /// Only the audio on the front, to practice listening
+    Listening,
+    /// A cloze note type with the target words of Japanese text hidden
+    ClozeJp,
+}
+
+impl FromStr for ModelPreset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sentence" => Ok(ModelPreset::Sentence),
+            "vocab" => Ok(ModelPreset::Vocab),
+            "listening" => Ok(ModelPreset::Listening),
+            "cloze-jp" => Ok(ModelPreset::ClozeJp),
+            _ => bail!(
+                "\"{}\" is not a valid model preset, expected sentence, vocab, listening or cloze-jp",
+                s
+            ),
+        }
+    }
+}
+
+/// Flags that are applied to every filter regex, instead of writing `(?i)` in each of them
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RegexFlags {
+    case_insensitive: bool,
+    multi_line: bool,
+    dot_matches_new_line: bool,
+    ignore_whitespace: bool,
+}
+
+impl FromStr for RegexFlags {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = RegexFlags::default();
+        for flag in s.chars() {
+            match flag {
+                'i' => flags.case_insensitive = true,
+                'm' => flags.multi_line = true,
+                's' => flags.dot_matches_new_line = true,
+                'x' => flags.ignore_whitespace = true,
+                _ => bail!(
+                    "\"{}\" is not a valid regex flag, expected i, m, s or x",
+                    flag
+                ),
+            }
+        }
+        Ok(flags)
+    }
+}
+
+impl RegexFlags {
+    /// Compiles the pattern of `re` again with the flags
+    fn apply(&self, re: &Regex) -> Result<Regex> {
+        RegexBuilder::new(re.as_str())
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .ignore_whitespace(self.ignore_whitespace)
+            .build()
+            .with_context(|| format!("Failed to compile regex \"{}\"", re.as_str()))
+    }
+
+    fn apply_all(&self, regexes: &mut [Regex]) -> Result<()> {
+        for re in regexes {
+            *re = self.apply(re)?;
+        }
+        Ok(())
+    }
+}
+
+/// The order in which the media jobs are handed to the thread pool
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Schedule {
+    /// Start extracting images first, since every file is extracted by a single long job
+    #[default]
+    ImagesFirst,
+    /// Generate the audio clips first
+    AudioFirst,
+    /// Run the jobs file by file
+    Interleave,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "images-first" => Ok(Schedule::ImagesFirst),
+            "audio-first" => Ok(Schedule::AudioFirst),
+            "interleave" => Ok(Schedule::Interleave),
+            _ => bail!(
+                "\"{}\" is not a valid schedule, expected images-first, audio-first or interleave",
+                s
+            ),
+        }
+    }
+}
 
 fn print_help(executable: &str) {
     println!("USAGE:");
@@ -30,28 +342,95 @@ fn print_help(executable: &str) {
     println!("    --version                     Print version and exit");
     println!("    -v                            Increase verbosity of program logs");
     println!("    -o FILE, --output=FILE        Specify the file to write the anki deck to [default: {}]", DEFAULT_DECK_FILE);
-    println!("    -s INDEX, --sub-stream=INDEX  Select which stream to use from SUBTITLE_FILE as the subtitle stream");
-    println!("    --sub-lang=LANGUAGE           Select which stream to use form SUBTITLE_FILE as the subtitle stream by language");
-    println!("    --start TIMESTAMP             Specify from when the program should extract subtitles in hh:mm:ss format");
-    println!("    --end TIMESTAMP               Specify until when the program should extract subtitles in hh:mm:ss format");
-    println!("    --ignore-styled               Ignore subtitle texts that have been styled (only for ass format)");
+    println!("    -s INDEX, --sub-stream=INDEX  Select which stream to use from SUBTITLE_FILE as the subtitle stream (can be used multiple times, see below)");
+    println!("    --sub-lang=LANGUAGE           Select which stream to use form SUBTITLE_FILE as the subtitle stream by language (can be used multiple times, every stream after the first one is added as a Text 2, Text 3, ... field)");
+    println!("    --sub-select=SELECTORS        Select the subtitle stream with the first matching selector of a comma separated list of lang=LANGUAGE, title=TITLE, index=INDEX, forced and best");
+    println!("    --prefer-bitmap-subs          Let best select a bitmap subtitle stream (like PGS or VobSub) even if there is a text subtitle stream in the same language, which is used by default");
+    println!("    --pair=NATIVE:TARGET          Use the TARGET language stream for the Text field and the NATIVE language (or undetermined) stream for a Translation field");
+    println!("    --default-sub-duration=MILLISECONDS  Give subtitles of which the end is unknown a duration of MILLISECONDS instead of dropping them");
+    println!("    --sub-encoding=ENCODING       Read text subtitle files using ENCODING (e.g. shift_jis, gbk or windows-1252) [default: detected]");
+    println!("    --sub-fps=FPS                 Frame rate used to convert the frame numbers of frame based subtitles (MicroDVD) to timestamps [default: from the file or the media file]");
+    println!("    --zero-length=POLICY          What to do with subtitles without a duration: next, keep or drop [default: next]");
+    println!("    --ocr[=LANGUAGE]              Recognize the text of bitmap subtitles with tesseract using LANGUAGE data (requires the `ocr` feature) [default: {}]", DEFAULT_OCR_LANG);
+    println!("    --start TIMESTAMP             Specify from when the program should extract subtitles in [[hh:]mm:]ss[.mmm] format, or in milliseconds followed by ms (e.g. 1500ms)");
+    println!("    --end TIMESTAMP               Specify until when the program should extract subtitles in [[hh:]mm:]ss[.mmm] format, or in milliseconds followed by ms (e.g. 1500ms). A negative TIMESTAMP (e.g. -3:00) is counted back from the end of every media file, to skip the credits");
+    println!("    --skip-chapters=REGEX         Do not include subtitles that start in a chapter of the media file of which the title matches REGEX (e.g. \"OP|ED|Preview\")");
+    println!("    --forced-only                 Only include forced subtitles (from a forced stream or marked as forced themselves)");
+    println!("    --skip-forced                 Do not include forced subtitles");
+    println!("    --min-cps=CPS                 Do not include subtitles of which the text is shown at fewer than CPS characters per second");
+    println!("    --max-cps=CPS                 Do not include subtitles of which the text is shown at more than CPS characters per second, which is typical of bad timing");
+    println!("    --ignore-styled               Ignore subtitle texts that have been styled (only for ass format) [default]");
+    println!("    --keep-styled                 Include subtitle texts that have been styled, such as signs (only for ass format). The last of `--ignore-styled` and `--keep-styled` wins. Notes of vertical signs get a `vertical` tag");
+    println!("    --max-layer=N                 Do not include ass subtitles on a layer above N, which is where typesetting like signs is usually placed");
+    println!("    --strip-tags                  Remove HTML-like inline tags (<i>, <b>, <font>, ...) from the text of subtitles before filtering them and creating the cards");
+    println!("    --strip-sdh                   Remove the annotations of subtitles for the deaf and hard of hearing (sound descriptions in brackets, speaker labels like `JOHN:` and lines with music notes) and drop the subtitles left without text");
+    println!("    --normalize=TRANSFORMS        Normalize the text that `--blacklist`, `--whitelist`, `--dedup`, `--merge-key=text`, `--min-cps`, `--max-cps`, `--known-words` and `--known-sentences` compare, so differently formatted sources behave the same, with a comma separated list of: lowercase, strip-punct (remove everything but letters, digits and whitespace) and collapse-whitespace. The transforms are applied in order and the text on the cards is not changed. Without it, `--dedup` and `--merge-key=text` ignore case, punctuation and whitespace");
+    println!("    --newline-mode=MODE           Collapse repeated spaces in the text of subtitles and replace its line breaks (\\N and newlines) with: keep, space, br (an HTML line break) or separator=TEXT");
+    println!("    --replace=PATTERN=>REPLACEMENT  Replace the matches of the regex PATTERN in the text of subtitles with REPLACEMENT ($1 refers to a capture group) before filtering them, e.g. to fix OCR errors. Subtitles left without text are dropped (can be used multiple times, applied in order)");
     println!("    --merge                       Merge nearby subtitles that are the same into one. See `--max-dist`");
+    println!("    --merge-across-files          Like `--merge`, but also drop the subtitles at the start of a file that are the same as one at the end of the previous file, such as a line that continues over the split between two episodes. Only subtitles within `--max-dist` of the split are compared");
+    println!("    --dedup                       Do not include subtitles of which the text (ignoring case and punctuation) already appeared earlier in the same or an earlier file");
+    println!("    --known-sentences=FILE        Skip subtitles that are the same as or very similar to a sentence in FILE, a plain text export of existing cards with a sentence per line (or per tab separated field). Case and punctuation are ignored");
+    println!("    --known-similarity=FRACTION   Used only with `--known-sentences`. How similar a subtitle has to be to a known sentence to be skipped, 1 only skips exact matches [default: {}]", DEFAULT_KNOWN_SIMILARITY);
+    println!("    --max-cards=N                 Make at most N cards over all files. Which subtitles are kept is set with `--card-selection`. The media of the other subtitles is never generated");
+    println!("    --card-selection=STRATEGY     Used only with `--max-cards`. Which subtitles to keep: first, spread (the subtitles nearest to times spread evenly over the runtime of all files) or random [default: spread]");
+    println!("    --sample=FRACTION|COUNT       Make cards of only a random sample of the subtitles of each file, either a fraction between 0 and 1 (such as 0.1) or a number of subtitles. The media of the other subtitles is never generated");
+    println!("    --seed=NUMBER                 Seed for `--sample` and `--card-selection=random`, so the same subtitles are chosen every run [default: random]");
+    println!("    --min-coverage=FRACTION       Warn about files of which less than FRACTION of the runtime is covered by the included subtitles, which happens with signs-only streams");
+    println!("    --sentences                   Join consecutive subtitles into whole sentences, making one card per sentence. Subtitles more than 2 seconds apart are not joined, and a sentence stops at 30 seconds or 300 characters, so songs and signs without punctuation don't become a single card");
+    println!("    --split-sentences             Split subtitles that contain more than one sentence into a card per sentence, dividing the time of the subtitle in proportion to the length of the sentences. Together with `--sentences` every card has exactly one sentence");
+    println!("    --context=N                   Put N consecutive subtitles on a single card, with their text combined and one audio clip and image spanning all of them. Useful for exchanges in which a single line means little on its own");
     println!("    --max-dist=MILLISECONDS       Used only with `--merge`. Will not merge subtitles that are more than MILLISECONDS apart [default: {}]", DEFAULT_MERGE_DIST);
+    println!("    --merge-key=KEY               Used only with `--merge`. What makes subtitles the same: dialogue (the text with its styling, or the image) or text (the text ignoring styling, case and punctuation) [default: dialogue]");
     println!("    -a, --audio                   Generate audio snippets for the anki cards");
     println!("    --audio-stream=INDEX          Select which stream to use to generate the audio snippets");
     println!("    --audio-lang=LANGUAGE  Select which stream to use to generate the audio snippets by language");
+    println!("    --audio-select=SELECTORS      Like `--sub-select`, but for the audio stream");
     println!("    --pad-begin=MILLISECONDS      Pad the start time of each audio clip with MILLISECONDS amount");
     println!("    --pad-end=MILLISECONDS        Pad the end time of each audio clip with MILLISECONDS amount");
-    println!("    --shift-audio=MILLISECONDS    Shift the audio timings by MILLISECONDS amount");
+    println!("    --trim-silence                Tighten every audio clip to the speech in it, found by the loudness of the audio, so `--pad-begin` and `--pad-end` can be generous without clips full of noise. Clips made of the lines of a context card with `--audio-separator` are not trimmed");
+    println!("    --shift-audio=MILLISECONDS    Shift the audio timings by MILLISECONDS amount, the subtitles themselves stay where they are");
+    println!("    --shift-subs=MILLISECONDS     Show all subtitles MILLISECONDS later, or earlier if negative. Unlike `--shift-audio`, this moves the subtitles themselves, so it also affects the images, `--start`, `--end` and the JSON output");
+    println!("    --offset=FILE=MILLISECONDS    Show the subtitles of the FILEth pair of subtitle and media files (counting from 1) MILLISECONDS later, or earlier if negative, e.g. `--offset=2=-1500`. Can be given for several files and adds to the delay of the manifest");
+    println!("    --sync-points=T1=T1',T2=T2'   Stretch and shift the timings of all subtitles so that T1 becomes T1' and T2 becomes T2', e.g. `--sync-points=1:00=1:02,20:00=20:50`. Fixes subtitles that drift over the episode because they were timed for another frame rate (like 23.976 and 25 fps). Applied before `--offset` and `--shift-subs`");
+    println!("    --auto-sync                   Shift the subtitles of every file to line up with the speech in its audio stream, by at most a minute either way. The offset is only applied if it clearly fits better, and is reported with `-v` when it is");
     println!("    --join-audio                  Join overlapping audio into one clip");
-    println!("    -j JOBS, --jobs=JOBS          Specify amount of concurrent jobs stos will spawn [default: system logical core count]");
+    println!("    --audio-separator=KIND        Used only with `--context`. Make the audio clip of a card from the clips of its lines with a tone or silence between them, instead of a single clip that spans all of them. The clips are joined by the ffmpeg command, which has to be installed for this");
+    println!("    --audio-separator-length=MILLISECONDS  Used only with `--audio-separator`. The length of the tone or silence [default: {}]", DEFAULT_AUDIO_SEPARATOR_LENGTH);
+    println!("    --verify-speech=MODEL         Transcribe the audio of every note with the whisper MODEL (a ggml file) and tag the notes of which the text differs too much from it with stos::speech-mismatch for review, which catches the wrong subtitle stream or heavily localized subtitles. Requires the `whisper` feature");
+    println!("    --verify-similarity=FRACTION  Used only with `--verify-speech`. How similar the text of a note has to be to the transcription of its audio to not be tagged [default: {}]", DEFAULT_VERIFY_SIMILARITY);
+    println!("    --normalize-audio             Normalize the loudness of every audio clip (EBU R128 with ffmpeg's loudnorm filter), so clips of quiet and loud scenes play at a similar volume. The clips are encoded again for this, which needs the ffmpeg command to be installed");
+    println!("    --fade-ms=MILLISECONDS        Fade every audio clip in and out over MILLISECONDS (at most half of the clip), so padded clips don't start or end with a click or a cut off word. The clips are encoded again for this, which needs the ffmpeg command to be installed");
+    println!("    --probe-audio                 Report audio clips for which the audio stream contains no data before generating them");
+    println!("    --play-sample=N               Play N random audio clips after generating them");
+    println!("    --player=COMMAND              Used only with `--play-sample`. Command used to play a clip, the clip is passed as the last argument [default: {}]", DEFAULT_PLAYER);
+    println!("    -j JOBS, --jobs=JOBS          Specify amount of concurrent jobs stos will spawn [default: a job per logical core, fewer when extracting images with little memory available]");
+    println!("    --schedule=ORDER              Order in which media jobs are run: images-first, audio-first or interleave (per file) [default: images-first]");
+    println!("    --memory-budget=MEGABYTES     Write decoded images to temporary files instead of keeping them in memory once the images waiting to be written take up more than MEGABYTES [default: no limit]");
     println!("    -i, --image                   Generate images for the anki cards");
     println!("    --video-stream=INDEX          Select which stream to use to generate the images");
+    println!("    --subtitle-region[=FRACTION]  Crop the generated images to the bottom FRACTION of the picture, ignoring black bars [default: 0.33]");
+    println!("    --render-ass[=WIDTHxHEIGHT]   Render ASS subtitles with their styling and fonts using libass (through ffmpeg) and add them as a Subtitle image field [default: 1280x720]");
+    println!("    --fonts-dir=DIR               Extract the fonts attached to the input files to DIR, they are used by `--render-ass` [default: a temporary directory]");
+    println!("    --contact-sheet=FILE          Write a grid of all generated images, labeled with their index and timestamp, to FILE");
+    println!("    --playlist=FILE               Write the timespans of all notes as an mpv playlist to FILE, which is an EDL (.edl) or m3u (.m3u, .m3u8) file pointing into the media files");
     println!("    -m, --media                   Specify media files from which to generate the audio snippets `-a` and/or images `-i`");
     println!("    --no-media                    Will not write media files specified by `-a` and/or `-i`");
     println!("    -b, --blacklist               Do not include subtitles that match this regex (can be used multiple times)");
     println!("    -w, --whitelist               Only include subtitles that match this regex (can be used multiple times)");
+    println!("    --filter-mode=MODE            How `--blacklist` and `--whitelist` are combined: and (keep subtitles that match a whitelist regex and no blacklist regex, so the blacklist wins when both match) or whitelist-overrides (keep subtitles that match no blacklist regex, and those that match a whitelist regex even if they do) [default: and]");
+    println!("    --actor=REGEX                 Only include ASS subtitles of which the actor (Name field) matches REGEX (can be used multiple times)");
+    println!("    --skip-actor=REGEX            Do not include ASS subtitles of which the actor (Name field) matches REGEX, e.g. a narrator (can be used multiple times)");
+    println!("    --boost=REGEX                 Tag subtitles that match REGEX with boosted and put their notes first in the deck, without leaving out the others (can be used multiple times)");
+    println!("    --target-words=FILE           Only include subtitles that contain one of the words in FILE, one word per line. Words are matched as whole words, ignoring case. The words a subtitle contains are put in a Target words field");
+    println!("    --known-words=FILE            Only include subtitles with at most `--unknown-max` words that are not in FILE, one word per line, for i+1 sentence mining. Numbers are not counted. Text without spaces, like Japanese, is split into the longest words of FILE and every other character counts as an unknown word");
+    println!("    --unknown-max=N               Used only with `--known-words`. The number of unknown words a subtitle may contain [default: {}]", DEFAULT_UNKNOWN_MAX);
+    println!("    --regex-flags=FLAGS           Flags for the regexes of `--blacklist`, `--whitelist`, `--boost`, `--replace`, `--actor`, `--skip-actor` and `--speaker-regex`: i (case insensitive), m (^ and $ match at line breaks), s (. matches line breaks) and x (ignore whitespace and # comments), e.g. `--regex-flags=im`. \\b and \\w are always Unicode aware");
+    println!("    --on-missing-media=POLICY     What to do with notes of which some media could not be generated, like the output of a failed job: drop-field, drop-note or error [default: drop-field]");
+    println!("    --notes-without-media         Keep the notes of which the audio or image jobs failed, or of which the images could not be extracted, without that media and tag them with stos::missing-media, instead of leaving them to `--on-missing-media`");
     println!("    --no-deck                     Do not write an anki deck package");
+    println!("    --temp-media                  Generate the media in a temporary directory that is removed once the deck is written, instead of in the working directory");
+    println!("    --write-ndjson                Write the subtitles of the notes to stdout as JSON, one object per line once the deck is written, or before the deck with `--no-media`. Every object is written and flushed on its own instead of serializing all subtitles into one string like `--write-json`, so other tools can read them line by line. Cannot be used together with `--write-json`");
     println!(
         "    --id=ID                       Specify the id to give the anki deck [default: random]"
     );
@@ -60,52 +439,173 @@ fn print_help(executable: &str) {
         DEFAULT_DECK_NAME
     );
     println!("    --desc=DESC                   Specify the description to give the anki deck [default: {}]", DEFAULT_DECK_DESC);
+    println!("    --append=FILE                 Append the new notes and media to an existing package generated by stos and write it back to FILE");
+    println!("    --split-deck-by=REGEX         Put every note of which the text matches REGEX in a subdeck named after the first capture group (or the whole match). The text is prefixed with `SPEAKER: ` if the subtitle has a speaker");
+    println!(
+        "    --deck-per-file               Write a separate anki deck package for every media file"
+    );
+    println!("    --deck-file-format=FORMAT     Used only with `--deck-per-file`. File name of each package, %f is replaced with the file index and %n with the media file name [default: {}]", DEFAULT_DECK_FILE_FORMAT);
+    println!("    --manifest=FILE               Read the subtitle files from a CSV manifest with a header and the columns subtitle, media, delay (in milliseconds), lang (of the subtitle stream) and deck, one row per file. Relative paths are relative to the manifest");
+    println!("    --recipe=FILE                 Write the options, input file hashes and selected streams of this run to FILE, to build the same deck again later");
+    println!("    --from-recipe=FILE            Build the deck using the options in a recipe written by `--recipe`, warning about input files that have changed since");
+    println!("    --word-clips=WORD_LIST        Read each SUBTITLE_FILE as forced alignment data (`START END WORD` lines in seconds) and make a vocab card for every word in WORD_LIST");
+    println!("    --model-preset=PRESET         The note type of the cards: sentence (image, audio and text on the front), vocab (only the text on the front), listening (only the audio on the front) or cloze-jp (a cloze of the target words, requires `--target-words`). The presets other than sentence come with their own CSS [default: sentence]");
+    println!("    --style-css                   Style the text of the cards like the ass style most of their subtitles use (its font, colors, outline, bold and italic), read from the styles of the subtitle file, so the cards look like the subtitles. The text always gets an outline that stands out from it, so it stays readable on any card background");
+    println!("    --index-fields                Add File index and Subtitle index fields with the index of the file and of the subtitle within that file to the notes");
+    println!("    --furigana-field              Add a Furigana field with the readings that ASS subtitles give as karaoke furigana (`漢字|かんじ`) or `\\rt` tags, in Anki's furigana syntax");
+    println!("    --run-metadata                Tag the notes with stos::run-YYYYMMDD (the date of the run) and add a Generator field with the version of stos, so the notes made by a run can be found and edited together in Anki");
+    println!("    --info-note                   Put a note at the top of the deck that lists the source files, the number of notes, the date and the options of the run, so the deck carries where it came from. With `--append` the files of the earlier runs are kept");
+    println!("    --reading-field               Add a Reading field with the reading of Japanese text in hiragana, found with a morphological analysis by lindera (requires the `reading` feature)");
+    println!("    --chinese-fields              Add a Segmented field with the words of Chinese text separated by spaces, split by jieba, and a Pinyin field with their pinyin (requires the `chinese` feature)");
+    println!("    --speaker-field               Add a Speaker field with the actor (Name field) of ASS subtitles, which the default note template shows above the text");
+    println!("    --speaker-regex=REGEX         Take the speaker of subtitles without an actor from the first capture group (or the whole match) of REGEX and remove the match from the text, e.g. `^([A-Z]+): `. Implies `--speaker-field`");
+    println!("    --color-speakers              Color the text of every speaker differently, the same speaker gets the same color in every deck. The color is put in a Speaker color field that the note templates use, so the Text field stays plain text. Implies `--speaker-field`");
+    println!("    --romanization-field          Add a Romanization field with the Hangul of Korean text in the Revised Romanization of Korean");
+    println!("    --pitch-accent=FILE           Add a Pitch accent field with the text, in which the words found in FILE (a dictionary with `WORD<TAB>READING<TAB>ACCENT` lines like kanjium's accents.txt) are wrapped in spans with the class accent-heiban, accent-atamadaka, accent-nakadaka or accent-odaka, for the note template to style");
+    println!("    --frequency-list=FILE         Tag the notes with freq::top1k, freq::top5k, freq::top10k, freq::top20k or freq::rare by the rarest word of the text in FILE, a list with the most common word first and one word per line");
+    println!("    --note-lang=CODE              Tag the notes with lang::CODE, so Anki's text to speech and add-ons can tell the language of the text. With auto the language of the subtitle stream of each file is used");
+    println!("    --timestamp-field             Add a Timestamp field with the start time of the subtitle to the notes");
+    println!("    --clock=CLOCK                 Use a 24h or 12h clock for the Timestamp field [default: 24h]");
+    println!("    --ms-separator=SEPARATOR      Separate the milliseconds in the Timestamp field with a dot or comma [default: dot]");
 }
 
 #[derive(Clone, Debug)]
 pub struct Args {
     program: String,
+    raw_args: Vec<OsString>,
 
     sub_files: Vec<PathBuf>,
-    sub_stream: Option<usize>,
-    sub_lang: Option<String>,
+    sub_stream: Vec<usize>,
+    sub_lang: Vec<String>,
+    sub_select: Vec<SelectorSpec>,
+    prefer_bitmap_subs: bool,
+    pair: Option<(String, String)>,
+    read_options: ReadOptions,
+    ocr_lang: Option<String>,
 
     start: Timestamp,
     end: Timestamp,
+    end_before_eof: Option<Duration>,
 
     blacklist: Vec<Regex>,
     whitelist: Vec<Regex>,
+    filter_mode: FilterMode,
+    boost: Vec<Regex>,
+    target_words: Option<PathBuf>,
+    known_words: Option<PathBuf>,
+    unknown_max: usize,
+    actors: Vec<Regex>,
+    skipped_actors: Vec<Regex>,
+    regex_flags: RegexFlags,
+    skip_chapters: Option<Regex>,
+    min_cps: Option<f64>,
+    max_cps: Option<f64>,
     ignore_styled: bool,
+    max_layer: Option<u32>,
+    strip_tags: bool,
+    strip_sdh: bool,
+    normalizations: Vec<TextNormalization>,
+    newline_mode: Option<NewlineMode>,
+    replacements: Vec<(Regex, String)>,
+    dedup: bool,
+    known_sentences: Option<PathBuf>,
+    known_similarity: f64,
+    max_cards: Option<usize>,
+    card_selection: CardSelection,
+    sample: Option<Sample>,
+    seed: Option<u64>,
+    min_coverage: Option<f64>,
+    forced_only: bool,
+    skip_forced: bool,
 
     merge: bool,
     merge_diff: Duration,
+    merge_key: MergeKey,
+    merge_across_files: bool,
+    sentences: bool,
+    split_sentences: bool,
+    context: Option<usize>,
 
     media_files: Vec<PathBuf>,
 
     gen_audio: bool,
     audio_stream: Option<usize>,
     audio_lang: Option<String>,
+    audio_select: Vec<SelectorSpec>,
     pad_begin: Duration,
     pad_end: Duration,
+    trim_silence: bool,
     shift_audio: Duration,
+    shift_subs: Duration,
+    sync_points: Option<SyncPoints>,
     join_audio: bool,
+    audio_separator: Option<AudioSeparator>,
+    audio_separator_length: Duration,
+    probe_audio: bool,
+    normalize_audio: bool,
+    fade: Option<Duration>,
+    verify_speech: Option<PathBuf>,
+    verify_similarity: f64,
+    play_sample: usize,
+    player: String,
 
     job_count: Option<usize>,
+    memory_budget: Option<u64>,
 
     gen_images: bool,
     video_stream: Option<usize>,
     image_width: Option<u32>,
     image_height: Option<u32>,
+    subtitle_region: Option<f64>,
+    render_ass: Option<(u32, u32)>,
+    fonts_dir: Option<PathBuf>,
+    contact_sheet: Option<PathBuf>,
+    playlist: Option<PathBuf>,
 
     no_media: bool,
+    schedule: Schedule,
+    offsets: Vec<(usize, Duration)>,
+    auto_sync: bool,
+    model_preset: ModelPreset,
+    style_css: bool,
+    on_missing_media: MissingMediaPolicy,
+    notes_without_media: bool,
     no_deck: bool,
+    temp_media: bool,
 
     deck_id: i64,
     deck_name: String,
     deck_desc: String,
     package: PathBuf,
+    append: Option<PathBuf>,
+    split_deck_by: Option<Regex>,
+    deck_per_file: bool,
+    deck_file_format: String,
+
+    word_list: Option<PathBuf>,
+    recipe: Option<PathBuf>,
+    from_recipe: Option<PathBuf>,
+    manifest: Vec<ManifestRow>,
+    replayed: Option<Recipe>,
+
+    timestamp_field: bool,
+    run_metadata: bool,
+    info_note: bool,
+    index_fields: bool,
+    note_lang: Option<NoteLang>,
+    furigana_field: bool,
+    reading_field: bool,
+    chinese_fields: bool,
+    speaker_field: bool,
+    speaker_regex: Option<Regex>,
+    color_speakers: bool,
+    romanization_field: bool,
+    pitch_accent: Option<PathBuf>,
+    frequency_list: Option<PathBuf>,
+    timestamp_format: TimestampFormat,
 
     write_json: bool,
+    write_ndjson: bool,
     dump: bool,
 
     verbosity: LevelFilter,
@@ -118,33 +618,123 @@ impl Default for Args {
             sub_files: Default::default(),
             sub_stream: Default::default(),
             sub_lang: Default::default(),
+            sub_select: Default::default(),
+            prefer_bitmap_subs: false,
+            pair: Default::default(),
+            read_options: Default::default(),
+            ocr_lang: None,
             start: Timestamp::MIN,
             end: Timestamp::MAX,
+            end_before_eof: None,
             blacklist: Default::default(),
             whitelist: Default::default(),
+            filter_mode: FilterMode::default(),
+            boost: Default::default(),
+            target_words: None,
+            known_words: None,
+            unknown_max: DEFAULT_UNKNOWN_MAX,
+            actors: Default::default(),
+            skipped_actors: Default::default(),
+            regex_flags: RegexFlags::default(),
+            skip_chapters: None,
+            min_cps: None,
+            max_cps: None,
             ignore_styled: true,
+            max_layer: None,
+            strip_tags: false,
+            strip_sdh: false,
+            normalizations: Vec::new(),
+            newline_mode: None,
+            replacements: Vec::new(),
+            dedup: false,
+            known_sentences: None,
+            known_similarity: DEFAULT_KNOWN_SIMILARITY,
+            max_cards: None,
+            card_selection: CardSelection::default(),
+            sample: None,
+            seed: None,
+            min_coverage: None,
+            forced_only: false,
+            skip_forced: false,
             merge: false,
             merge_diff: Duration::from_millis(DEFAULT_MERGE_DIST),
+            merge_key: MergeKey::default(),
+            merge_across_files: false,
+            sentences: false,
+            split_sentences: false,
+            context: None,
             media_files: Default::default(),
             gen_audio: false,
             audio_stream: Default::default(),
             audio_lang: Default::default(),
+            audio_select: Default::default(),
             pad_begin: Duration::from_millis(0),
             pad_end: Duration::from_millis(0),
+            trim_silence: false,
             shift_audio: Duration::from_millis(0),
+            shift_subs: Duration::from_millis(0),
+            sync_points: None,
             join_audio: false,
+            audio_separator: None,
+            audio_separator_length: Duration::from_millis(DEFAULT_AUDIO_SEPARATOR_LENGTH),
+            probe_audio: false,
+            normalize_audio: false,
+            fade: None,
+            verify_speech: None,
+            verify_similarity: DEFAULT_VERIFY_SIMILARITY,
+            play_sample: 0,
+            player: DEFAULT_PLAYER.to_string(),
             job_count: None,
+            memory_budget: None,
             gen_images: false,
             video_stream: Default::default(),
             image_width: Default::default(),
             image_height: Default::default(),
+            subtitle_region: None,
+            render_ass: None,
+            fonts_dir: None,
+            contact_sheet: None,
+            playlist: None,
             no_media: false,
+            schedule: Default::default(),
+            offsets: Vec::new(),
+            auto_sync: false,
+            model_preset: Default::default(),
+            style_css: false,
+            on_missing_media: Default::default(),
+            notes_without_media: false,
             no_deck: false,
+            temp_media: false,
             deck_id: random(),
             deck_name: DEFAULT_DECK_NAME.to_string(),
             deck_desc: DEFAULT_DECK_DESC.to_string(),
             package: DEFAULT_DECK_FILE.into(),
+            append: None,
+            split_deck_by: None,
+            deck_per_file: false,
+            deck_file_format: DEFAULT_DECK_FILE_FORMAT.to_string(),
+            word_list: None,
+            recipe: None,
+            from_recipe: None,
+            manifest: Vec::new(),
+            replayed: None,
+            timestamp_field: false,
+            run_metadata: false,
+            info_note: false,
+            index_fields: false,
+            note_lang: None,
+            furigana_field: false,
+            reading_field: false,
+            chinese_fields: false,
+            speaker_field: false,
+            speaker_regex: None,
+            color_speakers: false,
+            romanization_field: false,
+            pitch_accent: None,
+            frequency_list: None,
+            timestamp_format: Default::default(),
             write_json: false,
+            write_ndjson: false,
             dump: false,
             verbosity: LevelFilter::Error,
         }
@@ -153,10 +743,17 @@ impl Default for Args {
 
 impl Args {
     pub fn parse_from_env() -> Result<Self> {
+        Self::parse_from_args(std::env::args_os().collect())
+    }
+
+    pub(crate) fn parse_from_args(raw_args: Vec<OsString>) -> Result<Self> {
         use lexopt::prelude::*;
 
-        let mut args = Args::default();
-        let mut parser = lexopt::Parser::from_env();
+        let mut args = Args {
+            raw_args: raw_args.iter().skip(1).cloned().collect(),
+            ..Default::default()
+        };
+        let mut parser = lexopt::Parser::from_iter(raw_args);
 
         let mut taking_media = false;
 
@@ -178,44 +775,279 @@ impl Args {
                     taking_media = true;
                 }
                 Short('s') | Long("sub-stream") => {
-                    if args.sub_lang.is_some() {
+                    if !args.sub_select.is_empty() {
+                        eprintln!("--sub-select cannot be used together with --sub-stream, --sub-lang or --pair");
+                        std::process::exit(1);
+                    }
+                    if args.pair.is_some() {
+                        eprintln!("--pair cannot be used together with --sub-stream or --sub-lang");
+                        std::process::exit(1);
+                    }
+                    if !args.sub_lang.is_empty() {
                         eprintln!("--sub-stream and --sub-lang cannot be use at the same time");
                         std::process::exit(1);
                     }
-                    args.sub_stream = Some(Self::convert(parser.value()?)?.parse()?)
+                    args.sub_stream
+                        .push(Self::convert(parser.value()?)?.parse()?)
                 }
                 Long("sub-lang") => {
-                    if args.sub_stream.is_some() {
+                    if !args.sub_select.is_empty() {
+                        eprintln!("--sub-select cannot be used together with --sub-stream, --sub-lang or --pair");
+                        std::process::exit(1);
+                    }
+                    if args.pair.is_some() {
+                        eprintln!("--pair cannot be used together with --sub-stream or --sub-lang");
+                        std::process::exit(1);
+                    }
+                    if !args.sub_stream.is_empty() {
                         eprintln!("--sub-stream and --sub-lang cannot be use at the same time");
                         std::process::exit(1);
                     }
-                    args.sub_lang = Some(Self::convert(parser.value()?)?.parse()?)
+                    args.sub_lang.push(Self::convert(parser.value()?)?)
+                }
+                Long("sub-select") => {
+                    if args.pair.is_some()
+                        || !args.sub_stream.is_empty()
+                        || !args.sub_lang.is_empty()
+                    {
+                        eprintln!("--sub-select cannot be used together with --sub-stream, --sub-lang or --pair");
+                        std::process::exit(1);
+                    }
+                    args.sub_select = SelectorSpec::parse_chain(&Self::convert(parser.value()?)?)?;
+                }
+                Long("prefer-bitmap-subs") => {
+                    args.prefer_bitmap_subs = true;
+                }
+                Long("pair") => {
+                    if !args.sub_select.is_empty() {
+                        eprintln!("--sub-select cannot be used together with --sub-stream, --sub-lang or --pair");
+                        std::process::exit(1);
+                    }
+                    if !args.sub_stream.is_empty() || !args.sub_lang.is_empty() {
+                        eprintln!("--pair cannot be used together with --sub-stream or --sub-lang");
+                        std::process::exit(1);
+                    }
+                    let pair = Self::convert(parser.value()?)?;
+                    match pair.split_once(':') {
+                        Some((native, target)) if !native.is_empty() && !target.is_empty() => {
+                            args.pair = Some((native.to_string(), target.to_string()))
+                        }
+                        _ => bail!(
+                            "\"{}\" is not a valid language pair, expected NATIVE:TARGET",
+                            pair
+                        ),
+                    }
+                }
+                Long("default-sub-duration") => {
+                    args.read_options.default_duration =
+                        Some(Duration::from_millis(Self::convert_value(&mut parser)?))
+                }
+                Long("sub-encoding") => {
+                    let label = Self::convert(parser.value()?)?;
+                    args.read_options.encoding =
+                        Some(Encoding::for_label(label.as_bytes()).with_context(|| {
+                            format!("\"{}\" is not a known character encoding", label)
+                        })?);
+                }
+                Long("sub-fps") => {
+                    let fps: f64 = Self::convert(parser.value()?)?.parse()?;
+                    if !(fps.is_finite() && fps > 0.0) {
+                        bail!("The subtitle frame rate must be a positive number");
+                    }
+                    args.read_options.fps = Some(fps);
+                }
+                Long("zero-length") => {
+                    args.read_options.zero_length = Self::convert(parser.value()?)?.parse()?
+                }
+                Long("ocr") => {
+                    args.ocr_lang = Some(match parser.optional_value() {
+                        Some(lang) => Self::convert(lang)?,
+                        None => DEFAULT_OCR_LANG.to_string(),
+                    })
                 }
                 Long("start") => args.start = Self::convert(parser.value()?)?.parse()?,
-                Long("end") => args.end = Self::convert(parser.value()?)?.parse()?,
+                Long("end") => {
+                    let end = Self::convert(parser.value()?)?;
+                    match end.strip_prefix('-') {
+                        Some(before) => {
+                            let before: Timestamp = before.parse()?;
+                            args.end = Timestamp::MAX;
+                            args.end_before_eof = Some(Duration::from_millis(before.as_millis()));
+                        }
+                        None => {
+                            args.end = end.parse()?;
+                            args.end_before_eof = None;
+                        }
+                    }
+                }
                 Short('b') | Long("blacklist") => {
                     let re = Self::convert(parser.value()?)?;
                     args.blacklist
                         .push(Regex::new(&re).context("Failed to compile regex for blacklist")?)
                 }
+                Long("target-words") => {
+                    args.target_words = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("known-words") => {
+                    args.known_words = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("unknown-max") => {
+                    args.unknown_max = Self::convert_value(&mut parser)?;
+                }
                 Short('w') | Long("whitelist") => {
                     let re = Self::convert(parser.value()?)?;
                     args.whitelist
                         .push(Regex::new(&re).context("Failed to compile regex for whitelist")?)
                 }
+                Long("filter-mode") => {
+                    args.filter_mode = Self::convert(parser.value()?)?.parse()?;
+                }
+                Long("boost") => {
+                    let re = Self::convert(parser.value()?)?;
+                    args.boost
+                        .push(Regex::new(&re).context("Failed to compile regex for --boost")?)
+                }
+                Long("actor") => {
+                    let re = Self::convert(parser.value()?)?;
+                    args.actors
+                        .push(Regex::new(&re).context("Failed to compile regex for --actor")?)
+                }
+                Long("skip-actor") => {
+                    let re = Self::convert(parser.value()?)?;
+                    args.skipped_actors
+                        .push(Regex::new(&re).context("Failed to compile regex for --skip-actor")?)
+                }
+                Long("regex-flags") => {
+                    args.regex_flags = Self::convert(parser.value()?)?.parse()?;
+                }
+                Long("skip-chapters") => {
+                    let re = Self::convert(parser.value()?)?;
+                    args.skip_chapters = Some(
+                        Regex::new(&re).context("Failed to compile regex for --skip-chapters")?,
+                    );
+                }
+                Long("min-cps") => args.min_cps = Some(Self::parse_cps(parser.value()?)?),
+                Long("max-cps") => args.max_cps = Some(Self::parse_cps(parser.value()?)?),
+                Long("forced-only") => {
+                    if args.skip_forced {
+                        eprintln!(
+                            "--forced-only and --skip-forced cannot be used at the same time"
+                        );
+                        std::process::exit(1);
+                    }
+                    args.forced_only = true;
+                }
+                Long("skip-forced") => {
+                    if args.forced_only {
+                        eprintln!(
+                            "--forced-only and --skip-forced cannot be used at the same time"
+                        );
+                        std::process::exit(1);
+                    }
+                    args.skip_forced = true;
+                }
                 Long("ignore-styled") => {
                     args.ignore_styled = true;
                 }
+                Long("keep-styled") => {
+                    args.ignore_styled = false;
+                }
+                Long("max-layer") => {
+                    args.max_layer = Some(Self::convert(parser.value()?)?.parse()?)
+                }
+                Long("strip-tags") => {
+                    args.strip_tags = true;
+                }
+                Long("strip-sdh") => {
+                    args.strip_sdh = true;
+                }
+                Long("normalize") => {
+                    for normalization in Self::convert(parser.value()?)?.split(',') {
+                        args.normalizations.push(normalization.trim().parse()?);
+                    }
+                }
+                Long("newline-mode") => {
+                    args.newline_mode = Some(Self::convert(parser.value()?)?.parse()?);
+                }
+                Long("replace") => {
+                    let value = Self::convert(parser.value()?)?;
+                    let Some((pattern, replacement)) = value.split_once("=>") else {
+                        bail!(
+                            "\"{}\" is not a valid replacement, expected PATTERN=>REPLACEMENT",
+                            value
+                        );
+                    };
+                    args.replacements.push((
+                        Regex::new(pattern).context("Failed to compile regex for --replace")?,
+                        replacement.to_string(),
+                    ));
+                }
+                Long("dedup") => {
+                    args.dedup = true;
+                }
+                Long("known-sentences") => {
+                    args.known_sentences = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("known-similarity") => {
+                    let fraction: f64 = Self::convert(parser.value()?)?.parse()?;
+                    if !(0.0..=1.0).contains(&fraction) {
+                        bail!("The known similarity must be a fraction between 0 and 1");
+                    }
+                    args.known_similarity = fraction;
+                }
+                Long("max-cards") => {
+                    args.max_cards = Some(Self::convert_value(&mut parser)?);
+                }
+                Long("card-selection") => {
+                    args.card_selection = Self::convert(parser.value()?)?.parse()?;
+                }
+                Long("sample") => {
+                    args.sample = Some(Self::convert(parser.value()?)?.parse()?);
+                }
+                Long("seed") => {
+                    args.seed = Some(Self::convert_value(&mut parser)?);
+                }
+                Long("min-coverage") => {
+                    let fraction: f64 = Self::convert(parser.value()?)?.parse()?;
+                    if !(0.0..=1.0).contains(&fraction) {
+                        bail!("The minimum coverage must be a fraction between 0 and 1");
+                    }
+                    args.min_coverage = Some(fraction);
+                }
                 Long("merge") => {
                     args.merge = true;
                 }
+                Long("merge-across-files") => {
+                    args.merge = true;
+                    args.merge_across_files = true;
+                }
+                Long("sentences") => {
+                    args.sentences = true;
+                }
+                Long("split-sentences") => {
+                    args.split_sentences = true;
+                }
+                Long("context") => {
+                    let context: usize = Self::convert_value(&mut parser)?;
+                    if context == 0 {
+                        bail!("The context must be at least one subtitle");
+                    }
+                    args.context = Some(context);
+                }
                 Long("max-dist") => {
                     args.merge_diff = Duration::from_millis(Self::convert_value(&mut parser)?)
                 }
+                Long("merge-key") => {
+                    args.merge_key = Self::convert(parser.value()?)?.parse()?;
+                }
                 Short('a') => {
                     args.gen_audio = true;
                 }
                 Long("audio-stream") => {
+                    if !args.audio_select.is_empty() {
+                        eprintln!("--audio-select cannot be used together with --audio-stream or --audio-lang");
+                        std::process::exit(1);
+                    }
                     if args.audio_lang.is_some() {
                         eprintln!("--audio-stream and --audio-lang cannot be use at the same time");
                         std::process::exit(1);
@@ -223,39 +1055,168 @@ impl Args {
                     args.audio_stream = Some(Self::convert(parser.value()?)?.parse()?)
                 }
                 Long("audio-lang") => {
+                    if !args.audio_select.is_empty() {
+                        eprintln!("--audio-select cannot be used together with --audio-stream or --audio-lang");
+                        std::process::exit(1);
+                    }
                     if args.audio_stream.is_some() {
                         eprintln!("--audio-stream and --audio-lang cannot be use at the same time");
                         std::process::exit(1);
                     }
                     args.audio_lang = Some(Self::convert(parser.value()?)?.parse()?)
                 }
+                Long("audio-select") => {
+                    if args.audio_stream.is_some() || args.audio_lang.is_some() {
+                        eprintln!("--audio-select cannot be used together with --audio-stream or --audio-lang");
+                        std::process::exit(1);
+                    }
+                    args.audio_select =
+                        SelectorSpec::parse_chain(&Self::convert(parser.value()?)?)?;
+                }
                 Long("pad-begin") => {
                     args.pad_begin = Duration::from_millis(Self::convert_value(&mut parser)?)
                 }
                 Long("pad-end") => {
                     args.pad_end = Duration::from_millis(Self::convert_value(&mut parser)?)
                 }
+                Long("trim-silence") => {
+                    args.trim_silence = true;
+                }
                 Long("shift-audio") => {
                     args.shift_audio = Duration::from_millis(Self::convert_value(&mut parser)?)
                 }
+                Long("shift-subs") => {
+                    args.shift_subs = Duration::from_millis(Self::convert_value(&mut parser)?)
+                }
+                Long("sync-points") => {
+                    args.sync_points = Some(Self::convert(parser.value()?)?.parse()?)
+                }
+                Long("auto-sync") => {
+                    args.auto_sync = true;
+                }
+                Long("offset") => {
+                    let value = Self::convert(parser.value()?)?;
+                    let Some((file, millis)) = value.split_once('=') else {
+                        bail!(
+                            "\"{}\" is not a valid offset, expected FILE=MILLISECONDS",
+                            value
+                        );
+                    };
+                    let file: usize = file
+                        .parse()
+                        .with_context(|| format!("\"{}\" is not a valid file number", file))?;
+                    if file == 0 {
+                        bail!("The files of --offset are counted from 1");
+                    }
+                    let millis: i64 = millis
+                        .parse()
+                        .with_context(|| format!("\"{}\" is not a valid offset", millis))?;
+                    args.offsets.push((file - 1, Duration::from_millis(millis)));
+                }
                 Long("join-audio") => {
                     args.join_audio = true;
                 }
+                Long("audio-separator") => {
+                    args.audio_separator = Some(Self::convert(parser.value()?)?.parse()?);
+                }
+                Long("audio-separator-length") => {
+                    args.audio_separator_length =
+                        Duration::from_millis(Self::convert_value(&mut parser)?)
+                }
+                Long("probe-audio") => {
+                    args.probe_audio = true;
+                }
+                Long("normalize-audio") => {
+                    args.normalize_audio = true;
+                }
+                Long("fade-ms") => {
+                    let millis: i64 = Self::convert_value(&mut parser)?;
+                    if millis <= 0 {
+                        bail!("The fade must be a positive number of milliseconds");
+                    }
+                    args.fade = Some(Duration::from_millis(millis));
+                }
+                Long("verify-speech") => {
+                    args.verify_speech = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("verify-similarity") => {
+                    let fraction: f64 = Self::convert(parser.value()?)?.parse()?;
+                    if !(0.0..=1.0).contains(&fraction) {
+                        bail!("The verify similarity must be a fraction between 0 and 1");
+                    }
+                    args.verify_similarity = fraction;
+                }
+                Long("play-sample") => args.play_sample = Self::convert_value(&mut parser)?,
+                Long("player") => args.player = Self::convert(parser.value()?)?,
                 Short('j') | Long("jobs") => {
                     args.job_count = Some(Self::convert(parser.value()?)?.parse()?);
                 }
+                Long("memory-budget") => {
+                    let megabytes: u64 = Self::convert(parser.value()?)?.parse()?;
+                    args.memory_budget = Some(megabytes * 1024 * 1024);
+                }
                 Short('i') => {
                     args.gen_images = true;
                 }
                 Long("video-stream") => {
                     args.video_stream = Some(Self::convert(parser.value()?)?.parse()?)
                 }
+                Long("subtitle-region") => {
+                    let fraction = match parser.optional_value() {
+                        Some(value) => Self::convert(value)?.parse()?,
+                        None => DEFAULT_SUBTITLE_REGION,
+                    };
+                    if !(fraction > 0.0 && fraction <= 1.0) {
+                        bail!("The subtitle region must be a fraction between 0 and 1");
+                    }
+                    args.subtitle_region = Some(fraction);
+                }
+                Long("render-ass") => {
+                    args.render_ass = Some(match parser.optional_value() {
+                        Some(value) => {
+                            let value = Self::convert(value)?;
+                            match value.split_once('x') {
+                                Some((width, height)) => (width.parse()?, height.parse()?),
+                                None => bail!(
+                                    "\"{}\" is not a valid size, expected WIDTHxHEIGHT",
+                                    value
+                                ),
+                            }
+                        }
+                        None => DEFAULT_RENDER_SIZE,
+                    });
+                }
+                Long("fonts-dir") => args.fonts_dir = Some(Self::convert(parser.value()?)?.into()),
+                Long("contact-sheet") => {
+                    args.contact_sheet = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("playlist") => {
+                    let path: PathBuf = Self::convert(parser.value()?)?.into();
+                    PlaylistFormat::from_path(&path)?;
+                    args.playlist = Some(path);
+                }
                 Long("no-media") => {
                     args.no_media = true;
                 }
+                Long("schedule") => args.schedule = Self::convert(parser.value()?)?.parse()?,
+                Long("model-preset") => {
+                    args.model_preset = Self::convert(parser.value()?)?.parse()?
+                }
+                Long("style-css") => {
+                    args.style_css = true;
+                }
+                Long("on-missing-media") => {
+                    args.on_missing_media = Self::convert(parser.value()?)?.parse()?
+                }
+                Long("notes-without-media") => {
+                    args.notes_without_media = true;
+                }
                 Long("no-deck") => {
                     args.no_deck = true;
                 }
+                Long("temp-media") => {
+                    args.temp_media = true;
+                }
                 Long("id") => args.deck_id = Self::convert(parser.value()?)?.parse()?,
                 Long("name") => args.deck_name = Self::convert(parser.value()?)?,
                 Long("desc") | Long("description") => {
@@ -264,6 +1225,87 @@ impl Args {
                 Short('o') | Long("output") => {
                     args.package = Self::convert(parser.value()?)?.into()
                 }
+                Long("append") => {
+                    if args.split_deck_by.is_some() {
+                        eprintln!("--append cannot be used together with --split-deck-by");
+                        std::process::exit(1);
+                    }
+                    args.append = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("split-deck-by") => {
+                    if args.append.is_some() {
+                        eprintln!("--append cannot be used together with --split-deck-by");
+                        std::process::exit(1);
+                    }
+                    let re = Self::convert(parser.value()?)?;
+                    args.split_deck_by = Some(
+                        Regex::new(&re).context("Failed to compile regex for --split-deck-by")?,
+                    );
+                }
+                Long("deck-per-file") => {
+                    args.deck_per_file = true;
+                }
+                Long("deck-file-format") => args.deck_file_format = Self::convert(parser.value()?)?,
+                Long("word-clips") => args.word_list = Some(Self::convert(parser.value()?)?.into()),
+                Long("index-fields") => {
+                    args.index_fields = true;
+                }
+                Long("note-lang") => {
+                    args.note_lang = Some(Self::convert(parser.value()?)?.parse()?)
+                }
+                Long("furigana-field") => {
+                    args.furigana_field = true;
+                }
+                Long("reading-field") => {
+                    args.reading_field = true;
+                }
+                Long("chinese-fields") => {
+                    args.chinese_fields = true;
+                }
+                Long("speaker-field") => {
+                    args.speaker_field = true;
+                }
+                Long("speaker-regex") => {
+                    let re = Self::convert(parser.value()?)?;
+                    args.speaker_regex = Some(
+                        Regex::new(&re).context("Failed to compile regex for --speaker-regex")?,
+                    );
+                }
+                Long("color-speakers") => {
+                    args.color_speakers = true;
+                }
+                Long("romanization-field") => {
+                    args.romanization_field = true;
+                }
+                Long("pitch-accent") => {
+                    args.pitch_accent = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("frequency-list") => {
+                    args.frequency_list = Some(Self::convert(parser.value()?)?.into())
+                }
+                Long("timestamp-field") => {
+                    args.timestamp_field = true;
+                }
+                Long("run-metadata") => {
+                    args.run_metadata = true;
+                }
+                Long("info-note") => {
+                    args.info_note = true;
+                }
+                Long("clock") => {
+                    args.timestamp_format.clock = Self::convert(parser.value()?)?.parse()?
+                }
+                Long("ms-separator") => {
+                    args.timestamp_format.ms_separator =
+                        match Self::convert(parser.value()?)?.as_str() {
+                            "dot" | "." => '.',
+                            "comma" | "," => ',',
+                            other => bail!(
+                            "\"{}\" is not a valid millisecond separator, expected dot or comma",
+                            other
+                        ),
+                        }
+                }
                 Long("width") => args.image_width = Some(Self::convert(parser.value()?)?.parse()?),
                 Long("height") => {
                     args.image_height = Some(Self::convert(parser.value()?)?.parse()?)
@@ -271,9 +1313,25 @@ impl Args {
                 Long("write-json") => {
                     args.write_json = true;
                 }
+                Long("write-ndjson") => {
+                    args.write_ndjson = true;
+                }
                 Long("dump") => {
                     args.dump = true;
                 }
+                Long("recipe") => args.recipe = Some(Self::convert(parser.value()?)?.into()),
+                Long("manifest") => {
+                    if !args.manifest.is_empty() {
+                        bail!("--manifest can only be given once");
+                    }
+                    let path: PathBuf = Self::convert(parser.value()?)?.into();
+                    args.manifest = read_manifest(&path).with_context(|| {
+                        format!("Failed to read manifest \"{}\"", path.to_string_lossy())
+                    })?;
+                }
+                Long("from-recipe") => {
+                    args.from_recipe = Some(Self::convert(parser.value()?)?.into())
+                }
                 Value(file) if taking_media => args.media_files.push(file.into()),
                 Value(file) if !taking_media => args.sub_files.push(file.into()),
                 Short('v') => {
@@ -313,6 +1371,61 @@ impl Args {
             }
         }
 
+        if let Some(path) = args.from_recipe.take() {
+            if !args.sub_files.is_empty()
+                || !args.media_files.is_empty()
+                || !args.manifest.is_empty()
+            {
+                bail!("--from-recipe cannot be used together with subtitle or media files");
+            }
+            let recipe = Recipe::read(&path)
+                .with_context(|| format!("Failed to read recipe \"{}\"", path.to_string_lossy()))?;
+
+            let mut raw_args = vec![OsString::from(&args.program)];
+            raw_args.extend(recipe.args().iter().map(OsString::from));
+            let mut replayed = Self::parse_from_args(raw_args)?;
+            replayed.verbosity = replayed.verbosity.max(args.verbosity);
+            if args.recipe.is_some() {
+                replayed.recipe = args.recipe;
+            }
+            replayed.replayed = Some(recipe);
+            return Ok(replayed);
+        }
+
+        // The flags apply to the regexes given before them as well
+        if args.regex_flags != RegexFlags::default() {
+            let flags = args.regex_flags;
+            flags.apply_all(&mut args.blacklist)?;
+            flags.apply_all(&mut args.whitelist)?;
+            flags.apply_all(&mut args.boost)?;
+            flags.apply_all(&mut args.actors)?;
+            flags.apply_all(&mut args.skipped_actors)?;
+            if let Some(re) = args.speaker_regex.as_mut() {
+                *re = flags.apply(re)?;
+            }
+            for (re, _) in args.replacements.iter_mut() {
+                *re = flags.apply(re)?;
+            }
+        }
+
+        if !args.manifest.is_empty() {
+            if !args.sub_files.is_empty() || !args.media_files.is_empty() {
+                bail!("--manifest cannot be used together with subtitle or media files");
+            }
+            for row in &args.manifest {
+                args.sub_files.push(row.subtitle.clone());
+                args.media_files.extend(row.media.clone());
+            }
+        }
+
+        if args.write_json && args.write_ndjson {
+            bail!("--write-ndjson cannot be used together with --write-json");
+        }
+
+        if args.model_preset == ModelPreset::ClozeJp && args.target_words.is_none() {
+            bail!("--model-preset=cloze-jp requires --target-words, the words that are clozed");
+        }
+
         if args.sub_files.is_empty() {
             println!("The following argument was not provided:");
             println!("  <SUBTITLE_FILE>");
@@ -321,6 +1434,18 @@ impl Args {
             std::process::exit(0);
         }
 
+        if let Some((file_idx, _)) = args
+            .offsets
+            .iter()
+            .find(|(file_idx, _)| *file_idx >= args.sub_files.len())
+        {
+            bail!(
+                "--offset is given for file {}, but there are only {} file(s)",
+                file_idx + 1,
+                args.sub_files.len()
+            );
+        }
+
         Ok(args)
     }
 
@@ -332,6 +1457,14 @@ impl Args {
         }
     }
 
+    fn parse_cps(value: OsString) -> Result<f64> {
+        let cps: f64 = Self::convert(value)?.parse()?;
+        if !(cps.is_finite() && cps >= 0.0) {
+            bail!("The characters per second must be a positive number");
+        }
+        Ok(cps)
+    }
+
     fn convert_value<T: std::str::FromStr>(parser: &mut lexopt::Parser) -> Result<T>
     where
         <T as std::str::FromStr>::Err: std::error::Error + Sync + Send + 'static,
@@ -347,16 +1480,84 @@ impl Args {
         &self.sub_files
     }
 
+    /// The selector that `best` means for subtitles
+    fn best_sub_stream_selector(&self) -> StreamSelector {
+        if self.prefer_bitmap_subs {
+            StreamSelector::Best
+        } else {
+            StreamSelector::BestText
+        }
+    }
+
     pub fn sub_stream_selector(&self) -> StreamSelector {
-        if let Some(stream_idx) = self.sub_stream {
-            StreamSelector::Index(stream_idx)
-        } else if let Some(sub_lang) = self.sub_lang.as_deref() {
+        if !self.sub_select.is_empty() {
+            StreamSelector::Chain(
+                self.sub_select
+                    .iter()
+                    .map(|spec| match spec {
+                        SelectorSpec::Best => self.best_sub_stream_selector(),
+                        spec => spec.as_selector(),
+                    })
+                    .collect(),
+            )
+        } else if let Some(stream_idx) = self.sub_stream.first() {
+            StreamSelector::Index(*stream_idx)
+        } else if let Some(sub_lang) = self.sub_lang.first() {
             StreamSelector::Language(sub_lang)
+        } else if let Some((_, target)) = self.pair() {
+            StreamSelector::Language(target)
         } else {
-            StreamSelector::Best
+            self.best_sub_stream_selector()
         }
     }
 
+    /// The selector of the subtitle stream of the file at `file_idx`, which may have a language
+    /// of its own in the manifest
+    pub fn file_sub_stream_selector(&self, file_idx: usize) -> StreamSelector {
+        match self
+            .file_options(file_idx)
+            .and_then(|row| row.lang.as_deref())
+        {
+            Some(lang) => StreamSelector::Language(lang),
+            None => self.sub_stream_selector(),
+        }
+    }
+
+    /// The selectors of the streams that were selected after the first one, of which the
+    /// subtitles are aligned to the ones of the first stream
+    pub fn extra_sub_stream_selectors(&self) -> Vec<StreamSelector> {
+        self.sub_stream
+            .iter()
+            .skip(1)
+            .map(|stream_idx| StreamSelector::Index(*stream_idx))
+            .chain(
+                self.sub_lang
+                    .iter()
+                    .skip(1)
+                    .map(|sub_lang| StreamSelector::Language(sub_lang)),
+            )
+            .collect()
+    }
+
+    pub fn pair(&self) -> Option<(&str, &str)> {
+        self.pair
+            .as_ref()
+            .map(|(native, target)| (native.as_str(), target.as_str()))
+    }
+
+    pub fn translation_stream_selector(&self) -> Option<StreamSelector> {
+        self.pair()
+            .map(|(native, _)| StreamSelector::LanguageOrUndetermined(native))
+    }
+
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    pub fn ocr_lang(&self) -> Option<&str> {
+        self.ocr_lang.as_deref()
+    }
+
     pub fn start(&self) -> Timestamp {
         self.start
     }
@@ -365,6 +1566,12 @@ impl Args {
         self.end
     }
 
+    /// How long before the end of every media file to stop extracting subtitles, for a negative
+    /// `--end`
+    pub fn end_before_eof(&self) -> Option<Duration> {
+        self.end_before_eof
+    }
+
     pub fn blacklist(&self) -> &Vec<Regex> {
         &self.blacklist
     }
@@ -373,10 +1580,114 @@ impl Args {
         &self.whitelist
     }
 
+    pub fn filter_mode(&self) -> FilterMode {
+        self.filter_mode
+    }
+
+    pub fn boost(&self) -> &[Regex] {
+        &self.boost
+    }
+
+    pub fn target_words(&self) -> Option<&PathBuf> {
+        self.target_words.as_ref()
+    }
+
+    pub fn known_words(&self) -> Option<&PathBuf> {
+        self.known_words.as_ref()
+    }
+
+    pub fn unknown_max(&self) -> usize {
+        self.unknown_max
+    }
+
+    pub fn actors(&self) -> &[Regex] {
+        &self.actors
+    }
+
+    pub fn skipped_actors(&self) -> &[Regex] {
+        &self.skipped_actors
+    }
+
+    pub fn skip_chapters(&self) -> Option<&Regex> {
+        self.skip_chapters.as_ref()
+    }
+
+    pub fn min_cps(&self) -> Option<f64> {
+        self.min_cps
+    }
+
+    pub fn max_cps(&self) -> Option<f64> {
+        self.max_cps
+    }
+
     pub fn ignore_styled(&self) -> bool {
         self.ignore_styled
     }
 
+    pub fn max_layer(&self) -> Option<u32> {
+        self.max_layer
+    }
+
+    pub fn strip_tags(&self) -> bool {
+        self.strip_tags
+    }
+
+    pub fn strip_sdh(&self) -> bool {
+        self.strip_sdh
+    }
+
+    pub fn normalizations(&self) -> &[TextNormalization] {
+        &self.normalizations
+    }
+
+    pub fn newline_mode(&self) -> Option<&NewlineMode> {
+        self.newline_mode.as_ref()
+    }
+
+    pub fn replacements(&self) -> &[(Regex, String)] {
+        &self.replacements
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.dedup
+    }
+
+    pub fn known_sentences(&self) -> Option<&PathBuf> {
+        self.known_sentences.as_ref()
+    }
+
+    pub fn known_similarity(&self) -> f64 {
+        self.known_similarity
+    }
+
+    pub fn max_cards(&self) -> Option<usize> {
+        self.max_cards
+    }
+
+    pub fn card_selection(&self) -> CardSelection {
+        self.card_selection
+    }
+
+    pub fn sample(&self) -> Option<Sample> {
+        self.sample
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn min_coverage(&self) -> Option<f64> {
+        self.min_coverage
+    }
+
+    pub fn forced_only(&self) -> bool {
+        self.forced_only
+    }
+
+    pub fn skip_forced(&self) -> bool {
+        self.skip_forced
+    }
+
     pub fn merge_subs(&self) -> bool {
         self.merge
     }
@@ -385,12 +1696,39 @@ impl Args {
         self.merge_diff
     }
 
+    pub fn merge_key(&self) -> MergeKey {
+        self.merge_key
+    }
+
+    pub fn merge_across_files(&self) -> bool {
+        self.merge_across_files
+    }
+
+    pub fn sentences(&self) -> bool {
+        self.sentences
+    }
+
+    pub fn split_sentences(&self) -> bool {
+        self.split_sentences
+    }
+
+    pub fn context(&self) -> Option<usize> {
+        self.context
+    }
+
     pub fn media_files(&self) -> &Vec<PathBuf> {
         &self.media_files
     }
 
     pub fn audio_stream_selector(&self) -> StreamSelector {
-        if let Some(stream_idx) = self.audio_stream {
+        if !self.audio_select.is_empty() {
+            StreamSelector::Chain(
+                self.audio_select
+                    .iter()
+                    .map(SelectorSpec::as_selector)
+                    .collect(),
+            )
+        } else if let Some(stream_idx) = self.audio_stream {
             StreamSelector::Index(stream_idx)
         } else if let Some(audio_lang) = self.audio_lang.as_deref() {
             StreamSelector::Language(audio_lang)
@@ -411,6 +1749,10 @@ impl Args {
         self.pad_end
     }
 
+    pub fn trim_silence(&self) -> bool {
+        self.trim_silence
+    }
+
     pub fn shift_audio(&self) -> Duration {
         self.shift_audio
     }
@@ -419,10 +1761,52 @@ impl Args {
         self.join_audio
     }
 
+    pub fn audio_separator(&self) -> Option<AudioSeparator> {
+        self.audio_separator
+    }
+
+    pub fn audio_separator_length(&self) -> Duration {
+        self.audio_separator_length
+    }
+
+    pub fn probe_audio(&self) -> bool {
+        self.probe_audio
+    }
+
+    pub fn normalize_audio(&self) -> bool {
+        self.normalize_audio
+    }
+
+    /// The length of the fade in and out of audio clips, for `--fade-ms`
+    pub fn fade(&self) -> Option<Duration> {
+        self.fade
+    }
+
+    pub fn verify_speech(&self) -> Option<&PathBuf> {
+        self.verify_speech.as_ref()
+    }
+
+    pub fn verify_similarity(&self) -> f64 {
+        self.verify_similarity
+    }
+
+    pub fn play_sample(&self) -> usize {
+        self.play_sample
+    }
+
+    pub fn player(&self) -> &str {
+        &self.player
+    }
+
     pub fn job_count(&self) -> Option<usize> {
         self.job_count
     }
 
+    /// The memory in bytes that decoded images waiting to be written may take up
+    pub fn memory_budget(&self) -> Option<u64> {
+        self.memory_budget
+    }
+
     pub fn video_stream_selector(&self) -> StreamSelector {
         if let Some(stream_idx) = self.video_stream {
             StreamSelector::Index(stream_idx)
@@ -435,14 +1819,59 @@ impl Args {
         self.gen_images
     }
 
+    pub fn subtitle_region(&self) -> Option<f64> {
+        self.subtitle_region
+    }
+
+    /// The size of the frame to render ASS subtitles on, if they should be rendered
+    pub fn render_ass(&self) -> Option<(u32, u32)> {
+        self.render_ass
+    }
+
+    pub fn fonts_dir(&self) -> Option<&PathBuf> {
+        self.fonts_dir.as_ref()
+    }
+
+    pub fn contact_sheet(&self) -> Option<&PathBuf> {
+        self.contact_sheet.as_ref()
+    }
+
+    pub fn playlist(&self) -> Option<&PathBuf> {
+        self.playlist.as_ref()
+    }
+
     pub fn no_media(&self) -> bool {
         self.no_media
     }
 
+    pub fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+
+    pub fn model_preset(&self) -> ModelPreset {
+        self.model_preset
+    }
+
+    pub fn style_css(&self) -> bool {
+        self.style_css
+    }
+
+    pub fn on_missing_media(&self) -> MissingMediaPolicy {
+        self.on_missing_media
+    }
+
+    pub fn notes_without_media(&self) -> bool {
+        self.notes_without_media
+    }
+
     pub fn no_deck(&self) -> bool {
         self.no_deck
     }
 
+    pub fn temp_media(&self) -> bool {
+        self.temp_media
+    }
+
     pub fn deck_id(&self) -> i64 {
         self.deck_id
     }
@@ -459,10 +1888,142 @@ impl Args {
         &self.package
     }
 
+    pub fn append(&self) -> Option<&PathBuf> {
+        self.append.as_ref()
+    }
+
+    pub fn split_deck_by(&self) -> Option<&Regex> {
+        self.split_deck_by.as_ref()
+    }
+
+    pub fn deck_per_file(&self) -> bool {
+        self.deck_per_file
+    }
+
+    pub fn deck_file_format(&self) -> &str {
+        &self.deck_file_format
+    }
+
+    pub fn raw_args(&self) -> &[OsString] {
+        &self.raw_args
+    }
+
+    pub fn recipe(&self) -> Option<&PathBuf> {
+        self.recipe.as_ref()
+    }
+
+    /// The options the manifest gives for the file at `file_idx`
+    pub fn file_options(&self, file_idx: usize) -> Option<&ManifestRow> {
+        self.manifest.get(file_idx)
+    }
+
+    pub fn sync_points(&self) -> Option<SyncPoints> {
+        self.sync_points
+    }
+
+    pub fn auto_sync(&self) -> bool {
+        self.auto_sync
+    }
+
+    /// The delay of the subtitles of the file at `file_idx`, from the manifest, `--offset` and
+    /// `--shift-subs`
+    pub fn file_delay(&self, file_idx: usize) -> Duration {
+        let delay = self
+            .file_options(file_idx)
+            .map(|row| row.delay)
+            .unwrap_or_default();
+        let offset: i64 = self
+            .offsets
+            .iter()
+            .filter(|(idx, _)| *idx == file_idx)
+            .map(|(_, offset)| offset.as_millis())
+            .sum();
+        Duration::from_millis(delay.as_millis() + offset + self.shift_subs.as_millis())
+    }
+
+    /// The name of the deck the notes of the file at `file_idx` go in, if the manifest gives one
+    pub fn file_deck(&self, file_idx: usize) -> Option<&str> {
+        self.file_options(file_idx)
+            .and_then(|row| row.deck.as_deref())
+    }
+
+    /// The recipe the arguments were read from with `--from-recipe`
+    pub fn replayed(&self) -> Option<&Recipe> {
+        self.replayed.as_ref()
+    }
+
+    pub fn word_list(&self) -> Option<&PathBuf> {
+        self.word_list.as_ref()
+    }
+
+    pub fn timestamp_field(&self) -> bool {
+        self.timestamp_field
+    }
+
+    pub fn run_metadata(&self) -> bool {
+        self.run_metadata
+    }
+
+    pub fn info_note(&self) -> bool {
+        self.info_note
+    }
+
+    pub fn index_fields(&self) -> bool {
+        self.index_fields
+    }
+
+    pub fn note_lang(&self) -> Option<&NoteLang> {
+        self.note_lang.as_ref()
+    }
+
+    pub fn furigana_field(&self) -> bool {
+        self.furigana_field
+    }
+
+    pub fn reading_field(&self) -> bool {
+        self.reading_field
+    }
+
+    pub fn chinese_fields(&self) -> bool {
+        self.chinese_fields
+    }
+
+    pub fn speaker_field(&self) -> bool {
+        self.speaker_field || self.speaker_regex.is_some() || self.color_speakers
+    }
+
+    pub fn speaker_regex(&self) -> Option<&Regex> {
+        self.speaker_regex.as_ref()
+    }
+
+    pub fn color_speakers(&self) -> bool {
+        self.color_speakers
+    }
+
+    pub fn romanization_field(&self) -> bool {
+        self.romanization_field
+    }
+
+    pub fn pitch_accent(&self) -> Option<&PathBuf> {
+        self.pitch_accent.as_ref()
+    }
+
+    pub fn frequency_list(&self) -> Option<&PathBuf> {
+        self.frequency_list.as_ref()
+    }
+
+    pub fn timestamp_format(&self) -> &TimestampFormat {
+        &self.timestamp_format
+    }
+
     pub fn write_json(&self) -> bool {
         self.write_json
     }
 
+    pub fn write_ndjson(&self) -> bool {
+        self.write_ndjson
+    }
+
     pub fn dump(&self) -> bool {
         self.dump
     }
@@ -29,6 +29,135 @@ pub struct AssText {
     pub text: String,
     pub dialogue: String,
     styled: bool,
+    karaoke: bool,
+    /// The dialogue with its readings in Anki's furigana syntax, if the text has any readings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    furigana: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    vertical: bool,
+}
+
+/// Whether `tag` (without the backslash) is a karaoke timing tag, e.g. `k20` or `kf35`
+fn is_karaoke_tag(tag: &str) -> bool {
+    ["kf", "ko", "k", "K"]
+        .iter()
+        .filter_map(|name| tag.strip_prefix(name))
+        .any(|duration| !duration.is_empty() && duration.chars().all(|ch| ch.is_ascii_digit()))
+}
+
+/// Whether `tag` is a `\rt` tag, which some subtitles use to mark the text after it as the reading
+/// of the text before it. `\rt0` ends the reading.
+fn is_ruby_tag(tag: &str) -> bool {
+    matches!(tag, "rt" | "rt1" | "rt0")
+}
+
+/// Whether `tag` lays out the text vertically: a vertical (`@`) font or a rotation by a quarter turn
+fn is_vertical_tag(tag: &str) -> bool {
+    if tag.starts_with("fn@") {
+        return true;
+    }
+    ["frz", "fry", "fr"]
+        .iter()
+        .find_map(|name| tag.strip_prefix(name))
+        .and_then(|angle| angle.trim().parse::<f64>().ok())
+        .is_some_and(|angle| {
+            let angle = angle.rem_euclid(360.0);
+            angle == 90.0 || angle == 270.0
+        })
+}
+
+/// What an override block contains
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+struct BlockKind {
+    karaoke: bool,
+    ruby: bool,
+    /// Whether a reading starts after the block
+    reading: bool,
+    /// Any other tags. Text that is not a tag is a comment, which counts as well.
+    other: bool,
+    vertical: bool,
+}
+
+fn classify_block(block: &str) -> BlockKind {
+    let mut tags = block.split('\\');
+    let mut kind = BlockKind {
+        other: tags
+            .next()
+            .is_some_and(|comment| !comment.trim().is_empty()),
+        ..Default::default()
+    };
+    for tag in tags.map(str::trim).filter(|tag| !tag.is_empty()) {
+        if is_karaoke_tag(tag) {
+            kind.karaoke = true;
+        } else if is_ruby_tag(tag) {
+            kind.ruby = true;
+            kind.reading = tag != "rt0";
+        } else {
+            kind.other = true;
+            kind.vertical |= is_vertical_tag(tag);
+        }
+    }
+    kind
+}
+
+fn is_kanji(ch: char) -> bool {
+    matches!(ch, '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '々' | '〆' | 'ヶ')
+}
+
+/// Builds the dialogue of an event with its readings in Anki's furigana syntax (`漢字[かんじ]`)
+#[derive(Default)]
+struct Furigana {
+    text: String,
+    /// Where the text without a reading starts
+    unannotated: usize,
+    /// Where the text since the last override block starts
+    segment: usize,
+    found: bool,
+}
+
+impl Furigana {
+    fn start_segment(&mut self) {
+        self.segment = self.text.len();
+    }
+
+    fn segment(&self) -> &str {
+        &self.text[self.segment.max(self.unannotated)..]
+    }
+
+    /// Adds `reading` to the kanji right before it, or to the text since the last override block
+    /// if there are none. A `continued` reading belongs to the same text as the previous one.
+    fn annotate(&mut self, reading: &str, continued: bool) {
+        let reading = reading.trim();
+        if reading.is_empty() {
+            return;
+        }
+        if continued && self.text.ends_with(']') {
+            self.text.insert_str(self.text.len() - 1, reading);
+            self.unannotated = self.text.len();
+            return;
+        }
+
+        let unannotated = &self.text[self.unannotated..];
+        let kanji = unannotated
+            .char_indices()
+            .rev()
+            .take_while(|(_, ch)| is_kanji(*ch))
+            .last()
+            .map(|(idx, _)| self.unannotated + idx);
+        let start = match kanji {
+            Some(start) => start,
+            None if !self.segment().trim().is_empty() => self.segment.max(self.unannotated),
+            None => return,
+        };
+
+        // Anki takes everything since the previous space as the text of a reading
+        if start > 0 && !self.text[..start].ends_with(' ') {
+            self.text.insert(start, ' ');
+        }
+        self.text.push_str(&format!("[{}]", reading));
+        self.unannotated = self.text.len();
+        self.found = true;
+    }
 }
 
 impl FromStr for AssText {
@@ -39,10 +168,21 @@ impl FromStr for AssText {
         let mut brackets: u64 = 0;
         let mut dialogue = String::new();
         let mut styled = false;
+        let mut karaoke = false;
+        let mut vertical = false;
+        let mut block = String::new();
+        let mut furigana = Furigana::default();
+        // The reading that is being read and whether it continues the previous one
+        let mut reading: Option<String> = None;
+        let mut continued = false;
 
         for ch in s.chars() {
             if ch == '{' {
-                styled = true;
+                if brackets == 0 {
+                    if let Some(reading) = reading.take() {
+                        furigana.annotate(&reading, continued);
+                    }
+                }
                 brackets += 1;
             } else if ch == '}' {
                 if brackets > 0 {
@@ -50,26 +190,66 @@ impl FromStr for AssText {
                 } else {
                     return Err(AssError::UnbalancedBrackets);
                 }
-            } else if brackets == 0 {
-                if escaped {
-                    if ch == 'n' {
-                        dialogue.push('n');
-                    } else {
-                        dialogue.push('\\');
-                        dialogue.push(ch);
+                if brackets == 0 {
+                    // Karaoke and ruby tags don't change how the text looks
+                    let kind = classify_block(&block);
+                    karaoke |= kind.karaoke;
+                    vertical |= kind.vertical;
+                    styled |= kind.other || !(kind.karaoke || kind.ruby);
+                    if kind.reading {
+                        reading = Some(String::new());
+                        continued = false;
                     }
-                    escaped = false;
-                } else if ch == '\\' {
-                    escaped = true;
+                    furigana.start_segment();
+                    block.clear();
+                }
+            } else if brackets > 0 {
+                block.push(ch);
+            } else if escaped {
+                let escape = if ch == 'n' {
+                    "n".to_string()
                 } else {
-                    dialogue.push(ch);
+                    format!("\\{}", ch)
+                };
+                match reading.as_mut() {
+                    Some(reading) => reading.push_str(&escape),
+                    None => {
+                        dialogue.push_str(&escape);
+                        furigana.text.push_str(&escape);
+                    }
+                }
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '|' && karaoke && reading.is_none() {
+                // Karaoke syllables with a reading are written as `漢字|かんじ`, and `#|じ` adds
+                // to the reading of the previous syllable
+                continued = furigana.segment() == "#";
+                if continued {
+                    dialogue.pop();
+                    furigana.text.pop();
                 }
+                reading = Some(String::new());
+            } else if let Some(reading) = reading.as_mut() {
+                reading.push(ch);
+            } else {
+                dialogue.push(ch);
+                furigana.text.push(ch);
             }
         }
+        if brackets > 0 {
+            styled = true;
+        }
+        if let Some(reading) = reading {
+            furigana.annotate(&reading, continued);
+        }
         Ok(Self {
             text: s.to_string(),
             dialogue,
             styled,
+            karaoke,
+            furigana: furigana.found.then_some(furigana.text),
+            vertical,
         })
     }
 }
@@ -78,10 +258,44 @@ impl AssText {
     pub fn is_styled(&self) -> bool {
         self.styled
     }
+
+    /// Whether the text has karaoke timing tags (`\k`, `\kf`, `\ko`)
+    pub fn is_karaoke(&self) -> bool {
+        self.karaoke
+    }
+
+    /// Whether the text is laid out vertically, which is mostly done for signs in CJK subtitles
+    pub fn is_vertical(&self) -> bool {
+        self.vertical
+    }
+
+    /// The dialogue with the readings of the text in Anki's furigana syntax (`漢字[かんじ]`), if
+    /// it has any. The readings are left out of the dialogue itself.
+    pub fn furigana(&self) -> Option<&str> {
+        self.furigana.as_deref()
+    }
+
+    /// Replaces the dialogue, and the dialogue with readings, with the result of `f`
+    pub fn map_dialogue<F: Fn(&str) -> String>(&mut self, f: F) {
+        self.dialogue = f(&self.dialogue);
+        self.furigana = self.furigana.as_deref().map(&f);
+    }
+
+    /// Sets the dialogue to a part of it, like one of its sentences, with the readings of that
+    /// part if they are known
+    pub fn set_dialogue(&mut self, dialogue: String, furigana: Option<String>) {
+        self.dialogue = dialogue;
+        self.furigana = furigana;
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct DialogueEvent {
+    /// The layer the event is drawn on, higher layers are drawn over lower ones
+    #[serde(default)]
+    pub layer: u32,
+    #[serde(default)]
+    pub style: String,
     pub name: String,
     pub text: AssText,
 }
@@ -90,11 +304,25 @@ impl FromStr for DialogueEvent {
     type Err = AssError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.splitn(9, ',').skip(3);
+        // ReadOrder, Layer, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+        let mut parts = s.splitn(9, ',').skip(1);
 
+        // Some files have a negative or missing layer, which renderers treat as 0
+        let layer = parts
+            .next()
+            .ok_or(AssError::NotEnoughParts)?
+            .trim()
+            .parse()
+            .unwrap_or(0);
+        let style = parts.next().ok_or(AssError::NotEnoughParts)?.to_string();
         let name = parts.next().ok_or(AssError::NotEnoughParts)?.to_string();
         let text = parts.nth(4).ok_or(AssError::NotEnoughParts)?.parse()?;
-        Ok(Self { name, text })
+        Ok(Self {
+            layer,
+            style,
+            name,
+            text,
+        })
     }
 }
 
@@ -105,3 +333,257 @@ impl TryFrom<libav::subtitle::Ass<'_>> for DialogueEvent {
         ass.get().parse()
     }
 }
+
+/// The look of an ass style, from the `[V4+ Styles]` section of the header
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssStyle {
+    pub name: String,
+    font: String,
+    /// The primary and outline colors as `#rrggbb`
+    color: Option<String>,
+    outline_color: Option<String>,
+    outline: f64,
+    bold: bool,
+    italic: bool,
+}
+
+/// Converts an ass color (`&HAABBGGRR`, the alpha is optional) to `#rrggbb`
+fn css_color(color: &str) -> Option<String> {
+    let hex = color
+        .trim()
+        .trim_start_matches("&H")
+        .trim_start_matches("&h")
+        .trim_end_matches('&');
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let [_, b, g, r] = value.to_be_bytes();
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+/// The relative luminance of a `#rrggbb` color, from 0 (black) to 1 (white)
+fn luminance(color: &str) -> f64 {
+    let channel = |idx: usize| {
+        color
+            .get(idx..idx + 2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .map_or(0.0, |value| f64::from(value) / 255.0)
+    };
+    0.2126 * channel(1) + 0.7152 * channel(3) + 0.0722 * channel(5)
+}
+
+/// The outline of the text of a card has to differ this much in luminance from the text
+const MIN_OUTLINE_CONTRAST: f64 = 0.3;
+
+/// Reads the styles of the `[V4+ Styles]` (or `[V4 Styles]`) section of the header of an ass
+/// file. The fields are found by the `Format:` line of the section.
+pub fn parse_styles(header: &str) -> Vec<AssStyle> {
+    let mut styles = Vec::new();
+    let mut in_styles = false;
+    let mut format: Vec<String> = Vec::new();
+    for line in header.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_styles = line.eq_ignore_ascii_case("[V4+ Styles]")
+                || line.eq_ignore_ascii_case("[V4 Styles]");
+            continue;
+        }
+        if !in_styles {
+            continue;
+        }
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format = fields
+                .split(',')
+                .map(|field| field.trim().to_ascii_lowercase())
+                .collect();
+        } else if let Some(values) = line.strip_prefix("Style:") {
+            let values: Vec<&str> = values.splitn(format.len(), ',').map(str::trim).collect();
+            let field = |name: &str| {
+                format
+                    .iter()
+                    .position(|field| field == name)
+                    .and_then(|idx| values.get(idx).copied())
+            };
+            // -1 is true in ass
+            let flag = |name: &str| field(name).is_some_and(|value| value != "0");
+            let Some(name) = field("name") else {
+                continue;
+            };
+            styles.push(AssStyle {
+                name: name.to_string(),
+                font: field("fontname").unwrap_or_default().to_string(),
+                color: field("primarycolour").and_then(css_color),
+                outline_color: field("outlinecolour").and_then(css_color),
+                outline: field("outline")
+                    .and_then(|outline| outline.parse().ok())
+                    .unwrap_or_default(),
+                bold: flag("bold"),
+                italic: flag("italic"),
+            });
+        }
+    }
+    styles
+}
+
+impl AssStyle {
+    /// CSS that gives the text of a card the font, color, outline and emphasis of the style. Text
+    /// with a color always gets an outline, so it stays readable on a card of the same color, like
+    /// white text on the light theme. An outline that is too close to the text is replaced by
+    /// black or white.
+    pub fn css(&self) -> String {
+        let mut css = String::new();
+        let font: String = self
+            .font
+            .chars()
+            .filter(|ch| !matches!(ch, '"' | ';' | '{' | '}'))
+            .collect();
+        if !font.is_empty() {
+            css.push_str(&format!(
+                ".card {{ font-family: \"{}\", sans-serif; }}\n",
+                font
+            ));
+        }
+
+        let mut text = Vec::new();
+        if let Some(color) = &self.color {
+            text.push(format!("color: {};", color));
+        }
+        let outline = match &self.color {
+            Some(color) => {
+                let contrast = if luminance(color) > 0.5 {
+                    "#000000"
+                } else {
+                    "#ffffff"
+                };
+                let outline = self.outline_color.clone().filter(|outline| {
+                    (luminance(outline) - luminance(color)).abs() >= MIN_OUTLINE_CONTRAST
+                });
+                Some(outline.unwrap_or_else(|| contrast.to_string()))
+            }
+            None => self.outline_color.clone().filter(|_| self.outline > 0.0),
+        };
+        if let Some(outline) = outline {
+            let shadows = ["-1px -1px", "1px -1px", "-1px 1px", "1px 1px"]
+                .iter()
+                .map(|offset| format!("{} 0 {}", offset, outline))
+                .collect::<Vec<_>>();
+            text.push(format!("text-shadow: {};", shadows.join(", ")));
+        }
+        text.push(format!(
+            "font-weight: {};",
+            if self.bold { "bold" } else { "normal" }
+        ));
+        text.push(format!(
+            "font-style: {};",
+            if self.italic { "italic" } else { "normal" }
+        ));
+        css.push_str(&format!("h1 {{ {} }}\n", text.join(" ")));
+        css
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn karaoke_is_not_styled() {
+        let text: AssText = "{\\k20}ka{\\kf30}ra{\\ko15}o{\\K40}ke".parse().unwrap();
+        assert!(text.is_karaoke());
+        assert!(!text.is_styled());
+        assert_eq!(text.dialogue, "karaoke");
+    }
+
+    #[test]
+    fn karaoke_with_styling() {
+        let text: AssText = "{\\k20\\i1}ka{\\k30}ra".parse().unwrap();
+        assert!(text.is_karaoke());
+        assert!(text.is_styled());
+    }
+
+    #[test]
+    fn styled_without_karaoke() {
+        let text: AssText = "{\\pos(10,20)}sign".parse().unwrap();
+        assert!(!text.is_karaoke());
+        assert!(text.is_styled());
+
+        let text: AssText = "{a comment}line".parse().unwrap();
+        assert!(!text.is_karaoke());
+        assert!(text.is_styled());
+    }
+
+    #[test]
+    fn karaoke_furigana() {
+        let text: AssText = "{\\k20}今日|きょう{\\k10}は{\\k20}日|に{\\k10}#|ほん"
+            .parse()
+            .unwrap();
+        assert!(!text.is_styled());
+        assert_eq!(text.dialogue, "今日は日");
+        assert_eq!(text.furigana(), Some("今日[きょう]は 日[にほん]"));
+    }
+
+    #[test]
+    fn rt_furigana() {
+        let text: AssText = "これは漢字{\\rt}かんじ{\\rt0}です".parse().unwrap();
+        assert!(!text.is_styled());
+        assert_eq!(text.dialogue, "これは漢字です");
+        assert_eq!(text.furigana(), Some("これは 漢字[かんじ]です"));
+    }
+
+    #[test]
+    fn no_furigana() {
+        let text: AssText = "{\\i1}a|b".parse().unwrap();
+        assert_eq!(text.dialogue, "a|b");
+        assert_eq!(text.furigana(), None);
+    }
+
+    #[test]
+    fn dialogue_event_fields() {
+        let event: DialogueEvent = "12,3,Sign,Bob,0,0,0,,Hello, world".parse().unwrap();
+        assert_eq!(event.layer, 3);
+        assert_eq!(event.style, "Sign");
+        assert_eq!(event.name, "Bob");
+        assert_eq!(event.text.dialogue, "Hello, world");
+
+        let event: DialogueEvent = "12,,Default,,0,0,0,,Hi".parse().unwrap();
+        assert_eq!(event.layer, 0);
+        assert!("12,0,Default".parse::<DialogueEvent>().is_err());
+    }
+
+    #[test]
+    fn styles() {
+        let header = "[Script Info]\nScriptType: v4.00+\n\n[V4+ Styles]\n\
+            Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+            Style: Default,Open Sans,52,&H00F0F0F0,&H000000FF,&H00402010,&H00000000,-1,0,0,0,100,100,0,0,1,2.5,0,2,10,10,10,1\n\
+            Style: Sign,Arial,40,&H0000FFFF,&H000000FF,&H00000000,&H00000000,0,-1,0,0,100,100,0,0,1,0,0,8,10,10,10,1\n\
+            Style: White,Arial,40,&H00FFFFFF,&H000000FF,&H00F0F0F0,&H00000000,0,0,0,0,100,100,0,0,1,2,0,2,10,10,10,1\n\n\
+            [Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n";
+        let styles = parse_styles(header);
+        assert_eq!(styles.len(), 3);
+        assert_eq!(
+            styles[0].css(),
+            ".card { font-family: \"Open Sans\", sans-serif; }\n\
+             h1 { color: #f0f0f0; text-shadow: -1px -1px 0 #102040, 1px -1px 0 #102040, -1px 1px 0 #102040, 1px 1px 0 #102040; font-weight: bold; font-style: normal; }\n"
+        );
+        assert_eq!(styles[1].name, "Sign");
+        assert_eq!(
+            styles[1].css(),
+            ".card { font-family: \"Arial\", sans-serif; }\n\
+             h1 { color: #ffff00; text-shadow: -1px -1px 0 #000000, 1px -1px 0 #000000, -1px 1px 0 #000000, 1px 1px 0 #000000; font-weight: normal; font-style: italic; }\n"
+        );
+        // A white outline around white text would not stand out from a white card
+        assert!(styles[2]
+            .css()
+            .contains("color: #ffffff; text-shadow: -1px -1px 0 #000000,"));
+    }
+
+    #[test]
+    fn vertical_text() {
+        let text: AssText = "{\\fn@MS Gothic\\frz270}縦書き".parse().unwrap();
+        assert!(text.is_vertical());
+        assert!(text.is_styled());
+
+        let text: AssText = "{\\frz-90}sign".parse().unwrap();
+        assert!(text.is_vertical());
+
+        let text: AssText = "{\\frz45\\fnArial}sign".parse().unwrap();
+        assert!(!text.is_vertical());
+    }
+}
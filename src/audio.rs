@@ -1,30 +1,221 @@
-use crate::time::Timespan;
+use crate::args::AudioSeparator;
+use crate::time::{Duration, Timespan, Timestamp};
 use crate::util::{get_stream, StreamSelector};
 use anyhow::{Context, Result};
-use itertools::Itertools;
-use libav::media;
-use log::trace;
-use std::num::NonZeroUsize;
+use libav::codec::decoder;
+use libav::codec::packet::Packet;
+use libav::format::sample::{Sample, Type as SampleType};
+use libav::software::resampling;
+use libav::{frame, media, ChannelLayout};
+use log::{info, trace, warn};
+use rand::seq::IteratorRandom;
 use std::path::Path;
 use std::process::{Command, Stdio};
 
-fn generate_audio_command_from_stream<'a, P, I>(path: P, points: I, stream_idx: usize) -> Command
+/// Clips are extracted in jobs of this many, so the media file is opened once for every job
+/// instead of for every clip, while the jobs can still run in parallel
+pub const CLIPS_PER_JOB: usize = 32;
+
+/// Returns the index of the selected audio stream of `path`
+pub fn audio_stream_index<P: AsRef<Path>>(path: P, selector: StreamSelector<'_>) -> Result<usize> {
+    let ictx = libav::format::input(&path).context(format!(
+        "{}: Failed to open file",
+        path.as_ref().to_string_lossy()
+    ))?;
+    let stream = get_stream(&ictx, media::Type::Audio, selector)?;
+    trace!(
+        "Using {} stream at index {}",
+        stream.parameters().id().name(),
+        stream.index()
+    );
+    Ok(stream.index())
+}
+
+/// Copies the packets of the audio stream `stream_idx` of `input` that overlap with `span` to
+/// a new Matroska file `name`, without re-encoding them. The clip starts at the first packet.
+fn extract_clip(
+    input: &mut libav::format::context::Input,
+    stream_idx: usize,
+    span: Timespan,
+    name: &str,
+) -> Result<()> {
+    let (time_base, parameters) = {
+        let stream = input
+            .stream(stream_idx)
+            .context("The audio stream does not exist")?;
+        (stream.time_base(), stream.parameters())
+    };
+
+    let mut output =
+        libav::format::output(&name).with_context(|| format!("Failed to create \"{}\"", name))?;
+    {
+        let mut stream = output
+            .add_stream(libav::encoder::find(libav::codec::Id::None))
+            .context("Failed to add audio stream")?;
+        stream.set_parameters(parameters);
+        // The tag of the codec in the input container may mean something else in Matroska
+        unsafe {
+            (*stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+    }
+    output
+        .write_header()
+        .with_context(|| format!("Failed to write header of \"{}\"", name))?;
+    let out_time_base = output
+        .stream(0)
+        .context("The audio stream was not added")?
+        .time_base();
+
+    // Seeking lands on or before the start, the packets before it are skipped below
+    let start_us = span.start().as_millis() * 1000;
+    input
+        .seek(start_us, ..=start_us)
+        .context("Failed to seek to the start of the clip")?;
+
+    let mut first_ts = None;
+    for (stream, mut packet) in input.packets() {
+        if stream.index() != stream_idx {
+            continue;
+        }
+        let Some(ts) = packet.pts().or(packet.dts()) else {
+            continue;
+        };
+        let Ok(start) = Timestamp::from_libav_ts(ts, time_base) else {
+            continue;
+        };
+        if start >= span.end() {
+            break;
+        }
+        let duration = Timestamp::from_libav_ts(packet.duration(), time_base)
+            .map(|duration| Duration::from_millis(duration.as_millis()))
+            .unwrap_or_default();
+        if start + duration <= span.start() {
+            continue;
+        }
+
+        let offset = *first_ts.get_or_insert(ts);
+        packet.set_stream(0);
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        packet.rescale_ts(time_base, out_time_base);
+        packet.set_position(-1);
+        packet
+            .write_interleaved(&mut output)
+            .with_context(|| format!("Failed to write \"{}\"", name))?;
+    }
+    output
+        .write_trailer()
+        .with_context(|| format!("Failed to write trailer of \"{}\"", name))
+}
+
+/// Extracts every clip of `clips` from the audio stream `stream_idx` of `path` to the file it is
+/// paired with. `extracted` is called after every clip, for the progress.
+pub fn extract_audio_clips<P, F>(
+    path: P,
+    stream_idx: usize,
+    clips: &[(Timespan, &str)],
+    mut extracted: F,
+) -> Result<()>
 where
     P: AsRef<Path>,
-    I: Iterator<Item = (Timespan, &'a str)>,
+    F: FnMut(),
 {
+    let mut input = libav::format::input(&path).context(format!(
+        "{}: Failed to open file",
+        path.as_ref().to_string_lossy()
+    ))?;
+    for (span, name) in clips {
+        extract_clip(&mut input, stream_idx, *span, name)?;
+        extracted();
+    }
+    Ok(())
+}
+
+/// Normalizes the loudness following EBU R128, to the -16 LUFS that is common for speech on
+/// phones and computers
+const LOUDNORM_FILTER: &str = "loudnorm=I=-16:TP=-1.5:LRA=11";
+
+/// Resamples the normalized audio back to a common sample rate
+const RESAMPLE_FILTER: &str = "aresample=48000";
+
+/// The filters that are applied to every audio clip
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AudioFilters {
+    /// Normalize the loudness with `LOUDNORM_FILTER`
+    pub normalize: bool,
+    /// The length of the fade in and fade out
+    pub fade: Option<Duration>,
+}
+
+impl AudioFilters {
+    /// Whether the clips can be copied as they are, without running any filter
+    pub fn is_empty(&self) -> bool {
+        !self.normalize && self.fade.is_none()
+    }
+
+    /// The filter chain for a clip of `duration`, empty if there are no filters. The loudness is
+    /// normalized first, so the fades are not undone by it. A fade takes at most half of the clip.
+    fn chain(&self, duration: Duration) -> String {
+        let mut filters = Vec::new();
+        if self.normalize {
+            filters.push(LOUDNORM_FILTER.to_string());
+            // loudnorm outputs 192 kHz, which would make the clips four times as big
+            filters.push(RESAMPLE_FILTER.to_string());
+        }
+        if let Some(fade) = self.fade {
+            let secs = |millis: i64| millis as f64 / 1000.0;
+            let fade = fade.as_millis().min(duration.as_millis() / 2);
+            filters.push(format!("afade=t=in:d={:.3}", secs(fade)));
+            filters.push(format!(
+                "afade=t=out:st={:.3}:d={:.3}",
+                secs(duration.as_millis() - fade),
+                secs(fade)
+            ));
+        }
+        filters.join(",")
+    }
+}
+
+/// Returns a command that extracts every clip of `clips` from the audio stream `stream_idx` of
+/// `path` through `filters`. The audio has to be decoded and encoded again for that, so unlike
+/// `extract_audio_clips` this runs ffmpeg.
+///
+/// The input is seeked to the first clip, and every clip is cut out with `atrim` before the
+/// filters run, so they only see the audio of the clip and its timestamps start at zero. Seeking
+/// the outputs instead would run the filters over the file from its start.
+fn generate_filtered_audio_command<P: AsRef<Path>>(
+    path: P,
+    clips: &[(Timespan, &str)],
+    stream_idx: usize,
+    filters: AudioFilters,
+) -> Command {
     let mut command = Command::new("ffmpeg");
 
+    let secs = |millis: i64| millis as f64 / 1000.0;
+    let seek = clips
+        .iter()
+        .map(|(span, _)| span.start())
+        .min()
+        .unwrap_or(Timestamp::MIN);
     let stream_map = format!("0:{}", stream_idx);
-
-    for (span, name) in points {
-        command.arg("-ss").arg(span.start().to_string());
-        command.arg("-to").arg(span.end().to_string());
+    for (span, name) in clips {
+        let start = span.start().as_millis() - seek.as_millis();
+        let mut chain = format!(
+            "atrim=start={:.3}:end={:.3},asetpts=PTS-STARTPTS",
+            secs(start),
+            secs(start + span.duration().as_millis())
+        );
+        if !filters.is_empty() {
+            chain.push(',');
+            chain.push_str(&filters.chain(span.duration()));
+        }
         command.arg("-map").arg(&stream_map);
+        command.arg("-af").arg(chain);
         command.arg(name);
     }
 
     command.arg("-loglevel").arg("warning");
+    command.arg("-ss").arg(seek.to_string());
     command.arg("-i").arg(path.as_ref());
 
     command.stdin(Stdio::null());
@@ -32,47 +223,735 @@ where
     command
 }
 
-fn generate_audio_commands_from_stream_chunked<'a, P, I>(
+/// Returns the commands that extract `clips` through `filters`, `CLIPS_PER_JOB` clips per
+/// command
+pub fn generate_filtered_audio_commands<P: AsRef<Path>>(
     path: P,
-    points: I,
+    clips: &[(Timespan, &str)],
     stream_idx: usize,
-    chunk_size: NonZeroUsize,
-) -> Vec<Command>
-where
-    P: AsRef<Path>,
-    I: Iterator<Item = (Timespan, &'a str)>,
-{
-    points
-        .chunks(chunk_size.into())
-        .into_iter()
-        .map(|chunk| generate_audio_command_from_stream(&path, chunk, stream_idx))
+    filters: AudioFilters,
+) -> Vec<Command> {
+    clips
+        .chunks(CLIPS_PER_JOB)
+        .map(|clips| generate_filtered_audio_command(&path, clips, stream_idx, filters))
         .collect()
 }
 
-pub fn generate_audio_commands<'a, P, I>(
+/// The parts are converted to the same format before they are concatenated, because the tone or
+/// silence is generated in a different one than the stream has
+const SEPARATED_FORMAT: &str = "aformat=sample_fmts=fltp:sample_rates=48000:channel_layouts=stereo";
+
+/// Returns the filter graph that concatenates the audio stream `stream_idx` of `count` inputs
+/// with `separator` between them
+fn separated_filter(
+    count: usize,
+    stream_idx: usize,
+    separator: AudioSeparator,
+    length: Duration,
+) -> String {
+    let secs = length.as_millis() as f64 / 1000.0;
+    let source = match separator {
+        AudioSeparator::Tone => format!("sine=frequency=880:duration={:.3}", secs),
+        AudioSeparator::Silence => format!("aevalsrc=0:duration={:.3}", secs),
+    };
+
+    let mut filters = Vec::new();
+    let mut concat = String::new();
+    for idx in 0..count {
+        if idx > 0 {
+            filters.push(format!("{},{}[s{}]", source, SEPARATED_FORMAT, idx));
+            concat.push_str(&format!("[s{}]", idx));
+        }
+        filters.push(format!(
+            "[{}:{}]{}[a{}]",
+            idx, stream_idx, SEPARATED_FORMAT, idx
+        ));
+        concat.push_str(&format!("[a{}]", idx));
+    }
+    filters.push(format!(
+        "{}concat=n={}:v=0:a=1[out]",
+        concat,
+        (2 * count).saturating_sub(1)
+    ));
+    filters.join(";")
+}
+
+fn generate_separated_audio_command<P: AsRef<Path>>(
+    path: P,
+    parts: &[Timespan],
+    name: &str,
+    stream_idx: usize,
+    separator: AudioSeparator,
+    length: Duration,
+    filters: AudioFilters,
+) -> Command {
+    let mut command = Command::new("ffmpeg");
+
+    command.arg("-loglevel").arg("warning");
+    for part in parts {
+        command.arg("-ss").arg(part.start().to_string());
+        command.arg("-t").arg(format!(
+            "{:.3}",
+            part.duration().as_millis() as f64 / 1000.0
+        ));
+        command.arg("-i").arg(path.as_ref());
+    }
+    let mut graph = separated_filter(parts.len(), stream_idx, separator, length);
+    if !filters.is_empty() {
+        let count = parts.len() as i64;
+        let duration = parts
+            .iter()
+            .map(|part| part.duration().as_millis())
+            .sum::<i64>()
+            + (count - 1).max(0) * length.as_millis();
+        graph.push_str(&format!(
+            ";[out]{}[filtered]",
+            filters.chain(Duration::from_millis(duration))
+        ));
+    }
+    command.arg("-filter_complex").arg(graph);
+    command.arg("-map").arg(if filters.is_empty() {
+        "[out]"
+    } else {
+        "[filtered]"
+    });
+    command.arg(name);
+
+    command.stdin(Stdio::null());
+
+    command
+}
+
+/// Returns a command for every clip that joins the clips of its `parts` with `separator` between
+/// them, and runs it through `filters`
+pub fn generate_separated_audio_commands<'a, P, I>(
     path: P,
-    points: I,
+    clips: I,
     selector: StreamSelector<'_>,
+    separator: AudioSeparator,
+    length: Duration,
+    filters: AudioFilters,
 ) -> Result<Vec<Command>>
 where
     P: AsRef<Path>,
-    I: Iterator<Item = (Timespan, &'a str)>,
+    I: Iterator<Item = (&'a [Timespan], &'a str)>,
 {
     let ictx = libav::format::input(&path).context(format!(
         "{}: Failed to open file",
         path.as_ref().to_string_lossy()
     ))?;
-    let stream = get_stream(ictx.streams(), media::Type::Audio, selector)?;
+    let stream = get_stream(&ictx, media::Type::Audio, selector)?;
+    let stream_idx = stream.index();
+
+    Ok(clips
+        .map(|(parts, name)| {
+            generate_separated_audio_command(
+                &path, parts, name, stream_idx, separator, length, filters,
+            )
+        })
+        .collect())
+}
+
+/// Packets that are at most this far apart are considered to be continuous audio
+const PROBE_GAP_TOLERANCE: Duration = Duration::from_millis(100);
+
+/// Merges the time ranges of packets, sorted by their start, into the ranges that contain audio
+fn merge_ranges<I>(ranges: I) -> Vec<Timespan>
+where
+    I: Iterator<Item = Timespan>,
+{
+    let mut merged: Vec<Timespan> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.gap_to(&range) <= PROBE_GAP_TOLERANCE => {
+                *last = last.union(&range);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn has_data(ranges: &[Timespan], span: Timespan) -> bool {
+    ranges
+        .iter()
+        .any(|range| range.start() < span.end() && span.start() < range.end())
+}
+
+/// Checks for every span in `spans` whether the selected audio stream of `path` contains any data
+/// there. Only the packets are read, nothing is decoded.
+pub fn probe_audio<P, I>(path: P, spans: I, selector: StreamSelector<'_>) -> Result<Vec<bool>>
+where
+    P: AsRef<Path>,
+    I: Iterator<Item = Timespan>,
+{
+    let mut ictx = libav::format::input(&path).context(format!(
+        "{}: Failed to open file",
+        path.as_ref().to_string_lossy()
+    ))?;
+    let stream = get_stream(&ictx, media::Type::Audio, selector)?;
+    let stream_idx = stream.index();
+    let time_base = stream.time_base();
+
+    let mut ranges = Vec::new();
+    loop {
+        let mut packet = Packet::empty();
+        match packet.read(&mut ictx) {
+            Ok(()) => {}
+            Err(libav::Error::Eof) => break,
+            Err(_) => continue,
+        }
+        if packet.stream() != stream_idx {
+            continue;
+        }
+
+        let Some(start) = packet
+            .pts()
+            .or(packet.dts())
+            .and_then(|ts| Timestamp::from_libav_ts(ts, time_base).ok())
+        else {
+            continue;
+        };
+        let duration = Timestamp::from_libav_ts(packet.duration(), time_base)
+            .map(|duration| Duration::from_millis(duration.as_millis()))
+            .unwrap_or(Duration::from_millis(0));
+        ranges.push(Timespan::new(start, start + duration));
+    }
+    ranges.sort_by_key(|range| range.start());
+
+    let ranges = merge_ranges(ranges.into_iter());
     trace!(
-        "Using {} stream at index {}",
-        stream.parameters().id().name(),
-        stream.index()
+        "{}: audio stream has data in {} range(s)",
+        path.as_ref().to_string_lossy(),
+        ranges.len()
     );
+    Ok(spans.map(|span| has_data(&ranges, span)).collect())
+}
 
-    Ok(generate_audio_commands_from_stream_chunked(
-        path,
-        points,
-        stream.index(),
-        32usize.try_into().unwrap(),
-    ))
+/// The rate the audio is resampled to for `detect_offset`, which is plenty to tell speech from
+/// silence
+const ACTIVITY_RATE: usize = 8000;
+/// The length in milliseconds of the windows of which the loudness is measured
+const ACTIVITY_WINDOW: i64 = 10;
+
+/// How well the subtitles of a file line up with the speech in its audio
+#[derive(Copy, Clone, Debug)]
+pub struct SyncEstimate {
+    /// How much later the subtitles should be shown
+    pub offset: Duration,
+    /// The share of the time of the subtitles in which there is speech, as they are timed
+    pub before: f64,
+    /// The share of the time of the subtitles in which there is speech, shifted by `offset`
+    pub after: f64,
+}
+
+/// Decodes the samples in `decoder`, resamples them and passes them to `consume`
+fn receive_samples<F>(
+    decoder: &mut decoder::Audio,
+    resampler: &mut resampling::Context,
+    consume: &mut F,
+) -> Result<()>
+where
+    F: FnMut(&[f32]),
+{
+    let mut decoded = frame::Audio::empty();
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        let mut resampled = frame::Audio::empty();
+        resampler
+            .run(&decoded, &mut resampled)
+            .context("Failed to resample audio")?;
+        consume(resampled.plane::<f32>(0));
+    }
+    Ok(())
+}
+
+/// Decodes the selected audio stream of `path` to mono samples at `rate` and passes them to
+/// `consume` in order. The stream is assumed to start at 0 and to have no gaps.
+fn decode_mono<P, F>(
+    path: P,
+    selector: StreamSelector<'_>,
+    rate: usize,
+    mut consume: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&[f32]),
+{
+    let mut ictx = libav::format::input(&path).context(format!(
+        "{}: Failed to open file",
+        path.as_ref().to_string_lossy()
+    ))?;
+    let stream = get_stream(&ictx, media::Type::Audio, selector)?;
+    let stream_idx = stream.index();
+    let mut decoder = libav::codec::context::Context::from_parameters(stream.parameters())
+        .context("Failed to create codec context")?
+        .decoder()
+        .audio()
+        .context("Failed to create audio decoder")?;
+    let mut resampler = decoder
+        .resampler(
+            Sample::F32(SampleType::Packed),
+            ChannelLayout::MONO,
+            rate as u32,
+        )
+        .context("Failed to create resampler")?;
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_idx {
+            continue;
+        }
+        // A corrupt packet only loses a few milliseconds of audio
+        if decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+        receive_samples(&mut decoder, &mut resampler, &mut consume)?;
+    }
+    decoder
+        .send_eof()
+        .context("Failed to send EOF to decoder")?;
+    receive_samples(&mut decoder, &mut resampler, &mut consume)
+}
+
+/// Measures the loudness (root mean square) of every full window of `ACTIVITY_WINDOW` of the
+/// selected audio stream of `path`
+fn read_loudness<P: AsRef<Path>>(path: P, selector: StreamSelector<'_>) -> Result<Vec<f32>> {
+    let window = ACTIVITY_RATE * ACTIVITY_WINDOW as usize / 1000;
+    let mut pending = Vec::new();
+    let mut loudness = Vec::new();
+    decode_mono(path, selector, ACTIVITY_RATE, |samples| {
+        pending.extend_from_slice(samples);
+        let full = pending.len() / window * window;
+        for samples in pending[..full].chunks(window) {
+            let power = samples.iter().map(|sample| sample * sample).sum::<f32>() / window as f32;
+            loudness.push(power.sqrt());
+        }
+        pending.drain(..full);
+    })?;
+    Ok(loudness)
+}
+
+/// Decodes the parts `spans` of the selected audio stream of `path` to mono samples at `rate`, and
+/// passes them to `consume` with the index of their span. Like `extract_clip`, every span is
+/// decoded after seeking to it, so the rest of the stream is skipped.
+pub fn read_clip_samples<P, F>(
+    path: P,
+    selector: StreamSelector<'_>,
+    rate: usize,
+    spans: &[Timespan],
+    mut consume: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(usize, &[f32]) -> Result<()>,
+{
+    let mut ictx = libav::format::input(&path).context(format!(
+        "{}: Failed to open file",
+        path.as_ref().to_string_lossy()
+    ))?;
+    let stream = get_stream(&ictx, media::Type::Audio, selector)?;
+    let stream_idx = stream.index();
+    let time_base = stream.time_base();
+    let mut decoder = libav::codec::context::Context::from_parameters(stream.parameters())
+        .context("Failed to create codec context")?
+        .decoder()
+        .audio()
+        .context("Failed to create audio decoder")?;
+
+    for (idx, span) in spans.iter().enumerate() {
+        // Seeking lands on or before the start, the samples before it are cut off below
+        let start_us = span.start().as_millis() * 1000;
+        ictx.seek(start_us, ..=start_us)
+            .context("Failed to seek to the start of the clip")?;
+        decoder.flush();
+        let mut resampler = decoder
+            .resampler(
+                Sample::F32(SampleType::Packed),
+                ChannelLayout::MONO,
+                rate as u32,
+            )
+            .context("Failed to create resampler")?;
+
+        let mut first = None;
+        let mut samples = Vec::new();
+        for (stream, packet) in ictx.packets() {
+            if stream.index() != stream_idx {
+                continue;
+            }
+            let start = packet
+                .pts()
+                .and_then(|ts| Timestamp::from_libav_ts(ts, time_base).ok());
+            if let Some(start) = start {
+                if start >= span.end() {
+                    break;
+                }
+                first.get_or_insert(start);
+            }
+            // A corrupt packet only loses a few milliseconds of audio
+            if decoder.send_packet(&packet).is_err() {
+                continue;
+            }
+            receive_samples(&mut decoder, &mut resampler, &mut |decoded: &[f32]| {
+                samples.extend_from_slice(decoded)
+            })?;
+        }
+
+        let first = first.unwrap_or(span.start());
+        let sample = |ts: Timestamp| {
+            let millis = (ts.as_millis() - first.as_millis()).max(0) as usize;
+            (millis * rate / 1000).min(samples.len())
+        };
+        consume(idx, &samples[sample(span.start())..sample(span.end())])?;
+    }
+    Ok(())
+}
+
+/// Marks the windows that are louder than the median as speech. Dialogue is usually louder than
+/// what is around it, which makes this a crude but quick voice activity detection.
+fn speech_activity(loudness: &[f32]) -> Vec<bool> {
+    let mut sorted = loudness.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted.get(sorted.len() / 2).copied().unwrap_or_default();
+    loudness.iter().map(|loudness| *loudness > median).collect()
+}
+
+/// Finds the shift of `spans` (in windows), of at most `range` windows either way, that puts the
+/// most speech inside them. The smallest shift wins a tie. Returns the shift and the share of the
+/// windows inside the spans that are speech before and after shifting.
+fn best_shift(activity: &[bool], spans: &[(i64, i64)], range: i64) -> (i64, f64, f64) {
+    // prefix[i] is the number of windows before i that are speech
+    let mut prefix = vec![0usize];
+    for active in activity {
+        prefix.push(prefix.last().unwrap_or(&0) + usize::from(*active));
+    }
+    let len = activity.len() as i64;
+    let speech = |start: i64, end: i64| {
+        let start = start.clamp(0, len) as usize;
+        let end = end.clamp(0, len) as usize;
+        prefix[end] - prefix[start.min(end)]
+    };
+
+    let total: i64 = spans.iter().map(|(start, end)| end - start).sum();
+    if total <= 0 {
+        return (0, 0.0, 0.0);
+    }
+    let score = |shift: i64| -> usize {
+        spans
+            .iter()
+            .map(|(start, end)| speech(start + shift, end + shift))
+            .sum()
+    };
+
+    let before = score(0);
+    let (mut best, mut best_score) = (0, before);
+    for shift in (1..=range).flat_map(|shift| [-shift, shift]) {
+        let score = score(shift);
+        if score > best_score {
+            best = shift;
+            best_score = score;
+        }
+    }
+    (
+        best,
+        before as f64 / total as f64,
+        best_score as f64 / total as f64,
+    )
+}
+
+/// Estimates how much the subtitles `spans` have to be shifted, at most `range` either way, to
+/// line up with the speech in the selected audio stream of `path`
+pub fn detect_offset<P: AsRef<Path>>(
+    path: P,
+    selector: StreamSelector<'_>,
+    spans: &[Timespan],
+    range: Duration,
+) -> Result<SyncEstimate> {
+    let activity = speech_activity(&read_loudness(path, selector)?);
+    let windows: Vec<(i64, i64)> = spans
+        .iter()
+        .map(|span| {
+            (
+                span.start().as_millis() / ACTIVITY_WINDOW,
+                span.end().as_millis() / ACTIVITY_WINDOW,
+            )
+        })
+        .collect();
+    let (shift, before, after) =
+        best_shift(&activity, &windows, range.as_millis() / ACTIVITY_WINDOW);
+    Ok(SyncEstimate {
+        offset: Duration::from_millis(shift * ACTIVITY_WINDOW),
+        before,
+        after,
+    })
+}
+
+/// The loudness below which a clip is taken to be silent, and left as it is by `trim_silence`
+const SILENCE_LOUDNESS: f32 = 0.001;
+/// How far between the noise floor and the peak of a clip the loudness of speech is
+const SPEECH_THRESHOLD: f32 = 0.2;
+/// The number of windows in a row that have to be loud to count as speech, so a click doesn't
+const MIN_SPEECH_WINDOWS: usize = 3;
+/// The audio that `trim_silence` keeps around the speech, so the start and end of words are kept
+const TRIM_MARGIN: Duration = Duration::from_millis(100);
+
+/// Finds the first and last (exclusive) window of speech in the loudness of a clip. Speech is
+/// louder than the noise floor of the clip (its tenth percentile) by a share of the range up to
+/// its peak. `None` if the clip is silent or has no speech.
+fn speech_bounds(loudness: &[f32]) -> Option<(usize, usize)> {
+    let mut sorted = loudness.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let floor = sorted.get(sorted.len() / 10).copied()?;
+    let peak = sorted.last().copied()?;
+    if peak < SILENCE_LOUDNESS {
+        return None;
+    }
+    let threshold = floor + (peak - floor) * SPEECH_THRESHOLD;
+    let loud: Vec<bool> = loudness
+        .iter()
+        .map(|loudness| *loudness > threshold)
+        .collect();
+    let mut runs = loud
+        .windows(MIN_SPEECH_WINDOWS)
+        .enumerate()
+        .filter(|(_, windows)| windows.iter().all(|loud| *loud))
+        .map(|(idx, _)| idx);
+    let start = runs.next()?;
+    let end = runs.last().unwrap_or(start) + MIN_SPEECH_WINDOWS;
+    Some((start, end))
+}
+
+/// Tightens `spans` to the speech in them, with `TRIM_MARGIN` around it, using the loudness of the
+/// selected audio stream of `path`. The spans only ever get shorter, spans without speech are
+/// left as they are.
+pub fn trim_silence<P: AsRef<Path>>(
+    path: P,
+    selector: StreamSelector<'_>,
+    spans: &[Timespan],
+) -> Result<Vec<Timespan>> {
+    let loudness = read_loudness(path, selector)?;
+    Ok(spans
+        .iter()
+        .map(|span| {
+            let first = (span.start().as_millis() / ACTIVITY_WINDOW) as usize;
+            let last = ((span.end().as_millis() + ACTIVITY_WINDOW - 1) / ACTIVITY_WINDOW) as usize;
+            let Some((start, end)) = loudness
+                .get(first..last.min(loudness.len()))
+                .and_then(speech_bounds)
+            else {
+                return *span;
+            };
+            let at = |window: usize| {
+                Timestamp::MIN.saturating_add(Duration::from_millis(
+                    (first + window) as i64 * ACTIVITY_WINDOW,
+                ))
+            };
+            Timespan::new(
+                span.start().max(at(start).saturating_sub(TRIM_MARGIN)),
+                span.end().min(at(end).saturating_add(TRIM_MARGIN)),
+            )
+        })
+        .collect())
+}
+
+/// Plays `count` randomly chosen clips of `files` one after the other. `player` is split on
+/// whitespace and the clip is passed as its last argument.
+pub fn play_samples<'a, I>(player: &str, files: I, count: usize) -> Result<()>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut parts = player.split_whitespace();
+    let program = parts.next().context("No player command specified")?;
+    let player_args: Vec<&str> = parts.collect();
+
+    for file in files.choose_multiple(&mut rand::rng(), count) {
+        info!("Playing {}", file);
+        let status = Command::new(program)
+            .args(&player_args)
+            .arg(file)
+            .stdin(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to run player `{}`", program))?;
+
+        if !status.success() {
+            warn!("player exited with {} while playing {}", status, file);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: u32, end: u32) -> Timespan {
+        Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end))
+    }
+
+    #[test]
+    fn shift_to_speech() {
+        let mut activity = vec![false; 100];
+        activity[20..30].fill(true);
+        activity[60..70].fill(true);
+        let (shift, before, after) = best_shift(&activity, &[(15, 25), (55, 65)], 10);
+        assert_eq!(shift, 5);
+        assert_eq!(before, 0.5);
+        assert_eq!(after, 1.0);
+    }
+
+    #[test]
+    fn no_shift_without_speech() {
+        let activity = vec![false; 100];
+        assert_eq!(best_shift(&activity, &[(10, 20)], 10), (0, 0.0, 0.0));
+        assert_eq!(best_shift(&activity, &[], 10), (0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn speech_between_noise() {
+        let mut loudness = vec![0.01; 50];
+        loudness[10..30].fill(0.5);
+        // A click is too short to be speech
+        loudness[40] = 0.9;
+        assert_eq!(speech_bounds(&loudness), Some((10, 30)));
+        assert_eq!(speech_bounds(&[0.0001; 50]), None);
+        assert_eq!(speech_bounds(&[0.3; 50]), None);
+        assert_eq!(speech_bounds(&[]), None);
+    }
+
+    #[test]
+    fn louder_than_median_is_speech() {
+        assert_eq!(
+            speech_activity(&[0.1, 0.5, 0.1, 0.6, 0.2]),
+            vec![false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn merge_packet_ranges() {
+        let ranges = merge_ranges(
+            vec![span(0, 20), span(20, 40), span(100, 120), span(5000, 5020)].into_iter(),
+        );
+        assert_eq!(ranges, vec![span(0, 120), span(5000, 5020)]);
+    }
+
+    #[test]
+    fn separated_filter_graph() {
+        let format = SEPARATED_FORMAT;
+        assert_eq!(
+            separated_filter(
+                2,
+                1,
+                AudioSeparator::Tone,
+                Duration::from_millis(250)
+            ),
+            format!(
+                "[0:1]{format}[a0];sine=frequency=880:duration=0.250,{format}[s1];[1:1]{format}[a1];[a0][s1][a1]concat=n=3:v=0:a=1[out]"
+            )
+        );
+        assert_eq!(
+            separated_filter(1, 0, AudioSeparator::Silence, Duration::from_millis(250)),
+            format!("[0:0]{format}[a0];[a0]concat=n=1:v=0:a=1[out]")
+        );
+    }
+
+    #[test]
+    fn filtered_commands() {
+        let filters = AudioFilters {
+            normalize: true,
+            fade: None,
+        };
+        let mut clips = vec![(span(1000, 2000), "a.mka"); CLIPS_PER_JOB];
+        clips.push((span(3000, 3500), "b.mka"));
+        clips.push((span(2500, 3000), "c.mka"));
+        let commands = generate_filtered_audio_commands("in.mkv", &clips, 1, filters);
+        assert_eq!(commands.len(), 2);
+        let args: Vec<_> = commands[1].get_args().collect();
+        let chain = |trim: &str| format!("{},{},{}", trim, LOUDNORM_FILTER, RESAMPLE_FILTER);
+        let first = chain("atrim=start=0.500:end=1.000,asetpts=PTS-STARTPTS");
+        let second = chain("atrim=start=0.000:end=0.500,asetpts=PTS-STARTPTS");
+        assert_eq!(
+            args,
+            [
+                "-map",
+                "0:1",
+                "-af",
+                first.as_str(),
+                "b.mka",
+                "-map",
+                "0:1",
+                "-af",
+                second.as_str(),
+                "c.mka",
+                "-loglevel",
+                "warning",
+                "-ss",
+                "0:00:02.500",
+                "-i",
+                "in.mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn fade_filters() {
+        let fade = |millis| AudioFilters {
+            normalize: false,
+            fade: Some(Duration::from_millis(millis)),
+        };
+        assert!(AudioFilters::default().is_empty());
+        assert_eq!(
+            AudioFilters::default().chain(Duration::from_millis(1000)),
+            ""
+        );
+        assert_eq!(
+            fade(50).chain(Duration::from_millis(2000)),
+            "afade=t=in:d=0.050,afade=t=out:st=1.950:d=0.050"
+        );
+        // A fade never takes more than half of a short clip
+        assert_eq!(
+            fade(500).chain(Duration::from_millis(600)),
+            "afade=t=in:d=0.300,afade=t=out:st=0.300:d=0.300"
+        );
+    }
+
+    #[test]
+    fn filtered_clip_is_not_silent() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let name = dir.path().join("clip.mka").to_string_lossy().to_string();
+        let filters = AudioFilters {
+            normalize: true,
+            fade: Some(Duration::from_millis(50)),
+        };
+        // The fades have to run on the time of the clip, a fade out on the time of the file would
+        // end before the clip starts
+        let clips = [(span(1000, 1500), name.as_str())];
+        let mut commands =
+            generate_filtered_audio_commands("tests/media/1000hz.mp3", &clips, 0, filters);
+        let status = commands[0].status()?;
+        assert!(status.success());
+
+        let mut samples = Vec::new();
+        read_clip_samples(
+            &name,
+            StreamSelector::Best,
+            16000,
+            &[span(0, 500)],
+            |_, clip| {
+                samples.extend_from_slice(clip);
+                Ok(())
+            },
+        )?;
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(samples.len() > 4000);
+        assert!(peak > 0.1);
+        Ok(())
+    }
+
+    #[test]
+    fn spans_in_gaps_have_no_data() {
+        let ranges = vec![span(0, 1000), span(5000, 6000)];
+        assert!(has_data(&ranges, span(500, 1500)));
+        assert!(!has_data(&ranges, span(2000, 4000)));
+        assert!(!has_data(&ranges, span(7000, 8000)));
+    }
 }
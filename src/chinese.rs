@@ -0,0 +1,110 @@
+use anyhow::Result;
+
+/// Splits Chinese text into words and finds their pinyin
+#[cfg(not(feature = "chinese"))]
+pub struct ChineseAnalyzer;
+
+#[cfg(not(feature = "chinese"))]
+impl ChineseAnalyzer {
+    pub fn new() -> Result<Self> {
+        anyhow::bail!(
+            "stos was built without support for Chinese, rebuild it with the `chinese` feature enabled"
+        );
+    }
+
+    pub fn segment(&self, _text: &str) -> String {
+        unreachable!("a ChineseAnalyzer cannot be created without the `chinese` feature")
+    }
+
+    pub fn pinyin(&self, _text: &str) -> String {
+        unreachable!("a ChineseAnalyzer cannot be created without the `chinese` feature")
+    }
+}
+
+/// Splits Chinese text into words and finds their pinyin
+#[cfg(feature = "chinese")]
+pub struct ChineseAnalyzer {
+    jieba: jieba_rs::Jieba,
+}
+
+#[cfg(feature = "chinese")]
+impl ChineseAnalyzer {
+    /// Loads the jieba dictionary that is embedded in the binary
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            jieba: jieba_rs::Jieba::new(),
+        })
+    }
+
+    /// The words of every line of `text`, whitespace is dropped
+    fn words<'a>(&self, text: &'a str) -> Vec<Vec<&'a str>> {
+        text.split("\\N")
+            .map(|line| {
+                self.jieba
+                    .cut(line, true)
+                    .into_iter()
+                    .filter(|word| !word.trim().is_empty())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns `text` with a space between every two words
+    pub fn segment(&self, text: &str) -> String {
+        self.words(text)
+            .iter()
+            .map(|line| line.join(" "))
+            .collect::<Vec<_>>()
+            .join("\\N")
+    }
+
+    /// Returns the pinyin of `text` with tone marks, with a space between every two words
+    pub fn pinyin(&self, text: &str) -> String {
+        self.words(text)
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|word| word_pinyin(word))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\\N")
+    }
+}
+
+/// The pinyin of the syllables of a single word. Characters without pinyin, like punctuation and
+/// latin letters, are kept as they are.
+#[cfg(feature = "chinese")]
+fn word_pinyin(word: &str) -> String {
+    use pinyin::ToPinyin;
+
+    word.chars()
+        .map(|ch| match ch.to_pinyin() {
+            Some(pinyin) => pinyin.with_tone().to_string(),
+            None => ch.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "chinese"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinyin_of_word() {
+        assert_eq!(word_pinyin("中国"), "zhōngguó");
+        assert_eq!(word_pinyin("。"), "。");
+        assert_eq!(word_pinyin("OK"), "OK");
+    }
+
+    #[test]
+    fn segment_sentence() {
+        let analyzer = ChineseAnalyzer::new().unwrap();
+        assert_eq!(
+            analyzer.segment("我们中出了一个叛徒"),
+            "我们 中 出 了 一个 叛徒"
+        );
+        assert_eq!(analyzer.pinyin("你好\\N世界"), "nǐhǎo\\Nshìjiè");
+    }
+}
@@ -1,8 +1,9 @@
 use crate::time::Timestamp;
-use crate::util::{get_stream, StreamSelector};
+use crate::util::{get_stream, sanitize_filename, StreamSelector};
 use anyhow::{bail, Context, Result};
 use crossbeam_channel::{Receiver, Sender};
-pub use image::{DynamicImage, ImageBuffer, RgbImage, Rgba};
+use image::imageops::{self, FilterType};
+pub use image::{DynamicImage, ImageBuffer, Rgb, RgbImage, Rgba};
 use indicatif::ProgressBar;
 use libav::codec;
 use libav::codec::decoder;
@@ -11,10 +12,96 @@ use libav::media;
 use libav::software::scaling;
 use libav::util::frame;
 use log::{trace, warn};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// A decoded image on its way to `write_images`
+pub enum Frame {
+    Decoded(DynamicImage),
+    /// An image that did not fit in the memory budget, written to a temporary file
+    Spilled(PathBuf),
+}
+
+/// Limits the memory taken up by decoded images that have not been written yet. Images that
+/// don't fit are spilled to a temporary directory.
+pub struct FrameBudget {
+    limit: u64,
+    in_flight: AtomicU64,
+    spilled: AtomicU64,
+    dir: TempDir,
+}
+
+impl FrameBudget {
+    pub fn new(limit: u64) -> Result<Self> {
+        Ok(Self {
+            limit,
+            in_flight: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+            dir: tempfile::tempdir().context("Failed to create directory for spilled images")?,
+        })
+    }
+
+    /// Reserves `size` bytes, returns false if they don't fit in the budget
+    fn reserve(&self, size: u64) -> bool {
+        self.in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used + size <= self.limit).then_some(used + size)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, size: u64) {
+        self.in_flight.fetch_sub(size, Ordering::SeqCst);
+    }
+
+    fn spill(&self, image: &RgbImage) -> Result<PathBuf> {
+        let idx = self.spilled.fetch_add(1, Ordering::SeqCst);
+        // PPM is stored uncompressed, so spilling costs little more than the write itself
+        let path = self.dir.path().join(format!("{}.ppm", idx));
+        image
+            .save_with_format(&path, image::ImageFormat::Pnm)
+            .context("Failed to spill image")?;
+        Ok(path)
+    }
+
+    /// The amount of images that were spilled to disk
+    pub fn spilled(&self) -> u64 {
+        self.spilled.load(Ordering::SeqCst)
+    }
+}
+
+/// Sends decoded images to `write_images`, spilling them to disk if they exceed the budget. An
+/// image is sent once with the names of all files it is written to.
+#[derive(Clone)]
+pub struct FrameSender {
+    sender: Sender<(Vec<String>, Frame)>,
+    budget: Option<Arc<FrameBudget>>,
+}
+
+impl FrameSender {
+    pub fn new(sender: Sender<(Vec<String>, Frame)>, budget: Option<Arc<FrameBudget>>) -> Self {
+        Self { sender, budget }
+    }
+
+    fn send(&self, names: &[&str], image: &RgbImage) -> Result<()> {
+        let frame = match &self.budget {
+            Some(budget) if !budget.reserve(image.as_raw().len() as u64) => {
+                Frame::Spilled(budget.spill(image)?)
+            }
+            _ => Frame::Decoded(image.clone().into()),
+        };
+        let names = names.iter().map(|name| name.to_string()).collect();
+        self.sender
+            .send((names, frame))
+            .context("Failed to send image")
+    }
+}
 
 fn extract_images_from_stream<'a, I>(
-    sender: Sender<(String, DynamicImage)>,
+    sender: FrameSender,
     mut ictx: Input,
     mut decoder: decoder::video::Video,
     mut scaler: scaling::context::Context,
@@ -52,12 +139,14 @@ where
                     rgb_frame.height(),
                     rgb_frame.data(0).to_vec(),
                 ) {
+                    // Capture points that fall on the same frame share the image, so it is only
+                    // kept in memory and encoded once
+                    let mut names = Vec::new();
                     while let Some((_, name)) = points.next_if(|(ts, _)| frame_ts >= *ts) {
-                        pb.inc(1);
-                        sender
-                            .send((name.to_string(), image.clone().into()))
-                            .context("Failed to send image")?;
+                        names.push(name);
                     }
+                    pb.inc(names.len() as u64);
+                    sender.send(&names, &image)?;
                 } else {
                     bail!("Failed to convert frame to image");
                 }
@@ -110,7 +199,7 @@ pub fn extract_images_from_file<'a, P, I>(
     file: P,
     points: I,
     selector: StreamSelector<'_>,
-    sender: Sender<(String, DynamicImage)>,
+    sender: FrameSender,
     pb: ProgressBar,
 ) -> Result<()>
 where
@@ -118,7 +207,7 @@ where
     I: Iterator<Item = (Timestamp, &'a str)>,
 {
     let ictx = libav::format::input(&file).context("Failed to open file")?;
-    let stream = get_stream(ictx.streams(), media::Type::Video, selector)?;
+    let stream = get_stream(&ictx, media::Type::Video, selector)?;
     let stream_idx = stream.index();
     trace!(
         "Using {} stream at index {}",
@@ -147,13 +236,529 @@ where
     extract_images_from_stream(sender, ictx, decoder, scaler, points, stream_idx, pb)
 }
 
-pub fn write_images(receiver: Receiver<(String, DynamicImage)>) -> Result<()> {
-    while let Ok((file, image)) = receiver.recv() {
+/// Rows and columns of which the average brightness is below this are considered part of the
+/// letterbox
+const LETTERBOX_THRESHOLD: u64 = 24;
+
+/// Returns the range of the lines (rows or columns) that are not part of the letterbox
+fn content_range(lines: u32, brightness: impl Fn(u32) -> u64) -> (u32, u32) {
+    let is_content = |line: &u32| brightness(*line) > LETTERBOX_THRESHOLD;
+    match (
+        (0..lines).find(is_content),
+        (0..lines).rev().find(is_content),
+    ) {
+        (Some(first), Some(last)) => (first, last + 1),
+        _ => (0, lines),
+    }
+}
+
+/// Crops `image` to the bottom `fraction` of the picture, which is where subtitles are usually
+/// rendered. Letterbox and pillarbox bars are not counted as part of the picture.
+pub fn crop_subtitle_region(image: &DynamicImage, fraction: f64) -> DynamicImage {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let luma = |x: u32, y: u32| {
+        let Rgb([r, g, b]) = *rgb.get_pixel(x, y);
+        (u64::from(r) * 299 + u64::from(g) * 587 + u64::from(b) * 114) / 1000
+    };
+
+    let (top, bottom) = content_range(height, |y| {
+        (0..width).map(|x| luma(x, y)).sum::<u64>() / u64::from(width.max(1))
+    });
+    let (left, right) = content_range(width, |x| {
+        (0..height).map(|y| luma(x, y)).sum::<u64>() / u64::from(height.max(1))
+    });
+
+    let region = ((f64::from(bottom - top) * fraction).round() as u32).clamp(1, bottom - top);
+    image.crop_imm(left, bottom - region, right - left, region)
+}
+
+/// Writes the images from `receiver`. An image with more than one name is encoded once and copied
+/// to the other files.
+pub fn write_images(
+    receiver: Receiver<(Vec<String>, Frame)>,
+    subtitle_region: Option<f64>,
+    budget: Option<&FrameBudget>,
+) -> Result<()> {
+    while let Ok((names, frame)) = receiver.recv() {
+        let Some((file, copies)) = names.split_first() else {
+            continue;
+        };
+        // The memory of a decoded image is released once it has been written
+        let (image, reserved) = match frame {
+            Frame::Decoded(image) => {
+                let size = image.as_bytes().len() as u64;
+                (image, budget.map(|_| size))
+            }
+            Frame::Spilled(path) => {
+                let image = image::open(&path)
+                    .with_context(|| format!("{}: Failed to read spilled image", file))?;
+                let _ = std::fs::remove_file(&path);
+                (image, None)
+            }
+        };
+        let image = match subtitle_region {
+            Some(fraction) => crop_subtitle_region(&image, fraction),
+            None => image,
+        };
         image
-            .save(&file)
+            .save(file)
             .with_context(|| format!("{}: Failed to write image", file))?;
+        if let (Some(budget), Some(size)) = (budget, reserved) {
+            budget.release(size);
+        }
         trace!("{}: Wrote to file", file);
+        for copy in copies {
+            std::fs::copy(file, copy)
+                .with_context(|| format!("{}: Failed to write image", copy))?;
+            trace!("{}: Copied from {}", copy, file);
+        }
     }
     trace!("no more images to convert");
     Ok(())
 }
+
+/// Escapes `value` so it can be used as an option value of a filter in an ffmpeg filter graph.
+/// Filter graphs are parsed twice, once for the options of a filter and once for the graph, so
+/// the value is escaped for both.
+fn escape_filter_value(value: &str) -> String {
+    let escape = |value: &str, special: &[char]| {
+        let mut escaped = String::new();
+        for ch in value.chars() {
+            if special.contains(&ch) {
+                escaped.push('\\');
+            }
+            escaped.push(ch);
+        }
+        escaped
+    };
+    let value = escape(value, &['\\', '\'', ':']);
+    escape(&value, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn render_subtitle_command(
+    options: &str,
+    (width, height): (u32, u32),
+    time: Timestamp,
+    name: &str,
+) -> Command {
+    let mut command = Command::new("ffmpeg");
+
+    command.arg("-loglevel").arg("warning");
+    command.arg("-f").arg("lavfi");
+    command.arg("-i").arg(format!(
+        "color=c=black@0.0:s={}x{}:d=1,format=rgba",
+        width, height
+    ));
+    // The only frame that is rendered is moved to the time of the subtitle, so libass draws the
+    // subtitle the way it looks at that moment (including fades and karaoke)
+    command.arg("-vf").arg(format!(
+        "setpts=PTS+{}/TB,subtitles={}:alpha=1",
+        time.as_millis() as f64 / 1000.0,
+        options
+    ));
+    command.arg("-frames:v").arg("1");
+    command.arg("-y").arg(name);
+
+    command.stdin(Stdio::null());
+
+    command
+}
+
+/// Creates the commands that render the ASS subtitles of `file` at the given points with libass
+/// (through the ffmpeg `subtitles` filter) onto a transparent frame of `size`, using the styles
+/// and fonts of the subtitle file. Fonts in `fonts_dir` are used as well.
+pub fn render_subtitle_commands<'a, P, I>(
+    file: P,
+    points: I,
+    selector: StreamSelector<'_>,
+    size: (u32, u32),
+    fonts_dir: Option<&Path>,
+) -> Result<Vec<Command>>
+where
+    P: AsRef<Path>,
+    I: Iterator<Item = (Timestamp, &'a str)>,
+{
+    let ictx = libav::format::input(&file).context("Failed to open file")?;
+    let stream = get_stream(&ictx, media::Type::Subtitle, selector)?;
+    // The subtitles filter counts only the subtitle streams
+    let subtitle_idx = ictx
+        .streams()
+        .filter(|other| other.parameters().medium() == media::Type::Subtitle)
+        .position(|other| other.index() == stream.index())
+        .unwrap_or(0);
+    trace!("Rendering subtitle stream {} with libass", subtitle_idx);
+
+    let path = file
+        .as_ref()
+        .to_str()
+        .context("Path of subtitle file is not valid unicode")?;
+    let mut options = format!("filename={}:si={}", escape_filter_value(path), subtitle_idx);
+    if let Some(dir) = fonts_dir {
+        let dir = dir
+            .to_str()
+            .context("Path of fonts directory is not valid unicode")?;
+        options.push_str(&format!(":fontsdir={}", escape_filter_value(dir)));
+    }
+
+    Ok(points
+        .map(|(time, name)| render_subtitle_command(&options, size, time, name))
+        .collect())
+}
+
+/// Mime types Matroska files use for font attachments
+const FONT_MIME_TYPES: &[&str] = &[
+    "application/x-truetype-font",
+    "application/x-font-ttf",
+    "application/x-font-otf",
+    "application/vnd.ms-opentype",
+    "application/font-sfnt",
+    "font/ttf",
+    "font/otf",
+    "font/sfnt",
+    "font/collection",
+];
+
+fn is_font(mime_type: Option<&str>, name: &str) -> bool {
+    let ext = Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    mime_type.is_some_and(|mime_type| FONT_MIME_TYPES.contains(&mime_type.to_lowercase().as_str()))
+        || ["ttf", "otf", "ttc"]
+            .iter()
+            .any(|font_ext| ext.eq_ignore_ascii_case(font_ext))
+}
+
+/// Writes the fonts attached to `file` (as Matroska releases with ASS subtitles do) to `dir`.
+/// Returns the paths of the written fonts.
+pub fn extract_fonts<P: AsRef<Path>>(file: P, dir: &Path) -> Result<Vec<PathBuf>> {
+    let ictx = libav::format::input(&file).context("Failed to open file")?;
+
+    let mut fonts = Vec::new();
+    for stream in ictx.streams() {
+        if stream.parameters().medium() != media::Type::Attachment {
+            continue;
+        }
+        let metadata = stream.metadata();
+        let Some(name) = metadata.get("filename") else {
+            continue;
+        };
+        if !is_font(metadata.get("mimetype"), name) {
+            trace!("Skipping attachment {}, it is not a font", name);
+            continue;
+        }
+
+        // The data of an attachment is stored as the extradata of the stream
+        let data = unsafe {
+            let params = stream.parameters().as_ptr();
+            if (*params).extradata.is_null() {
+                continue;
+            }
+            std::slice::from_raw_parts(
+                (*params).extradata,
+                (*params).extradata_size.try_into().unwrap_or(0),
+            )
+            .to_vec()
+        };
+
+        let file_name = sanitize_filename(name);
+        if file_name.is_empty() {
+            continue;
+        }
+        let path = dir.join(file_name);
+        std::fs::write(&path, data)
+            .with_context(|| format!("Failed to write font \"{}\"", path.to_string_lossy()))?;
+        trace!("Extracted font {}", name);
+        fonts.push(path);
+    }
+    Ok(fonts)
+}
+
+/// Returns the range of the lines (rows or columns) that have a visible pixel
+fn visible_range(lines: u32, visible: impl Fn(u32) -> bool) -> Option<(u32, u32)> {
+    let first = (0..lines).find(|line| visible(*line))?;
+    let last = (0..lines).rev().find(|line| visible(*line))?;
+    Some((first, last + 1))
+}
+
+/// Crops the rendered subtitle image at `path` to the part that is not transparent
+pub fn trim_transparent<P: AsRef<Path>>(path: P) -> Result<()> {
+    let image = image::open(&path)
+        .with_context(|| format!("Failed to open \"{}\"", path.as_ref().to_string_lossy()))?
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+    let visible = |x: u32, y: u32| image.get_pixel(x, y)[3] > 0;
+
+    let (Some((top, bottom)), Some((left, right))) = (
+        visible_range(height, |y| (0..width).any(|x| visible(x, y))),
+        visible_range(width, |x| (0..height).any(|y| visible(x, y))),
+    ) else {
+        warn!(
+            "\"{}\" is empty, the subtitle was not rendered",
+            path.as_ref().to_string_lossy()
+        );
+        return Ok(());
+    };
+
+    imageops::crop_imm(&image, left, top, right - left, bottom - top)
+        .to_image()
+        .save(&path)
+        .with_context(|| format!("Failed to write \"{}\"", path.as_ref().to_string_lossy()))
+}
+
+const SHEET_COLUMNS: u32 = 6;
+const SHEET_TILE_WIDTH: u32 = 320;
+const GLYPH_SCALE: u32 = 3;
+
+/// A 3x5 pixel glyph for the characters used in contact sheet labels, every row is 3 bits
+fn glyph(ch: char) -> [u8; 5] {
+    match ch {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        _ => [0; 5],
+    }
+}
+
+/// Draws `text` in white on a black box with its top left corner at (`x`, `y`)
+fn draw_label(sheet: &mut RgbImage, x: u32, y: u32, text: &str) {
+    let advance = 4 * GLYPH_SCALE;
+    let width = text.chars().count() as u32 * advance + GLYPH_SCALE;
+    let height = 7 * GLYPH_SCALE;
+
+    for dy in 0..height {
+        for dx in 0..width {
+            if x + dx < sheet.width() && y + dy < sheet.height() {
+                sheet.put_pixel(x + dx, y + dy, Rgb([0, 0, 0]));
+            }
+        }
+    }
+
+    for (idx, ch) in text.chars().enumerate() {
+        let left = x + GLYPH_SCALE + idx as u32 * advance;
+        for (row, bits) in glyph(ch).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for sy in 0..GLYPH_SCALE {
+                    for sx in 0..GLYPH_SCALE {
+                        let px = left + col * GLYPH_SCALE + sx;
+                        let py = y + GLYPH_SCALE + row as u32 * GLYPH_SCALE + sy;
+                        if px < sheet.width() && py < sheet.height() {
+                            sheet.put_pixel(px, py, Rgb([255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Writes a grid of the extracted frames `frames` to `path`, labeled with their index and
+/// timestamp. Frames that could not be read are left black.
+pub fn write_contact_sheet<'a, P, I>(path: P, frames: I) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: Iterator<Item = (usize, Timestamp, &'a str)>,
+{
+    let frames: Vec<_> = frames
+        .map(|(idx, ts, file)| {
+            let image = image::open(file)
+                .map_err(|err| warn!("{}: Failed to read image: {}", file, err))
+                .ok();
+            (idx, ts, image)
+        })
+        .collect();
+
+    if frames.is_empty() {
+        bail!("There are no frames to put on a contact sheet");
+    }
+
+    // All tiles get the aspect ratio of the first frame that could be read
+    let tile_height = frames
+        .iter()
+        .find_map(|(_, _, image)| image.as_ref())
+        .map(|image| {
+            (u64::from(SHEET_TILE_WIDTH) * u64::from(image.height())
+                / u64::from(image.width().max(1))) as u32
+        })
+        .unwrap_or(SHEET_TILE_WIDTH * 9 / 16)
+        .max(1);
+
+    let columns = SHEET_COLUMNS.min(frames.len() as u32);
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let mut sheet = RgbImage::new(columns * SHEET_TILE_WIDTH, rows * tile_height);
+
+    for (pos, (idx, ts, image)) in frames.iter().enumerate() {
+        let x = pos as u32 % columns * SHEET_TILE_WIDTH;
+        let y = pos as u32 / columns * tile_height;
+        if let Some(image) = image {
+            let tile = image
+                .resize_exact(SHEET_TILE_WIDTH, tile_height, FilterType::Triangle)
+                .to_rgb8();
+            imageops::replace(&mut sheet, &tile, x.into(), y.into());
+        }
+        draw_label(&mut sheet, x, y, &format!("#{} {}", idx, ts));
+    }
+
+    sheet
+        .save(&path)
+        .with_context(|| format!("{}: Failed to write contact sheet", path.as_ref().display()))?;
+    trace!("wrote contact sheet with {} frames", frames.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_filter_path() {
+        assert_eq!(escape_filter_value("subs.ass"), "subs.ass");
+        assert_eq!(
+            escape_filter_value("C:\\subs\\it's [1].ass"),
+            "C\\\\:\\\\\\\\subs\\\\\\\\it\\\\\\'s \\[1\\].ass"
+        );
+    }
+
+    #[test]
+    fn font_attachments() {
+        assert!(is_font(Some("application/x-truetype-font"), "font"));
+        assert!(is_font(Some("Font/TTF"), "font"));
+        assert!(is_font(None, "Arial.TTF"));
+        assert!(is_font(Some("application/octet-stream"), "font.otf"));
+        assert!(!is_font(Some("image/jpeg"), "cover.jpg"));
+    }
+
+    #[test]
+    fn trim_rendered_subtitle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sub.png");
+        let mut frame = ImageBuffer::from_pixel(64, 36, Rgba([0u8, 0, 0, 0]));
+        for x in 10..20 {
+            frame.put_pixel(x, 30, Rgba([255, 255, 255, 255]));
+        }
+        frame.save(&path)?;
+
+        trim_transparent(&path)?;
+        assert_eq!(image::open(&path)?.to_rgba8().dimensions(), (10, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn subtitle_region_of_letterboxed_frame() {
+        // 100x100 frame with 20 pixel bars at the top and bottom
+        let frame = RgbImage::from_fn(100, 100, |_, y| {
+            if (20..80).contains(&y) {
+                Rgb([200, 200, 200])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+        let region = crop_subtitle_region(&frame.into(), 0.5);
+        assert_eq!((region.width(), region.height()), (100, 30));
+        assert_eq!(region.to_rgb8().get_pixel(0, 29), &Rgb([200, 200, 200]));
+    }
+
+    #[test]
+    fn subtitle_region_of_black_frame() {
+        let frame = RgbImage::new(90, 60);
+        let region = crop_subtitle_region(&frame.into(), 1.0 / 3.0);
+        assert_eq!((region.width(), region.height()), (90, 20));
+    }
+
+    #[test]
+    fn contact_sheet_grid() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let frame = dir.path().join("frame.png");
+        RgbImage::from_pixel(64, 36, Rgb([255, 0, 0])).save(&frame)?;
+        let frame = frame.to_string_lossy().to_string();
+        let missing = dir.path().join("missing.png").to_string_lossy().to_string();
+
+        let frames: Vec<(usize, Timestamp, &str)> = (0..7)
+            .map(|idx| {
+                let file = if idx == 3 { &missing } else { &frame };
+                (idx, Timestamp::from_secs(idx as u32), file.as_str())
+            })
+            .collect();
+
+        let sheet_path = dir.path().join("sheet.png");
+        write_contact_sheet(&sheet_path, frames.into_iter())?;
+
+        let sheet = image::open(&sheet_path)?.to_rgb8();
+        assert_eq!(sheet.dimensions(), (6 * SHEET_TILE_WIDTH, 2 * 180));
+        // the label is drawn over the top left corner, the rest of the tile is the frame
+        assert_eq!(sheet.get_pixel(0, 0), &Rgb([0, 0, 0]));
+        assert_eq!(sheet.get_pixel(300, 170), &Rgb([255, 0, 0]));
+        // the missing frame is left black
+        assert_eq!(
+            sheet.get_pixel(3 * SHEET_TILE_WIDTH + 300, 170),
+            &Rgb([0, 0, 0])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shared_frame() -> Result<()> {
+        let image = RgbImage::from_pixel(4, 4, Rgb([0, 0, 255]));
+        let budget = Arc::new(FrameBudget::new(image.as_raw().len() as u64)?);
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sender = FrameSender::new(sender, Some(budget.clone()));
+
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("0.png").to_string_lossy().into_owned();
+        let second = dir.path().join("1.png").to_string_lossy().into_owned();
+        sender.send(&[first.as_str(), second.as_str()], &image)?;
+        drop(sender);
+        // One image takes up the budget once, whatever the amount of files it is written to
+        assert_eq!(budget.spilled(), 0);
+
+        write_images(receiver, None, Some(&budget))?;
+        assert_eq!(image::open(&first)?.to_rgb8(), image);
+        assert_eq!(image::open(&second)?.to_rgb8(), image);
+        Ok(())
+    }
+
+    #[test]
+    fn spill_over_budget() -> Result<()> {
+        let image = RgbImage::from_pixel(4, 4, Rgb([255, 0, 0]));
+        // Room for a single image
+        let budget = Arc::new(FrameBudget::new(image.as_raw().len() as u64)?);
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sender = FrameSender::new(sender, Some(budget.clone()));
+
+        let dir = tempfile::tempdir()?;
+        let names: Vec<String> = (0..3)
+            .map(|idx| {
+                dir.path()
+                    .join(format!("{}.png", idx))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        for name in &names {
+            sender.send(&[name.as_str()], &image)?;
+        }
+        drop(sender);
+        assert_eq!(budget.spilled(), 2);
+
+        write_images(receiver, None, Some(&budget))?;
+        for name in &names {
+            assert_eq!(image::open(name)?.to_rgb8(), image);
+        }
+        assert_eq!(budget.in_flight.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+}
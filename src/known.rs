@@ -0,0 +1,135 @@
+use crate::subtitle::normalize_text;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Sentences of cards the user already has, read from a plain text export
+#[derive(Debug, Default)]
+pub struct KnownSentences {
+    exact: HashSet<String>,
+    /// The normalized sentences as characters, for the edit distance
+    sentences: Vec<Vec<char>>,
+}
+
+/// Returns the number of single character insertions, deletions and substitutions it takes to
+/// turn `a` into `b`
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// How similar `a` and `b` are, ignoring case and punctuation: the fraction of the characters of
+/// the longer text that don't have to be edited to turn one into the other
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = normalize_text(a).chars().collect();
+    let b: Vec<char> = normalize_text(b).chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(&a, &b) as f64 / longest as f64
+}
+
+impl KnownSentences {
+    /// Parses an export with a sentence per line. Every tab separated field of a line is taken
+    /// as a sentence, so notes exported from Anki with all their fields work as well. Lines
+    /// starting with `#` are Anki's export headers and are ignored.
+    fn parse(data: &str) -> Self {
+        // The HTML and `[sound:...]` references that Anki puts in exported fields
+        let markup = Regex::new(r"<[^>]*>|\[sound:[^\]]*\]|&nbsp;").unwrap();
+        let mut known = Self::default();
+        for field in data
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .flat_map(|line| line.split('\t'))
+        {
+            let sentence = normalize_text(&markup.replace_all(field, " "));
+            if !sentence.is_empty() && known.exact.insert(sentence.clone()) {
+                known.sentences.push(sentence.chars().collect());
+            }
+        }
+        known
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read_to_string(path).context("Failed to read known sentences")?;
+        Ok(Self::parse(&data))
+    }
+
+    pub fn count(&self) -> usize {
+        self.sentences.len()
+    }
+
+    /// Whether a known sentence is at least `similarity` (between 0 and 1) similar to `text`,
+    /// ignoring case and punctuation. The similarity is the fraction of the characters of the
+    /// longer sentence that don't have to be edited.
+    pub fn contains_similar(&self, text: &str, similarity: f64) -> bool {
+        let text = normalize_text(text);
+        if self.exact.contains(&text) {
+            return true;
+        }
+        let text: Vec<char> = text.chars().collect();
+        if text.is_empty() || similarity >= 1.0 {
+            return false;
+        }
+
+        self.sentences.iter().any(|known| {
+            let longest = text.len().max(known.len());
+            // Without the epsilon, 10 characters at 0.9 would allow 0.999... edits
+            let max_edits = ((1.0 - similarity) * longest as f64 + 1e-9).floor() as usize;
+            // The edit distance is at least the difference in length, which is cheap to check
+            text.len().abs_diff(known.len()) <= max_edits
+                && edit_distance(&text, known) <= max_edits
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("abc")), 3);
+        assert_eq!(edit_distance(&chars("same"), &chars("same")), 0);
+    }
+
+    #[test]
+    fn text_similarity() {
+        assert_eq!(similarity("Hello, World!", "hello world"), 1.0);
+        assert_eq!(similarity("", "..."), 1.0);
+        assert_eq!(similarity("abcd", "abxy"), 0.5);
+        assert_eq!(similarity("abc", ""), 0.0);
+    }
+
+    #[test]
+    fn parse_anki_export() {
+        let known = KnownSentences::parse(
+            "#separator:tab\n#html:true\n1\t<b>Where are you going?</b>\t[sound:a.mka]\n",
+        );
+        assert_eq!(known.count(), 2);
+        assert!(known.contains_similar("where are you going", 1.0));
+    }
+
+    #[test]
+    fn near_duplicates() {
+        let known = KnownSentences::parse("I don't know what you mean.\nOk\n");
+        assert!(known.contains_similar("I dont know what you mean!", 0.9));
+        assert!(known.contains_similar("I don't know what you meant", 0.9));
+        assert!(!known.contains_similar("I don't know where you went", 0.9));
+        assert!(!known.contains_similar("No", 0.9));
+        assert!(known.contains_similar("OK!", 0.9));
+    }
+}
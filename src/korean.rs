@@ -0,0 +1,148 @@
+/// The first precomposed Hangul syllable, 가
+const FIRST_SYLLABLE: u32 = 0xac00;
+/// The last precomposed Hangul syllable, 힣
+const LAST_SYLLABLE: u32 = 0xd7a3;
+
+const INITIALS: [&str; 19] = [
+    "g", "kk", "n", "d", "tt", "r", "m", "b", "pp", "s", "ss", "", "j", "jj", "ch", "k", "t", "p",
+    "h",
+];
+const MEDIALS: [&str; 21] = [
+    "a", "ae", "ya", "yae", "eo", "e", "yeo", "ye", "o", "wa", "wae", "oe", "yo", "u", "wo", "we",
+    "wi", "yu", "eu", "ui", "i",
+];
+/// The finals as they are pronounced at the end of a syllable
+const FINALS: [&str; 28] = [
+    "", "k", "k", "k", "n", "n", "n", "t", "l", "k", "m", "l", "l", "l", "p", "l", "m", "p", "p",
+    "t", "t", "ng", "t", "t", "k", "t", "p", "t",
+];
+/// The finals before a syllable that starts with ㅇ, split in what stays at the end of the syllable
+/// and what moves to the start of the next one
+const LIAISON: [(&str, &str); 28] = [
+    ("", ""),
+    ("", "g"),
+    ("", "kk"),
+    ("k", "s"),
+    ("", "n"),
+    ("n", "j"),
+    ("", "n"),
+    ("", "d"),
+    ("", "r"),
+    ("l", "g"),
+    ("l", "m"),
+    ("l", "b"),
+    ("l", "s"),
+    ("l", "t"),
+    ("l", "p"),
+    ("", "r"),
+    ("", "m"),
+    ("", "b"),
+    ("p", "s"),
+    ("", "s"),
+    ("", "ss"),
+    ("ng", ""),
+    ("", "j"),
+    ("", "ch"),
+    ("", "k"),
+    ("", "t"),
+    ("", "p"),
+    ("", ""),
+];
+
+/// The indices of the initial ㄴ, ㄹ, ㅁ and ㅇ
+const NIEUN: usize = 2;
+const RIEUL: usize = 5;
+const MIEUM: usize = 6;
+const IEUNG: usize = 11;
+
+/// Splits a Hangul syllable into the indices of its initial, medial and final
+fn decompose(ch: char) -> Option<(usize, usize, usize)> {
+    let code = ch as u32;
+    if !(FIRST_SYLLABLE..=LAST_SYLLABLE).contains(&code) {
+        return None;
+    }
+    let idx = (code - FIRST_SYLLABLE) as usize;
+    Some((idx / 588, idx % 588 / 28, idx % 28))
+}
+
+/// A stop at the end of a syllable becomes a nasal before a nasal
+fn nasalize(coda: &'static str) -> &'static str {
+    match coda {
+        "k" => "ng",
+        "t" => "n",
+        "p" => "m",
+        _ => coda,
+    }
+}
+
+/// Romanizes the Hangul of `text` with the Revised Romanization of Korean, leaving everything else
+/// as it is. The common sound changes between syllables are applied: the final moving to a next
+/// syllable that starts with ㅇ, nasalization and the assimilation of ㄹ.
+pub fn romanize(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut res = String::new();
+    // The start of the syllable, if the previous syllable changed it
+    let mut onset: Option<&'static str> = None;
+    for (idx, ch) in chars.iter().enumerate() {
+        let Some((initial, medial, fin)) = decompose(*ch) else {
+            res.push(*ch);
+            onset = None;
+            continue;
+        };
+        res.push_str(onset.take().unwrap_or(INITIALS[initial]));
+        res.push_str(MEDIALS[medial]);
+
+        let next = chars.get(idx + 1).and_then(|ch| decompose(*ch));
+        let coda = match next.map(|(initial, _, _)| initial) {
+            Some(IEUNG) => {
+                let (coda, moved) = LIAISON[fin];
+                if !moved.is_empty() {
+                    onset = Some(moved);
+                }
+                coda
+            }
+            Some(NIEUN | MIEUM) => nasalize(FINALS[fin]),
+            Some(RIEUL) if fin != 0 => match FINALS[fin] {
+                "l" | "n" => {
+                    onset = Some("l");
+                    "l"
+                }
+                coda => {
+                    onset = Some("n");
+                    nasalize(coda)
+                }
+            },
+            _ => FINALS[fin],
+        };
+        res.push_str(coda);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn romanize_words() {
+        assert_eq!(romanize("한국어"), "hangugeo");
+        assert_eq!(romanize("안녕하세요"), "annyeonghaseyo");
+        assert_eq!(romanize("라면"), "ramyeon");
+        assert_eq!(romanize("없어요"), "eopseoyo");
+        assert_eq!(romanize("좋아"), "joa");
+        assert_eq!(romanize("닭"), "dak");
+    }
+
+    #[test]
+    fn sound_changes() {
+        assert_eq!(romanize("감사합니다"), "gamsahamnida");
+        assert_eq!(romanize("신라"), "silla");
+        assert_eq!(romanize("종로"), "jongno");
+        assert_eq!(romanize("백로"), "baengno");
+    }
+
+    #[test]
+    fn other_text_is_kept() {
+        assert_eq!(romanize("K-pop 좋아!\\N네"), "K-pop joa!\\Nne");
+    }
+}
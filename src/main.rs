@@ -1,33 +1,88 @@
 extern crate ffmpeg_next as libav;
 use anyhow::{bail, Context, Result};
-use crossbeam_channel::{unbounded, Sender};
+use crossbeam_channel::unbounded;
 use genanki_rs::{Deck, Package};
 use human_panic::setup_panic;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use indicatif_log_bridge::LogWrapper;
-use log::{error, trace, warn};
+use log::{error, info, trace, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
+use regex::Regex;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+mod accent;
 mod anki;
+mod apkg;
 mod args;
 mod ass;
 mod audio;
+mod chinese;
 mod image;
+mod known;
+mod korean;
+mod manifest;
+mod ocr;
+mod playlist;
+mod reading;
+mod recipe;
+mod speech;
 mod subtitle;
 mod time;
 mod util;
-
-use crate::image::{extract_images_from_file, write_images};
-use anki::create_notes;
-use args::Args;
-use audio::generate_audio_commands;
-use subtitle::{read_subtitles_from_file, Dialogue, Subtitle};
+mod words;
+
+use crate::image::{
+    extract_fonts, extract_images_from_file, render_subtitle_commands, trim_transparent,
+    write_contact_sheet, write_images, FrameBudget, FrameSender,
+};
+use accent::AccentDictionary;
+use anki::{boosted_first, create_notes, info_note};
+use apkg::ExistingPackage;
+use args::{
+    Args, CardSelection, FilterMode, MergeKey, MissingMediaPolicy, NoteLang, Sample, Schedule,
+};
+use ass::parse_styles;
+use audio::{
+    audio_stream_index, detect_offset, extract_audio_clips, generate_filtered_audio_commands,
+    generate_separated_audio_commands, play_samples, probe_audio, read_clip_samples, trim_silence,
+    AudioFilters, CLIPS_PER_JOB,
+};
+use chinese::ChineseAnalyzer;
+use known::{similarity, KnownSentences};
+use ocr::recognize_bitmaps;
+use playlist::write_playlist;
+use reading::ReadingAnalyzer;
+use recipe::Recipe;
+use speech::{SpeechRecognizer, SPEECH_RATE};
+use subtitle::{
+    apply_normalizations, group_context, is_frame_based, join_bidi, join_sentences, merge_karaoke,
+    normalize_text, read_subtitles_from_file, reorder_subtitles, split_sentences, strip_sdh,
+    Dialogue, ReadOptions, Subtitle, TextNormalization,
+};
 use time::{Duration, Timespan, Timestamp};
-use util::StreamSelector;
+use util::{
+    ass_header, available_memory, check_stream, expand_template, fnv1a, frame_rate, media_duration,
+    media_name, read_chapters, sanitize_filename, subtitle_language, StreamSelector, FNV_OFFSET,
+};
+use words::{read_word_clips, read_word_list, target_words_in, FrequencyList, KnownWords};
+
+/// How far `--auto-sync` looks for a better timing either way
+const AUTO_SYNC_RANGE: Duration = Duration::from_millis(60_000);
+/// How much more of the time of the subtitles has to have speech for `--auto-sync` to shift them
+const MIN_SYNC_IMPROVEMENT: f64 = 0.05;
+
+/// The directory the output of failed jobs is moved to
+const FAILED_DIR: &str = "failed";
+/// A rough estimate of the memory an image extraction job takes up: the decoder with its
+/// reference frames and the decoded images waiting to be written
+const EXTRACTION_MEMORY: u64 = 512 * 1024 * 1024;
 
 #[derive(Serialize)]
 pub struct SubtitleBundle {
@@ -35,6 +90,32 @@ pub struct SubtitleBundle {
     sub_image: Option<String>,
     audio: Option<String>,
     image: Option<String>,
+    translation: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aligned: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    target_words: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reading: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pitch_accent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segmented: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pinyin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    difficulty: Option<&'static str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    media_failed: bool,
+    /// What whisper heard in the audio, for `--verify-speech`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transcription: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    speech_mismatch: bool,
+    file_idx: usize,
+    sub_idx: usize,
 }
 
 impl From<Subtitle> for SubtitleBundle {
@@ -44,6 +125,20 @@ impl From<Subtitle> for SubtitleBundle {
             sub_image: None,
             audio: None,
             image: None,
+            translation: None,
+            aligned: Vec::new(),
+            lang: None,
+            target_words: Vec::new(),
+            reading: None,
+            pitch_accent: None,
+            segmented: None,
+            pinyin: None,
+            difficulty: None,
+            media_failed: false,
+            transcription: None,
+            speech_mismatch: false,
+            file_idx: 0,
+            sub_idx: 0,
         }
     }
 }
@@ -79,12 +174,181 @@ impl SubtitleBundle {
         self.image = Some(image.to_string());
         self
     }
+
+    pub fn translation(&self) -> Option<&str> {
+        self.translation.as_deref()
+    }
+
+    pub fn set_translation(&mut self, translation: &str) -> &mut Self {
+        self.translation = Some(translation.to_string());
+        self
+    }
+
+    /// The texts of the subtitles of the extra streams that overlap with this subtitle
+    pub fn aligned(&self) -> &[String] {
+        &self.aligned
+    }
+
+    pub fn push_aligned(&mut self, text: &str) -> &mut Self {
+        self.aligned.push(text.to_string());
+        self
+    }
+
+    /// The language the note is tagged with
+    pub fn lang(&self) -> Option<&str> {
+        self.lang.as_deref()
+    }
+
+    pub fn set_lang(&mut self, lang: &str) -> &mut Self {
+        self.lang = Some(lang.to_string());
+        self
+    }
+
+    pub fn target_words(&self) -> &[String] {
+        &self.target_words
+    }
+
+    pub fn set_target_words(&mut self, words: Vec<String>) -> &mut Self {
+        self.target_words = words;
+        self
+    }
+
+    pub fn reading(&self) -> Option<&str> {
+        self.reading.as_deref()
+    }
+
+    pub fn set_reading(&mut self, reading: &str) -> &mut Self {
+        self.reading = Some(reading.to_string());
+        self
+    }
+
+    pub fn pitch_accent(&self) -> Option<&str> {
+        self.pitch_accent.as_deref()
+    }
+
+    pub fn set_pitch_accent(&mut self, pitch_accent: &str) -> &mut Self {
+        self.pitch_accent = Some(pitch_accent.to_string());
+        self
+    }
+
+    /// The text with spaces between the words, for Chinese
+    pub fn segmented(&self) -> Option<&str> {
+        self.segmented.as_deref()
+    }
+
+    pub fn set_segmented(&mut self, segmented: &str) -> &mut Self {
+        self.segmented = Some(segmented.to_string());
+        self
+    }
+
+    pub fn pinyin(&self) -> Option<&str> {
+        self.pinyin.as_deref()
+    }
+
+    pub fn set_pinyin(&mut self, pinyin: &str) -> &mut Self {
+        self.pinyin = Some(pinyin.to_string());
+        self
+    }
+
+    pub fn difficulty(&self) -> Option<&str> {
+        self.difficulty
+    }
+
+    pub fn set_difficulty(&mut self, difficulty: &'static str) -> &mut Self {
+        self.difficulty = Some(difficulty);
+        self
+    }
+
+    /// The index of the file the subtitle was read from
+    pub fn file_idx(&self) -> usize {
+        self.file_idx
+    }
+
+    /// The index of the subtitle within its file, after filtering
+    pub fn sub_idx(&self) -> usize {
+        self.sub_idx
+    }
+
+    pub fn set_index(&mut self, file_idx: usize, sub_idx: usize) -> &mut Self {
+        self.file_idx = file_idx;
+        self.sub_idx = sub_idx;
+        self
+    }
+
+    fn is_missing(media: &Option<String>) -> bool {
+        media
+            .as_deref()
+            .is_some_and(|media| !Path::new(media).exists())
+    }
+
+    /// Whether any of the media files this subtitle refers to does not exist
+    pub fn has_missing_media(&self) -> bool {
+        Self::is_missing(&self.sub_image)
+            || Self::is_missing(&self.audio)
+            || Self::is_missing(&self.image)
+    }
+
+    /// Whether `path` is one of the media files this subtitle refers to
+    pub fn refers_to(&self, path: &Path) -> bool {
+        [&self.sub_image, &self.audio, &self.image]
+            .into_iter()
+            .flatten()
+            .any(|media| Path::new(media) == path)
+    }
+
+    /// Removes the references to media files that do not exist
+    pub fn drop_missing_media(&mut self) {
+        for media in [&mut self.sub_image, &mut self.audio, &mut self.image] {
+            if Self::is_missing(media) {
+                *media = None;
+            }
+        }
+    }
+
+    /// Removes the references to media files that do not exist and marks the subtitle if there
+    /// were any, so its note can be tagged for repair
+    pub fn keep_without_missing_media(&mut self) {
+        if self.has_missing_media() {
+            self.media_failed = true;
+            self.drop_missing_media();
+        }
+    }
+
+    /// Whether some of the media of this subtitle could not be generated
+    pub fn media_failed(&self) -> bool {
+        self.media_failed
+    }
+
+    /// Sets what whisper heard in the audio, and whether that is too different from the text
+    pub fn set_transcription(&mut self, transcription: &str, mismatch: bool) -> &mut Self {
+        self.transcription = Some(transcription.to_string());
+        self.speech_mismatch = mismatch;
+        self
+    }
+
+    /// Whether the text of this subtitle differs too much from the speech in its audio
+    pub fn speech_mismatch(&self) -> bool {
+        self.speech_mismatch
+    }
 }
 
 enum Job<'a, 'b, 'c> {
+    ExtractAudio {
+        pb: ProgressBar,
+        path: &'a PathBuf,
+        stream_idx: usize,
+        clips: Vec<(Timespan, &'a str)>,
+    },
     Command {
         pb: ProgressBar,
         command: std::process::Command,
+        /// Every file the command writes
+        paths: Vec<&'a str>,
+    },
+    RenderSubtitle {
+        pb: ProgressBar,
+        command: std::process::Command,
+        path: &'a str,
     },
     WriteImage {
         path: &'a std::path::Path,
@@ -95,15 +359,32 @@ enum Job<'a, 'b, 'c> {
         path: &'a PathBuf,
         points: Vec<(Timestamp, &'b str)>,
         selector: StreamSelector<'c>,
-        sender: Sender<(String, image::DynamicImage)>,
+        sender: FrameSender,
     },
 }
 
 impl Job<'_, '_, '_> {
     pub fn execute(self) -> Result<()> {
         match self {
-            Job::Command { pb, command } => {
+            Job::ExtractAudio {
+                pb,
+                path,
+                stream_idx,
+                clips,
+            } => extract_audio_clips(path, stream_idx, &clips, || pb.inc(1)).with_context(|| {
+                format!(
+                    "Failed to extract audio from \"{}\"",
+                    path.to_string_lossy()
+                )
+            }),
+            Job::Command { pb, command, .. } => {
+                Self::execute_command(command)?;
+                pb.inc(1);
+                Ok(())
+            }
+            Job::RenderSubtitle { pb, command, path } => {
                 Self::execute_command(command)?;
+                trim_transparent(path)?;
                 pb.inc(1);
                 Ok(())
             }
@@ -126,6 +407,30 @@ impl Job<'_, '_, '_> {
         }
     }
 
+    /// The files the job writes. The images of `ExtractImages` are written whole by the image
+    /// writers, so it has none.
+    fn outputs(&self) -> Vec<PathBuf> {
+        match self {
+            Job::ExtractAudio { clips, .. } => {
+                clips.iter().map(|(_, name)| PathBuf::from(name)).collect()
+            }
+            Job::Command { paths, .. } => paths.iter().map(PathBuf::from).collect(),
+            Job::RenderSubtitle { path, .. } => vec![PathBuf::from(path)],
+            Job::WriteImage { path, .. } => vec![path.to_path_buf()],
+            Job::ExtractImages { .. } => Vec::new(),
+        }
+    }
+
+    /// The position of the job in the queue for `schedule`, lower goes first
+    fn priority(&self, schedule: Schedule) -> u8 {
+        match (schedule, self) {
+            (Schedule::ImagesFirst, Job::ExtractImages { .. }) => 0,
+            (Schedule::AudioFirst, Job::ExtractAudio { .. } | Job::Command { .. }) => 0,
+            (Schedule::Interleave, _) => 0,
+            _ => 1,
+        }
+    }
+
     fn execute_command(mut command: std::process::Command) -> Result<()> {
         match command
             .status()
@@ -138,175 +443,1675 @@ impl Job<'_, '_, '_> {
     }
 }
 
-fn merge_overlapping<I>(subs: I, max_dist: Duration) -> Vec<Subtitle>
+/// The text that duplicates are found by: `text` with the `--normalize` transforms applied, or
+/// with case, punctuation and whitespace ignored without them
+fn comparison_key(normalizations: &[TextNormalization], text: &str) -> String {
+    if normalizations.is_empty() {
+        normalize_text(text)
+    } else {
+        apply_normalizations(normalizations, text)
+    }
+}
+
+/// What subtitles are compared by when merging them
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum MergeKeyValue {
+    Dialogue(Dialogue),
+    Text(String),
+}
+
+impl MergeKeyValue {
+    fn new(sub: &Subtitle, key: MergeKey, normalizations: &[TextNormalization]) -> Self {
+        match (key, sub.text()) {
+            (MergeKey::Text, Some(text)) => {
+                MergeKeyValue::Text(comparison_key(normalizations, text))
+            }
+            _ => MergeKeyValue::Dialogue(sub.dialogue().clone()),
+        }
+    }
+}
+
+/// The merge keys of the subtitles at the end of a file, with how long before the end of its
+/// last subtitle they end, for `--merge-across-files`
+type FileTail = HashMap<MergeKeyValue, Duration>;
+
+/// Merges nearby subtitles that are the same. If `earlier` is given, subtitles at the start of
+/// this file that continue one at the end of the earlier file are dropped, and `earlier` is
+/// replaced by the end of this file. A continued subtitle is at most `max_dist` from the split
+/// between the files.
+fn merge_overlapping<I>(
+    subs: I,
+    max_dist: Duration,
+    key: MergeKey,
+    normalizations: &[TextNormalization],
+    earlier: Option<&mut FileTail>,
+) -> Vec<Subtitle>
 where
     I: Iterator<Item = Subtitle>,
 {
     let mut result: Vec<Subtitle> = Vec::new();
-    let mut diags: HashMap<Dialogue, usize> = HashMap::new();
+    let mut diags: HashMap<MergeKeyValue, usize> = HashMap::new();
+    let mut continued: HashMap<MergeKeyValue, Timespan> = HashMap::new();
     let mut count = 0;
 
     for sub in subs {
         count += 1usize;
-        if let Some(idx) = diags.get(sub.dialogue()) {
+        let sub_key = MergeKeyValue::new(&sub, key, normalizations);
+        // The span of a subtitle of another file can't be extended, so it is merged by dropping
+        // the later one, and the ones that it would be merged with
+        let continues = match continued.get(&sub_key) {
+            Some(span) => span.gap_to(&sub.timespan()) <= max_dist,
+            None => earlier
+                .as_ref()
+                .and_then(|earlier| earlier.get(&sub_key))
+                .is_some_and(|tail| {
+                    tail.as_millis() + sub.timespan().start().as_millis() <= max_dist.as_millis()
+                }),
+        };
+        if continues {
+            continued.insert(sub_key, sub.timespan());
+            continue;
+        }
+        if let Some(idx) = diags.get(&sub_key) {
             let prev_sub = &mut result[*idx];
-            if prev_sub.timespan().end() + max_dist >= sub.timespan().start() {
-                prev_sub.set_timespan(Timespan::new(
-                    prev_sub.timespan().start(),
-                    sub.timespan().end(),
-                ));
+            if prev_sub.timespan().gap_to(&sub.timespan()) <= max_dist {
+                prev_sub.set_timespan(prev_sub.timespan().union(&sub.timespan()));
                 continue;
             }
         }
-        diags.insert(sub.dialogue().clone(), result.len());
+        diags.insert(sub_key, result.len());
         result.push(sub);
     }
 
     trace!("merged {} subs into {}", count, result.len());
 
+    if let Some(earlier) = earlier {
+        earlier.clear();
+        let end = result.iter().map(|sub| sub.timespan().end()).max();
+        for sub in &result {
+            let tail = end.map_or(0, |end| end.as_millis() - sub.timespan().end().as_millis());
+            if tail <= max_dist.as_millis() {
+                earlier.insert(
+                    MergeKeyValue::new(sub, key, normalizations),
+                    Duration::from_millis(tail),
+                );
+            }
+        }
+    }
     result
 }
 
-fn read_subtitles(args: &Args) -> Result<Vec<Vec<Subtitle>>> {
+/// The read options for the subtitle file at `file_idx`. Frame based subtitles get the frame rate
+/// of their media file, in case they don't declare one themselves.
+fn read_options(args: &Args, file_idx: usize) -> ReadOptions {
+    let mut options = args.read_options();
+    let file = &args.sub_files()[file_idx];
+    if options.fps.is_none() && is_frame_based(file) {
+        options.media_fps = args
+            .media_files()
+            .get(file_idx)
+            .and_then(|media| frame_rate(media, args.video_stream_selector()));
+    }
+    options
+}
+
+/// Shows the subtitles `delay` later, or earlier if it is negative
+fn delay_subtitles(subs: &mut [Subtitle], delay: Duration) {
+    if delay.as_millis() == 0 {
+        return;
+    }
+    for sub in subs {
+        let span = sub.timespan();
+        sub.set_timespan(Timespan::new(
+            span.start().saturating_add(delay),
+            span.end().saturating_add(delay),
+        ));
+    }
+}
+
+/// Drops the subtitles of which the text was removed by a transform
+fn drop_empty(subs: &mut Vec<Subtitle>) {
+    subs.retain(|sub| !sub.text().is_some_and(|text| text.trim().is_empty()));
+}
+
+/// Applies the `--replace` replacements in order and drops the subtitles that are left without text
+fn replace_text(subs: &mut Vec<Subtitle>, replacements: &[(Regex, String)]) {
+    if replacements.is_empty() {
+        return;
+    }
+    for sub in subs.iter_mut() {
+        sub.map_text(|text| {
+            replacements
+                .iter()
+                .fold(text.to_string(), |text, (re, replacement)| {
+                    re.replace_all(&text, replacement.as_str()).into_owned()
+                })
+        });
+    }
+    drop_empty(subs);
+}
+
+/// How many subtitles have to be read over all files. With `--max-cards` and
+/// `--card-selection=first` the cards are the first subtitles that are read, so the rest of the
+/// streams is never decoded, as long as nothing drops, joins or reorders subtitles before the
+/// cards are chosen.
+fn read_limit(args: &Args) -> Option<usize> {
+    let max = args.max_cards()?;
+    let chooses = args.card_selection() != CardSelection::First
+        || args.start() > Timestamp::MIN
+        || args.end() < Timestamp::MAX
+        || args.end_before_eof().is_some()
+        || args.skip_chapters().is_some()
+        || args.strip_sdh()
+        || !args.replacements().is_empty()
+        || args.merge_subs()
+        || args.ignore_styled()
+        || args.max_layer().is_some()
+        || !args.actors().is_empty()
+        || !args.skipped_actors().is_empty()
+        || args.forced_only()
+        || args.skip_forced()
+        || args.min_cps().is_some()
+        || args.max_cps().is_some()
+        || args.sentences()
+        || args.split_sentences()
+        || !args.blacklist().is_empty()
+        || !args.whitelist().is_empty()
+        || args.context().is_some()
+        || args.sample().is_some()
+        || args.target_words().is_some()
+        || args.known_words().is_some()
+        || args.known_sentences().is_some()
+        || args.dedup();
+    (!chooses).then_some(max)
+}
+
+/// Reads the subtitles of every subtitle file, using the stream that `selector` selects for the
+/// index of the file. At most `limit` subtitles are read over all files, the rest of the streams
+/// is not decoded.
+fn read_subtitles<'a, F>(
+    args: &'a Args,
+    selector: F,
+    limit: Option<usize>,
+) -> Result<Vec<Vec<Subtitle>>>
+where
+    F: Fn(usize) -> StreamSelector<'a>,
+{
+    let mut left = limit.unwrap_or(usize::MAX);
+    args.sub_files()
+        .iter()
+        .enumerate()
+        .map(|(file_idx, file)| {
+            read_subtitles_from_file(&file, selector(file_idx), read_options(args, file_idx))
+                .and_then(|subs| subs.take(left).collect::<Result<Vec<_>>>())
+                .map(|mut subs| {
+                    left -= subs.len();
+                    reorder_subtitles(&mut subs);
+                    if let Some(points) = args.sync_points() {
+                        subs.iter_mut().for_each(|sub| {
+                            sub.set_timespan(points.apply_span(sub.timespan()));
+                        });
+                    }
+                    delay_subtitles(&mut subs, args.file_delay(file_idx));
+                    if args.strip_tags() {
+                        subs.iter_mut().for_each(|sub| {
+                            sub.strip_tags();
+                        });
+                    }
+                    if args.strip_sdh() {
+                        subs.iter_mut().for_each(|sub| {
+                            sub.map_text(strip_sdh);
+                        });
+                        drop_empty(&mut subs);
+                    }
+                    if let Some(mode) = args.newline_mode() {
+                        subs.iter_mut().for_each(|sub| {
+                            sub.normalize_whitespace(mode.separator());
+                        });
+                    }
+                    replace_text(&mut subs, args.replacements());
+                    subs
+                })
+                .with_context(|| {
+                    format!(
+                        "Failed to read subtitles from \"{}\"",
+                        file.to_string_lossy()
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Prints the subtitles of every file as they are decoded, for `--dump`. Nothing else is done, so
+/// the rest of the streams is not decoded once the reader of the output is gone or `--max-cards`
+/// with `--card-selection=first` is reached.
+fn dump_subtitles(args: &Args) -> Result<()> {
+    let mut left = args
+        .max_cards()
+        .filter(|_| args.card_selection() == CardSelection::First)
+        .unwrap_or(usize::MAX);
+    let mut stdout = std::io::stdout().lock();
+    for (file_idx, file) in args.sub_files().iter().enumerate() {
+        let subs = read_subtitles_from_file(
+            &file,
+            args.file_sub_stream_selector(file_idx),
+            read_options(args, file_idx),
+        )
+        .with_context(|| {
+            format!(
+                "Failed to read subtitles from \"{}\"",
+                file.to_string_lossy()
+            )
+        })?;
+        for sub in subs.take(left) {
+            let sub = sub?;
+            left -= 1;
+            let line = writeln!(
+                stdout,
+                "{}|{}|{}",
+                sub.timespan().start(),
+                sub.timespan().end(),
+                sub.text().unwrap_or(""),
+            );
+            if line.is_err() {
+                trace!("stopped dumping subtitles, stdout was closed");
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_words(args: &Args, word_list: &Path) -> Result<Vec<Vec<Subtitle>>> {
+    let words = read_word_list(word_list)
+        .with_context(|| format!("Failed to read \"{}\"", word_list.to_string_lossy()))?;
     args.sub_files()
         .iter()
-        .map(|file| {
-            read_subtitles_from_file(&file, args.sub_stream_selector()).with_context(|| {
+        .enumerate()
+        .map(|(file_idx, file)| {
+            let mut clips = read_word_clips(file, &words).with_context(|| {
                 format!(
-                    "Failed to read subtitles from \"{}\"",
+                    "Failed to read alignment data from \"{}\"",
                     file.to_string_lossy()
                 )
-            })
+            })?;
+            delay_subtitles(&mut clips, args.file_delay(file_idx));
+            Ok(clips)
         })
-        .map(|result| result.map(|subs| subs.collect()))
         .collect()
 }
 
-fn process_subtitles(args: &Args, subs: Vec<Subtitle>) -> Vec<SubtitleBundle> {
+/// Joins the texts of the subtitles in `others` that overlap with `span`
+fn overlapping_text(span: Timespan, others: &[Subtitle]) -> String {
+    let texts = others
+        .iter()
+        .filter(|other| other.timespan().intersects(&span))
+        .filter_map(Subtitle::text)
+        .collect::<Vec<_>>();
+    join_bidi(&texts, " ")
+}
+
+fn attach_translations(subs: &mut [SubtitleBundle], translations: &[Subtitle]) {
+    for bundle in subs {
+        let translation = overlapping_text(bundle.sub().timespan(), translations);
+        bundle.set_translation(&translation);
+    }
+}
+
+fn attach_aligned(subs: &mut [SubtitleBundle], aligned: &[Subtitle]) {
+    for bundle in subs {
+        let text = overlapping_text(bundle.sub().timespan(), aligned);
+        bundle.push_aligned(&text);
+    }
+}
+
+/// Whether a subtitle with `text` is kept by `--blacklist` and `--whitelist`, combined as `mode`
+/// says. Subtitles without text (bitmaps) match no regex.
+fn passes_filters(
+    mode: FilterMode,
+    blacklist: &[Regex],
+    whitelist: &[Regex],
+    text: Option<&str>,
+) -> bool {
+    let blacklisted = text.is_some_and(|text| blacklist.iter().any(|re| re.is_match(text)));
+    let whitelisted = text.is_some_and(|text| whitelist.iter().any(|re| re.is_match(text)));
+    match mode {
+        FilterMode::And => (whitelist.is_empty() || whitelisted) && !blacklisted,
+        FilterMode::WhitelistOverrides => whitelisted || !blacklisted,
+    }
+}
+
+/// `merged` holds the end of the previous file, for `--merge-across-files`
+fn process_subtitles(
+    args: &Args,
+    subs: Vec<Subtitle>,
+    end: Timestamp,
+    skipped: &[Timespan],
+    merged: &mut FileTail,
+    rng: &mut StdRng,
+) -> Vec<SubtitleBundle> {
+    let subs = merge_karaoke(subs);
     let subs = if args.merge_subs() {
         trace!("merging subtitles");
-        merge_overlapping(subs.into_iter(), args.merge_diff())
+        merge_overlapping(
+            subs.into_iter(),
+            args.merge_diff(),
+            args.merge_key(),
+            args.normalizations(),
+            args.merge_across_files().then_some(merged),
+        )
     } else {
         trace!("not merging subtitles");
         subs
     };
 
-    subs.into_iter()
+    let subs = subs
+        .into_iter()
         .filter(|sub| sub.timespan().start() >= args.start())
-        .filter(|sub| sub.timespan().start() <= args.end())
-        .filter(|sub| {
-            !sub.text()
-                .map(|text| args.blacklist().iter().any(|re| re.is_match(text)))
-                .unwrap_or(false)
-        })
+        .filter(|sub| sub.timespan().start() <= end)
         .filter(|sub| {
-            if args.whitelist().is_empty() {
-                true
-            } else {
-                sub.text()
-                    .map(|text| args.whitelist().iter().any(|re| re.is_match(text)))
-                    .unwrap_or(false)
-            }
+            let start = sub.timespan().start();
+            !skipped.iter().any(|span| span.contains(start))
         })
         .filter(|sub| {
             if let Dialogue::Ass(ass) = sub.dialogue() {
-                !args.ignore_styled() || !ass.text.is_styled()
+                (!args.ignore_styled() || !ass.text.is_styled())
+                    && !args.max_layer().is_some_and(|max| ass.layer > max)
             } else {
                 true
             }
         })
-        .map(Into::into)
-        .collect()
-}
-
-fn run(args: &Args, multi: MultiProgress) -> Result<()> {
-    trace!(
-        "extracting subtitles form {} file(s)",
-        args.sub_files().len()
-    );
+        .filter(|sub| match sub.speaker() {
+            Some(speaker) => {
+                (args.actors().is_empty() || args.actors().iter().any(|re| re.is_match(speaker)))
+                    && !args.skipped_actors().iter().any(|re| re.is_match(speaker))
+            }
+            None => args.actors().is_empty(),
+        })
+        .filter(|sub| !args.forced_only() || sub.forced())
+        .filter(|sub| !args.skip_forced() || !sub.forced())
+        .filter(|sub| match sub.cps(args.normalizations()) {
+            Some(cps) => {
+                !args.min_cps().is_some_and(|min| cps < min)
+                    && !args.max_cps().is_some_and(|max| cps > max)
+            }
+            None => true,
+        });
 
-    let media_files = if !args.media_files().is_empty() {
-        args.media_files()
+    // Styled subtitles are usually signs, so they are filtered out before joining sentences. The
+    // blacklist and whitelist are matched against the whole sentence.
+    let subs = if args.sentences() {
+        join_sentences(subs.collect())
     } else {
-        trace!("will use subtitle files argument as media files");
-        args.sub_files()
+        subs.collect()
+    };
+    // Splitting after joining leaves exactly one sentence per subtitle
+    let subs = if args.split_sentences() {
+        split_sentences(subs)
+    } else {
+        subs
     };
 
-    if args.sub_files().is_empty() {
-        bail!("no subtitle files specified");
+    let mut subs: Vec<Subtitle> = subs
+        .into_iter()
+        .filter(|sub| {
+            let text = sub
+                .text()
+                .map(|text| apply_normalizations(args.normalizations(), text));
+            passes_filters(
+                args.filter_mode(),
+                args.blacklist(),
+                args.whitelist(),
+                text.as_deref(),
+            )
+        })
+        .collect();
+
+    // The notes and the output of --write-json follow the time order, however the subtitles were
+    // read and merged. The sort is stable, so subtitles with the same timespan keep their order.
+    subs.sort_by_key(|sub| (sub.timespan().start(), sub.timespan().end()));
+    // Only the subtitles that made it through the filters are grouped, so every card has the
+    // full context
+    let subs = match args.context() {
+        Some(size) => group_context(subs, size),
+        None => subs,
+    };
+    let subs = match args.sample() {
+        Some(sample) => sample_subtitles(subs, sample, rng),
+        None => subs,
+    };
+    subs.into_iter().map(Into::into).collect()
+}
+
+/// Keeps only the subtitles that contain one of `words`, and records which of them they contain
+fn keep_target_words(subtitles: &mut [Vec<SubtitleBundle>], words: &HashSet<String>) {
+    let mut kept = 0usize;
+    for subs in subtitles.iter_mut() {
+        subs.retain_mut(|bundle| {
+            let found = bundle
+                .sub()
+                .text()
+                .map(|text| target_words_in(text, words))
+                .unwrap_or_default();
+            if found.is_empty() {
+                return false;
+            }
+            bundle.set_target_words(found);
+            true
+        });
+        kept += subs.len();
     }
+    info!("{} subtitle(s) contain a target word", kept);
+}
 
-    trace!("got {} media file(s)", media_files.len());
-    if media_files.len() != args.sub_files().len() {
-        bail!("the amount of media files must be the same as the amount of subtitle files");
+/// Keeps only the subtitles with at most `max` words that are not known, in their text after
+/// `normalizations`
+fn keep_comprehensible(
+    subtitles: &mut [Vec<SubtitleBundle>],
+    known: &KnownWords,
+    max: usize,
+    normalizations: &[TextNormalization],
+) {
+    let mut kept = 0usize;
+    for subs in subtitles.iter_mut() {
+        subs.retain(|bundle| match bundle.sub().text() {
+            Some(text) => known.unknown_count(&apply_normalizations(normalizations, text)) <= max,
+            None => true,
+        });
+        kept += subs.len();
     }
+    info!("{} subtitle(s) have at most {} unknown word(s)", kept, max);
+}
 
-    let max_file_width = (media_files.len().ilog10() + 1) as usize;
+/// Removes the subtitles that are (nearly) the same as a sentence the user already has a card of,
+/// comparing their text after `normalizations`
+fn skip_known(
+    subtitles: &mut [Vec<SubtitleBundle>],
+    known: &KnownSentences,
+    similarity: f64,
+    normalizations: &[TextNormalization],
+) {
+    let mut removed = 0usize;
+    for subs in subtitles.iter_mut() {
+        let count = subs.len();
+        subs.retain(|bundle| match bundle.sub().text() {
+            Some(text) => {
+                !known.contains_similar(&apply_normalizations(normalizations, text), similarity)
+            }
+            None => true,
+        });
+        removed += count - subs.len();
+    }
+    info!(
+        "skipped {} subtitle(s) similar to one of {} known sentences",
+        removed,
+        known.count()
+    );
+}
 
-    let subtitles = read_subtitles(args)?;
-    let mut subtitles: Vec<Vec<SubtitleBundle>> = subtitles
-        .into_iter()
-        .map(|subs| process_subtitles(args, subs))
-        .collect();
+/// Removes the subtitles of which the normalized text already appeared in an earlier subtitle,
+/// in the same file or an earlier one
+fn dedup_subtitles(subtitles: &mut [Vec<SubtitleBundle>], normalizations: &[TextNormalization]) {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut removed = 0usize;
+    for subs in subtitles.iter_mut() {
+        let count = subs.len();
+        subs.retain(|bundle| match bundle.sub().text() {
+            Some(text) => seen.insert(comparison_key(normalizations, text)),
+            None => true,
+        });
+        removed += count - subs.len();
+    }
+    info!("removed {} duplicate subtitle(s)", removed);
+}
 
-    if subtitles.iter().all(|arr| arr.is_empty()) {
-        warn!("All subtitles were ignored due to filter specified");
+/// Returns the random number generator for `--sample` and `--card-selection=random`, which
+/// chooses the same subtitles every run when `seed` is given
+fn make_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
     }
+}
 
-    let audio_files: Vec<Vec<(Timespan, String)>> = subtitles
-        .iter_mut()
+/// Keeps a random sample of `subs`, in their original order
+fn sample_subtitles(subs: Vec<Subtitle>, sample: Sample, rng: &mut StdRng) -> Vec<Subtitle> {
+    let count = subs.len();
+    let kept = select_cards(count, sample.count(count), CardSelection::Random, rng);
+
+    let mut kept = kept.into_iter().peekable();
+    let subs: Vec<Subtitle> = subs
+        .into_iter()
         .enumerate()
-        .map(|(file_idx, subs)| {
-            let mut audio_files: Vec<(Timespan, String)> = Vec::new();
+        .filter(|(idx, _)| kept.next_if_eq(idx).is_some())
+        .map(|(_, sub)| sub)
+        .collect();
+    trace!("sampled {} of {} subtitles", subs.len(), count);
+    subs
+}
 
-            if subs.is_empty() || !args.gen_audio() {
-                return audio_files;
+/// Returns the sorted indices of the `max` of `count` cards that are kept. `--card-selection=spread`
+/// needs the times of the cards, so it is done by [`spread_cards`] instead.
+fn select_cards(
+    count: usize,
+    max: usize,
+    selection: CardSelection,
+    rng: &mut StdRng,
+) -> Vec<usize> {
+    if count <= max {
+        return (0..count).collect();
+    }
+    match selection {
+        CardSelection::First | CardSelection::Spread => (0..max).collect(),
+        CardSelection::Random => {
+            let mut indices = rand::seq::index::sample(rng, count, max).into_vec();
+            indices.sort_unstable();
+            indices
+        }
+    }
+}
+
+/// Returns the sorted indices of the `max` cards nearest to `max` timestamps spread evenly over
+/// `runtime`, so they cover all of it even where the subtitles are denser. `times` are the middles
+/// of the cards in milliseconds.
+fn spread_cards(times: &[i64], runtime: i64, max: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..times.len()).collect();
+    order.sort_by_key(|&idx| times[idx]);
+    let mut taken = vec![false; order.len()];
+
+    let mut kept = Vec::new();
+    for target in 0..max as i64 {
+        let time = (2 * target + 1) * runtime / (2 * max as i64);
+        let pos = order.partition_point(|&idx| times[idx] < time);
+        // The nearest card on either side of the timestamp that isn't kept yet
+        let before = (0..pos).rev().find(|&pos| !taken[pos]);
+        let after = (pos..order.len()).find(|&pos| !taken[pos]);
+        let nearest = match (before, after) {
+            (Some(before), Some(after)) => {
+                if time - times[order[before]] <= times[order[after]] - time {
+                    before
+                } else {
+                    after
+                }
             }
+            (before, after) => match before.or(after) {
+                Some(nearest) => nearest,
+                None => break,
+            },
+        };
+        taken[nearest] = true;
+        kept.push(order[nearest]);
+    }
+    kept.sort_unstable();
+    kept
+}
 
-            let max_index = subs.len();
-            let max_width: usize = (max_index.ilog10() + 1) as usize;
-            let mut sub_idx = 0usize;
-            let count_before = subs.len();
+/// The runtime of `file`, or the end of its last subtitle if it has no duration, like a subtitle
+/// file
+fn runtime(file: &Path, subs: &[SubtitleBundle]) -> Duration {
+    media_duration(file).unwrap_or_else(|| {
+        let end = subs
+            .iter()
+            .map(|bundle| bundle.sub().timespan().end())
+            .max();
+        Duration::from_millis(end.unwrap_or(Timestamp::MIN).as_millis())
+    })
+}
 
-            for sub in subs {
-                let sub_span = sub.sub().timespan();
-                let sub_span = Timespan::new(
-                    sub_span
-                        .start()
-                        .saturating_sub(args.pad_begin())
-                        .saturating_add(args.shift_audio()),
-                    sub_span
-                        .end()
-                        .saturating_add(args.pad_end())
-                        .saturating_add(args.shift_audio()),
-                );
+/// Keeps at most `max` subtitles over all files, before any media is generated for them.
+/// `runtimes` are the runtimes of the files, over which `--card-selection=spread` spreads the
+/// cards as if the files were played one after another.
+fn limit_cards(
+    subtitles: &mut [Vec<SubtitleBundle>],
+    max: usize,
+    selection: CardSelection,
+    runtimes: &[Duration],
+    rng: &mut StdRng,
+) {
+    let count: usize = subtitles.iter().map(Vec::len).sum();
+    let kept = if selection == CardSelection::Spread && count > max {
+        let mut offset = 0;
+        let mut times = Vec::with_capacity(count);
+        for (subs, runtime) in subtitles.iter().zip(runtimes) {
+            times.extend(subs.iter().map(|bundle| {
+                let span = bundle.sub().timespan();
+                offset + (span.start().as_millis() + span.end().as_millis()) / 2
+            }));
+            offset += runtime.as_millis();
+        }
+        spread_cards(&times, offset, max)
+    } else {
+        select_cards(count, max, selection, rng)
+    };
 
-                if args.join_audio() {
-                    if let Some((span, name)) = audio_files.last_mut() {
-                        if span.end() >= sub_span.start() {
-                            *span = Timespan::new(span.start(), sub_span.end());
+    let mut idx = 0usize;
+    let mut kept = kept.into_iter().peekable();
+    for subs in subtitles.iter_mut() {
+        subs.retain(|_| {
+            let keep = kept.next_if_eq(&idx).is_some();
+            idx += 1;
+            keep
+        });
+    }
+    if count > max {
+        info!("kept {} of {} cards", max, count);
+    }
+}
+
+/// Returns the fraction of `runtime` that is covered by at least one of the timespans
+fn coverage(mut spans: Vec<Timespan>, runtime: Duration) -> f64 {
+    if runtime.as_millis() <= 0 {
+        return 0.0;
+    }
+    spans.sort_by_key(|span| span.start());
+
+    let mut covered = 0;
+    let mut end = Timestamp::MIN;
+    for span in spans {
+        let start = span.start().max(end);
+        if span.end() > start {
+            covered += span.end().as_millis() - start.as_millis();
+            end = span.end();
+        }
+    }
+    (covered as f64 / runtime.as_millis() as f64).min(1.0)
+}
+
+/// Reports how much of the runtime of every media file is covered by the subtitles that are
+/// left, and warns about files below `--min-coverage`
+fn report_coverage(args: &Args, media_files: &[PathBuf], subtitles: &[Vec<SubtitleBundle>]) {
+    for (file, subs) in media_files.iter().zip(subtitles.iter()) {
+        let spans: Vec<Timespan> = subs.iter().map(|bundle| bundle.sub().timespan()).collect();
+        let coverage = coverage(spans, runtime(file, subs));
+        info!(
+            "subtitles cover {:.1}% of \"{}\"",
+            coverage * 100.0,
+            file.to_string_lossy()
+        );
+
+        if let Some(min) = args.min_coverage() {
+            if coverage < min {
+                warn!(
+                    "subtitles only cover {:.1}% of \"{}\", the subtitle stream may only have signs or songs",
+                    coverage * 100.0,
+                    file.to_string_lossy()
+                );
+            }
+        }
+    }
+}
+
+/// Returns until when subtitles of `file` are extracted. A negative `--end` is counted back from
+/// the duration of the container, or from the end of the last subtitle if it doesn't have one.
+fn end_timestamp(args: &Args, file: &Path, subs: &[Subtitle]) -> Timestamp {
+    let Some(before) = args.end_before_eof() else {
+        return args.end();
+    };
+    let runtime = media_duration(file).unwrap_or_else(|| {
+        warn!(
+            "\"{}\" does not have a duration, --end is counted back from its last subtitle",
+            file.to_string_lossy()
+        );
+        let end = subs.iter().map(|sub| sub.timespan().end()).max();
+        Duration::from_millis(end.unwrap_or(Timestamp::MIN).as_millis())
+    });
+    let end = (Timestamp::MIN + runtime).saturating_sub(before);
+    trace!(
+        "extracting subtitles of \"{}\" until {}",
+        file.to_string_lossy(),
+        end
+    );
+    end
+}
+
+/// Transcribes the audio of every subtitle with whisper and marks the subtitles of which the text
+/// differs too much from what was heard, which happens with the wrong subtitle stream or heavily
+/// localized subtitles
+fn verify_speech(
+    args: &Args,
+    model: &Path,
+    media_files: &[PathBuf],
+    subtitles: &mut [Vec<SubtitleBundle>],
+) -> Result<()> {
+    let mut recognizer = SpeechRecognizer::new(model)?;
+    let mut mismatched = 0usize;
+    for (file, subs) in media_files.iter().zip(subtitles.iter_mut()) {
+        if subs.is_empty() {
+            continue;
+        }
+        // Only the audio of the subtitles with text is decoded
+        let (indices, spans): (Vec<usize>, Vec<Timespan>) = subs
+            .iter()
+            .enumerate()
+            .filter(|(_, bundle)| bundle.sub().text().is_some())
+            .map(|(idx, bundle)| {
+                let span = bundle.sub().timespan();
+                let span = Timespan::new(
+                    span.start().saturating_add(args.shift_audio()),
+                    span.end().saturating_add(args.shift_audio()),
+                );
+                (idx, span)
+            })
+            .unzip();
+
+        read_clip_samples(
+            file,
+            args.audio_stream_selector(),
+            SPEECH_RATE,
+            &spans,
+            |idx, samples| {
+                let bundle = &mut subs[indices[idx]];
+                let Some(text) = bundle.sub().text() else {
+                    return Ok(());
+                };
+                if samples.is_empty() {
+                    return Ok(());
+                }
+                let span = bundle.sub().timespan();
+
+                let transcription = recognizer.transcribe(samples)?;
+                let score = similarity(text, &transcription);
+                trace!(
+                    "\"{}\" was heard as \"{}\" ({:.0}% similar)",
+                    text,
+                    transcription,
+                    score * 100.0
+                );
+                let mismatch = score < args.verify_similarity();
+                if mismatch {
+                    warn!(
+                        "the subtitle at {} of \"{}\" does not match its audio: \"{}\" was heard as \"{}\" ({:.0}% similar)",
+                        span.start(),
+                        file.to_string_lossy(),
+                        text,
+                        transcription,
+                        score * 100.0
+                    );
+                    mismatched += 1;
+                }
+                bundle.set_transcription(&transcription, mismatch);
+                Ok(())
+            },
+        )
+        .with_context(|| format!("Failed to verify the speech of \"{}\"", file.to_string_lossy()))?;
+    }
+
+    if mismatched > 0 {
+        warn!(
+            "the text of {} note(s) differs from the speech in their audio, they are tagged with stos::speech-mismatch for review",
+            mismatched
+        );
+    } else {
+        info!("the text of all notes matches the speech in their audio");
+    }
+    Ok(())
+}
+
+/// Returns the timespans of the chapters of `file` of which the title matches `regex`
+fn skipped_chapters(regex: &Regex, file: &Path) -> Result<Vec<Timespan>> {
+    let chapters = read_chapters(file)?;
+    if chapters.is_empty() {
+        warn!(
+            "\"{}\" has no chapters, not skipping any subtitles",
+            file.to_string_lossy()
+        );
+    }
+
+    Ok(chapters
+        .into_iter()
+        .filter(|chapter| regex.is_match(&chapter.title))
+        .map(|chapter| {
+            trace!(
+                "skipping chapter \"{}\" ({} - {})",
+                chapter.title,
+                chapter.span.start(),
+                chapter.span.end()
+            );
+            chapter.span
+        })
+        .collect())
+}
+
+/// Moves the output of a failed job, which may be a partial file, into `dir` so it does not end up
+/// in a deck
+fn quarantine(path: &Path, dir: &Path) -> Result<()> {
+    let Some(name) = path.file_name() else {
+        return Ok(());
+    };
+    if !path.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory \"{}\"", dir.to_string_lossy()))?;
+    let dest = dir.join(name);
+    // With --temp-media, the output may be on another file system than the directory
+    std::fs::rename(path, &dest)
+        .or_else(|_| std::fs::copy(path, &dest).and_then(|_| std::fs::remove_file(path)))
+        .with_context(|| {
+            format!(
+                "Failed to move \"{}\" to \"{}\"",
+                path.to_string_lossy(),
+                dir.to_string_lossy()
+            )
+        })
+}
+
+/// Shifts the subtitles of every file to line up with the speech in its audio. Returns the
+/// offsets, so the other subtitle streams of the files can be shifted as well.
+fn auto_sync(
+    args: &Args,
+    media_files: &[PathBuf],
+    subtitles: &mut [Vec<Subtitle>],
+) -> Result<Vec<Duration>> {
+    let mut offsets = Vec::new();
+    for (file, subs) in media_files.iter().zip(subtitles.iter_mut()) {
+        if subs.is_empty() {
+            offsets.push(Duration::default());
+            continue;
+        }
+        let spans: Vec<Timespan> = subs.iter().map(Subtitle::timespan).collect();
+        let estimate = detect_offset(file, args.audio_stream_selector(), &spans, AUTO_SYNC_RANGE)
+            .with_context(|| format!("Failed to sync \"{}\"", file.to_string_lossy()))?;
+
+        if estimate.after - estimate.before < MIN_SYNC_IMPROVEMENT {
+            info!(
+                "\"{}\": the subtitles seem to be in sync ({:.0}% speech)",
+                file.to_string_lossy(),
+                estimate.before * 100.0
+            );
+            offsets.push(Duration::default());
+            continue;
+        }
+        warn!(
+            "\"{}\": shifting the subtitles by {} ms, {:.0}% instead of {:.0}% of their time has speech",
+            file.to_string_lossy(),
+            estimate.offset.as_millis(),
+            estimate.after * 100.0,
+            estimate.before * 100.0
+        );
+        delay_subtitles(subs, estimate.offset);
+        offsets.push(estimate.offset);
+    }
+    Ok(offsets)
+}
+
+/// The number of notes that refer to the output of a failed job
+fn count_failed(subtitles: &[Vec<SubtitleBundle>], failed: &[PathBuf]) -> usize {
+    subtitles
+        .iter()
+        .flatten()
+        .filter(|bundle| failed.iter().any(|path| bundle.refers_to(path)))
+        .count()
+}
+
+/// Keeps the notes of which some media could not be generated without that media, tagged so they
+/// can be repaired later
+fn keep_without_media(subtitles: &mut [Vec<SubtitleBundle>], failed: &[PathBuf]) {
+    let mut kept = 0usize;
+    for bundle in subtitles.iter_mut().flatten() {
+        bundle.keep_without_missing_media();
+        kept += usize::from(bundle.media_failed());
+    }
+    if kept > 0 {
+        warn!(
+            "{} job(s) failed, kept {} note(s) without their media and moved the partial output to \"{}\"",
+            failed.len(),
+            kept,
+            FAILED_DIR
+        );
+    }
+}
+
+/// Checks that every media file has the streams that the requested media is generated from, so
+/// the problems of all files are reported before any work is done
+fn preflight(args: &Args, media_files: &[PathBuf]) -> Result<()> {
+    let mut checks = Vec::new();
+    if args.gen_audio() {
+        checks.push((libav::media::Type::Audio, args.audio_stream_selector()));
+    }
+    if args.gen_images() {
+        checks.push((libav::media::Type::Video, args.video_stream_selector()));
+    }
+    if checks.is_empty() {
+        return Ok(());
+    }
+
+    let mut problems = Vec::new();
+    for file in media_files {
+        for (medium, selector) in &checks {
+            if let Err(err) = check_stream(file, *medium, selector.clone()) {
+                problems.push(format!("\"{}\": {:#}", file.to_string_lossy(), err));
+            }
+        }
+    }
+    if !problems.is_empty() {
+        bail!(
+            "{} problem(s) with the media files:\n{}",
+            problems.len(),
+            problems.join("\n")
+        );
+    }
+    trace!("all media files have the selected streams");
+    Ok(())
+}
+
+/// Applies the `--on-missing-media` policy to the subtitles that refer to media files that could
+/// not be generated
+fn handle_missing_media(args: &Args, subtitles: &mut [Vec<SubtitleBundle>]) -> Result<()> {
+    let missing = subtitles
+        .iter()
+        .flatten()
+        .filter(|bundle| bundle.has_missing_media())
+        .count();
+    if missing == 0 {
+        return Ok(());
+    }
+
+    match args.on_missing_media() {
+        MissingMediaPolicy::DropField => {
+            for bundle in subtitles.iter_mut().flatten() {
+                bundle.drop_missing_media();
+            }
+            warn!(
+                "removed missing media from {} note(s), see --on-missing-media",
+                missing
+            );
+        }
+        MissingMediaPolicy::DropNote => {
+            for subs in subtitles.iter_mut() {
+                subs.retain(|bundle| !bundle.has_missing_media());
+            }
+            warn!(
+                "dropped {} note(s) of which media is missing, see --on-missing-media",
+                missing
+            );
+        }
+        MissingMediaPolicy::Error => {
+            bail!(
+                "{} note(s) refer to media that could not be generated",
+                missing
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of the subdeck the note of `sub` belongs in according to `--split-deck-by`
+fn subdeck_key(regex: &Regex, sub: &Subtitle) -> Option<String> {
+    let text = sub.text()?;
+    let text = match sub.speaker() {
+        Some(speaker) => format!("{}: {}", speaker, text),
+        None => text.to_string(),
+    };
+    let captures = regex.captures(&text)?;
+    let key = captures.get(1).or(captures.get(0))?.as_str().trim();
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+/// Returns the name of the deck the note of `bundle` goes in if that is not the deck `name`: the
+/// deck the manifest gives for its file and the subdeck of `--split-deck-by`
+fn note_deck(args: &Args, name: &str, bundle: &SubtitleBundle) -> Option<String> {
+    let file_deck = args.file_deck(bundle.file_idx());
+    let key = args
+        .split_deck_by()
+        .and_then(|regex| subdeck_key(regex, bundle.sub()));
+    match (file_deck, key) {
+        (None, None) => None,
+        (file_deck, Some(key)) => Some(format!("{}::{}", file_deck.unwrap_or(name), key)),
+        (Some(file_deck), None) => Some(file_deck.to_string()),
+    }
+}
+
+/// The paths of the packages of `--deck-per-file`, next to `package` and named by `format`. Fails
+/// if two media files would get the same package, which would be overwritten otherwise.
+fn deck_file_paths(package: &Path, format: &str, media_files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut paths = Vec::new();
+    for (file_idx, file) in media_files.iter().enumerate() {
+        let stem = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let path =
+            package.with_file_name(sanitize_filename(&expand_template(format, file_idx, &stem)));
+        if !seen.insert(path.clone()) {
+            bail!(
+                "more than one media file would be written to \"{}\", add %f to --deck-file-format to make the names unique",
+                path.to_string_lossy()
+            );
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Fails if media of `subtitles` has the name of media that is already in the package that is
+/// appended to. That happens when a file is appended again, of which the notes would otherwise
+/// silently keep the media of the earlier run.
+fn check_media_clashes(
+    existing: &ExistingPackage,
+    subtitles: &[Vec<SubtitleBundle>],
+) -> Result<()> {
+    let clash = subtitles
+        .iter()
+        .flatten()
+        .flat_map(|bundle| [bundle.sub_image(), bundle.image(), bundle.audio()])
+        .flatten()
+        .map(media_name)
+        .find(|name| existing.has_media(name));
+    if let Some(name) = clash {
+        bail!(
+            "the package already contains \"{}\", the media file was appended before. Rename it to append it again",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Derives the id of another deck from the id of the deck, so rebuilding the deck with the same
+/// id puts the notes in the same decks
+fn subdeck_id(id: i64, name: &str) -> i64 {
+    id.wrapping_add((fnv1a(FNV_OFFSET, name.as_bytes()) >> 16) as i64)
+}
+
+/// Writes every subtitle of `subs` as a JSON object on a line of its own, flushing after every
+/// line so other tools can read them as they come
+fn write_ndjson<'a, W, I>(mut writer: W, subs: I) -> Result<()>
+where
+    W: std::io::Write,
+    I: Iterator<Item = &'a SubtitleBundle>,
+{
+    for bundle in subs {
+        serde_json::to_writer(&mut writer, bundle)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// The CSS of the ass style that most of `subs` use, read from the header of their subtitle file,
+/// for `--style-css`
+fn dominant_style_css<'a, I>(args: &Args, subs: I) -> Option<String>
+where
+    I: Iterator<Item = &'a SubtitleBundle>,
+{
+    let mut counts: HashMap<(usize, &str), usize> = HashMap::new();
+    for bundle in subs {
+        if let Dialogue::Ass(ass) = bundle.sub().dialogue() {
+            *counts
+                .entry((bundle.file_idx(), ass.style.as_str()))
+                .or_default() += 1;
+        }
+    }
+    let Some(((file_idx, name), _)) = counts
+        .into_iter()
+        .max_by_key(|(key, count)| (*count, std::cmp::Reverse(*key)))
+    else {
+        warn!("not styling the cards, none of their subtitles have an ass style");
+        return None;
+    };
+
+    let file = &args.sub_files()[file_idx];
+    let styles = ass_header(file, args.file_sub_stream_selector(file_idx))
+        .map(|header| parse_styles(&header))
+        .unwrap_or_default();
+    match styles.iter().find(|style| style.name == name) {
+        Some(style) => {
+            trace!("styling the cards like the \"{}\" style", name);
+            Some(style.css())
+        }
+        None => {
+            warn!(
+                "not styling the cards, \"{}\" does not have a \"{}\" style",
+                file.to_string_lossy(),
+                name
+            );
+            None
+        }
+    }
+}
+
+fn write_deck<'a, I>(
+    args: &Args,
+    id: i64,
+    name: &str,
+    existing: Option<&ExistingPackage>,
+    subs: I,
+    path: &Path,
+) -> Result<()>
+where
+    I: Iterator<Item = &'a SubtitleBundle> + Clone,
+{
+    let style_css = if args.style_css() {
+        dominant_style_css(args, subs.clone())
+    } else {
+        None
+    };
+    // The notes are put in their decks by zipping them with `ordered`, so it has to be the order
+    // they were made in
+    let ordered = boosted_first(args, subs.clone());
+    let notes = create_notes(
+        args,
+        existing,
+        style_css.as_deref(),
+        ordered.iter().copied(),
+    )?;
+    trace!("creates {} notes", notes.len());
+
+    let mut deck = match existing {
+        Some(existing) => Deck::new(
+            existing.deck_id(),
+            existing.deck_name(),
+            existing.deck_desc(),
+        ),
+        None => Deck::new(id, name, args.deck_desc()),
+    };
+    trace!("created anki deck");
+
+    if args.info_note() {
+        let deck_id = existing.map_or(id, ExistingPackage::deck_id);
+        let deck_name = existing.map_or(name, ExistingPackage::deck_name);
+        deck.add_note(info_note(
+            args,
+            deck_id,
+            deck_name,
+            notes.len(),
+            existing.and_then(ExistingPackage::info),
+            subs.clone(),
+        )?);
+    }
+
+    let mut subdecks: BTreeMap<String, Deck> = BTreeMap::new();
+    if existing.is_none() {
+        // Without existing notes, there is a note for every subtitle
+        for (note, sub) in notes.into_iter().zip(ordered) {
+            match note_deck(args, name, sub) {
+                Some(name) => subdecks
+                    .entry(name.clone())
+                    .or_insert_with(|| Deck::new(subdeck_id(id, &name), &name, args.deck_desc()))
+                    .add_note(note),
+                None => deck.add_note(note),
+            }
+        }
+        trace!("put notes in {} other deck(s)", subdecks.len());
+    } else {
+        for note in notes {
+            deck.add_note(note);
+        }
+    }
+
+    let assets = subs.flat_map(|sub| {
+        let mut assets = Vec::new();
+        if let Some(sub_image) = sub.sub_image() {
+            assets.push(sub_image);
+        }
+        if let Some(image) = sub.image() {
+            assets.push(image);
+        }
+        if let Some(audio) = sub.audio() {
+            assets.push(audio);
+        }
+        assets.into_iter()
+    });
+
+    let mut media: Vec<&str> = Vec::new();
+    if let Some(existing) = existing {
+        for path in existing.media() {
+            media.push(
+                path.to_str()
+                    .context("Path of extracted media file is not valid unicode")?,
+            );
+        }
+    }
+    media.extend(assets);
+
+    let decks = std::iter::once(deck)
+        .chain(subdecks.into_values())
+        .collect();
+    let mut package = Package::new(decks, media).context("Failed to create anki package")?;
+    trace!("created package");
+
+    if !args.no_deck() {
+        package
+            .write_to_file(path)
+            .with_context(|| format!("Failed to write package to \"{}\"", path.display()))?;
+    } else {
+        trace!("did not write an anki deck because --no-deck was specified");
+    }
+    Ok(())
+}
+
+fn run(args: &Args, multi: MultiProgress) -> Result<()> {
+    trace!(
+        "extracting subtitles form {} file(s)",
+        args.sub_files().len()
+    );
+
+    let media_files = if !args.media_files().is_empty() {
+        args.media_files()
+    } else {
+        trace!("will use subtitle files argument as media files");
+        args.sub_files()
+    };
+
+    if args.sub_files().is_empty() {
+        bail!("no subtitle files specified");
+    }
+
+    trace!("got {} media file(s)", media_files.len());
+    if media_files.len() != args.sub_files().len() {
+        bail!("the amount of media files must be the same as the amount of subtitle files");
+    }
+
+    if let Some(recipe) = args.replayed() {
+        recipe.verify(args)?;
+    }
+
+    if args.dump() {
+        return dump_subtitles(args);
+    }
+
+    preflight(args, media_files)?;
+
+    let existing = match args.append() {
+        Some(path) => {
+            if args.deck_per_file() {
+                bail!("--append cannot be used together with --deck-per-file");
+            }
+            if (0..args.sub_files().len()).any(|file_idx| args.file_deck(file_idx).is_some()) {
+                bail!("--append cannot be used together with a manifest with a deck column");
+            }
+            Some(ExistingPackage::open(path).with_context(|| {
+                format!("Failed to read package \"{}\"", path.to_string_lossy())
+            })?)
+        }
+        None => None,
+    };
+
+    // Checked before any media is generated, so a clash doesn't waste the run
+    let deck_paths = if args.deck_per_file() {
+        deck_file_paths(args.package(), args.deck_file_format(), media_files)?
+    } else {
+        Vec::new()
+    };
+
+    let max_file_width = (media_files.len().ilog10() + 1) as usize;
+
+    // Media of an appended run must not clash with the media that is already in the package
+    let prefixes: Vec<String> = media_files
+        .iter()
+        .map(|file| match (&existing, file.file_stem()) {
+            (Some(_), Some(stem)) => format!("{}_", stem.to_string_lossy()),
+            _ => String::new(),
+        })
+        .collect();
+
+    // The media is only needed to write the package, so it does not have to end up in the working
+    // directory
+    let media_dir = if args.temp_media() {
+        if args.no_deck() {
+            warn!("the media is removed again because of --temp-media and --no-deck");
+        }
+        Some(tempfile::tempdir().context("Failed to create media directory")?)
+    } else {
+        None
+    };
+    let media_path = |name: String| match &media_dir {
+        Some(dir) => dir.path().join(name).to_string_lossy().into_owned(),
+        None => name,
+    };
+
+    let mut subtitles = match args.word_list() {
+        Some(word_list) => {
+            if args.pair().is_some() || !args.extra_sub_stream_selectors().is_empty() {
+                bail!(
+                    "--word-clips cannot be used together with --pair or multiple subtitle streams"
+                );
+            }
+            read_words(args, word_list)?
+        }
+        None => read_subtitles(
+            args,
+            |file_idx| args.file_sub_stream_selector(file_idx),
+            read_limit(args),
+        )?,
+    };
+
+    let sync_offsets = if args.auto_sync() {
+        auto_sync(args, media_files, &mut subtitles)?
+    } else {
+        vec![Duration::default(); media_files.len()]
+    };
+
+    if let Some(lang) = args.ocr_lang() {
+        for subs in subtitles.iter_mut() {
+            let recognized = recognize_bitmaps(subs, lang)?;
+            trace!("recognized the text of {} bitmap subtitle(s)", recognized);
+        }
+    }
+
+    let skipped: Vec<Vec<Timespan>> = match args.skip_chapters() {
+        Some(regex) => media_files
+            .iter()
+            .map(|file| {
+                skipped_chapters(regex, file).with_context(|| {
+                    format!(
+                        "Failed to read chapters from \"{}\"",
+                        file.to_string_lossy()
+                    )
+                })
+            })
+            .collect::<Result<_>>()?,
+        None => vec![Vec::new(); media_files.len()],
+    };
+
+    let ends: Vec<Timestamp> = media_files
+        .iter()
+        .zip(subtitles.iter())
+        .map(|(file, subs)| end_timestamp(args, file, subs))
+        .collect();
+
+    let mut rng = make_rng(args.seed());
+    let mut merged = FileTail::new();
+    let mut subtitles: Vec<Vec<SubtitleBundle>> = subtitles
+        .into_iter()
+        .zip(ends)
+        .zip(skipped.iter())
+        .map(|((subs, end), skipped)| {
+            process_subtitles(args, subs, end, skipped, &mut merged, &mut rng)
+        })
+        .collect();
+
+    if let Some(path) = args.target_words() {
+        let words = read_word_list(path)
+            .with_context(|| format!("Failed to read \"{}\"", path.to_string_lossy()))?;
+        keep_target_words(&mut subtitles, &words);
+    }
+    if let Some(path) = args.known_words() {
+        let known = KnownWords::read(path)
+            .with_context(|| format!("Failed to read \"{}\"", path.to_string_lossy()))?;
+        keep_comprehensible(
+            &mut subtitles,
+            &known,
+            args.unknown_max(),
+            args.normalizations(),
+        );
+    }
+    if let Some(path) = args.known_sentences() {
+        let known = KnownSentences::read(path)
+            .with_context(|| format!("Failed to read \"{}\"", path.to_string_lossy()))?;
+        skip_known(
+            &mut subtitles,
+            &known,
+            args.known_similarity(),
+            args.normalizations(),
+        );
+    }
+    if args.dedup() {
+        dedup_subtitles(&mut subtitles, args.normalizations());
+    }
+    // The cards are limited here and sampled in process_subtitles, before any media job is
+    // generated, so no media is made for the notes that don't end up in the package
+    if let Some(max) = args.max_cards() {
+        let runtimes: Vec<Duration> = media_files
+            .iter()
+            .zip(subtitles.iter())
+            .map(|(file, subs)| runtime(file, subs))
+            .collect();
+        limit_cards(
+            &mut subtitles,
+            max,
+            args.card_selection(),
+            &runtimes,
+            &mut rng,
+        );
+    }
+    report_coverage(args, media_files, &subtitles);
+    if let Some(model) = args.verify_speech() {
+        verify_speech(args, model, media_files, &mut subtitles)?;
+    }
+
+    if let Some(note_lang) = args.note_lang() {
+        for (file_idx, subs) in subtitles.iter_mut().enumerate() {
+            let file = &args.sub_files()[file_idx];
+            let lang = match note_lang {
+                NoteLang::Code(code) => Some(code.clone()),
+                NoteLang::Auto => subtitle_language(file, args.file_sub_stream_selector(file_idx)),
+            };
+            match lang {
+                Some(lang) => subs.iter_mut().for_each(|bundle| {
+                    bundle.set_lang(&lang);
+                }),
+                None => warn!(
+                    "not tagging the notes of \"{}\" with a language, the subtitle stream has none",
+                    file.to_string_lossy()
+                ),
+            }
+        }
+    }
+
+    if args.reading_field() {
+        let analyzer = ReadingAnalyzer::new()?;
+        for bundle in subtitles.iter_mut().flatten() {
+            let Some(text) = bundle.sub().text() else {
+                continue;
+            };
+            let reading = analyzer.reading(text)?;
+            bundle.set_reading(&reading);
+        }
+    }
+
+    if args.chinese_fields() {
+        let analyzer = ChineseAnalyzer::new()?;
+        for bundle in subtitles.iter_mut().flatten() {
+            let Some(text) = bundle.sub().text() else {
+                continue;
+            };
+            let segmented = analyzer.segment(text);
+            let pinyin = analyzer.pinyin(text);
+            bundle.set_segmented(&segmented).set_pinyin(&pinyin);
+        }
+    }
+
+    if let Some(path) = args.pitch_accent() {
+        let dict = AccentDictionary::read(path)
+            .with_context(|| format!("Failed to read \"{}\"", path.to_string_lossy()))?;
+        for bundle in subtitles.iter_mut().flatten() {
+            let Some(text) = bundle.sub().text() else {
+                continue;
+            };
+            let annotated = dict.annotate(text);
+            bundle.set_pitch_accent(&annotated);
+        }
+    }
+
+    if let Some(path) = args.frequency_list() {
+        let list = FrequencyList::read(path)
+            .with_context(|| format!("Failed to read \"{}\"", path.to_string_lossy()))?;
+        for bundle in subtitles.iter_mut().flatten() {
+            if let Some(difficulty) = bundle.sub().text().and_then(|text| list.difficulty(text)) {
+                bundle.set_difficulty(difficulty);
+            }
+        }
+    }
+
+    if let Some(selector) = args.translation_stream_selector() {
+        trace!("reading translations");
+        let mut translations = read_subtitles(args, |_| selector.clone(), None)?;
+        for ((subs, translations), offset) in subtitles
+            .iter_mut()
+            .zip(translations.iter_mut())
+            .zip(sync_offsets.iter())
+        {
+            delay_subtitles(translations, *offset);
+            attach_translations(subs, translations);
+        }
+    }
+
+    for selector in args.extra_sub_stream_selectors() {
+        trace!("reading aligned subtitles");
+        let mut aligned = read_subtitles(args, |_| selector.clone(), None)?;
+        for ((subs, aligned), offset) in subtitles
+            .iter_mut()
+            .zip(aligned.iter_mut())
+            .zip(sync_offsets.iter())
+        {
+            delay_subtitles(aligned, *offset);
+            attach_aligned(subs, aligned);
+        }
+    }
+
+    if subtitles.iter().all(|arr| arr.is_empty()) {
+        warn!("All subtitles were ignored due to filter specified");
+    }
+
+    // The clips of context cards with an audio separator are made from the clips of their lines,
+    // the parts. The parts are empty for all other clips.
+    let mut audio_files: Vec<Vec<(Timespan, String, Vec<Timespan>)>> = subtitles
+        .iter_mut()
+        .enumerate()
+        .map(|(file_idx, subs)| {
+            let mut audio_files: Vec<(Timespan, String, Vec<Timespan>)> = Vec::new();
+
+            if subs.is_empty() || !args.gen_audio() {
+                return audio_files;
+            }
+
+            let max_index = subs.len();
+            let max_width: usize = (max_index.ilog10() + 1) as usize;
+            let mut sub_idx = 0usize;
+            let count_before = subs.len();
+
+            for sub in subs {
+                let sub_span = sub.sub().timespan();
+                let padded = Timespan::new(
+                    sub_span.start().saturating_sub(args.pad_begin()),
+                    sub_span.end().saturating_add(args.pad_end()),
+                );
+                let sub_span = Timespan::new(
+                    padded.start().saturating_add(args.shift_audio()),
+                    padded.end().saturating_add(args.shift_audio()),
+                );
+
+                let parts: Vec<Timespan> = match args.audio_separator() {
+                    Some(_) if sub.sub().parts().len() > 1 => {
+                        let last = sub.sub().parts().len() - 1;
+                        sub.sub()
+                            .parts()
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, part)| {
+                                // The padding only goes before the first and after the last part,
+                                // the shift goes on all of them
+                                let start = if idx == 0 {
+                                    padded.start()
+                                } else {
+                                    part.start()
+                                };
+                                let end = if idx == last {
+                                    padded.end()
+                                } else {
+                                    part.end()
+                                };
+                                Timespan::new(
+                                    start.saturating_add(args.shift_audio()),
+                                    end.saturating_add(args.shift_audio()),
+                                )
+                            })
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+
+                if args.join_audio() && parts.is_empty() {
+                    if let Some((span, name, prev_parts)) = audio_files.last_mut() {
+                        if prev_parts.is_empty() && span.gap_to(&sub_span).as_millis() == 0 {
+                            *span = span.union(&sub_span);
                             sub.set_audio(name);
                             continue;
                         }
                     }
                 }
 
-                let file_name = format!(
-                    "audio_{:0max_file_width$}_{:0max_width$}.mka",
-                    file_idx, sub_idx
-                );
+                let file_name = media_path(sanitize_filename(&format!(
+                    "{}audio_{:0max_file_width$}_{:0max_width$}.mka",
+                    prefixes[file_idx], file_idx, sub_idx
+                )));
                 sub.set_audio(&file_name);
-                audio_files.push((sub_span, file_name));
+                audio_files.push((sub_span, file_name, parts));
                 sub_idx += 1;
             }
-            trace!(
-                "joined {} audio files into {}",
-                count_before,
-                audio_files.len()
-            );
-            audio_files
-        })
-        .collect();
+            trace!(
+                "joined {} audio files into {}",
+                count_before,
+                audio_files.len()
+            );
+            audio_files
+        })
+        .collect();
+
+    if args.trim_silence() {
+        let mut trimmed = 0i64;
+        for (file, clips) in media_files.iter().zip(audio_files.iter_mut()) {
+            let spans: Vec<Timespan> = clips
+                .iter()
+                .filter(|(_, _, parts)| parts.is_empty())
+                .map(|(span, _, _)| *span)
+                .collect();
+            if spans.is_empty() {
+                continue;
+            }
+            let tightened =
+                trim_silence(file, args.audio_stream_selector(), &spans).with_context(|| {
+                    format!("Failed to trim silence of \"{}\"", file.to_string_lossy())
+                })?;
+            for ((span, _, _), tightened) in clips
+                .iter_mut()
+                .filter(|(_, _, parts)| parts.is_empty())
+                .zip(tightened)
+            {
+                trimmed += span.duration().as_millis() - tightened.duration().as_millis();
+                *span = tightened;
+            }
+        }
+        info!(
+            "trimmed {:.1} second(s) of silence from the audio clips",
+            trimmed as f64 / 1000.0
+        );
+    }
+
+    if args.probe_audio() {
+        let mut empty = 0usize;
+        for (file, clips) in media_files.iter().zip(audio_files.iter()) {
+            if clips.is_empty() {
+                continue;
+            }
+            let probed = probe_audio(
+                file,
+                clips.iter().map(|(span, _, _)| *span),
+                args.audio_stream_selector(),
+            )
+            .with_context(|| format!("Failed to probe audio of \"{}\"", file.to_string_lossy()))?;
+
+            for ((span, name, _), has_data) in clips.iter().zip(probed) {
+                if !has_data {
+                    warn!(
+                        "{} ({} - {}) of \"{}\" will be empty, the audio stream has no data there",
+                        name,
+                        span.start(),
+                        span.end(),
+                        file.to_string_lossy()
+                    );
+                    empty += 1;
+                }
+            }
+        }
+        if empty > 0 {
+            warn!("{} audio clip(s) would be empty", empty);
+        } else {
+            info!("all audio clips contain audio data");
+        }
+    }
 
     let mut jobs: Vec<Job> = Vec::new();
 
@@ -319,23 +2124,86 @@ fn run(args: &Args, multi: MultiProgress) -> Result<()> {
         let max_width: usize = (max_index.ilog10() + 1) as usize;
 
         for (sub_idx, sub) in subs.iter_mut().enumerate() {
-            if let Dialogue::Bitmap(_) = sub.sub().dialogue() {
-                sub.set_sub_image(&format!(
-                    "sub_{:0max_file_width$}_{:0max_width$}.jpg",
-                    file_idx, sub_idx
-                ));
+            sub.set_index(file_idx, sub_idx);
+
+            match sub.sub().dialogue() {
+                Dialogue::Bitmap(_) => {
+                    sub.set_sub_image(&media_path(sanitize_filename(&format!(
+                        "{}sub_{:0max_file_width$}_{:0max_width$}.jpg",
+                        prefixes[file_idx], file_idx, sub_idx
+                    ))));
+                }
+                Dialogue::Ass(_) if args.render_ass().is_some() => {
+                    sub.set_sub_image(&media_path(sanitize_filename(&format!(
+                        "{}sub_{:0max_file_width$}_{:0max_width$}.png",
+                        prefixes[file_idx], file_idx, sub_idx
+                    ))));
+                }
+                _ => {}
             }
 
             if args.gen_images() {
-                sub.set_image(&format!(
-                    "image_{:0max_file_width$}_{:0max_width$}.jpg",
-                    file_idx, sub_idx
-                ));
+                sub.set_image(&media_path(sanitize_filename(&format!(
+                    "{}image_{:0max_file_width$}_{:0max_width$}.jpg",
+                    prefixes[file_idx], file_idx, sub_idx
+                ))));
+            }
+        }
+    }
+
+    // Checked before any media is generated, so the media of the package is never replaced
+    if let Some(existing) = &existing {
+        check_media_clashes(existing, &subtitles)?;
+    }
+
+    // libass only loads fonts from a directory, so the fonts attached to the files are extracted
+    // before rendering
+    let fonts_tempdir = match (args.render_ass(), args.fonts_dir()) {
+        (Some(_), None) => Some(tempfile::tempdir().context("Failed to create fonts directory")?),
+        _ => None,
+    };
+    let fonts_dir = args
+        .fonts_dir()
+        .map(PathBuf::as_path)
+        .or(fonts_tempdir.as_ref().map(|dir| dir.path()));
+    if let Some(dir) = fonts_dir {
+        std::fs::create_dir_all(dir).with_context(|| {
+            format!(
+                "Failed to create fonts directory \"{}\"",
+                dir.to_string_lossy()
+            )
+        })?;
+
+        let mut fonts = 0usize;
+        let mut seen: Vec<&PathBuf> = Vec::new();
+        for file in args.sub_files().iter().chain(media_files.iter()) {
+            if seen.contains(&file) {
+                continue;
+            }
+            seen.push(file);
+            match extract_fonts(file, dir) {
+                Ok(extracted) => fonts += extracted.len(),
+                Err(err) => trace!(
+                    "not extracting fonts from \"{}\": {:?}",
+                    file.to_string_lossy(),
+                    err
+                ),
             }
         }
+        info!(
+            "extracted {} font(s) to \"{}\"",
+            fonts,
+            dir.to_string_lossy()
+        );
     }
 
+    let budget = args
+        .memory_budget()
+        .map(FrameBudget::new)
+        .transpose()?
+        .map(Arc::new);
     let (sender, receiver) = unbounded();
+    let sender = FrameSender::new(sender, budget.clone());
 
     let style = ProgressStyle::with_template(
         "{msg:9!} [{elapsed_precise}] {bar:50.cyan/blue} {percent:>4}% [eta {eta:<}]",
@@ -345,29 +2213,114 @@ fn run(args: &Args, multi: MultiProgress) -> Result<()> {
     let audio_pb = multi.add(ProgressBar::new(0));
     audio_pb.set_message("audio");
     audio_pb.set_style(style.clone());
+    let render_pb = multi.add(ProgressBar::new(0));
+    render_pb.set_message("subtitles");
+    render_pb.set_style(style.clone());
 
     for (idx, (sender, (file, subs))) in std::iter::repeat(sender)
         .zip(media_files.iter().zip(subtitles.iter()))
         .enumerate()
     {
         if args.gen_audio() {
-            let commands = generate_audio_commands(
-                file,
-                audio_files[idx].iter().map(|(a, b)| (*a, b.as_ref())),
-                args.audio_stream_selector(),
-            )?;
-            audio_pb.inc_length(commands.len().try_into().unwrap());
+            let (separated, whole): (Vec<_>, Vec<_>) = audio_files[idx]
+                .iter()
+                .partition(|(_, _, parts)| !parts.is_empty());
+            let stream_idx = audio_stream_index(file, args.audio_stream_selector())?;
+            let filters = AudioFilters {
+                normalize: args.normalize_audio(),
+                fade: args.fade(),
+            };
+            if !filters.is_empty() {
+                let clips: Vec<(Timespan, &str)> = whole
+                    .iter()
+                    .map(|(span, name, _)| (*span, name.as_str()))
+                    .collect();
+                let commands = generate_filtered_audio_commands(file, &clips, stream_idx, filters);
+                audio_pb.inc_length(commands.len().try_into().unwrap());
+
+                for (command, clips) in commands.into_iter().zip(clips.chunks(CLIPS_PER_JOB)) {
+                    jobs.push(Job::Command {
+                        pb: audio_pb.clone(),
+                        command,
+                        paths: clips.iter().map(|(_, name)| *name).collect(),
+                    });
+                }
+            } else {
+                audio_pb.inc_length(whole.len().try_into().unwrap());
+
+                for clips in whole.chunks(CLIPS_PER_JOB) {
+                    jobs.push(Job::ExtractAudio {
+                        pb: audio_pb.clone(),
+                        path: file,
+                        stream_idx,
+                        clips: clips
+                            .iter()
+                            .map(|(span, name, _)| (*span, name.as_str()))
+                            .collect(),
+                    });
+                }
+            }
 
-            for command in commands {
-                jobs.push(Job::Command {
-                    pb: audio_pb.clone(),
-                    command,
-                });
+            if let Some(separator) = args.audio_separator() {
+                let commands = generate_separated_audio_commands(
+                    file,
+                    separated
+                        .iter()
+                        .map(|(_, name, parts)| (parts.as_slice(), name.as_str())),
+                    args.audio_stream_selector(),
+                    separator,
+                    args.audio_separator_length(),
+                    filters,
+                )?;
+                audio_pb.inc_length(commands.len().try_into().unwrap());
+
+                for (command, (_, path, _)) in commands.into_iter().zip(separated) {
+                    jobs.push(Job::Command {
+                        pb: audio_pb.clone(),
+                        command,
+                        paths: vec![path.as_str()],
+                    });
+                }
             }
         }
 
         //jobs.extend(tmp.into_iter().map(Into::into));
 
+        if let Some(size) = args.render_ass() {
+            let points: Vec<(Timestamp, &str)> = subs
+                .iter()
+                .filter(|bundle| matches!(bundle.sub().dialogue(), Dialogue::Ass(_)))
+                .filter_map(|bundle| {
+                    let span = bundle.sub().timespan();
+                    let middle =
+                        span.start() + Duration::from_millis(span.duration().as_millis() / 2);
+                    bundle.sub_image().map(|name| (middle, name))
+                })
+                .collect();
+            let commands = render_subtitle_commands(
+                &args.sub_files()[idx],
+                points.iter().copied(),
+                args.file_sub_stream_selector(idx),
+                size,
+                fonts_dir,
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to render subtitles of \"{}\"",
+                    args.sub_files()[idx].to_string_lossy()
+                )
+            })?;
+            render_pb.inc_length(commands.len().try_into().unwrap());
+
+            for (command, (_, path)) in commands.into_iter().zip(points) {
+                jobs.push(Job::RenderSubtitle {
+                    pb: render_pb.clone(),
+                    command,
+                    path,
+                });
+            }
+        }
+
         if args.gen_images() {
             let image_pb = multi.add(ProgressBar::new(subs.len().try_into().unwrap()));
             image_pb.set_style(style.clone());
@@ -400,156 +2353,906 @@ fn run(args: &Args, multi: MultiProgress) -> Result<()> {
     }
 
     trace!("generated {} jobs", jobs.len());
+    // Sorting is stable, so jobs of the same kind stay in file order
+    jobs.sort_by_key(|job| job.priority(args.schedule()));
 
     if !args.no_media() {
-        std::thread::scope(|s| -> Result<()> {
+        let failed = std::thread::scope(|s| -> Result<Vec<PathBuf>> {
             std::iter::repeat(receiver).take(5).for_each(|receiver| {
-                s.spawn(|| match write_images(receiver) {
-                    Ok(_) => {
-                        trace!("converted images");
-                    }
-                    Err(err) => {
-                        error!("failed to convert images: {:?}", err);
+                s.spawn(|| {
+                    match write_images(receiver, args.subtitle_region(), budget.as_deref()) {
+                        Ok(_) => {
+                            trace!("converted images");
+                        }
+                        Err(err) => {
+                            error!("failed to convert images: {:?}", err);
+                        }
                     }
                 });
             });
 
-            jobs.into_par_iter()
-                .map(Job::execute)
-                .collect::<Result<_>>()
+            let failures: Vec<(Vec<PathBuf>, anyhow::Error)> = jobs
+                .into_iter()
+                // Bridged, so the idle threads take the jobs one by one in the sorted order.
+                // into_par_iter would split the jobs into contiguous halves for the threads.
+                .par_bridge()
+                .filter_map(|job| {
+                    let outputs = job.outputs();
+                    job.execute().err().map(|err| (outputs, err))
+                })
+                .collect();
+
+            let mut failed = Vec::new();
+            for (outputs, err) in failures {
+                // The images that an extraction did not write are missing, which the notes
+                // without media handle like any other failure
+                if outputs.is_empty() && !args.notes_without_media() {
+                    return Err(err);
+                }
+                error!("{:?}", err);
+                for output in outputs {
+                    quarantine(&output, Path::new(FAILED_DIR))?;
+                    failed.push(output);
+                }
+            }
+            Ok(failed)
         })?;
+
+        if let Some(spilled) = budget.as_ref().map(|budget| budget.spilled()) {
+            if spilled > 0 {
+                info!("spilled {} image(s) to disk, see --memory-budget", spilled);
+            }
+        }
+        if args.notes_without_media() {
+            keep_without_media(&mut subtitles, &failed);
+        } else if !failed.is_empty() {
+            // The output was moved away, so --on-missing-media decides about these notes
+            warn!(
+                "{} job(s) failed for {} note(s), moved their partial output to \"{}\"",
+                failed.len(),
+                count_failed(&subtitles, &failed),
+                FAILED_DIR
+            );
+        }
+        handle_missing_media(args, &mut subtitles)?;
     } else {
         trace!("not executing jobs because --no-media is specified");
+        // Nothing is left to wait for, so the subtitles don't have to wait for the deck
+        if args.write_ndjson() {
+            write_ndjson(std::io::stdout().lock(), subtitles.iter().flatten())
+                .context("Failed to write subtitles as JSON")?;
+        }
+    }
+
+    audio_pb.finish_with_message("done");
+    render_pb.finish_with_message("done");
+
+    trace!("executed all jobs");
+
+    if let Some(path) = args.contact_sheet() {
+        if args.no_media() || !args.gen_images() {
+            warn!("not writing a contact sheet because no images were generated");
+        } else {
+            write_contact_sheet(
+                path,
+                subtitles
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .filter_map(|(idx, bundle)| {
+                        bundle
+                            .image()
+                            .map(|image| (idx, bundle.sub().timespan().start(), image))
+                    }),
+            )?;
+        }
+    }
+
+    if let Some(path) = args.playlist() {
+        write_playlist(
+            path,
+            media_files
+                .iter()
+                .zip(subtitles.iter())
+                .flat_map(|(file, subs)| {
+                    subs.iter().map(move |bundle| {
+                        (
+                            file.as_path(),
+                            bundle.sub().timespan(),
+                            bundle.sub().text().unwrap_or_default(),
+                        )
+                    })
+                }),
+        )?;
+        info!("wrote playlist to \"{}\"", path.to_string_lossy());
+    }
+
+    if args.deck_per_file() {
+        for (file_idx, ((file, subs), path)) in media_files
+            .iter()
+            .zip(subtitles.iter())
+            .zip(&deck_paths)
+            .enumerate()
+        {
+            let stem = file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            write_deck(
+                args,
+                args.deck_id().wrapping_add(file_idx as i64),
+                &format!("{}::{}", args.deck_name(), stem),
+                None,
+                subs.iter(),
+                path,
+            )?;
+        }
+    } else {
+        write_deck(
+            args,
+            args.deck_id(),
+            args.deck_name(),
+            existing.as_ref(),
+            subtitles.iter().flat_map(|subs| subs.iter()),
+            args.append().unwrap_or(args.package()),
+        )?;
+    }
+
+    if let Some(path) = args.recipe() {
+        Recipe::new(args)?.write(path)?;
+        info!("wrote recipe to \"{}\"", path.to_string_lossy());
+    }
+
+    if args.play_sample() > 0 {
+        if args.no_media() || !args.gen_audio() {
+            warn!("not playing samples because no audio clips were generated");
+        } else {
+            play_samples(
+                args.player(),
+                audio_files
+                    .iter()
+                    .flat_map(|files| files.iter().map(|(_, name, _)| name.as_str())),
+                args.play_sample(),
+            )?;
+        }
+    }
+
+    if args.write_json() {
+        let serialized = serde_json::to_string(&subtitles)?;
+        print!("{}", serialized);
+    }
+
+    if args.write_ndjson() && !args.no_media() {
+        write_ndjson(std::io::stdout().lock(), subtitles.iter().flatten())
+            .context("Failed to write subtitles as JSON")?;
+    }
+
+    //read subtitles
+    //filter/transform subtitles
+    //generate media
+    //generate deck
+    Ok(())
+}
+
+/// Decides how many jobs run at a time, with the reason for the choice. The ffmpeg commands run
+/// in their own processes and take up little memory, so they get a job per logical core. The
+/// images are decoded in-process, so when they are extracted the jobs are also limited by the
+/// available memory, unless `--memory-budget` keeps it in check already.
+fn job_plan(args: &Args, cores: usize, available_memory: Option<u64>) -> (usize, String) {
+    if let Some(job_count) = args.job_count() {
+        return (job_count, "set with --jobs".to_string());
+    }
+    let extracts_images = args.gen_images() && !args.no_media();
+    if let Some(memory) =
+        available_memory.filter(|_| extracts_images && args.memory_budget().is_none())
+    {
+        let fits = usize::try_from(memory / EXTRACTION_MEMORY)
+            .unwrap_or(usize::MAX)
+            .max(1);
+        if fits < cores {
+            return (
+                fits,
+                format!(
+                    "image extraction is limited by the {} MiB of available memory",
+                    memory / (1024 * 1024)
+                ),
+            );
+        }
+    }
+    (cores, "one per logical core".to_string())
+}
+
+fn main() -> Result<()> {
+    setup_panic!();
+
+    let args = Args::parse_from_env()?;
+
+    let logger = pretty_env_logger::formatted_builder()
+        .filter_level(args.verbosity())
+        .build();
+
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    let (job_count, reason) = job_plan(&args, cores, available_memory());
+    ThreadPoolBuilder::new()
+        .num_threads(job_count)
+        .build_global()
+        .context("failed to initialize thread pool")?;
+
+    let multi = MultiProgress::new();
+    LogWrapper::new(multi.clone(), logger).try_init().unwrap();
+    trace!("initialized logger");
+    // The plan explains why a run is faster or slower than the number of cores suggests
+    warn!("running {} job(s) at a time, {}", job_count, reason);
+    //execute
+
+    libav::init().context("Failed to initialize libav")?;
+
+    run(&args, multi.clone())?;
+    /*
+    if let Err(error) = run() {
+        //print pretty error
+    }*/
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ass::DialogueEvent;
+    use crate::time::{Duration, Timespan, Timestamp};
+    use assert_cmd::prelude::*;
+    use serde::Deserialize;
+    use std::process::Command;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize)]
+    enum Dialogue {
+        Text(String),
+        Ass(DialogueEvent),
+        Bitmap(String),
+    }
+
+    #[derive(Deserialize)]
+    struct Subtitle {
+        pub timespan: Timespan,
+        pub diag: Dialogue,
+    }
+
+    #[derive(Deserialize)]
+    struct SubtitleBundle {
+        pub sub: Subtitle,
+        pub sub_image: Option<String>,
+        pub audio: Option<String>,
+        pub image: Option<String>,
+        pub translation: Option<String>,
+        pub file_idx: usize,
+        pub sub_idx: usize,
+    }
+
+    #[test]
+    fn boost_with_split_deck() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let deck = dir.path().join("deck.apkg");
+        Command::cargo_bin("stos")?
+            .arg("tests/media/actors.ass")
+            .arg("--no-media")
+            .arg("--split-deck-by")
+            .arg("^(\\w+):")
+            .arg("--boost")
+            .arg("Hi")
+            .arg("-o")
+            .arg(&deck)
+            .assert()
+            .success();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&deck)?)?;
+        let collection = dir.path().join("collection.anki2");
+        std::io::copy(
+            &mut archive.by_name("collection.anki2")?,
+            &mut std::fs::File::create(&collection)?,
+        )?;
+        let conn = rusqlite::Connection::open(&collection)?;
+        let decks: String = conn.query_row("SELECT decks FROM col", [], |row| row.get(0))?;
+        let decks: std::collections::HashMap<String, serde_json::Value> =
+            serde_json::from_str(&decks)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT notes.flds, cards.did FROM notes JOIN cards ON cards.nid = notes.id",
+        )?;
+        let notes = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let notes: Vec<(String, String)> = notes
+            .into_iter()
+            .map(|(fields, did)| {
+                let text = fields.split('\x1f').nth(3).unwrap_or_default().to_string();
+                let deck = decks[&did.to_string()]["name"].as_str().unwrap_or_default();
+                (
+                    text,
+                    deck.rsplit("::").next().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+        assert!(notes.contains(&("Hi Alice".to_string(), "Bob".to_string())));
+        assert!(notes.contains(&("Long ago".to_string(), "Narrator".to_string())));
+        assert!(notes.contains(&("Hello".to_string(), "Alice".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn offset_one_file() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/sub.srt")
+            .arg("tests/media/sub.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .arg("--offset=2=1500")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        let first = subs[0][0].sub.timespan;
+        let second = subs[1][0].sub.timespan;
+        assert_eq!(first.start(), Timestamp::from_millis(0));
+        assert_eq!(second.start(), Timestamp::from_millis(1500));
+        assert_eq!(second.end(), first.end() + Duration::from_millis(1500));
+        Ok(())
+    }
+
+    #[test]
+    fn end_timestamp() -> TestResult {
+        let args = |end: &[&str]| {
+            let raw_args = ["stos", "tests/media/sub.srt"].iter().chain(end);
+            super::Args::parse_from_args(raw_args.map(Into::into).collect())
+        };
+        let subs = vec![crate::subtitle::Subtitle::new(
+            Timespan::new(Timestamp::from_millis(0), Timestamp::from_millis(10_000)),
+            crate::subtitle::Dialogue::Text("Hi".to_string()),
+        )];
+        let end = |args: &super::Args, file: &str| {
+            super::end_timestamp(args, std::path::Path::new(file), &subs)
+        };
+
+        // Without a duration, a negative --end is counted back from the last subtitle
+        let missing = "tests/media/doesnt_exist.mkv";
+        assert_eq!(
+            end(&args(&["--end=-2"])?, missing),
+            Timestamp::from_millis(8000)
+        );
+        assert_eq!(end(&args(&["--end=-20"])?, missing), Timestamp::MIN);
+        // The last --end wins
+        assert_eq!(
+            end(&args(&["--end=-2", "--end=5"])?, missing),
+            Timestamp::from_millis(5000)
+        );
+        assert_eq!(
+            end(&args(&["--end=5", "--end=-2"])?, missing),
+            Timestamp::from_millis(8000)
+        );
+
+        let audio = "tests/media/1000hz.mp3";
+        let duration = crate::util::media_duration(audio).ok_or("no duration")?;
+        assert_eq!(
+            end(&args(&["--end=-1"])?, audio),
+            (Timestamp::MIN + duration).saturating_sub(Duration::from_millis(1000))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn end_before_eof() -> TestResult {
+        let run = |end: &[&str]| -> Result<usize, Box<dyn std::error::Error>> {
+            let out = Command::cargo_bin("stos")?
+                .arg("tests/media/mergable_sub.srt")
+                .arg("--no-deck")
+                .arg("--no-media")
+                .arg("--write-json")
+                .args(end)
+                .assert()
+                .success();
+            let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+            let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+            Ok(subs[0].len())
+        };
+
+        // The last subtitle starts less than a second before the end of the file
+        assert_eq!(run(&["--end=-1"])?, 3);
+        assert_eq!(run(&["--end=-1", "--end=5"])?, 2);
+        assert_eq!(run(&["--end=5", "--end=-1"])?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn dedup() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/sub.srt")
+            .arg("tests/media/mergable_sub.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .arg("--dedup")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs[0].len(), 1);
+        assert_eq!(subs[1].len(), 1);
+        assert_eq!(subs[1][0].sub.diag, Dialogue::Text("Something".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn write_json_order() -> TestResult {
+        let run = || -> Result<String, Box<dyn std::error::Error>> {
+            let out = Command::cargo_bin("stos")?
+                .arg("tests/media/mergable_sub.srt")
+                .arg("tests/media/sub.srt")
+                .arg("--no-deck")
+                .arg("--no-media")
+                .arg("--write-json")
+                .arg("-j4")
+                .assert()
+                .success();
+            Ok(String::from_utf8(out.get_output().stdout.clone())?)
+        };
+        let stdout = run()?;
+        assert_eq!(stdout, run()?);
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs.len(), 2);
+        for (file_idx, subs) in subs.iter().enumerate() {
+            assert!(subs.iter().all(|bundle| bundle.file_idx == file_idx));
+            assert!(subs
+                .windows(2)
+                .all(|pair| pair[0].sub.timespan.start() <= pair[1].sub.timespan.start()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_by_text() {
+        let sub = |start: u32, text: &str| {
+            crate::subtitle::Subtitle::new(
+                Timespan::new(
+                    Timestamp::from_millis(start),
+                    Timestamp::from_millis(start + 1000),
+                ),
+                crate::subtitle::Dialogue::Ass(
+                    format!("0,0,Default,,0,0,0,,{}", text).parse().unwrap(),
+                ),
+            )
+        };
+        let subs = || vec![sub(0, "Hello!"), sub(1000, "{\\i1}hello{\\i0}")];
+        let max_dist = crate::time::Duration::from_millis(250);
+
+        assert_eq!(
+            super::merge_overlapping(
+                subs().into_iter(),
+                max_dist,
+                super::MergeKey::Dialogue,
+                &[],
+                None
+            )
+            .len(),
+            2
+        );
+        let merged = super::merge_overlapping(
+            subs().into_iter(),
+            max_dist,
+            super::MergeKey::Text,
+            &[],
+            None,
+        );
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].timespan().end(), Timestamp::from_millis(2000));
+    }
+
+    #[test]
+    fn merge_across_files() -> TestResult {
+        let run =
+            |files: [&str; 2]| -> Result<Vec<Vec<SubtitleBundle>>, Box<dyn std::error::Error>> {
+                let out = Command::cargo_bin("stos")?
+                    .args(files)
+                    .arg("--no-deck")
+                    .arg("--no-media")
+                    .arg("--write-json")
+                    .arg("--merge-across-files")
+                    .assert()
+                    .success();
+                let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+                Ok(serde_json::from_str(&stdout)?)
+            };
+
+        // The line at the end of the first file continues at the start of the second
+        let subs = run(["tests/media/sub.srt", "tests/media/mergable_sub.srt"])?;
+        assert_eq!(subs[0].len(), 1);
+        assert_eq!(subs[1].len(), 1);
+        assert_eq!(subs[1][0].sub.diag, Dialogue::Text("Something".to_string()));
+
+        // A line that is repeated away from the split is kept
+        let subs = run(["tests/media/mergable_sub.srt", "tests/media/sub.srt"])?;
+        assert_eq!(subs[0].len(), 2);
+        assert_eq!(subs[1].len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn continue_at_file_split() {
+        let sub = |start: u32, end: u32, text: &str| {
+            crate::subtitle::Subtitle::new(
+                Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end)),
+                crate::subtitle::Dialogue::Text(text.to_string()),
+            )
+        };
+        let max_dist = crate::time::Duration::from_millis(250);
+        let mut tail = super::FileTail::new();
+        let merge = |subs: Vec<crate::subtitle::Subtitle>, tail: &mut super::FileTail| {
+            let merged = super::merge_overlapping(
+                subs.into_iter(),
+                max_dist,
+                super::MergeKey::Text,
+                &[],
+                Some(tail),
+            );
+            merged
+                .iter()
+                .filter_map(|sub| sub.text().map(str::to_string))
+                .collect::<Vec<_>>()
+        };
+
+        merge(
+            vec![
+                sub(0, 1000, "What?"),
+                sub(5000, 6000, "Wait for me"),
+                sub(59_000, 60_000, "I'm coming"),
+            ],
+            &mut tail,
+        );
+        assert_eq!(
+            merge(
+                vec![
+                    sub(100, 1000, "I'm coming"),
+                    sub(1100, 2000, "I'm coming"),
+                    sub(3000, 4000, "What?"),
+                ],
+                &mut tail,
+            ),
+            vec!["What?"]
+        );
     }
 
-    audio_pb.finish_with_message("done");
-
-    trace!("executed all jobs");
+    #[test]
+    fn replace_text() {
+        let sub = |text: &str| {
+            crate::subtitle::Subtitle::new(
+                Timespan::default(),
+                crate::subtitle::Dialogue::Text(text.to_string()),
+            )
+        };
+        let replacements = vec![
+            (regex::Regex::new(r"\[TN: [^\]]*\]").unwrap(), String::new()),
+            (regex::Regex::new(r"\bl(\d)").unwrap(), "I$1".to_string()),
+            (regex::Regex::new("rn").unwrap(), "m".to_string()),
+        ];
+        let mut subs = vec![sub("[TN: a pun]"), sub("The rnoon l1 [TN: note]")];
+        super::replace_text(&mut subs, &replacements);
+        let texts: Vec<_> = subs.iter().map(|sub| sub.text().unwrap()).collect();
+        assert_eq!(texts, vec!["The moon I1 "]);
+    }
 
-    let notes = create_notes(subtitles.iter().flat_map(|subs| subs.iter()))?;
-    trace!("creates {} notes", notes.len());
+    #[test]
+    fn quarantine_failed_output() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let partial = dir.path().join("audio_0_1.mka");
+        std::fs::write(&partial, b"partial")?;
+        let failed = dir.path().join("failed");
+
+        super::quarantine(&partial, &failed)?;
+        assert!(!partial.exists());
+        assert_eq!(std::fs::read(failed.join("audio_0_1.mka"))?, b"partial");
+        // A job that failed before writing anything has nothing to quarantine
+        super::quarantine(&dir.path().join("image_0_1.jpg"), &failed)?;
+        Ok(())
+    }
 
-    let mut deck = Deck::new(args.deck_id(), args.deck_name(), args.deck_desc());
-    trace!("created anki deck");
+    #[test]
+    fn count_failed() {
+        let subtitles: Vec<Vec<super::SubtitleBundle>> = vec![(0..3)
+            .map(|idx| {
+                let mut bundle = super::SubtitleBundle::from(crate::subtitle::Subtitle::new(
+                    Timespan::new(Timestamp::from_millis(0), Timestamp::from_millis(1000)),
+                    crate::subtitle::Dialogue::Text("Hi".to_string()),
+                ));
+                bundle.set_audio(&format!("audio_0_{}.mka", idx));
+                bundle
+            })
+            .collect()];
 
-    for note in notes {
-        deck.add_note(note);
+        let failed = [std::path::PathBuf::from("audio_0_1.mka")];
+        assert_eq!(super::count_failed(&subtitles, &failed), 1);
+        assert_eq!(super::count_failed(&subtitles, &[]), 0);
     }
 
-    let assets = subtitles
-        .iter()
-        .flat_map(|subs| subs.iter())
-        .flat_map(|sub| {
-            let mut assets = Vec::new();
-            if let Some(sub_image) = sub.sub_image() {
-                assets.push(sub_image);
-            }
-            if let Some(image) = sub.image() {
-                assets.push(image);
-            }
-            if let Some(audio) = sub.audio() {
-                assets.push(audio);
-            }
-            assets.into_iter()
-        });
+    #[test]
+    fn select_cards() {
+        use crate::args::CardSelection;
 
-    let mut package =
-        Package::new(vec![deck], assets.collect()).context("Failed to create anki package")?;
-    trace!("created package");
+        let mut rng = super::make_rng(None);
+        assert_eq!(
+            super::select_cards(3, 5, CardSelection::Random, &mut rng),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            super::select_cards(10, 3, CardSelection::First, &mut rng),
+            vec![0, 1, 2]
+        );
 
-    if !args.no_deck() {
-        package
-            .write_to_file(args.package())
-            .context("Failed to write package to file")?;
-    } else {
-        trace!("did not write an anki deck because --no-deck was specified");
+        let random = super::select_cards(10, 3, CardSelection::Random, &mut rng);
+        assert_eq!(random.len(), 3);
+        assert!(random.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(random.iter().all(|&idx| idx < 10));
     }
 
-    if args.write_json() {
-        let serialized = serde_json::to_string(&subtitles)?;
-        print!("{}", serialized);
+    #[test]
+    fn sample_with_seed() {
+        use crate::args::Sample;
+
+        let subs = || -> Vec<crate::subtitle::Subtitle> {
+            (0..20)
+                .map(|idx| {
+                    crate::subtitle::Subtitle::new(
+                        Timespan::new(
+                            Timestamp::from_millis(idx * 1000),
+                            Timestamp::from_millis(idx * 1000 + 500),
+                        ),
+                        crate::subtitle::Dialogue::Text(idx.to_string()),
+                    )
+                })
+                .collect()
+        };
+        let sample = |sample: Sample, seed: u64| -> Vec<String> {
+            super::sample_subtitles(subs(), sample, &mut super::make_rng(Some(seed)))
+                .iter()
+                .filter_map(|sub| sub.text().map(str::to_string))
+                .collect()
+        };
+
+        assert_eq!(sample(Sample::Fraction(0.25), 7).len(), 5);
+        assert_eq!(sample(Sample::Count(50), 7).len(), 20);
+        assert_eq!(sample(Sample::Count(4), 7), sample(Sample::Count(4), 7));
+
+        let starts: Vec<u32> = sample(Sample::Count(8), 1)
+            .iter()
+            .map(|text| text.parse().unwrap())
+            .collect();
+        assert!(starts.windows(2).all(|pair| pair[0] < pair[1]));
     }
 
-    if args.dump() {
-        for file in &subtitles {
-            for bundle in file {
-                println!(
-                    "{}|{}|{}",
-                    bundle.sub.timespan().start(),
-                    bundle.sub.timespan().end(),
-                    bundle.sub.text().unwrap_or(""),
-                );
-            }
-        }
+    #[test]
+    fn spread_cards_over_runtime() {
+        let times: Vec<i64> = (0..10).map(|idx| idx * 1000 + 500).collect();
+        assert_eq!(super::spread_cards(&times, 10_000, 3), vec![1, 4, 8]);
+
+        // The subtitles are dense at the start, but the cards still cover the end
+        let times = [500, 1500, 2500, 3500, 4500, 95_000];
+        assert_eq!(super::spread_cards(&times, 100_000, 2), vec![4, 5]);
+        // A card that is nearest to two timestamps is kept once
+        assert_eq!(super::spread_cards(&times, 100_000, 3), vec![3, 4, 5]);
     }
 
-    //read subtitles
-    //filter/transform subtitles
-    //generate media
-    //generate deck
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    setup_panic!();
+    #[test]
+    fn limit_cards_over_files() {
+        let bundles = |count: usize, file_idx: usize| -> Vec<super::SubtitleBundle> {
+            (0..count)
+                .map(|sub_idx| {
+                    let start = sub_idx as u32 * 1000;
+                    let mut bundle = super::SubtitleBundle::from(crate::subtitle::Subtitle::new(
+                        Timespan::new(
+                            Timestamp::from_millis(start),
+                            Timestamp::from_millis(start + 1000),
+                        ),
+                        crate::subtitle::Dialogue::Text("Hi".to_string()),
+                    ));
+                    bundle.set_audio(&format!("audio_{}_{}.mka", file_idx, sub_idx));
+                    bundle
+                })
+                .collect()
+        };
+        let mut subtitles = vec![bundles(3, 0), bundles(4, 1)];
+
+        super::limit_cards(
+            &mut subtitles,
+            3,
+            crate::args::CardSelection::Spread,
+            &[Duration::from_millis(3000), Duration::from_millis(4000)],
+            &mut super::make_rng(None),
+        );
+        let audio: Vec<_> = subtitles
+            .iter()
+            .flatten()
+            .map(|bundle| bundle.audio())
+            .collect();
+        assert_eq!(
+            audio,
+            vec![
+                Some("audio_0_1.mka"),
+                Some("audio_1_0.mka"),
+                Some("audio_1_2.mka")
+            ]
+        );
+    }
 
-    let args = Args::parse_from_env()?;
+    #[test]
+    fn dump_first_cards() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/mergable_sub.srt")
+            .arg("tests/media/sub.srt")
+            .arg("--dump")
+            .arg("--max-cards=2")
+            .arg("--card-selection=first")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
 
-    let logger = pretty_env_logger::formatted_builder()
-        .filter_level(args.verbosity())
-        .build();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0:00:00.000|0:00:02.500|Hello World!",
+                "0:00:02.000|0:00:02.800|Hello World!"
+            ]
+        );
+        Ok(())
+    }
 
-    if let Some(job_count) = args.job_count() {
-        ThreadPoolBuilder::new()
-            .num_threads(job_count)
-            .build_global()
-            .context("failed to initialize thread pool")?;
+    #[test]
+    fn coverage() {
+        let span =
+            |start, end| Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end));
+        let runtime = crate::time::Duration::from_millis(10_000);
+        assert_eq!(super::coverage(Vec::new(), runtime), 0.0);
+        assert_eq!(
+            super::coverage(
+                vec![span(5000, 7000), span(0, 2000), span(1000, 3000)],
+                runtime
+            ),
+            0.5
+        );
+        assert_eq!(super::coverage(vec![span(0, 20_000)], runtime), 1.0);
     }
 
-    let multi = MultiProgress::new();
-    LogWrapper::new(multi.clone(), logger).try_init().unwrap();
-    trace!("initialized logger");
-    //execute
+    #[test]
+    fn unique_deck_file_paths() {
+        use std::path::{Path, PathBuf};
 
-    libav::init().context("Failed to initialize libav")?;
+        let files = [PathBuf::from("a/ep01.mkv"), PathBuf::from("b/ep01.mkv")];
+        let package = Path::new("out/deck.apkg");
+        assert!(super::deck_file_paths(package, "%n.apkg", &files).is_err());
+        assert_eq!(
+            super::deck_file_paths(package, "%f_%n.apkg", &files).unwrap(),
+            vec![
+                PathBuf::from("out/0_ep01.apkg"),
+                PathBuf::from("out/1_ep01.apkg")
+            ]
+        );
+    }
 
-    run(&args, multi.clone())?;
-    /*
-    if let Err(error) = run() {
-        //print pretty error
-    }*/
-    Ok(())
-}
+    #[test]
+    fn subdeck_key() {
+        use crate::subtitle::{Dialogue, Subtitle};
+        use regex::Regex;
+
+        let span = Timespan::new(Timestamp::MIN, Timestamp::from_millis(1000));
+        let text = Subtitle::new(
+            span,
+            Dialogue::Text("[Narrator] Once upon a time".to_string()),
+        );
+        let ass = Subtitle::new(
+            span,
+            Dialogue::Ass(DialogueEvent {
+                layer: 0,
+                style: String::new(),
+                name: "Bob".to_string(),
+                text: "Hello".parse().unwrap(),
+            }),
+        );
 
-#[cfg(test)]
-mod tests {
-    use crate::ass::DialogueEvent;
-    use crate::time::{Timespan, Timestamp};
-    use assert_cmd::prelude::*;
-    use serde::Deserialize;
-    use std::process::Command;
+        let regex = Regex::new(r"^\[(\w+)\]").unwrap();
+        assert_eq!(
+            super::subdeck_key(&regex, &text).as_deref(),
+            Some("Narrator")
+        );
+        assert_eq!(super::subdeck_key(&regex, &ass), None);
 
-    type TestResult = Result<(), Box<dyn std::error::Error>>;
+        let regex = Regex::new(r"^\w+:").unwrap();
+        assert_eq!(super::subdeck_key(&regex, &ass).as_deref(), Some("Bob:"));
+        let regex = Regex::new(r"^(\w+):").unwrap();
+        assert_eq!(super::subdeck_key(&regex, &ass).as_deref(), Some("Bob"));
+    }
 
-    #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize)]
-    enum Dialogue {
-        Text(String),
-        Ass(DialogueEvent),
-        Bitmap(String),
+    #[test]
+    fn filter_modes() {
+        use super::{passes_filters, FilterMode};
+        use regex::Regex;
+
+        let blacklist = [Regex::new("bad").unwrap()];
+        let whitelist = [Regex::new("good").unwrap(), Regex::new("great").unwrap()];
+        let kept = |mode| {
+            ["good bad", "good", "good great", "bad", "neither"]
+                .into_iter()
+                .filter(|text| passes_filters(mode, &blacklist, &whitelist, Some(text)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kept(FilterMode::And), vec!["good", "good great"]);
+        assert_eq!(
+            kept(FilterMode::WhitelistOverrides),
+            vec!["good bad", "good", "good great", "neither"]
+        );
+        assert!(passes_filters(FilterMode::And, &blacklist, &[], None));
+        assert!(!passes_filters(
+            FilterMode::And,
+            &blacklist,
+            &whitelist,
+            None
+        ));
     }
 
-    #[derive(Deserialize)]
-    struct Subtitle {
-        pub timespan: Timespan,
-        pub diag: Dialogue,
+    #[test]
+    fn ndjson() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/sub.srt")
+            .arg("tests/media/mergable_sub.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-ndjson")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs = stdout
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<SubtitleBundle>, _>>()?;
+        assert!(subs.len() > 1);
+        assert_eq!(subs[0].file_idx, 0);
+        assert_eq!(subs.last().map(|bundle| bundle.file_idx), Some(1));
+
+        let raw_args = [
+            "stos",
+            "tests/media/sub.srt",
+            "--write-json",
+            "--write-ndjson",
+        ];
+        assert!(super::Args::parse_from_args(raw_args.map(Into::into).to_vec()).is_err());
+        Ok(())
     }
 
-    #[derive(Deserialize)]
-    struct SubtitleBundle {
-        pub sub: Subtitle,
-        pub sub_image: Option<String>,
-        pub audio: Option<String>,
-        pub image: Option<String>,
+    #[test]
+    fn normalize_only_compared_text() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/sub.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .arg("--normalize=lowercase,strip-punct")
+            .arg("-w")
+            .arg("^hello world$")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs[0].len(), 1);
+        assert_eq!(
+            subs[0][0].sub.diag,
+            Dialogue::Text("Hello World!".to_string())
+        );
+        Ok(())
     }
 
     #[test]
@@ -571,6 +3274,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn blacklist_regex_flags() -> TestResult {
+        for (flags, count) in [(None, 1), (Some("--regex-flags=i"), 0)] {
+            let out = Command::cargo_bin("stos")?
+                .arg("tests/media/sub.srt")
+                .arg("--no-deck")
+                .arg("--no-media")
+                .arg("--write-json")
+                .arg("-b")
+                .arg("^hello")
+                .args(flags)
+                .assert()
+                .success();
+            let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+            let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+            assert_eq!(subs[0].len(), count);
+        }
+        Ok(())
+    }
+
     #[test]
     fn blacklist_no_match() -> TestResult {
         let out = Command::cargo_bin("stos")?
@@ -628,6 +3352,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn indices() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/sub.srt")
+            .arg("tests/media/mergable_sub.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs.len(), 2);
+        for (file_idx, subs) in subs.iter().enumerate() {
+            for (sub_idx, sub) in subs.iter().enumerate() {
+                assert_eq!(sub.file_idx, file_idx);
+                assert_eq!(sub.sub_idx, sub_idx);
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn merge_subs() -> TestResult {
         let out = Command::cargo_bin("stos")?
@@ -652,6 +3399,129 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn zero_length_dropped() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/zero_length.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn default_sub_duration() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/zero_length.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .arg("--default-sub-duration=1500")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].len(), 1);
+        assert_eq!(subs[0][0].sub.timespan.start(), Timestamp::from_millis(0));
+        assert_eq!(subs[0][0].sub.timespan.end(), Timestamp::from_millis(1500));
+        Ok(())
+    }
+
+    #[test]
+    fn zero_length_keep() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/zero_length.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .arg("--zero-length=keep")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].len(), 1);
+        assert_eq!(subs[0][0].sub.timespan.start(), Timestamp::from_millis(0));
+        assert_eq!(subs[0][0].sub.timespan.end(), Timestamp::from_millis(0));
+        Ok(())
+    }
+
+    #[test]
+    fn zero_length_drop() -> TestResult {
+        let out = Command::cargo_bin("stos")?
+            .arg("tests/media/zero_length.srt")
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .arg("--zero-length=drop")
+            .arg("--default-sub-duration=1500")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].len(), 0);
+        Ok(())
+    }
+
+    fn subtitle_count(file: &str, args: &[&str]) -> Result<usize, Box<dyn std::error::Error>> {
+        let out = Command::cargo_bin("stos")?
+            .arg(file)
+            .arg("--no-deck")
+            .arg("--no-media")
+            .arg("--write-json")
+            .args(args)
+            .assert()
+            .success();
+        let stdout = String::from_utf8(out.get_output().stdout.clone())?;
+
+        let subs: Vec<Vec<SubtitleBundle>> = serde_json::from_str(&stdout)?;
+        Ok(subs[0].len())
+    }
+
+    #[test]
+    fn keep_styled() -> TestResult {
+        let styled = "tests/media/styled.ass";
+        assert_eq!(subtitle_count(styled, &[])?, 1);
+        assert_eq!(subtitle_count(styled, &["--keep-styled"])?, 2);
+        assert_eq!(
+            subtitle_count(styled, &["--keep-styled", "--ignore-styled"])?,
+            1
+        );
+        assert_eq!(
+            subtitle_count(styled, &["--ignore-styled", "--keep-styled"])?,
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn actors() -> TestResult {
+        let actors = "tests/media/actors.ass";
+        assert_eq!(subtitle_count(actors, &["--actor=Alice"])?, 1);
+        assert_eq!(
+            subtitle_count(actors, &["--actor=Alice", "--actor=^Bob$"])?,
+            2
+        );
+        assert_eq!(subtitle_count(actors, &["--skip-actor=Narrator"])?, 3);
+        assert_eq!(
+            subtitle_count(actors, &["--actor=.", "--skip-actor=Narrator"])?,
+            2
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_ass() -> TestResult {
         let out = Command::cargo_bin("stos")?
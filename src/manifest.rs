@@ -0,0 +1,195 @@
+use crate::time::Duration;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// A row of a manifest: a subtitle file and everything that belongs to it
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ManifestRow {
+    pub subtitle: PathBuf,
+    pub media: Option<PathBuf>,
+    /// How much later the subtitles should be shown
+    pub delay: Duration,
+    /// The language of the subtitle stream to use
+    pub lang: Option<String>,
+    /// The deck the notes of the file go in
+    pub deck: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Column {
+    Subtitle,
+    Media,
+    Delay,
+    Lang,
+    Deck,
+}
+
+impl Column {
+    fn from_header(header: &str) -> Result<Self> {
+        match header.trim().to_ascii_lowercase().as_str() {
+            "subtitle" | "sub" => Ok(Column::Subtitle),
+            "media" => Ok(Column::Media),
+            "delay" => Ok(Column::Delay),
+            "lang" | "language" => Ok(Column::Lang),
+            "deck" => Ok(Column::Deck),
+            other => bail!(
+                "\"{}\" is not a valid manifest column, expected subtitle, media, delay, lang or deck",
+                other
+            ),
+        }
+    }
+}
+
+/// Splits a CSV line into its fields. Fields may be quoted, with `""` for a quote inside them.
+fn split_csv(line: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(ch),
+        }
+    }
+    if quoted {
+        bail!("Unterminated quote");
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Parses a CSV manifest with a header row. Relative paths are relative to `dir`.
+fn parse(data: &str, dir: &Path) -> Result<Vec<ManifestRow>> {
+    let mut lines = data
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim_start_matches('\u{feff}')))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+
+    let Some((_, header)) = lines.next() else {
+        bail!("The manifest is empty");
+    };
+    let columns = split_csv(header)?
+        .iter()
+        .map(|header| Column::from_header(header))
+        .collect::<Result<Vec<_>>>()?;
+    if !columns.contains(&Column::Subtitle) {
+        bail!("The manifest does not have a subtitle column");
+    }
+
+    let mut rows = Vec::new();
+    for (line_nr, line) in lines {
+        let fields =
+            split_csv(line).with_context(|| format!("Invalid manifest line {}", line_nr))?;
+        if fields.len() != columns.len() {
+            bail!(
+                "Manifest line {} has {} fields, but the header has {} columns",
+                line_nr,
+                fields.len(),
+                columns.len()
+            );
+        }
+
+        let mut row = ManifestRow::default();
+        for (column, field) in columns.iter().zip(fields) {
+            let field = field.trim();
+            if field.is_empty() {
+                continue;
+            }
+            match column {
+                Column::Subtitle => row.subtitle = dir.join(field),
+                Column::Media => row.media = Some(dir.join(field)),
+                Column::Delay => {
+                    row.delay = Duration::from_millis(field.parse().with_context(|| {
+                        format!(
+                            "Manifest line {}: \"{}\" is not a delay in milliseconds",
+                            line_nr, field
+                        )
+                    })?)
+                }
+                Column::Lang => row.lang = Some(field.to_string()),
+                Column::Deck => row.deck = Some(field.to_string()),
+            }
+        }
+        if row.subtitle.as_os_str().is_empty() {
+            bail!("Manifest line {} does not have a subtitle file", line_nr);
+        }
+        rows.push(row);
+    }
+
+    if rows.is_empty() {
+        bail!("The manifest does not list any files");
+    }
+    // Pairing some subtitle files with media files and others with themselves is a mistake
+    let with_media = rows.iter().filter(|row| row.media.is_some()).count();
+    if with_media != 0 && with_media != rows.len() {
+        bail!("Either every row of the manifest or none of them must have a media file");
+    }
+    Ok(rows)
+}
+
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestRow>> {
+    let data = std::fs::read_to_string(path).context("Failed to open file")?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse(&data, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields() {
+        assert_eq!(
+            split_csv("a, \"b, c\" ,\"d \"\"e\"\"\"").unwrap(),
+            vec!["a", "b, c ", "d \"e\""]
+        );
+        assert!(split_csv("\"a").is_err());
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let rows = parse(
+            "# a comment\nsubtitle,media,delay,lang,deck\nep1.srt,ep1.mkv,-250,jpn,Show::S01\n\"ep 2.srt\",/media/ep2.mkv,,,\n",
+            Path::new("library"),
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                ManifestRow {
+                    subtitle: PathBuf::from("library/ep1.srt"),
+                    media: Some(PathBuf::from("library/ep1.mkv")),
+                    delay: Duration::from_millis(-250),
+                    lang: Some("jpn".to_string()),
+                    deck: Some("Show::S01".to_string()),
+                },
+                ManifestRow {
+                    subtitle: PathBuf::from("library/ep 2.srt"),
+                    media: Some(PathBuf::from("/media/ep2.mkv")),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_manifest() {
+        let dir = Path::new("");
+        assert!(parse("media\nep1.mkv\n", dir).is_err());
+        assert!(parse("subtitle,colour\nep1.srt,red\n", dir).is_err());
+        assert!(parse("subtitle,media\nep1.srt\n", dir).is_err());
+        assert!(parse("subtitle,media\nep1.srt,ep1.mkv\nep2.srt,\n", dir).is_err());
+    }
+}
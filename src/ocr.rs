@@ -0,0 +1,104 @@
+use crate::subtitle::Subtitle;
+use anyhow::Result;
+
+/// Replaces the bitmap dialogue of `subs` with the text tesseract recognizes in it, using the
+/// `lang` tesseract language data. Returns the amount of recognized subtitles.
+#[cfg(not(feature = "ocr"))]
+pub fn recognize_bitmaps(_subs: &mut [Subtitle], _lang: &str) -> Result<usize> {
+    anyhow::bail!("stos was built without OCR support, rebuild it with the `ocr` feature enabled");
+}
+
+/// Replaces the bitmap dialogue of `subs` with the text tesseract recognizes in it, using the
+/// `lang` tesseract language data. Returns the amount of recognized subtitles.
+#[cfg(feature = "ocr")]
+pub fn recognize_bitmaps(subs: &mut [Subtitle], lang: &str) -> Result<usize> {
+    use crate::subtitle::Dialogue;
+    use anyhow::Error;
+    use leptess::LepTess;
+    use log::{trace, warn};
+
+    let mut tess = LepTess::new(None, lang).map_err(|err| {
+        Error::msg(format!(
+            "Failed to initialize tesseract with language \"{}\": {}",
+            lang, err
+        ))
+    })?;
+
+    let mut recognized = 0;
+    for sub in subs.iter_mut() {
+        let Dialogue::Bitmap(image) = sub.dialogue() else {
+            continue;
+        };
+
+        tess.set_image_from_mem(&prepare(image)?)
+            .map_err(|err| Error::msg(format!("Failed to pass image to tesseract: {:?}", err)))?;
+        let text = tess
+            .get_utf8_text()
+            .map_err(|err| Error::msg(format!("Failed to get text from tesseract: {}", err)))?;
+
+        let text = clean(&text);
+        if text.is_empty() {
+            warn!(
+                "could not recognize any text in the subtitle at {}",
+                sub.timespan().start()
+            );
+            continue;
+        }
+        trace!("recognized \"{}\"", text);
+        let forced = sub.forced();
+        *sub = Subtitle::new(sub.timespan(), Dialogue::Text(text));
+        sub.set_forced(forced);
+        recognized += 1;
+    }
+    Ok(recognized)
+}
+
+/// Bitmap subtitles are usually light text with a dark outline on a transparent background,
+/// tesseract works best with dark text on a light background. Returns the image encoded as PNG.
+#[cfg(feature = "ocr")]
+fn prepare(image: &image::RgbaImage) -> Result<Vec<u8>> {
+    use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+    use std::io::Cursor;
+
+    let gray = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let luma = (u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000;
+        // composite on black and invert
+        Luma([255 - (luma * u32::from(a) / 255) as u8])
+    });
+
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(gray).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Removes the empty lines and surrounding whitespace tesseract adds
+#[cfg(feature = "ocr")]
+fn clean(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(all(test, feature = "ocr"))]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn clean_ocr_output() {
+        assert_eq!(clean("  Hello\n\n world \n\x0c"), "Hello\nworld");
+    }
+
+    #[test]
+    fn prepare_inverts_text() {
+        let mut bitmap = RgbaImage::new(2, 1);
+        bitmap.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        let png = prepare(&bitmap).unwrap();
+        let gray = image::load_from_memory(&png).unwrap().to_luma8();
+        assert_eq!(gray.get_pixel(0, 0).0, [0]);
+        assert_eq!(gray.get_pixel(1, 0).0, [255]);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::time::Timespan;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PlaylistFormat {
+    /// An mpv EDL file, which mpv plays as a single file
+    Edl,
+    /// An m3u playlist with an `edl://` entry for every timespan
+    M3u,
+}
+
+impl PlaylistFormat {
+    /// Determines the format of a playlist from the extension of `path`
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match ext.as_str() {
+            "edl" => Ok(PlaylistFormat::Edl),
+            "m3u" | "m3u8" => Ok(PlaylistFormat::M3u),
+            _ => bail!(
+                "\"{}\" is not a supported playlist, expected a .edl, .m3u or .m3u8 file",
+                path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+/// The playlist refers to the media files from wherever it is played, so relative paths are
+/// made absolute
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn seconds(millis: i64) -> String {
+    format!("{:.3}", millis as f64 / 1000.0)
+}
+
+/// A segment of an mpv EDL. The path is prefixed with its length, so it may contain commas.
+fn edl_segment(file: &Path, span: Timespan) -> String {
+    let file = absolute(file).to_string_lossy().into_owned();
+    let start = span.start().as_millis();
+    format!(
+        "%{}%{},{},{}",
+        file.len(),
+        file,
+        seconds(start),
+        seconds(span.duration().as_millis())
+    )
+}
+
+fn format_playlist<'a, I>(format: PlaylistFormat, entries: I) -> String
+where
+    I: Iterator<Item = (&'a Path, Timespan, &'a str)>,
+{
+    let mut res = String::new();
+    match format {
+        PlaylistFormat::Edl => {
+            res.push_str("# mpv EDL v0\n");
+            for (file, span, _) in entries {
+                res.push_str(&edl_segment(file, span));
+                res.push('\n');
+            }
+        }
+        PlaylistFormat::M3u => {
+            res.push_str("#EXTM3U\n");
+            for (file, span, text) in entries {
+                let secs = span.duration().as_millis() / 1000;
+                let title = text.replace("\\N", " ").replace(['\r', '\n'], " ");
+                res.push_str(&format!("#EXTINF:{},{}\n", secs, title.trim()));
+                res.push_str(&format!("edl://{}\n", edl_segment(file, span)));
+            }
+        }
+    }
+    res
+}
+
+/// Writes a playlist of the timespans `entries` of the media files to `path`, so the mined
+/// moments can be watched again in mpv
+pub fn write_playlist<'a, I>(path: &Path, entries: I) -> Result<()>
+where
+    I: Iterator<Item = (&'a Path, Timespan, &'a str)>,
+{
+    let format = PlaylistFormat::from_path(path)?;
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create playlist \"{}\"", path.to_string_lossy()))?;
+    let mut writer = BufWriter::new(file);
+    writer
+        .write_all(format_playlist(format, entries).as_bytes())
+        .and_then(|_| writer.flush())
+        .context("Failed to write playlist")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Timestamp;
+
+    fn span(start: u32, end: u32) -> Timespan {
+        Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end))
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            PlaylistFormat::from_path(Path::new("review.EDL")).unwrap(),
+            PlaylistFormat::Edl
+        );
+        assert_eq!(
+            PlaylistFormat::from_path(Path::new("review.m3u8")).unwrap(),
+            PlaylistFormat::M3u
+        );
+        assert!(PlaylistFormat::from_path(Path::new("review.txt")).is_err());
+    }
+
+    #[test]
+    fn edl_playlist() {
+        let entries = vec![(Path::new("/media/a,b.mkv"), span(1500, 4000), "Hi")];
+        assert_eq!(
+            format_playlist(PlaylistFormat::Edl, entries.into_iter()),
+            "# mpv EDL v0\n%14%/media/a,b.mkv,1.500,2.500\n"
+        );
+    }
+
+    #[test]
+    fn m3u_playlist() {
+        let entries = vec![(Path::new("/media/a.mkv"), span(0, 2000), "Hello\\Nworld")];
+        assert_eq!(
+            format_playlist(PlaylistFormat::M3u, entries.into_iter()),
+            "#EXTM3U\n#EXTINF:2,Hello world\nedl://%12%/media/a.mkv,0.000,2.000\n"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+
+/// Finds the readings of Japanese text with a morphological analysis
+#[cfg(not(feature = "reading"))]
+pub struct ReadingAnalyzer;
+
+#[cfg(not(feature = "reading"))]
+impl ReadingAnalyzer {
+    pub fn new() -> Result<Self> {
+        anyhow::bail!(
+            "stos was built without support for readings, rebuild it with the `reading` feature enabled"
+        );
+    }
+
+    pub fn reading(&self, _text: &str) -> Result<String> {
+        unreachable!("a ReadingAnalyzer cannot be created without the `reading` feature")
+    }
+}
+
+/// Finds the readings of Japanese text with a morphological analysis
+#[cfg(feature = "reading")]
+pub struct ReadingAnalyzer {
+    tokenizer: lindera::tokenizer::Tokenizer,
+}
+
+#[cfg(feature = "reading")]
+impl ReadingAnalyzer {
+    /// Loads the IPADIC dictionary that is embedded in the binary
+    pub fn new() -> Result<Self> {
+        use anyhow::Context;
+        use lindera::dictionary::{load_dictionary_from_config, DictionaryConfig, DictionaryKind};
+        use lindera::mode::Mode;
+        use lindera::tokenizer::Tokenizer;
+
+        let dictionary = load_dictionary_from_config(DictionaryConfig {
+            kind: Some(DictionaryKind::IPADIC),
+            path: None,
+        })
+        .context("Failed to load the IPADIC dictionary")?;
+        Ok(Self {
+            tokenizer: Tokenizer::new(dictionary, None, Mode::Normal),
+        })
+    }
+
+    /// Returns the reading of `text` in hiragana. Words without kanji are kept as they are
+    /// written, so katakana words and punctuation stay the same.
+    pub fn reading(&self, text: &str) -> Result<String> {
+        let text = text.replace("\\N", "\n");
+        let mut tokens = self.tokenizer.tokenize(&text).map_err(|err| {
+            anyhow::Error::msg(format!("Failed to analyze \"{}\": {}", text, err))
+        })?;
+
+        let mut reading = String::new();
+        for token in tokens.iter_mut() {
+            let surface = token.text.to_string();
+            // The reading is the eighth IPADIC feature, unknown words only have "UNK"
+            let kana = token
+                .get_details()
+                .and_then(|details| details.get(7).map(|kana| kana.to_string()))
+                .filter(|kana| kana != "*");
+            reading.push_str(&token_reading(&surface, kana.as_deref()));
+        }
+        Ok(reading.replace('\n', "\\N"))
+    }
+}
+
+/// Whether `ch` is a kanji, including the iteration mark
+#[cfg(feature = "reading")]
+fn is_kanji(ch: char) -> bool {
+    matches!(ch, '\u{3005}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}')
+}
+
+/// Converts the katakana of `text` to hiragana
+#[cfg(feature = "reading")]
+fn to_hiragana(text: &str) -> String {
+    text.chars()
+        .map(|ch| match ch {
+            '\u{30a1}'..='\u{30f6}' => char::from_u32(ch as u32 - 0x60).unwrap_or(ch),
+            _ => ch,
+        })
+        .collect()
+}
+
+/// The reading of a single word: the dictionary reading (in katakana) if the word is written with
+/// kanji, the word itself otherwise
+#[cfg(feature = "reading")]
+fn token_reading(surface: &str, kana: Option<&str>) -> String {
+    match kana {
+        Some(kana) if surface.chars().any(is_kanji) => to_hiragana(kana),
+        _ => surface.to_string(),
+    }
+}
+
+#[cfg(all(test, feature = "reading"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn katakana_to_hiragana() {
+        assert_eq!(to_hiragana("キョウ"), "きょう");
+        assert_eq!(to_hiragana("ヴァー!"), "ゔぁー!");
+    }
+
+    #[test]
+    fn only_kanji_words_are_read() {
+        assert_eq!(token_reading("天気", Some("テンキ")), "てんき");
+        assert_eq!(token_reading("コーヒー", Some("コーヒー")), "コーヒー");
+        assert_eq!(token_reading("。", None), "。");
+    }
+
+    #[test]
+    fn sentence_reading() {
+        let analyzer = ReadingAnalyzer::new().unwrap();
+        assert_eq!(
+            analyzer.reading("今日はいい天気ですね").unwrap(),
+            "きょうはいいてんきですね"
+        );
+    }
+}
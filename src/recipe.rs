@@ -0,0 +1,245 @@
+use crate::args::Args;
+use crate::util::{fnv1a, get_medium_name, get_stream, StreamSelector, FNV_OFFSET};
+use anyhow::{Context, Result};
+use libav::media;
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Everything that is needed to build the same deck again: the arguments stos was run with and
+/// the state of the input files at that time.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    version: String,
+    args: Vec<String>,
+    inputs: Vec<RecipeInput>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct RecipeInput {
+    path: PathBuf,
+    hash: String,
+    /// The index of the selected stream for every medium that was read from the file
+    streams: BTreeMap<String, usize>,
+}
+
+impl Recipe {
+    pub fn new(args: &Args) -> Result<Self> {
+        let media_files = if args.media_files().is_empty() {
+            args.sub_files()
+        } else {
+            args.media_files()
+        };
+
+        let mut inputs: Vec<RecipeInput> = Vec::new();
+        for (file_idx, (sub_file, media_file)) in
+            args.sub_files().iter().zip(media_files.iter()).enumerate()
+        {
+            Self::add_input(
+                &mut inputs,
+                sub_file,
+                media::Type::Subtitle,
+                args.file_sub_stream_selector(file_idx),
+            )?;
+            if args.gen_audio() {
+                Self::add_input(
+                    &mut inputs,
+                    media_file,
+                    media::Type::Audio,
+                    args.audio_stream_selector(),
+                )?;
+            }
+            if args.gen_images() {
+                Self::add_input(
+                    &mut inputs,
+                    media_file,
+                    media::Type::Video,
+                    args.video_stream_selector(),
+                )?;
+            }
+        }
+
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            args: recorded_args(args.raw_args(), args.deck_id())?,
+            inputs,
+        })
+    }
+
+    fn add_input(
+        inputs: &mut Vec<RecipeInput>,
+        path: &Path,
+        medium: media::Type,
+        selector: StreamSelector,
+    ) -> Result<()> {
+        let idx = match inputs.iter().position(|input| input.path == path) {
+            Some(idx) => idx,
+            None => {
+                inputs.push(RecipeInput {
+                    path: path.to_path_buf(),
+                    hash: hash_file(path)?,
+                    streams: BTreeMap::new(),
+                });
+                inputs.len() - 1
+            }
+        };
+
+        // Files that stos parses itself (TTML, PGS) can't be opened by libav and have no streams
+        if let Some(stream_idx) = stream_index(path, medium, selector) {
+            inputs[idx]
+                .streams
+                .insert(get_medium_name(medium).to_string(), stream_idx);
+        }
+        Ok(())
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(&path).context("Failed to open file")?;
+        serde_json::from_reader(BufReader::new(file)).context("Failed to parse recipe")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(&path).with_context(|| {
+            format!(
+                "Failed to create recipe \"{}\"",
+                path.as_ref().to_string_lossy()
+            )
+        })?;
+        serde_json::to_writer_pretty(file, self).context("Failed to write recipe")
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Warns about every difference between the inputs recorded in this recipe and the inputs
+    /// of `args`, which would make the deck turn out differently.
+    pub fn verify(&self, args: &Args) -> Result<()> {
+        if self.version != env!("CARGO_PKG_VERSION") {
+            warn!(
+                "The recipe was written by stos {}, the deck may turn out differently",
+                self.version
+            );
+        }
+
+        let current = Self::new(args)?;
+        for (recorded, input) in self.inputs.iter().zip(current.inputs.iter()) {
+            if recorded.hash != input.hash {
+                warn!(
+                    "\"{}\" has changed since the recipe was written",
+                    input.path.to_string_lossy()
+                );
+            }
+            if recorded.streams != input.streams {
+                warn!(
+                    "Different streams of \"{}\" are selected than when the recipe was written",
+                    input.path.to_string_lossy()
+                );
+            }
+        }
+        trace!("verified {} recipe input(s)", self.inputs.len());
+        Ok(())
+    }
+}
+
+fn stream_index(path: &Path, medium: media::Type, selector: StreamSelector) -> Option<usize> {
+    let ictx = libav::format::input(path).ok()?;
+    let stream = get_stream(&ictx, medium, selector).ok()?;
+    Some(stream.index())
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = BufReader::new(
+        File::open(path)
+            .with_context(|| format!("Failed to open \"{}\"", path.to_string_lossy()))?,
+    );
+    let mut hash = FNV_OFFSET;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .with_context(|| format!("Failed to read \"{}\"", path.to_string_lossy()))?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buf[..read]);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// The arguments to store in a recipe. The recipe options themselves are left out, and the deck
+/// id is pinned so the rebuilt deck replaces the old one when imported.
+fn recorded_args(raw: &[OsString], deck_id: i64) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut raw = raw.iter();
+    while let Some(arg) = raw.next() {
+        let arg = arg
+            .to_str()
+            .context("A recipe can only record arguments that are valid unicode")?;
+        if arg == "--recipe" {
+            raw.next();
+            continue;
+        }
+        if !arg.starts_with("--recipe=") {
+            args.push(arg.to_string());
+        }
+    }
+
+    if !args
+        .iter()
+        .any(|arg| arg == "--id" || arg.starts_with("--id="))
+    {
+        args.push(format!("--id={}", deck_id));
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn os(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn recorded_args_drop_recipe() {
+        assert_eq!(
+            recorded_args(&os(&["sub.srt", "--recipe", "stos.lock", "-a"]), 5).unwrap(),
+            vec!["sub.srt", "-a", "--id=5"]
+        );
+        assert_eq!(
+            recorded_args(&os(&["--recipe=stos.lock", "sub.srt"]), 5).unwrap(),
+            vec!["sub.srt", "--id=5"]
+        );
+    }
+
+    #[test]
+    fn recorded_args_keep_id() {
+        assert_eq!(
+            recorded_args(&os(&["sub.srt", "--id", "42"]), 5).unwrap(),
+            vec!["sub.srt", "--id", "42"]
+        );
+        assert_eq!(
+            recorded_args(&os(&["sub.srt", "--id=42"]), 5).unwrap(),
+            vec!["sub.srt", "--id=42"]
+        );
+    }
+
+    #[test]
+    fn hash_changes_with_content() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(b"1\n00:00:01,000 --> 00:00:02,000\nHello\n")?;
+        let before = hash_file(file.path())?;
+        assert_eq!(before, hash_file(file.path())?);
+
+        file.write_all(b"\n")?;
+        assert_ne!(before, hash_file(file.path())?);
+        Ok(())
+    }
+}
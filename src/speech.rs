@@ -0,0 +1,89 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// The sample rate whisper expects its audio in
+pub const SPEECH_RATE: usize = 16000;
+
+/// Transcribes speech with a whisper model
+#[cfg(not(feature = "whisper"))]
+pub struct SpeechRecognizer;
+
+#[cfg(not(feature = "whisper"))]
+impl SpeechRecognizer {
+    pub fn new(_model: &Path) -> Result<Self> {
+        anyhow::bail!(
+            "stos was built without support for speech recognition, rebuild it with the `whisper` feature enabled"
+        );
+    }
+
+    pub fn transcribe(&mut self, _samples: &[f32]) -> Result<String> {
+        unreachable!("a SpeechRecognizer cannot be created without the `whisper` feature")
+    }
+}
+
+/// Transcribes speech with a whisper model
+#[cfg(feature = "whisper")]
+pub struct SpeechRecognizer {
+    state: whisper_rs::WhisperState,
+}
+
+#[cfg(feature = "whisper")]
+impl SpeechRecognizer {
+    /// Loads the whisper model (a ggml file) at `model`
+    pub fn new(model: &Path) -> Result<Self> {
+        use anyhow::Error;
+        use whisper_rs::{WhisperContext, WhisperContextParameters};
+
+        let context = WhisperContext::new_with_params(
+            &model.to_string_lossy(),
+            WhisperContextParameters::default(),
+        )
+        .map_err(|err| {
+            Error::msg(format!(
+                "Failed to load whisper model \"{}\": {}",
+                model.to_string_lossy(),
+                err
+            ))
+        })?;
+        let state = context
+            .create_state()
+            .map_err(|err| Error::msg(format!("Failed to create whisper state: {}", err)))?;
+        Ok(Self { state })
+    }
+
+    /// Transcribes `samples`, mono audio at `SPEECH_RATE`. The language is detected by whisper.
+    pub fn transcribe(&mut self, samples: &[f32]) -> Result<String> {
+        use anyhow::Error;
+        use whisper_rs::{FullParams, SamplingStrategy};
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(Some("auto"));
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_special(false);
+        params.set_print_timestamps(false);
+        // A clip is a line or two, there is nothing to carry over between clips
+        params.set_no_context(true);
+        params.set_single_segment(true);
+
+        // whisper refuses clips shorter than a second, silence doesn't change the transcription
+        let mut samples = samples.to_vec();
+        samples.resize(samples.len().max(SPEECH_RATE), 0.0);
+        self.state
+            .full(params, &samples)
+            .map_err(|err| Error::msg(format!("Failed to transcribe audio: {}", err)))?;
+        let segments = self
+            .state
+            .full_n_segments()
+            .map_err(|err| Error::msg(format!("Failed to transcribe audio: {}", err)))?;
+        let mut texts = Vec::new();
+        for segment in 0..segments {
+            let text = self
+                .state
+                .full_get_segment_text(segment)
+                .map_err(|err| Error::msg(format!("Failed to transcribe audio: {}", err)))?;
+            texts.push(text.trim().to_string());
+        }
+        Ok(texts.join(" "))
+    }
+}
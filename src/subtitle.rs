@@ -1,16 +1,23 @@
 use crate::ass::DialogueEvent;
-use crate::time::Timespan;
+use crate::time::{Duration, Timespan};
 use crate::util::StreamSelector;
-use anyhow::Result;
+use anyhow::{bail, Context, Error, Result};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
 use image::RgbaImage;
+use itertools::Itertools;
+use log::{info, trace, warn};
 use serde::{Serialize, Serializer};
-use std::path::Path;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 mod av {
     use crate::ass::DialogueEvent;
     use crate::time::{Duration, Timestamp};
-    use crate::util::{get_stream, StreamSelector};
+    use crate::util::{get_stream, is_forced, StreamSelector};
     use anyhow::{bail, Context, Error, Result};
+    use encoding_rs::Encoding;
     use image::RgbaImage;
     use libav::codec;
     use libav::codec::{decoder, packet::Packet, subtitle};
@@ -18,9 +25,11 @@ mod av {
     use libav::mathematics::rescale::Rescale;
     use libav::media;
     use libav::util::rational::Rational;
-    use log::{trace, warn};
+    use log::{info, trace, warn};
     use std::path::Path;
 
+    use super::{CueSettings, ReadOptions, ZeroLengthPolicy};
+
     #[derive(Clone, Debug, Eq, PartialEq, Hash)]
     pub(super) enum Rect {
         Text(String),
@@ -33,6 +42,9 @@ mod av {
         start: Timestamp,
         end: Option<Timestamp>,
         pub(super) rects: Vec<Rect>,
+        pub(super) settings: Option<CueSettings>,
+        /// Whether the subtitle is shown even when subtitles are turned off
+        pub(super) forced: bool,
     }
 
     struct AVSubtitle {
@@ -88,6 +100,11 @@ mod av {
 
             let end = duration.map(|duration| start + duration);
 
+            let forced = av_sub
+                .subtitle
+                .rects()
+                .any(|rect| rect.flags().contains(subtitle::Flags::FORCED));
+
             let rects = av_sub
                 .subtitle
                 .rects()
@@ -101,11 +118,27 @@ mod av {
                 })
                 .collect();
 
-            Ok(Self { start, end, rects })
+            Ok(Self {
+                start,
+                end,
+                rects,
+                settings: None,
+                forced,
+            })
         }
     }
 
     impl Subtitle {
+        pub(super) fn new(start: Timestamp, end: Option<Timestamp>, rects: Vec<Rect>) -> Self {
+            Self {
+                start,
+                end,
+                rects,
+                settings: None,
+                forced: false,
+            }
+        }
+
         pub(super) fn start(&self) -> Timestamp {
             self.start
         }
@@ -204,6 +237,7 @@ mod av {
 
     fn create_decoder(
         params: codec::parameters::Parameters,
+        encoding: Option<&'static Encoding>,
     ) -> Result<decoder::subtitle::Subtitle> {
         let codec = params.id();
         let context = codec::context::Context::from_parameters(params).with_context(|| {
@@ -213,153 +247,2879 @@ mod av {
             )
         })?;
 
-        context
-            .decoder()
-            .subtitle()
-            .with_context(|| format!("Failed to create decoder for `{}` codec", codec.name()))
+        match encoding {
+            // libav converts the text to UTF-8 before decoding
+            Some(encoding) => {
+                let mut options = libav::Dictionary::new();
+                options.set("sub_charenc", encoding.name());
+                let decoder = decoder::find(codec)
+                    .with_context(|| format!("No decoder for `{}` codec", codec.name()))?;
+                context
+                    .decoder()
+                    .open_as_with(decoder, options)
+                    .and_then(|decoder| decoder.subtitle())
+            }
+            None => context.decoder().subtitle(),
+        }
+        .with_context(|| format!("Failed to create decoder for `{}` codec", codec.name()))
     }
 
-    fn read_subtitles_from_stream(
-        mut ictx: Input,
-        mut decoder: decoder::subtitle::Subtitle,
-        stream_idx: usize,
-    ) -> Result<Vec<Subtitle>> {
-        let mut subs: Vec<Subtitle> = Vec::new();
+    /// Fixes up a subtitle whose timespan runs backwards, which can happen after mux errors.
+    /// Returns whether the subtitle had to be repaired.
+    fn repair_timespan(sub: &mut Subtitle) -> bool {
+        match sub.end {
+            Some(end) if end < sub.start => {
+                sub.end = Some(sub.start);
+                sub.start = end;
+                true
+            }
+            _ => false,
+        }
+    }
 
-        for (stream, packet) in ictx.packets() {
-            if stream.index() != stream_idx {
-                continue;
+    enum Source {
+        Decoder {
+            ictx: Input,
+            decoder: decoder::subtitle::Subtitle,
+            stream_idx: usize,
+            time_base: Rational,
+            /// Every subtitle of a forced stream is forced
+            forced: bool,
+        },
+        /// Subtitles that were already parsed without libav
+        Parsed(std::vec::IntoIter<Subtitle>),
+    }
+
+    /// Lazily decodes the subtitles of a single stream, so callers that only need a part of the
+    /// subtitles don't have to decode the whole stream
+    pub(super) struct SubtitleReader {
+        source: Source,
+        options: ReadOptions,
+        pending: Option<Subtitle>,
+        finished: bool,
+        read: usize,
+        swapped: usize,
+        backfilled: usize,
+        hidden: usize,
+        zero_length: usize,
+        dropped: usize,
+        reported: bool,
+    }
+
+    impl SubtitleReader {
+        /// Opens the selected stream of `file`. If `encoding` is given, the text of the subtitles is
+        /// converted from it.
+        pub(super) fn open<P: AsRef<Path>>(
+            file: &P,
+            selector: StreamSelector<'_>,
+            options: ReadOptions,
+            encoding: Option<&'static Encoding>,
+        ) -> Result<Self> {
+            let file_str = file.as_ref().to_string_lossy();
+            let ictx =
+                libav::format::input(file).context(format!("{}: Failed to open file", file_str))?;
+            trace!("Opened a {} for reading subtitles", file_str);
+
+            let stream = get_stream(&ictx, media::Type::Subtitle, selector)?;
+            let stream_idx = stream.index();
+            let time_base = stream.time_base();
+            let forced = is_forced(&stream);
+            trace!(
+                "Using {} stream at index {}{}",
+                stream.parameters().id().name(),
+                stream_idx,
+                if forced { " (forced)" } else { "" }
+            );
+
+            let decoder = create_decoder(stream.parameters(), encoding)?;
+            trace!("Created {} decoder", stream.parameters().id().name());
+
+            Ok(Self::new(
+                Source::Decoder {
+                    ictx,
+                    decoder,
+                    stream_idx,
+                    time_base,
+                    forced,
+                },
+                options,
+            ))
+        }
+
+        /// Creates a reader over subtitles that were parsed without libav. They go through the
+        /// same clean up as decoded subtitles.
+        pub(super) fn from_parsed(subs: Vec<Subtitle>, options: ReadOptions) -> Self {
+            Self::new(Source::Parsed(subs.into_iter()), options)
+        }
+
+        fn new(source: Source, options: ReadOptions) -> Self {
+            Self {
+                source,
+                options,
+                pending: None,
+                finished: false,
+                read: 0,
+                swapped: 0,
+                backfilled: 0,
+                hidden: 0,
+                zero_length: 0,
+                dropped: 0,
+                reported: false,
+            }
+        }
+
+        fn decode_next(&mut self) -> Result<Option<Subtitle>> {
+            let (ictx, decoder, stream_idx, time_base, forced) = match &mut self.source {
+                Source::Decoder {
+                    ictx,
+                    decoder,
+                    stream_idx,
+                    time_base,
+                    forced,
+                } => (ictx, decoder, *stream_idx, *time_base, *forced),
+                Source::Parsed(subs) => return Ok(subs.next()),
+            };
+
+            loop {
+                let mut packet = Packet::empty();
+                match packet.read(ictx) {
+                    Ok(()) => {}
+                    Err(libav::Error::Eof) => return Ok(None),
+                    Err(_) => continue,
+                }
+
+                if packet.stream() != stream_idx {
+                    continue;
+                }
+
+                if let Some(av_sub) = AVSubtitle::decode(packet, decoder, time_base)? {
+                    match <AVSubtitle as TryInto<Subtitle>>::try_into(av_sub) {
+                        Ok(mut sub) => {
+                            sub.forced |= forced;
+                            return Ok(Some(sub));
+                        }
+                        Err(err) => {
+                            warn!("failed to convert subtitle: {}", err);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn finish(&mut self, mut sub: Subtitle) -> Option<Subtitle> {
+            if repair_timespan(&mut sub) {
+                self.swapped += 1;
+            }
+            if let (None, Some(duration)) = (sub.end, self.options.default_duration) {
+                sub.end = Some(sub.start + duration);
+                self.backfilled += 1;
+            }
+
+            if sub.end.is_none() {
+                self.dropped += 1;
+                return None;
+            }
+            self.read += 1;
+            Some(sub)
+        }
+
+        fn report(&self) {
+            if self.swapped > 0 {
+                warn!(
+                    "repaired {} subtitle(s) whose end preceded their start",
+                    self.swapped
+                );
             }
+            if self.backfilled > 0 {
+                trace!(
+                    "Gave {} subtitle(s) without an end the default duration",
+                    self.backfilled
+                );
+            }
+            if self.hidden > 0 || self.zero_length > 0 {
+                info!(
+                    "Encountered {} hidden (screen clearing) event(s) and {} zero-length event(s)",
+                    self.hidden, self.zero_length
+                );
+            }
+            if self.dropped > 0 {
+                warn!(
+                    "dropped {} subtitle(s) of which the end is unknown, see --zero-length and --default-sub-duration",
+                    self.dropped
+                );
+            }
+            trace!("Read {} subtitles", self.read);
+        }
+    }
+
+    impl Iterator for SubtitleReader {
+        type Item = Result<Subtitle>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            while !self.finished {
+                match self.decode_next() {
+                    Ok(Some(mut sub)) => {
+                        // A subtitle without a known duration lasts until the next one starts
+                        let prev = self.pending.take().map(|mut prev| {
+                            if prev.end.is_none() {
+                                prev.end = Some(sub.start);
+                            }
+                            prev
+                        });
 
-            if let Some(av_sub) = AVSubtitle::decode(packet, &mut decoder, stream.time_base())? {
-                match <AVSubtitle as TryInto<Subtitle>>::try_into(av_sub) {
-                    Ok(sub) => {
-                        if let Some(prev_sub) = subs.last_mut() {
-                            if prev_sub.end.is_none() {
-                                prev_sub.end = Some(sub.start);
+                        if sub.rects.is_empty() {
+                            self.hidden += 1;
+                        } else {
+                            if sub.end.is_none() {
+                                self.zero_length += 1;
+                                match self.options.zero_length {
+                                    ZeroLengthPolicy::Next => {}
+                                    ZeroLengthPolicy::Keep => sub.end = Some(sub.start),
+                                    ZeroLengthPolicy::Drop => sub.rects.clear(),
+                                }
+                            }
+
+                            if !sub.rects.is_empty() {
+                                self.pending = Some(sub);
                             }
                         }
 
-                        if !sub.rects.is_empty() {
-                            subs.push(sub);
+                        if let Some(sub) = prev.and_then(|prev| self.finish(prev)) {
+                            return Some(Ok(sub));
                         }
                     }
+                    Ok(None) => {
+                        self.finished = true;
+                    }
                     Err(err) => {
-                        warn!("failed to convert subtitle: {}", err);
+                        self.finished = true;
+                        return Some(Err(err));
                     }
                 }
             }
+
+            if let Some(sub) = self.pending.take().and_then(|sub| self.finish(sub)) {
+                return Some(Ok(sub));
+            }
+
+            if !self.reported {
+                self.report();
+                self.reported = true;
+            }
+            None
         }
-        trace!("Read {} subtitles", subs.len());
-        Ok(subs)
     }
 
-    fn read_subtitles(ictx: Input, selector: StreamSelector<'_>) -> Result<Vec<Subtitle>> {
-        let stream = get_stream(ictx.streams(), media::Type::Subtitle, selector)?;
-        let stream_idx = stream.index();
-        trace!(
-            "Using {} stream at index {}",
-            stream.parameters().id().name(),
-            stream_idx
-        );
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn sub(start: u32, end: u32) -> Subtitle {
+            Subtitle {
+                start: Timestamp::from_millis(start),
+                end: Some(Timestamp::from_millis(end)),
+                rects: Vec::new(),
+                settings: None,
+                forced: false,
+            }
+        }
 
-        let decoder = create_decoder(stream.parameters())?;
-        trace!("Created {} decoder", stream.parameters().id().name());
+        #[test]
+        fn repair_swaps_reversed_span() {
+            let mut reversed = sub(3000, 2000);
+            assert!(repair_timespan(&mut reversed));
+            assert_eq!(reversed.start(), Timestamp::from_millis(2000));
+            assert_eq!(reversed.end(), Some(Timestamp::from_millis(3000)));
+        }
 
-        read_subtitles_from_stream(ictx, decoder, stream_idx)
+        #[test]
+        fn repair_keeps_valid_span() {
+            let mut valid = sub(0, 1000);
+            assert!(!repair_timespan(&mut valid));
+            assert_eq!(valid, sub(0, 1000));
+        }
     }
+}
 
-    pub(super) fn read_subtitles_from_file<P: AsRef<Path>>(
-        file: &P,
-        selector: StreamSelector<'_>,
-    ) -> Result<Vec<Subtitle>> {
-        let file_str = file.as_ref().to_string_lossy();
-        let ictx =
-            libav::format::input(file).context(format!("{}: Failed to open file", file_str))?;
-        trace!("Opened a {} for reading subtitles", file_str);
+/// A parser for plain text subtitle formats, so they can be read without the libav subtitle
+/// decoders. The text is converted to ASS the same way libav does, so both give the same
+/// dialogue.
+mod text {
+    use super::av::{Rect, Subtitle};
+    use super::CueSettings;
+    use crate::ass::DialogueEvent;
+    use crate::time::{Duration, Timestamp};
+    use anyhow::{bail, Context, Result};
+    use encoding_rs::Encoding;
+    use log::{trace, warn};
+    use std::path::Path;
 
-        read_subtitles(ictx, selector)
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub(super) enum Format {
+        Srt,
+        WebVtt,
+        /// TTML, also known as DFXP
+        Ttml,
     }
-}
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub enum Dialogue {
-    Text(String),
-    Ass(DialogueEvent),
-    Bitmap(RgbaImage),
-}
+    impl Format {
+        /// Guesses the format of `file` from its extension
+        pub(super) fn detect(file: &Path) -> Option<Self> {
+            let ext = file.extension()?.to_str()?.to_ascii_lowercase();
+            match ext.as_str() {
+                "srt" => Some(Format::Srt),
+                "vtt" => Some(Format::WebVtt),
+                "ttml" | "dfxp" | "xml" => Some(Format::Ttml),
+                _ => None,
+            }
+        }
+    }
 
-impl Serialize for Dialogue {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Dialogue::Text(text) => {
-                serializer.serialize_newtype_variant("Dialogue", 0, "Text", text)
+    pub(super) fn read(
+        file: &Path,
+        format: Format,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<Vec<Subtitle>> {
+        let file_str = file.to_string_lossy();
+        let data =
+            std::fs::read(file).with_context(|| format!("{}: Failed to open file", file_str))?;
+        let data = super::decode_text(&data, encoding);
+        trace!("Parsing {} as {:?}", file_str, format);
+        match format {
+            Format::Ttml => super::ttml::parse(&data),
+            _ => parse(&data, format),
+        }
+    }
+
+    pub(super) fn parse(data: &str, format: Format) -> Result<Vec<Subtitle>> {
+        let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+        let lines: Vec<&str> = data.lines().collect();
+        let blocks = lines
+            .split(|line| line.trim().is_empty())
+            .filter(|block| !block.is_empty());
+
+        let mut subs = Vec::new();
+        for (idx, block) in blocks.enumerate() {
+            if format == Format::WebVtt {
+                if idx == 0 {
+                    if !block[0].starts_with("WEBVTT") {
+                        bail!("WebVTT file does not start with a WEBVTT header");
+                    }
+                    continue;
+                }
+                if ["NOTE", "STYLE", "REGION"]
+                    .iter()
+                    .any(|kind| block[0].starts_with(kind))
+                {
+                    continue;
+                }
             }
-            Dialogue::Ass(ass) => serializer.serialize_newtype_variant("Dialogue", 1, "Ass", ass),
-            Dialogue::Bitmap(_) => {
-                serializer.serialize_newtype_variant("Dialogue", 2, "Bitmap", "empty")
+
+            // The timing line is optionally preceded by a cue number (SRT) or identifier (WebVTT)
+            let Some(timing) = block.iter().position(|line| line.contains("-->")) else {
+                warn!(
+                    "skipping subtitle block without a timing line: \"{}\"",
+                    block[0]
+                );
+                continue;
+            };
+            let Some((start, end, settings)) = parse_timing(block[timing]) else {
+                warn!(
+                    "skipping subtitle with invalid timing: \"{}\"",
+                    block[timing]
+                );
+                continue;
+            };
+
+            let payload = &block[timing + 1..];
+            let rects = if payload.is_empty() {
+                Vec::new()
+            } else {
+                vec![to_rect(&payload.join("\n"), format)]
+            };
+
+            // Like libav, an event without a duration has an unknown end
+            let end = (end != start).then_some(end);
+            let mut sub = Subtitle::new(start, end, rects);
+            if format == Format::WebVtt {
+                sub.settings = parse_settings(settings);
             }
+            subs.push(sub);
         }
+        Ok(subs)
     }
-}
 
-#[derive(Serialize)]
-pub struct Subtitle {
-    timespan: Timespan,
-    diag: Dialogue,
-}
+    /// Parses the start, end and the (WebVTT) cue settings that follow them
+    fn parse_timing(line: &str) -> Option<(Timestamp, Timestamp, &str)> {
+        let (start, rest) = line.split_once("-->")?;
+        let rest = rest.trim();
+        let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        Some((parse_timestamp(start)?, parse_timestamp(end)?, settings))
+    }
 
-impl From<av::Rect> for Dialogue {
-    fn from(rect: av::Rect) -> Self {
-        match rect {
-            av::Rect::Text(text) => Dialogue::Text(text),
-            av::Rect::Ass(ass) => Dialogue::Ass(ass),
-            av::Rect::Bitmap(image) => Dialogue::Bitmap(image),
+    fn parse_settings(settings: &str) -> Option<CueSettings> {
+        let mut res = CueSettings::default();
+        for setting in settings.split_whitespace() {
+            let Some((key, value)) = setting.split_once(':') else {
+                continue;
+            };
+            let value = Some(value.to_string());
+            match key {
+                "vertical" => res.vertical = value,
+                "line" => res.line = value,
+                "position" => res.position = value,
+                "size" => res.size = value,
+                "align" => res.align = value,
+                "region" => res.region = value,
+                _ => trace!("ignoring unknown cue setting \"{}\"", setting),
+            }
         }
+        (res != CueSettings::default()).then_some(res)
     }
-}
 
-impl Subtitle {
-    fn convert(subtitle: av::Subtitle) -> impl Iterator<Item = Subtitle> {
-        let start = subtitle.start();
-        let end = subtitle.end();
-        subtitle.rects.into_iter().filter_map(move |rect| {
-            end.map(|end| Self {
-                timespan: Timespan::new(start, end),
-                diag: rect.into(),
-            })
+    /// Returns the speaker of the first voice span (`<v Speaker>`) in `text`
+    fn voice(text: &str) -> Option<&str> {
+        text.match_indices('<').find_map(|(open, _)| {
+            let close = open + text[open..].find('>')?;
+            let tag = text[open + 1..close].strip_prefix('v')?;
+            if !tag.starts_with([' ', '\t', '.']) {
+                return None;
+            }
+            // The classes of the tag come before the speaker (<v.loud Speaker>)
+            let (_, name) = tag.split_once(char::is_whitespace)?;
+            Some(name.trim()).filter(|name| !name.is_empty())
         })
     }
 
-    pub const fn timespan(&self) -> Timespan {
-        self.timespan
+    /// Parses `[hh:]mm:ss[,.]fff`. Like libav, the fractional part is read as a number of
+    /// milliseconds, so `0,5` is 5 milliseconds.
+    fn parse_timestamp(s: &str) -> Option<Timestamp> {
+        let s = s.trim();
+        let (hms, millis) = match s.rsplit_once([',', '.']) {
+            Some((hms, millis)) => (hms, millis.parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+
+        let parts = hms
+            .split(':')
+            .map(|part| part.trim().parse::<i64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let secs = match parts[..] {
+            [mins, secs] => mins * 60 + secs,
+            [hours, mins, secs] => (hours * 60 + mins) * 60 + secs,
+            _ => return None,
+        };
+
+        if secs < 0 || millis < 0 {
+            return None;
+        }
+        Some(Timestamp::MIN + Duration::from_millis(secs * 1000 + millis))
     }
 
-    pub fn set_timespan(&mut self, span: Timespan) -> &mut Self {
-        self.timespan = span;
-        self
+    fn to_rect(text: &str, format: Format) -> Rect {
+        let name = match format {
+            Format::WebVtt => voice(text).unwrap_or_default(),
+            _ => "",
+        };
+        ass_rect(&to_ass(text, format), name, text)
     }
 
-    pub fn dialogue(&self) -> &Dialogue {
-        &self.diag
+    /// Creates an ASS rect from already converted `ass` text, or a text rect with `text` if the
+    /// conversion produced something invalid
+    pub(super) fn ass_rect(ass: &str, name: &str, text: &str) -> Rect {
+        match ass.parse() {
+            Ok(ass) => Rect::Ass(DialogueEvent {
+                layer: 0,
+                style: String::new(),
+                name: name.to_string(),
+                text: ass,
+            }),
+            Err(err) => {
+                warn!("failed to convert \"{}\" to an ass event: {}", text, err);
+                Rect::Text(text.to_string())
+            }
+        }
     }
 
-    pub fn text(&self) -> Option<&str> {
-        match self.dialogue() {
-            Dialogue::Text(text) => Some(text),
-            Dialogue::Ass(ass) => Some(&ass.text.dialogue),
-            Dialogue::Bitmap(_) => None,
+    /// Converts the HTML-like markup of SRT and WebVTT to ASS override tags. Unsupported tags are
+    /// dropped.
+    fn to_ass(text: &str, format: Format) -> String {
+        let mut res = String::new();
+        let mut rest = text;
+
+        while let Some(open) = rest.find('<') {
+            push_text(&mut res, &rest[..open], format);
+            let Some(close) = rest[open..].find('>') else {
+                rest = &rest[open..];
+                break;
+            };
+
+            let tag = &rest[open + 1..open + close];
+            rest = &rest[open + close + 1..];
+
+            let closing = tag.starts_with('/');
+            let tag = tag.trim_start_matches('/');
+            // WebVTT allows classes on tags (<i.loud>)
+            let name = tag
+                .split(|ch: char| ch.is_whitespace() || ch == '.')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+
+            match name.as_str() {
+                "i" | "b" | "u" | "s" => {
+                    res.push_str(&format!("{{\\{}{}}}", name, if closing { 0 } else { 1 }))
+                }
+                "font" if closing => res.push_str("{\\c}"),
+                "font" => {
+                    if let Some(color) = font_color(tag) {
+                        res.push_str(&format!("{{\\c&H{}&}}", color));
+                    }
+                }
+                _ => {}
+            }
+        }
+        push_text(&mut res, rest, format);
+        res
+    }
+
+    fn push_text(res: &mut String, text: &str, format: Format) {
+        let text = text.replace("\r\n", "\n").replace('\n', "\\N");
+        match format {
+            Format::Srt | Format::Ttml => res.push_str(&text),
+            Format::WebVtt => res.push_str(
+                &text
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&nbsp;", "\u{a0}")
+                    .replace("&lrm;", "\u{200e}")
+                    .replace("&rlm;", "\u{200f}")
+                    .replace("&amp;", "&"),
+            ),
+        }
+    }
+
+    /// Returns the color of a `<font color="#rrggbb">` tag in the BGR order ASS uses
+    fn font_color(tag: &str) -> Option<String> {
+        let (_, value) = tag.split_once("color=")?;
+        let value = value.trim_start_matches(['"', '\'']);
+        let hex = value.strip_prefix('#')?.get(..6)?;
+        if !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(format!("{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2]).to_ascii_uppercase())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn event(sub: &Subtitle) -> &DialogueEvent {
+            match &sub.rects[..] {
+                [Rect::Ass(ass)] => ass,
+                _ => panic!("expected a single ass rect"),
+            }
+        }
+
+        fn dialogue(sub: &Subtitle) -> &str {
+            &event(sub).text.dialogue
+        }
+
+        #[test]
+        fn parse_srt() {
+            let subs = parse(
+                "\u{feff}1\r\n00:00:0,0 --> 00:00:2,500 \r\nHello\r\nWorld!\r\n\r\n2\r\n00:01:02,003 --> 00:01:03,000\r\n<i>Bye</i>\r\n",
+                Format::Srt,
+            )
+            .unwrap();
+            assert_eq!(subs.len(), 2);
+            assert_eq!(subs[0].start(), Timestamp::from_millis(0));
+            assert_eq!(subs[0].end(), Some(Timestamp::from_millis(2500)));
+            assert_eq!(dialogue(&subs[0]), "Hello\\NWorld!");
+            assert_eq!(subs[1].start(), Timestamp::from_millis(62003));
+            assert_eq!(dialogue(&subs[1]), "Bye");
+        }
+
+        #[test]
+        fn parse_srt_zero_length() {
+            let subs = parse("1\n00:00:01,000 --> 00:00:01,000\nHi\n", Format::Srt).unwrap();
+            assert_eq!(subs[0].end(), None);
+        }
+
+        #[test]
+        fn parse_vtt() {
+            let subs = parse(
+                "WEBVTT - Some title\n\nNOTE a comment\n\nintro\n00:01.000 --> 00:02.000 align:start\n<v Bob>Fish &amp; chips</v>\n\n01:00:00.000 --> 01:00:01.000\n<c.yellow>Yes</c>\n",
+                Format::WebVtt,
+            )
+            .unwrap();
+            assert_eq!(subs.len(), 2);
+            assert_eq!(subs[0].start(), Timestamp::from_millis(1000));
+            assert_eq!(subs[0].end(), Some(Timestamp::from_millis(2000)));
+            assert_eq!(dialogue(&subs[0]), "Fish & chips");
+            assert_eq!(event(&subs[0]).name, "Bob");
+            assert_eq!(
+                subs[0].settings,
+                Some(CueSettings {
+                    align: Some("start".to_string()),
+                    ..Default::default()
+                })
+            );
+            assert_eq!(subs[1].start(), Timestamp::from_secs(3600));
+            assert_eq!(dialogue(&subs[1]), "Yes");
+            assert_eq!(event(&subs[1]).name, "");
+            assert_eq!(subs[1].settings, None);
+        }
+
+        #[test]
+        fn vtt_voice() {
+            assert_eq!(
+                voice("<v.first.loud Esme Tester>Hi</v>"),
+                Some("Esme Tester")
+            );
+            assert_eq!(voice("<i>Hi</i>"), None);
+            assert_eq!(voice("<v>Hi"), None);
+        }
+
+        #[test]
+        fn vtt_settings() {
+            let settings = parse_settings("line:0 position:10%,line-left foo:bar").unwrap();
+            assert_eq!(settings.line.as_deref(), Some("0"));
+            assert_eq!(settings.position.as_deref(), Some("10%,line-left"));
+            assert_eq!(parse_settings(""), None);
+        }
+
+        #[test]
+        fn parse_vtt_without_header() {
+            assert!(parse("00:01.000 --> 00:02.000\nHi\n", Format::WebVtt).is_err());
+        }
+
+        #[test]
+        fn srt_markup_to_ass() {
+            assert_eq!(
+                to_ass("<b>a</b> <font color=\"#ff8000\">b</font>", Format::Srt),
+                "{\\b1}a{\\b0} {\\c&H0080FF&}b{\\c}"
+            );
         }
     }
 }
 
-pub fn read_subtitles_from_file<P: AsRef<Path>>(
-    file: &P,
-    selector: StreamSelector<'_>,
-) -> Result<impl Iterator<Item = Subtitle>> {
-    let subs = av::read_subtitles_from_file(file, selector)?;
-    Ok(subs.into_iter().flat_map(Subtitle::convert))
+/// A parser for TTML (DFXP) subtitles, which libav can't read at all
+mod ttml {
+    use super::av::Subtitle;
+    use super::text::ass_rect;
+    use crate::time::{Duration, Timestamp};
+    use anyhow::{Context, Result};
+    use log::warn;
+    use quick_xml::events::{BytesStart, Event};
+    use quick_xml::reader::Reader;
+
+    /// The rates that frame (`f`) and tick (`t`) time expressions are relative to
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Rates {
+        frame_rate: f64,
+        tick_rate: f64,
+    }
+
+    impl Default for Rates {
+        fn default() -> Self {
+            Self {
+                frame_rate: 30.0,
+                tick_rate: 1.0,
+            }
+        }
+    }
+
+    /// A `<p>` element that is being read
+    struct Paragraph {
+        start: Timestamp,
+        end: Option<Timestamp>,
+        text: String,
+        plain: String,
+        /// Whether each of the open spans is italic
+        spans: Vec<bool>,
+    }
+
+    fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+        for attr in element.attributes() {
+            let attr = attr?;
+            if attr.key.local_name().as_ref() == name {
+                return Ok(Some(attr.unescape_value()?.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn parse_time(s: &str, rates: &Rates) -> Option<Timestamp> {
+        let s = s.trim();
+        let millis = if s.contains(':') {
+            // Clock time: hh:mm:ss(.fraction) or hh:mm:ss:frames(.subframes)
+            let parts = s
+                .split(':')
+                .map(|part| part.parse::<f64>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            match parts[..] {
+                [hours, mins, secs] => ((hours * 60.0 + mins) * 60.0 + secs) * 1000.0,
+                [hours, mins, secs, frames] => {
+                    ((hours * 60.0 + mins) * 60.0 + secs + frames / rates.frame_rate) * 1000.0
+                }
+                _ => return None,
+            }
+        } else {
+            // Offset time: a number followed by a metric
+            let idx = s.find(|ch: char| !ch.is_ascii_digit() && ch != '.')?;
+            let (value, metric) = s.split_at(idx);
+            let value: f64 = value.parse().ok()?;
+            match metric {
+                "h" => value * 60.0 * 60.0 * 1000.0,
+                "m" => value * 60.0 * 1000.0,
+                "s" => value * 1000.0,
+                "ms" => value,
+                "f" => value / rates.frame_rate * 1000.0,
+                "t" => value / rates.tick_rate * 1000.0,
+                _ => return None,
+            }
+        };
+
+        (millis.is_finite() && millis >= 0.0)
+            .then(|| Timestamp::MIN + Duration::from_millis(millis.round() as i64))
+    }
+
+    fn start_paragraph(element: &BytesStart, rates: &Rates) -> Result<Option<Paragraph>> {
+        let time = |name: &[u8]| -> Result<Option<Timestamp>> {
+            Ok(match attribute(element, name)? {
+                Some(value) => {
+                    let time = parse_time(&value, rates);
+                    if time.is_none() {
+                        warn!("ignoring invalid TTML time expression \"{}\"", value);
+                    }
+                    time
+                }
+                None => None,
+            })
+        };
+
+        let Some(start) = time(b"begin")? else {
+            warn!("skipping TTML paragraph without a begin time");
+            return Ok(None);
+        };
+        let end = match (time(b"end")?, time(b"dur")?) {
+            (Some(end), _) => Some(end),
+            (None, Some(dur)) => Some(start + Duration::from_millis(dur.as_millis())),
+            (None, None) => None,
+        };
+
+        Ok(Some(Paragraph {
+            start,
+            // Like libav, an event without a duration has an unknown end
+            end: end.filter(|end| *end != start),
+            text: String::new(),
+            plain: String::new(),
+            spans: Vec::new(),
+        }))
+    }
+
+    /// Collapses whitespace like TTML does by default and trims every line
+    fn collapse(text: &str, separator: &str) -> String {
+        text.split(separator)
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    pub(super) fn parse(data: &str) -> Result<Vec<Subtitle>> {
+        let mut reader = Reader::from_str(data);
+        let mut rates = Rates::default();
+        let mut current: Option<Paragraph> = None;
+        let mut subs = Vec::new();
+
+        loop {
+            match reader.read_event().context("Failed to parse TTML")? {
+                Event::Start(element) => match element.local_name().as_ref() {
+                    b"tt" => {
+                        if let Some(rate) = attribute(&element, b"frameRate")? {
+                            rates.frame_rate = rate.parse().context("Invalid frameRate")?;
+                        }
+                        if let Some(rate) = attribute(&element, b"tickRate")? {
+                            rates.tick_rate = rate.parse().context("Invalid tickRate")?;
+                        }
+                    }
+                    b"p" if current.is_none() => current = start_paragraph(&element, &rates)?,
+                    b"span" => {
+                        if let Some(paragraph) = current.as_mut() {
+                            let italic =
+                                attribute(&element, b"fontStyle")?.as_deref() == Some("italic");
+                            if italic {
+                                paragraph.text.push_str("{\\i1}");
+                            }
+                            paragraph.spans.push(italic);
+                        }
+                    }
+                    _ => {}
+                },
+                Event::End(element) => match element.local_name().as_ref() {
+                    b"span" => {
+                        if let Some(paragraph) = current.as_mut() {
+                            if paragraph.spans.pop() == Some(true) {
+                                paragraph.text.push_str("{\\i0}");
+                            }
+                        }
+                    }
+                    b"p" => {
+                        if let Some(paragraph) = current.take() {
+                            let text = collapse(&paragraph.text, "\\N");
+                            let rects = if text.is_empty() {
+                                Vec::new()
+                            } else {
+                                vec![ass_rect(&text, "", &collapse(&paragraph.plain, "\n"))]
+                            };
+                            subs.push(Subtitle::new(paragraph.start, paragraph.end, rects));
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Empty(element) => {
+                    if let (b"br", Some(paragraph)) =
+                        (element.local_name().as_ref(), current.as_mut())
+                    {
+                        paragraph.text.push_str("\\N");
+                        paragraph.plain.push('\n');
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some(paragraph) = current.as_mut() {
+                        let text = text.unescape().context("Failed to parse TTML")?;
+                        paragraph.text.push_str(&text);
+                        paragraph.plain.push_str(&text);
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(subs)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::av::Rect;
+        use super::*;
+
+        #[test]
+        fn time_expressions() {
+            let rates = Rates {
+                frame_rate: 25.0,
+                tick_rate: 10_000_000.0,
+            };
+            let ms = |s| parse_time(s, &rates).map(|ts| ts.as_millis());
+            assert_eq!(ms("00:01:02.5"), Some(62500));
+            assert_eq!(ms("00:00:01:05"), Some(1200));
+            assert_eq!(ms("1.5s"), Some(1500));
+            assert_eq!(ms("250ms"), Some(250));
+            assert_eq!(ms("1m"), Some(60000));
+            assert_eq!(ms("25f"), Some(1000));
+            assert_eq!(ms("15000000t"), Some(1500));
+            assert_eq!(ms("1x"), None);
+        }
+
+        #[test]
+        fn parse_paragraphs() {
+            let subs = parse(
+                r#"<?xml version="1.0" encoding="utf-8"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:tts="http://www.w3.org/ns/ttml#styling" xmlns:ttp="http://www.w3.org/ns/ttml#parameter" ttp:tickRate="10000000">
+  <body>
+    <div>
+      <p begin="10000000t" end="25000000t">
+        Fish &amp;
+        <span tts:fontStyle="italic">chips</span><br/>please
+      </p>
+      <p begin="00:00:03.000" dur="1s"></p>
+    </div>
+  </body>
+</tt>"#,
+            )
+            .unwrap();
+
+            assert_eq!(subs.len(), 2);
+            assert_eq!(subs[0].start(), Timestamp::from_millis(1000));
+            assert_eq!(subs[0].end(), Some(Timestamp::from_millis(2500)));
+            match &subs[0].rects[..] {
+                [Rect::Ass(ass)] => {
+                    assert_eq!(ass.text.text, "Fish & {\\i1}chips{\\i0}\\Nplease");
+                    assert_eq!(ass.text.dialogue, "Fish & chips\\Nplease");
+                }
+                _ => panic!("expected a single ass rect"),
+            }
+            assert_eq!(subs[1].end(), Some(Timestamp::from_millis(4000)));
+            assert!(subs[1].rects.is_empty());
+        }
+    }
+}
+
+/// A parser for MicroDVD subtitles, of which the timings are frame numbers instead of timestamps
+mod microdvd {
+    use super::av::Subtitle;
+    use super::text::ass_rect;
+    use crate::time::{Duration, Timestamp};
+    use anyhow::{Context, Result};
+    use encoding_rs::Encoding;
+    use log::{trace, warn};
+    use std::path::Path;
+
+    /// The frame rate of most films, used when the frame rate is not known in any other way
+    const DEFAULT_FPS: f64 = 23.976;
+
+    /// Whether `file` is a MicroDVD file. A `.sub` file is also used for VobSub bitmaps and some
+    /// other text formats, so the first line has to look like a MicroDVD event as well.
+    pub(super) fn detect(file: &Path) -> bool {
+        let is_sub = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("sub"));
+        if !is_sub || file.with_extension("idx").exists() {
+            return false;
+        }
+
+        let Ok(data) = std::fs::read(file) else {
+            return false;
+        };
+        let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data);
+        let mut data = data.iter().skip_while(|ch| ch.is_ascii_whitespace());
+        data.next() == Some(&b'{') && data.next().is_some_and(u8::is_ascii_digit)
+    }
+
+    pub(super) fn read(
+        file: &Path,
+        encoding: Option<&'static Encoding>,
+        fps: Option<f64>,
+        media_fps: Option<f64>,
+    ) -> Result<Vec<Subtitle>> {
+        let file_str = file.to_string_lossy();
+        let data =
+            std::fs::read(file).with_context(|| format!("{}: Failed to open file", file_str))?;
+        let data = super::decode_text(&data, encoding);
+        trace!("Parsing {} as MicroDVD", file_str);
+        Ok(parse(&data, fps, media_fps))
+    }
+
+    /// Splits a line into its start frame, end frame (empty if unknown) and text
+    fn split_line(line: &str) -> Option<(u64, Option<u64>, &str)> {
+        let rest = line.trim_start().strip_prefix('{')?;
+        let (start, rest) = rest.split_once('}')?;
+        let rest = rest.strip_prefix('{')?;
+        let (end, text) = rest.split_once('}')?;
+
+        let start = start.trim().parse().ok()?;
+        let end = match end.trim() {
+            "" => None,
+            end => Some(end.parse().ok()?),
+        };
+        Some((start, end, text))
+    }
+
+    /// Returns the frame rate declared by a `{1}{1}25.000` line at the start of the file
+    fn declared_fps(line: &str) -> Option<f64> {
+        match split_line(line)? {
+            (0..=1, Some(0..=1), fps) => {
+                let fps: f64 = fps.trim().parse().ok()?;
+                (fps.is_finite() && fps > 0.0).then_some(fps)
+            }
+            _ => None,
+        }
+    }
+
+    fn to_timestamp(frame: u64, fps: f64) -> Timestamp {
+        Timestamp::MIN + Duration::from_millis((frame as f64 / fps * 1000.0).round() as i64)
+    }
+
+    pub(super) fn parse(data: &str, fps: Option<f64>, media_fps: Option<f64>) -> Vec<Subtitle> {
+        let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+        let mut lines = data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .peekable();
+
+        let declared = lines.peek().and_then(|line| declared_fps(line));
+        if declared.is_some() {
+            lines.next();
+        }
+        let fps = match (fps, declared, media_fps) {
+            (Some(fps), _, _) => fps,
+            (None, Some(fps), _) => {
+                trace!("Using the frame rate declared in the file: {}", fps);
+                fps
+            }
+            (None, None, Some(fps)) => {
+                trace!("Using the frame rate of the media file: {}", fps);
+                fps
+            }
+            (None, None, None) => {
+                warn!(
+                    "frame rate of MicroDVD subtitles is unknown, assuming {} (see --sub-fps)",
+                    DEFAULT_FPS
+                );
+                DEFAULT_FPS
+            }
+        };
+
+        let mut subs = Vec::new();
+        for line in lines {
+            let Some((start, end, text)) = split_line(line) else {
+                warn!("skipping invalid MicroDVD line: \"{}\"", line);
+                continue;
+            };
+
+            let rects = if text.trim().is_empty() {
+                Vec::new()
+            } else {
+                let plain = text.replace('|', "\n");
+                vec![ass_rect(&to_ass(text), "", &plain)]
+            };
+            // Like libav, an event without a duration has an unknown end
+            let end = end
+                .filter(|end| *end != start)
+                .map(|end| to_timestamp(end, fps));
+            subs.push(Subtitle::new(to_timestamp(start, fps), end, rects));
+        }
+        subs
+    }
+
+    /// Converts the control codes at the start of every line to ASS override tags. Codes with a
+    /// lowercase letter apply to their line, those with an uppercase letter to the whole subtitle.
+    /// Unsupported codes (fonts, sizes, positions) are dropped.
+    fn to_ass(text: &str) -> String {
+        let mut global = String::new();
+        let mut lines = Vec::new();
+
+        for line in text.split('|') {
+            let mut open = String::new();
+            let mut close = String::new();
+            let mut rest = line;
+            while let Some((code, tail)) = control_code(rest) {
+                rest = tail;
+                let (kind, value) = code.split_at(1);
+                let tags: Vec<(String, String)> = match kind.to_ascii_lowercase().as_str() {
+                    "y" => value
+                        .chars()
+                        .filter(|style| matches!(style, 'i' | 'b' | 'u' | 's'))
+                        .map(|style| (format!("{{\\{}1}}", style), format!("{{\\{}0}}", style)))
+                        .collect(),
+                    "c" => match value.strip_prefix('$') {
+                        // MicroDVD colors are already in the BGR order ASS uses
+                        Some(color) if color.chars().all(|ch| ch.is_ascii_hexdigit()) => vec![(
+                            format!("{{\\c&H{}&}}", color.to_ascii_uppercase()),
+                            "{\\c}".to_string(),
+                        )],
+                        _ => Vec::new(),
+                    },
+                    _ => {
+                        trace!("ignoring MicroDVD control code \"{}\"", code);
+                        Vec::new()
+                    }
+                };
+
+                for (tag, reset) in tags {
+                    if kind.chars().all(|ch| ch.is_ascii_uppercase()) {
+                        global.push_str(&tag);
+                    } else {
+                        open.push_str(&tag);
+                        close.push_str(&reset);
+                    }
+                }
+            }
+            lines.push(format!("{}{}{}", open, rest, close));
+        }
+        format!("{}{}", global, lines.join("\\N"))
+    }
+
+    /// Splits a leading `{y:i}` control code off `text`, returning the code without its braces
+    /// and colon, e.g. `yi`
+    fn control_code(text: &str) -> Option<(String, &str)> {
+        let rest = text.strip_prefix('{')?;
+        let (code, tail) = rest.split_once('}')?;
+        let (kind, value) = code.split_once(':')?;
+        if kind.len() != 1 || !kind.chars().all(|ch| ch.is_ascii_alphabetic()) {
+            return None;
+        }
+        Some((format!("{}{}", kind, value.trim()), tail))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::av::Rect;
+        use super::*;
+
+        fn dialogue(sub: &Subtitle) -> &str {
+            match &sub.rects[..] {
+                [Rect::Ass(ass)] => &ass.text.dialogue,
+                _ => panic!("expected a single ass rect"),
+            }
+        }
+
+        #[test]
+        fn parse_declared_fps() {
+            let subs = parse(
+                "{1}{1}25.000\n{25}{50}Hello|World\n{100}{}Bye\n",
+                None,
+                None,
+            );
+            assert_eq!(subs.len(), 2);
+            assert_eq!(subs[0].start(), Timestamp::from_millis(1000));
+            assert_eq!(subs[0].end(), Some(Timestamp::from_millis(2000)));
+            assert_eq!(dialogue(&subs[0]), "Hello\\NWorld");
+            assert_eq!(subs[1].start(), Timestamp::from_millis(4000));
+            assert_eq!(subs[1].end(), None);
+        }
+
+        #[test]
+        fn fps_precedence() {
+            let data = "{1}{1}25\n{50}{100}Hi\n";
+            assert_eq!(
+                parse(data, Some(50.0), Some(10.0))[0].start(),
+                Timestamp::from_millis(1000)
+            );
+            assert_eq!(
+                parse(data, None, Some(10.0))[0].start(),
+                Timestamp::from_millis(2000)
+            );
+            assert_eq!(
+                parse("{50}{100}Hi\n", None, Some(10.0))[0].start(),
+                Timestamp::from_millis(5000)
+            );
+        }
+
+        #[test]
+        fn control_codes_to_ass() {
+            assert_eq!(
+                to_ass("{y:i}Hello|{c:$0000FF}World"),
+                "{\\i1}Hello{\\i0}\\N{\\c&H0000FF&}World{\\c}"
+            );
+            assert_eq!(to_ass("{Y:b}{f:Arial}A|B"), "{\\b1}A\\NB");
+            assert_eq!(to_ass("{not a code}"), "{not a code}");
+        }
+    }
+}
+
+/// A demuxer and decoder for standalone PGS (Blu-ray) subtitle files
+mod pgs {
+    use super::av::{Rect, Subtitle};
+    use crate::time::{Duration, Timestamp};
+    use anyhow::{bail, Context, Result};
+    use image::{imageops, Rgba, RgbaImage};
+    use log::{trace, warn};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    const SEGMENT_PDS: u8 = 0x14;
+    const SEGMENT_ODS: u8 = 0x15;
+    const SEGMENT_PCS: u8 = 0x16;
+    const SEGMENT_WDS: u8 = 0x17;
+    const SEGMENT_END: u8 = 0x80;
+
+    /// Composition state of a display set that starts a new epoch, which resets all objects and
+    /// palettes
+    const EPOCH_START: u8 = 0x80;
+
+    /// Whether `file` is a standalone PGS file
+    pub(super) fn detect(file: &Path) -> bool {
+        file.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("sup"))
+    }
+
+    struct Cursor<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Cursor<'a> {
+        fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+            if self.data.len() < len {
+                bail!("Unexpected end of PGS data");
+            }
+            let (head, tail) = self.data.split_at(len);
+            self.data = tail;
+            Ok(head)
+        }
+
+        fn u8(&mut self) -> Result<u8> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn u16(&mut self) -> Result<u16> {
+            let bytes = self.take(2)?;
+            Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+        }
+
+        fn u24(&mut self) -> Result<u32> {
+            let bytes = self.take(3)?;
+            Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+        }
+
+        fn u32(&mut self) -> Result<u32> {
+            let bytes = self.take(4)?;
+            Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        }
+    }
+
+    struct CompositionObject {
+        object_id: u16,
+        forced: bool,
+        crop: Option<(u32, u32, u32, u32)>,
+    }
+
+    struct Composition {
+        state: u8,
+        palette_update: bool,
+        palette_id: u8,
+        objects: Vec<CompositionObject>,
+    }
+
+    struct Object {
+        width: u16,
+        height: u16,
+        data: Vec<u8>,
+    }
+
+    /// Converts a BT.709 (limited range) palette entry to RGBA
+    fn to_rgba(y: u8, cr: u8, cb: u8, alpha: u8) -> Rgba<u8> {
+        let y = 1.164 * (f32::from(y) - 16.0);
+        let cr = f32::from(cr) - 128.0;
+        let cb = f32::from(cb) - 128.0;
+        let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+        Rgba([
+            clamp(y + 1.793 * cr),
+            clamp(y - 0.213 * cb - 0.533 * cr),
+            clamp(y + 2.112 * cb),
+            alpha,
+        ])
+    }
+
+    fn parse_composition(data: &[u8]) -> Result<Composition> {
+        let mut cursor = Cursor { data };
+        // width, height, frame rate and composition number
+        cursor.take(7)?;
+        let state = cursor.u8()?;
+        let palette_update = cursor.u8()? & 0x80 != 0;
+        let palette_id = cursor.u8()?;
+        let count = cursor.u8()?;
+
+        let mut objects = Vec::new();
+        for _ in 0..count {
+            let object_id = cursor.u16()?;
+            let _window_id = cursor.u8()?;
+            let flags = cursor.u8()?;
+            let cropped = flags & 0x80 != 0;
+            let forced = flags & 0x40 != 0;
+            // position on screen
+            cursor.take(4)?;
+            let crop = if cropped {
+                Some((
+                    cursor.u16()?.into(),
+                    cursor.u16()?.into(),
+                    cursor.u16()?.into(),
+                    cursor.u16()?.into(),
+                ))
+            } else {
+                None
+            };
+            objects.push(CompositionObject {
+                object_id,
+                forced,
+                crop,
+            });
+        }
+
+        Ok(Composition {
+            state,
+            palette_update,
+            palette_id,
+            objects,
+        })
+    }
+
+    fn parse_palette(data: &[u8], palette: &mut [Rgba<u8>; 256]) -> Result<()> {
+        let mut cursor = Cursor { data };
+        // palette id and version
+        cursor.take(2)?;
+        while !cursor.data.is_empty() {
+            let entry = cursor.take(5)?;
+            palette[usize::from(entry[0])] = to_rgba(entry[1], entry[2], entry[3], entry[4]);
+        }
+        Ok(())
+    }
+
+    /// Decodes the run-length encoded object into palette indices
+    fn decode_rle(object: &Object) -> Result<Vec<u8>> {
+        let width = usize::from(object.width);
+        let height = usize::from(object.height);
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut cursor = Cursor { data: &object.data };
+
+        while !cursor.data.is_empty() {
+            let byte = cursor.u8()?;
+            if byte != 0 {
+                pixels.push(byte);
+                continue;
+            }
+
+            let flags = cursor.u8()?;
+            if flags == 0 {
+                // end of line, pad lines that were shorter than the width
+                let line = pixels.len() % width.max(1);
+                if line != 0 {
+                    pixels.resize(pixels.len() + width - line, 0);
+                }
+                continue;
+            }
+
+            let len = match flags & 0x40 {
+                0 => usize::from(flags & 0x3f),
+                _ => (usize::from(flags & 0x3f) << 8) | usize::from(cursor.u8()?),
+            };
+            let color = match flags & 0x80 {
+                0 => 0,
+                _ => cursor.u8()?,
+            };
+            pixels.resize(pixels.len() + len, color);
+        }
+
+        pixels.resize(width * height, 0);
+        Ok(pixels)
+    }
+
+    fn render(object: &Object, palette: &[Rgba<u8>; 256]) -> Result<RgbaImage> {
+        let indices = decode_rle(object)?;
+        let mut image = RgbaImage::new(object.width.into(), object.height.into());
+        for (pixel, index) in image.pixels_mut().zip(indices) {
+            *pixel = palette[usize::from(index)];
+        }
+        Ok(image)
+    }
+
+    pub(super) fn read(file: &Path) -> Result<Vec<Subtitle>> {
+        let data = std::fs::read(file)
+            .with_context(|| format!("{}: Failed to open file", file.to_string_lossy()))?;
+        trace!("Parsing {} as PGS", file.to_string_lossy());
+        parse(&data)
+    }
+
+    pub(super) fn parse(data: &[u8]) -> Result<Vec<Subtitle>> {
+        let mut cursor = Cursor { data };
+        let mut palettes: HashMap<u8, [Rgba<u8>; 256]> = HashMap::new();
+        let mut objects: HashMap<u16, Object> = HashMap::new();
+        let mut composition: Option<(Timestamp, Composition)> = None;
+        let mut subs = Vec::new();
+
+        while !cursor.data.is_empty() {
+            if cursor.take(2)? != b"PG" {
+                bail!("Invalid PGS segment header");
+            }
+            let pts = cursor.u32()?;
+            let _dts = cursor.u32()?;
+            let kind = cursor.u8()?;
+            let len = cursor.u16()?;
+            let segment = cursor.take(len.into())?;
+
+            match kind {
+                SEGMENT_PCS => {
+                    let pcs = parse_composition(segment)?;
+                    if pcs.state & EPOCH_START != 0 {
+                        palettes.clear();
+                        objects.clear();
+                    }
+                    // The timestamps use a 90kHz clock
+                    let start = Timestamp::MIN + Duration::from_millis(i64::from(pts) / 90);
+                    composition = Some((start, pcs));
+                }
+                SEGMENT_PDS => {
+                    let id = *segment.first().context("Empty PGS palette")?;
+                    let palette = palettes.entry(id).or_insert([Rgba([0, 0, 0, 0]); 256]);
+                    parse_palette(segment, palette)?;
+                }
+                SEGMENT_ODS => {
+                    let mut ods = Cursor { data: segment };
+                    let id = ods.u16()?;
+                    let _version = ods.u8()?;
+                    let sequence = ods.u8()?;
+                    if sequence & 0x80 != 0 {
+                        // first fragment
+                        let _len = ods.u24()?;
+                        let width = ods.u16()?;
+                        let height = ods.u16()?;
+                        objects.insert(
+                            id,
+                            Object {
+                                width,
+                                height,
+                                data: ods.data.to_vec(),
+                            },
+                        );
+                    } else if let Some(object) = objects.get_mut(&id) {
+                        object.data.extend_from_slice(ods.data);
+                    }
+                }
+                SEGMENT_WDS => {}
+                SEGMENT_END => {
+                    let Some((start, pcs)) = composition.take() else {
+                        continue;
+                    };
+                    // Palette updates only change the colors of the current subtitle (fades)
+                    if pcs.palette_update {
+                        continue;
+                    }
+
+                    let mut rects = Vec::new();
+                    for comp in &pcs.objects {
+                        let (Some(object), Some(palette)) =
+                            (objects.get(&comp.object_id), palettes.get(&pcs.palette_id))
+                        else {
+                            warn!("PGS composition refers to a missing object or palette");
+                            continue;
+                        };
+
+                        let mut image = render(object, palette)?;
+                        if let Some((x, y, width, height)) = comp.crop {
+                            image = imageops::crop_imm(&image, x, y, width, height).to_image();
+                        }
+                        rects.push(Rect::Bitmap(image));
+                    }
+                    // Display sets without objects clear the screen, which ends the previous one
+                    let mut sub = Subtitle::new(start, None, rects);
+                    sub.forced = pcs.objects.iter().any(|comp| comp.forced);
+                    subs.push(sub);
+                }
+                _ => warn!("ignoring unknown PGS segment type {:#x}", kind),
+            }
+        }
+        Ok(subs)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn segment(kind: u8, pts: u32, data: &[u8]) -> Vec<u8> {
+            let mut res = b"PG".to_vec();
+            res.extend_from_slice(&pts.to_be_bytes());
+            res.extend_from_slice(&0u32.to_be_bytes());
+            res.push(kind);
+            res.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            res.extend_from_slice(data);
+            res
+        }
+
+        fn composition(objects: u8) -> Vec<u8> {
+            let mut pcs = vec![0x07, 0x80, 0x04, 0x38, 0x10, 0x00, 0x00, 0x80, 0x00, 0x00];
+            pcs.push(objects);
+            for _ in 0..objects {
+                pcs.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x10]);
+            }
+            pcs
+        }
+
+        #[test]
+        fn decode_runs() {
+            let object = Object {
+                width: 4,
+                height: 2,
+                // 1 pixel of color 5, 3 pixels of color 0, end of line, 4 pixels of color 7
+                data: vec![0x05, 0x00, 0x03, 0x00, 0x00, 0x00, 0x84, 0x07, 0x00, 0x00],
+            };
+            assert_eq!(decode_rle(&object).unwrap(), vec![5, 0, 0, 0, 7, 7, 7, 7]);
+        }
+
+        #[test]
+        fn parse_display_sets() {
+            let mut data = Vec::new();
+            let mut pcs = composition(1);
+            // object is forced
+            pcs[14] = 0x40;
+            data.extend(segment(SEGMENT_PCS, 90_000, &pcs));
+            // palette 0, entry 1 is opaque white
+            data.extend(segment(SEGMENT_PDS, 90_000, &[0, 0, 1, 235, 128, 128, 255]));
+            let mut ods = vec![
+                0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x08, 0x00, 0x02, 0x00, 0x01,
+            ];
+            ods.extend_from_slice(&[0x00, 0x82, 0x01, 0x00, 0x00]);
+            data.extend(segment(SEGMENT_ODS, 90_000, &ods));
+            data.extend(segment(SEGMENT_END, 90_000, &[]));
+
+            let mut clear = composition(0);
+            clear[7] = 0x00;
+            data.extend(segment(SEGMENT_PCS, 180_000, &clear));
+            data.extend(segment(SEGMENT_END, 180_000, &[]));
+
+            let subs = parse(&data).unwrap();
+            assert_eq!(subs.len(), 2);
+            assert_eq!(subs[0].start(), Timestamp::from_millis(1000));
+            match &subs[0].rects[..] {
+                [Rect::Bitmap(image)] => {
+                    assert_eq!(image.dimensions(), (2, 1));
+                    assert_eq!(image.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+                }
+                _ => panic!("expected a single bitmap"),
+            }
+            assert!(subs[0].forced);
+            assert_eq!(subs[1].start(), Timestamp::from_millis(2000));
+            assert!(subs[1].rects.is_empty());
+            assert!(!subs[1].forced);
+        }
+    }
+}
+
+/// What to do with subtitle events that have no duration. Some muxers use those to clear the
+/// screen, others just don't store the duration.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ZeroLengthPolicy {
+    /// Let the event last until the next event starts
+    #[default]
+    Next,
+    /// Keep the event as is, with the same start and end
+    Keep,
+    /// Drop the event
+    Drop,
+}
+
+impl FromStr for ZeroLengthPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "next" => Ok(ZeroLengthPolicy::Next),
+            "keep" => Ok(ZeroLengthPolicy::Keep),
+            "drop" => Ok(ZeroLengthPolicy::Drop),
+            _ => bail!(
+                "\"{}\" is not a valid zero-length policy, expected next, keep or drop",
+                s
+            ),
+        }
+    }
+}
+
+/// What line breaks in subtitle texts are replaced with
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NewlineMode {
+    /// Keep them as ASS line breaks (`\N`)
+    Keep,
+    /// Replace them with a space
+    Space,
+    /// Replace them with an HTML line break
+    Br,
+    /// Replace them with the given text
+    Separator(String),
+}
+
+impl NewlineMode {
+    pub fn separator(&self) -> &str {
+        match self {
+            NewlineMode::Keep => "\\N",
+            NewlineMode::Space => " ",
+            NewlineMode::Br => "<br>",
+            NewlineMode::Separator(separator) => separator,
+        }
+    }
+}
+
+impl FromStr for NewlineMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(NewlineMode::Keep),
+            "space" => Ok(NewlineMode::Space),
+            "br" => Ok(NewlineMode::Br),
+            _ => match s.strip_prefix("separator=") {
+                Some(separator) => Ok(NewlineMode::Separator(separator.to_string())),
+                None => bail!(
+                    "\"{}\" is not a valid newline mode, expected keep, space, br or separator=TEXT",
+                    s
+                ),
+            },
+        }
+    }
+}
+
+/// A transform of the text of subtitles for `--normalize`, so texts that are only formatted
+/// differently compare equal. ASS line breaks (`\N`) and override tags (`{...}`) are kept.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TextNormalization {
+    Lowercase,
+    /// Remove every character that is not a letter, digit or whitespace
+    StripPunct,
+    /// Replace runs of whitespace with a single space and trim the lines
+    CollapseWhitespace,
+}
+
+impl TextNormalization {
+    pub fn apply(&self, text: &str) -> String {
+        text.split("\\N")
+            .map(|line| match self {
+                TextNormalization::Lowercase => line.to_lowercase(),
+                TextNormalization::StripPunct => {
+                    let mut in_tag = false;
+                    line.chars()
+                        .filter(|&ch| {
+                            match ch {
+                                '{' => in_tag = true,
+                                '}' if in_tag => {
+                                    in_tag = false;
+                                    return true;
+                                }
+                                _ => {}
+                            }
+                            in_tag || ch.is_alphanumeric() || ch.is_whitespace()
+                        })
+                        .collect()
+                }
+                TextNormalization::CollapseWhitespace => {
+                    line.split_whitespace().collect::<Vec<_>>().join(" ")
+                }
+            })
+            .join("\\N")
+    }
+}
+
+/// Applies `normalizations` to `text` in order
+pub fn apply_normalizations(normalizations: &[TextNormalization], text: &str) -> String {
+    normalizations
+        .iter()
+        .fold(text.to_string(), |text, normalization| {
+            normalization.apply(&text)
+        })
+}
+
+impl FromStr for TextNormalization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lowercase" => Ok(TextNormalization::Lowercase),
+            "strip-punct" => Ok(TextNormalization::StripPunct),
+            "collapse-whitespace" => Ok(TextNormalization::CollapseWhitespace),
+            _ => bail!(
+                "\"{}\" is not a valid normalization, expected lowercase, strip-punct or collapse-whitespace",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReadOptions {
+    /// The duration given to subtitles of which the end is still unknown after applying the
+    /// zero-length policy. Those subtitles are dropped if this is `None`.
+    pub default_duration: Option<Duration>,
+    pub zero_length: ZeroLengthPolicy,
+    /// The encoding of text subtitle files, it is detected if this is `None`
+    pub encoding: Option<&'static Encoding>,
+    /// The frame rate of frame based subtitles, overriding the one declared in the file
+    pub fps: Option<f64>,
+    /// The frame rate of the media file that belongs to the subtitle file, used for frame based
+    /// subtitles that don't declare one
+    pub media_fps: Option<f64>,
+}
+
+/// The positioning settings of a WebVTT cue, kept as written in the file
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize)]
+pub struct CueSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vertical: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Dialogue {
+    Text(String),
+    Ass(DialogueEvent),
+    Bitmap(RgbaImage),
+}
+
+impl Serialize for Dialogue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Dialogue::Text(text) => {
+                serializer.serialize_newtype_variant("Dialogue", 0, "Text", text)
+            }
+            Dialogue::Ass(ass) => serializer.serialize_newtype_variant("Dialogue", 1, "Ass", ass),
+            Dialogue::Bitmap(_) => {
+                serializer.serialize_newtype_variant("Dialogue", 2, "Bitmap", "empty")
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Subtitle {
+    timespan: Timespan,
+    diag: Dialogue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    settings: Option<CueSettings>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    forced: bool,
+    /// The timespans of the subtitles this subtitle was grouped from by `group_context`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parts: Vec<Timespan>,
+}
+
+impl From<av::Rect> for Dialogue {
+    fn from(rect: av::Rect) -> Self {
+        match rect {
+            av::Rect::Text(text) => Dialogue::Text(text),
+            av::Rect::Ass(ass) => Dialogue::Ass(ass),
+            av::Rect::Bitmap(image) => Dialogue::Bitmap(image),
+        }
+    }
+}
+
+impl Subtitle {
+    pub fn new(timespan: Timespan, diag: Dialogue) -> Self {
+        Self {
+            timespan,
+            diag,
+            settings: None,
+            forced: false,
+            parts: Vec::new(),
+        }
+    }
+
+    fn convert(subtitle: av::Subtitle) -> impl Iterator<Item = Subtitle> {
+        let start = subtitle.start();
+        let end = subtitle.end();
+        let settings = subtitle.settings;
+        let forced = subtitle.forced;
+        subtitle.rects.into_iter().filter_map(move |rect| {
+            end.map(|end| Self {
+                timespan: Timespan::new(start, end),
+                diag: rect.into(),
+                settings: settings.clone(),
+                forced,
+                parts: Vec::new(),
+            })
+        })
+    }
+
+    pub const fn timespan(&self) -> Timespan {
+        self.timespan
+    }
+
+    /// The timespans of the lines of a subtitle made by `group_context`, empty for other subtitles
+    pub fn parts(&self) -> &[Timespan] {
+        &self.parts
+    }
+
+    pub fn set_timespan(&mut self, span: Timespan) -> &mut Self {
+        self.timespan = span;
+        self
+    }
+
+    pub fn dialogue(&self) -> &Dialogue {
+        &self.diag
+    }
+
+    /// Whether the subtitle is forced, either because it comes from a forced stream or because
+    /// the event itself is marked as forced
+    pub fn forced(&self) -> bool {
+        self.forced
+    }
+
+    pub fn set_forced(&mut self, forced: bool) -> &mut Self {
+        self.forced = forced;
+        self
+    }
+
+    /// Replaces the text of the subtitle with the result of `f`. Bitmap subtitles are left alone.
+    pub fn map_text<F: Fn(&str) -> String>(&mut self, f: F) -> &mut Self {
+        match &mut self.diag {
+            Dialogue::Text(text) => *text = f(text),
+            Dialogue::Ass(ass) => ass.text.map_dialogue(f),
+            Dialogue::Bitmap(_) => {}
+        }
+        self
+    }
+
+    /// Removes the HTML-like inline tags from the text of the subtitle. See [`strip_tags`].
+    pub fn strip_tags(&mut self) -> &mut Self {
+        self.map_text(strip_tags)
+    }
+
+    /// Collapses the whitespace of the text of the subtitle and replaces its line breaks with
+    /// `separator`. See [`normalize_whitespace`].
+    pub fn normalize_whitespace(&mut self, separator: &str) -> &mut Self {
+        self.map_text(|text| normalize_whitespace(text, separator))
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self.dialogue() {
+            Dialogue::Text(text) => Some(text),
+            Dialogue::Ass(ass) => Some(&ass.text.dialogue),
+            Dialogue::Bitmap(_) => None,
+        }
+    }
+
+    /// The reading speed the subtitle asks for in characters per second, counting the characters
+    /// of the text after `normalizations`. `None` for subtitles without text.
+    pub fn cps(&self, normalizations: &[TextNormalization]) -> Option<f64> {
+        let text = apply_normalizations(normalizations, self.text()?);
+        let chars = text
+            .replace("\\N", " ")
+            .replace("\\n", " ")
+            .trim()
+            .chars()
+            .count();
+        let millis = self.timespan.duration().as_millis();
+        Some(match (chars, millis) {
+            (0, _) => 0.0,
+            (_, 0) => f64::INFINITY,
+            _ => chars as f64 * 1000.0 / millis as f64,
+        })
+    }
+
+    /// The name of the speaker of the subtitle (the Name of an ASS event or the voice of a
+    /// WebVTT cue), if it has one
+    pub fn speaker(&self) -> Option<&str> {
+        match self.dialogue() {
+            Dialogue::Ass(ass) if !ass.name.trim().is_empty() => Some(ass.name.trim()),
+            _ => None,
+        }
+    }
+}
+
+/// Extensions of text subtitle formats that libav reads. libav expects these to be UTF-8.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "ass", "ssa", "sub", "smi", "sami", "txt", "lrc", "mpl", "jss", "pjs", "rt", "stl",
+];
+
+fn has_text_extension(file: &Path) -> bool {
+    let ext = file.extension().and_then(OsStr::to_str).unwrap_or_default();
+    TEXT_EXTENSIONS
+        .iter()
+        .any(|text_ext| ext.eq_ignore_ascii_case(text_ext))
+}
+
+/// Guesses the encoding of text that is not valid UTF-8. Returns `None` for UTF-8 text.
+fn detect_encoding(data: &[u8]) -> Option<&'static Encoding> {
+    if Encoding::for_bom(data).is_some() || std::str::from_utf8(data).is_ok() {
+        return None;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(data, true);
+    let encoding = detector.guess(None, true);
+    info!(
+        "subtitle file is not valid UTF-8, reading it as {} (see --sub-encoding)",
+        encoding.name()
+    );
+    Some(encoding)
+}
+
+fn detect_file_encoding(file: &Path) -> Result<Option<&'static Encoding>> {
+    let data = std::fs::read(file)
+        .with_context(|| format!("{}: Failed to open file", file.to_string_lossy()))?;
+    Ok(detect_encoding(&data))
+}
+
+/// Decodes the contents of a text subtitle file. A byte order mark takes precedence over
+/// `encoding`, without either the encoding is detected.
+fn decode_text(data: &[u8], encoding: Option<&'static Encoding>) -> String {
+    let encoding = Encoding::for_bom(data)
+        .map(|(encoding, _)| encoding)
+        .or(encoding)
+        .or_else(|| detect_encoding(data))
+        .unwrap_or(UTF_8);
+    let (text, _, had_errors) = encoding.decode(data);
+    if had_errors {
+        warn!(
+            "subtitle file contains text that is not valid {}",
+            encoding.name()
+        );
+    }
+    text.into_owned()
+}
+
+/// Whether the timings of `file` are frame numbers, which need the frame rate of the media to be
+/// converted to timestamps
+pub fn is_frame_based(file: &Path) -> bool {
+    microdvd::detect(file)
+}
+
+/// VobSub subtitles come as an `.idx` file with the timings and palette and a `.sub` file with
+/// the bitmaps. libav reads the pair through the `.idx` file, so that is the file to open when
+/// given either of them.
+fn vobsub_index(file: &Path) -> Result<Option<PathBuf>> {
+    let ext = file.extension().and_then(OsStr::to_str).unwrap_or_default();
+    if ext.eq_ignore_ascii_case("idx") {
+        if !file.with_extension("sub").exists() {
+            bail!(
+                "VobSub index \"{}\" has no matching .sub file",
+                file.to_string_lossy()
+            );
+        }
+        return Ok(Some(file.to_path_buf()));
+    }
+
+    // A .sub file without an index is most likely a text (MicroDVD) subtitle
+    let index = file.with_extension("idx");
+    if ext.eq_ignore_ascii_case("sub") && index.exists() {
+        trace!(
+            "Reading VobSub subtitles of {} through {}",
+            file.to_string_lossy(),
+            index.to_string_lossy()
+        );
+        return Ok(Some(index));
+    }
+    Ok(None)
+}
+
+/// Returns an iterator that decodes the subtitles from `file` on demand
+pub fn read_subtitles_from_file<P: AsRef<Path>>(
+    file: &P,
+    selector: StreamSelector<'_>,
+    options: ReadOptions,
+) -> Result<impl Iterator<Item = Result<Subtitle>>> {
+    // Text and PGS files only have a single stream, any other selector goes through libav so it
+    // fails the same way it would for other files
+    let path = file.as_ref();
+    let parsed = if !matches!(
+        selector,
+        StreamSelector::Best | StreamSelector::BestText | StreamSelector::Index(0)
+    ) {
+        None
+    } else if let Some(format) = text::Format::detect(path) {
+        Some(text::read(path, format, options.encoding)?)
+    } else if pgs::detect(path) {
+        Some(pgs::read(path)?)
+    } else if microdvd::detect(path) {
+        Some(microdvd::read(
+            path,
+            options.encoding,
+            options.fps,
+            options.media_fps,
+        )?)
+    } else {
+        None
+    };
+
+    let reader = match (parsed, vobsub_index(path)?) {
+        (Some(subs), _) => av::SubtitleReader::from_parsed(subs, options),
+        (None, Some(index)) => av::SubtitleReader::open(&index, selector, options, None)?,
+        (None, None) => {
+            let encoding = if has_text_extension(path) {
+                options.encoding.or(detect_file_encoding(path)?)
+            } else {
+                None
+            };
+            av::SubtitleReader::open(file, selector, options, encoding)?
+        }
+    };
+    Ok(reader.map_ok(Subtitle::convert).flatten_ok())
+}
+
+/// Characters that end a sentence. An ellipsis is left out on purpose since subtitles use it to
+/// continue a sentence in the next subtitle.
+const SENTENCE_ENDINGS: &[char] = &['.', '!', '?', '。', '！', '？', '؟', '۔'];
+/// Characters that may follow the end of a sentence
+const SENTENCE_CLOSERS: &[char] = &['"', '\'', ')', '”', '’', '»', '」', '』', '）'];
+/// Invisible marks that right-to-left subtitles use to keep punctuation on the right side
+const DIRECTION_MARKS: &[char] = &['\u{200e}', '\u{200f}', '\u{061c}'];
+
+fn ends_sentence(text: &str) -> bool {
+    let text = text
+        .trim_end_matches(|ch: char| ch.is_whitespace() || DIRECTION_MARKS.contains(&ch))
+        .trim_end_matches(SENTENCE_CLOSERS);
+    text.ends_with(SENTENCE_ENDINGS) && !text.ends_with("..")
+}
+
+/// Removes HTML-like inline tags (`<i>`, `</b>`, `<font color="...">`, ...) from `text`. A `<`
+/// that does not start a tag, as in `a < b`, is kept.
+pub fn strip_tags(text: &str) -> String {
+    let mut res = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find('<') {
+        res.push_str(&rest[..open]);
+        let tag = &rest[open + 1..];
+        let is_tag = tag
+            .trim_start_matches('/')
+            .starts_with(|ch: char| ch.is_ascii_alphabetic());
+        match tag.find(['<', '>', '\n']) {
+            Some(close) if is_tag && tag[close..].starts_with('>') => {
+                rest = &tag[close + 1..];
+            }
+            _ => {
+                res.push('<');
+                rest = tag;
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Whether `label` is a speaker label of hearing impaired subtitles, e.g. `JOHN` or `MAN #2`
+fn is_speaker_label(label: &str) -> bool {
+    label.chars().any(char::is_uppercase)
+        && !label.chars().any(char::is_lowercase)
+        && label
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || " .'-#&".contains(ch))
+}
+
+/// Removes the annotations of subtitles for the deaf and hard of hearing: sound descriptions in
+/// brackets or parentheses, speaker labels (`JOHN:`) and lines with music notes. Lines that are
+/// left empty are dropped.
+pub fn strip_sdh(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut closing: Option<char> = None;
+    for ch in text.chars() {
+        match (closing, ch) {
+            (Some(close), _) if ch == close => closing = None,
+            (Some(_), _) => {}
+            (None, '[') => closing = Some(']'),
+            (None, '(') => closing = Some(')'),
+            (None, _) => stripped.push(ch),
+        }
+    }
+
+    let strip_line = |line: &str| -> Option<String> {
+        if line.contains(['♪', '♫']) {
+            return None;
+        }
+        let line = line.trim();
+        let (dash, rest) = match line.strip_prefix('-') {
+            Some(rest) => ("- ", rest.trim_start()),
+            None => ("", line),
+        };
+        // The colon of a label is followed by a space, unlike the one in `5:30`
+        let rest = match rest.split_once(':') {
+            Some((label, spoken))
+                if is_speaker_label(label)
+                    && (spoken.is_empty() || spoken.starts_with(char::is_whitespace)) =>
+            {
+                spoken
+            }
+            _ => rest,
+        };
+        // Removing a sound description in the middle of a line leaves two spaces
+        let rest = rest.split_whitespace().join(" ");
+        (!rest.is_empty()).then(|| format!("{}{}", dash, rest))
+    };
+
+    stripped
+        .split("\\N")
+        .map(|part| part.split('\n').filter_map(strip_line).join("\n"))
+        .filter(|part| !part.is_empty())
+        .join("\\N")
+}
+
+/// Collapses runs of spaces in `text` and joins its lines, separated by `\N` or hard newlines, with
+/// `separator`. Empty lines are dropped.
+pub fn normalize_whitespace(text: &str, separator: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace("\\N", "\n")
+        .split('\n')
+        .map(|line| {
+            line.split([' ', '\t'])
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Normalizes `text` for comparing subtitles: case, punctuation, line breaks and the amount of
+/// whitespace are ignored
+pub fn normalize_text(text: &str) -> String {
+    text.replace("\\N", " ")
+        .replace("\\n", " ")
+        .to_lowercase()
+        .split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns whether the first character with a strong direction is right-to-left, which is how
+/// the direction of a paragraph is determined. `None` if `text` has no such character.
+fn direction(text: &str) -> Option<bool> {
+    text.chars().find_map(|ch| match ch {
+        '\u{200f}' | '\u{061c}' => Some(true),
+        '\u{200e}' => Some(false),
+        '\u{0590}'..='\u{08ff}'
+        | '\u{fb1d}'..='\u{fdff}'
+        | '\u{fe70}'..='\u{feff}'
+        | '\u{10800}'..='\u{10fff}'
+        | '\u{1e800}'..='\u{1efff}'
+            if ch.is_alphabetic() =>
+        {
+            Some(true)
+        }
+        _ if ch.is_alphabetic() => Some(false),
+        _ => None,
+    })
+}
+
+/// Whether `text` is written right-to-left (Hebrew, Arabic, ...)
+pub fn is_rtl(text: &str) -> bool {
+    direction(text) == Some(true)
+}
+
+/// Whether the parts are not all written in the same direction. Joining such parts as they are
+/// lets the bidi algorithm reorder them and move the separators between them.
+fn mixed_direction<S: AsRef<str>>(parts: &[S]) -> bool {
+    let mut directions = parts.iter().filter_map(|part| direction(part.as_ref()));
+    match directions.next() {
+        Some(first) => directions.any(|dir| dir != first),
+        None => false,
+    }
+}
+
+/// Wraps `text` in a first strong isolate, so it keeps its own direction within other text
+fn isolate(text: &str) -> String {
+    format!("\u{2068}{}\u{2069}", text)
+}
+
+/// Joins the parts with `separator`. Parts are isolated if they are written in different
+/// directions.
+pub fn join_bidi<S: AsRef<str>>(parts: &[S], separator: &str) -> String {
+    if mixed_direction(parts) {
+        parts
+            .iter()
+            .map(|part| isolate(part.as_ref()))
+            .collect::<Vec<_>>()
+            .join(separator)
+    } else {
+        parts
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+/// Whether `ch` belongs to a script that doesn't separate words with spaces
+pub fn is_cjk(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{3000}'..='\u{30ff}'
+            | '\u{3400}'..='\u{4dbf}'
+            | '\u{4e00}'..='\u{9fff}'
+            | '\u{ff00}'..='\u{ffef}'
+    )
+}
+
+fn combine_sentence(mut subs: Vec<Subtitle>) -> Option<Subtitle> {
+    if subs.len() <= 1 {
+        return subs.pop();
+    }
+
+    let start = subs.first()?.timespan().start();
+    let end = subs.iter().map(|sub| sub.timespan().end()).max()?;
+
+    let parts: Vec<String> = subs
+        .iter()
+        .filter_map(Subtitle::text)
+        .map(|part| {
+            let part = part.replace("\\N", " ").replace("\\n", " ");
+            part.trim_matches(|ch: char| ch.is_whitespace() || DIRECTION_MARKS.contains(&ch))
+                .to_string()
+        })
+        .collect();
+    let isolated = mixed_direction(&parts);
+
+    let mut text = String::new();
+    let mut prev = None;
+    for part in &parts {
+        if let (Some(prev), Some(next)) = (prev, part.chars().next()) {
+            if !is_cjk(prev) || !is_cjk(next) {
+                text.push(' ');
+            }
+        }
+        prev = part.chars().last().or(prev);
+        if isolated {
+            text.push_str(&isolate(part));
+        } else {
+            text.push_str(part);
+        }
+    }
+    let forced = subs.iter().any(Subtitle::forced);
+    let mut sentence = Subtitle::new(Timespan::new(start, end), Dialogue::Text(text));
+    sentence.set_forced(forced);
+    Some(sentence)
+}
+
+/// Subtitles that are further apart than this are not joined into a sentence, like the lines of
+/// a song that has no punctuation
+const MAX_SENTENCE_GAP: Duration = Duration::from_millis(2000);
+/// A sentence is not joined beyond this length or this many characters, so a track without
+/// punctuation doesn't become a single card
+const MAX_SENTENCE_DURATION: Duration = Duration::from_millis(30_000);
+const MAX_SENTENCE_CHARS: usize = 300;
+
+/// Whether `next` continues the unfinished `sentence`, which it doesn't if it is too far from the
+/// last subtitle of the sentence or would make the sentence too long
+fn continues_sentence(sentence: &[Subtitle], next: &Subtitle) -> bool {
+    let (Some(first), Some(last)) = (sentence.first(), sentence.last()) else {
+        return true;
+    };
+    let duration = next.timespan().end().as_millis() - first.timespan().start().as_millis();
+    let chars: usize = sentence
+        .iter()
+        .chain(std::iter::once(next))
+        .filter_map(Subtitle::text)
+        .map(|text| text.chars().count())
+        .sum();
+    last.timespan().gap_to(&next.timespan()) <= MAX_SENTENCE_GAP
+        && duration <= MAX_SENTENCE_DURATION.as_millis()
+        && chars <= MAX_SENTENCE_CHARS
+}
+
+/// Joins consecutive subtitles until one ends a sentence, or the next one is too far away or
+/// would make the sentence too long. Subtitles without text (bitmaps) are never joined.
+pub fn join_sentences(subs: Vec<Subtitle>) -> Vec<Subtitle> {
+    let count = subs.len();
+    let mut res = Vec::new();
+    let mut sentence = Vec::new();
+
+    for sub in subs {
+        match sub.text().map(ends_sentence) {
+            Some(ends) => {
+                if !continues_sentence(&sentence, &sub) {
+                    res.extend(combine_sentence(std::mem::take(&mut sentence)));
+                }
+                sentence.push(sub);
+                if ends {
+                    res.extend(combine_sentence(std::mem::take(&mut sentence)));
+                }
+            }
+            None => {
+                res.extend(combine_sentence(std::mem::take(&mut sentence)));
+                res.push(sub);
+            }
+        }
+    }
+    res.extend(combine_sentence(sentence));
+
+    trace!("joined {} subtitles into {} sentences", count, res.len());
+    res
+}
+
+/// Abbreviations that end with a period without ending the sentence, compared without case
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "st", "jr", "sr", "prof", "mt", "vs", "e.g", "i.e",
+];
+
+/// Whether the word that ends right before `end` in `text` is an abbreviation or an initial, like
+/// "Mr" in "Mr. Smith" or "J" in "J. Smith"
+fn is_abbreviation(text: &str, end: usize) -> bool {
+    let word = text[..end]
+        .rsplit(|ch: char| ch.is_whitespace() || ch == '(' || ch == '"')
+        .next()
+        .unwrap_or_default();
+    let mut chars = word.chars();
+    let initial =
+        matches!((chars.next(), chars.next()), (Some(ch), None) if ch.is_uppercase() && ch != 'I');
+    initial
+        || ABBREVIATIONS
+            .iter()
+            .any(|abbreviation| word.eq_ignore_ascii_case(abbreviation))
+}
+
+/// Splits `text` into its sentences. A sentence ends at a run of sentence endings (and closing
+/// quotes or brackets) that is followed by whitespace and the start of a new sentence, which is
+/// anything but a lowercase letter or a digit, or by anything after a full width ending. Ellipses
+/// and the periods of abbreviations like "Mr." don't end a sentence.
+fn sentences_of(text: &str) -> Vec<String> {
+    let text = text.replace("\\N", " ").replace("\\n", " ");
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let offset = |idx: usize| chars.get(idx).map_or(text.len(), |(offset, _)| *offset);
+
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut idx = 0;
+    while idx < chars.len() {
+        if !SENTENCE_ENDINGS.contains(&chars[idx].1) {
+            idx += 1;
+            continue;
+        }
+        let run_start = idx;
+        while idx < chars.len() && SENTENCE_ENDINGS.contains(&chars[idx].1) {
+            idx += 1;
+        }
+        let run = &text[offset(run_start)..offset(idx)];
+        while idx < chars.len() && SENTENCE_CLOSERS.contains(&chars[idx].1) {
+            idx += 1;
+        }
+        let full_width = run.chars().any(|ch| !ch.is_ascii());
+        let at_break = if full_width {
+            idx < chars.len()
+        } else {
+            let next = chars[idx..]
+                .iter()
+                .map(|(_, ch)| *ch)
+                .find(|ch| !ch.is_whitespace());
+            idx < chars.len()
+                && chars[idx].1.is_whitespace()
+                && next.is_some_and(|ch| !ch.is_lowercase() && !ch.is_numeric())
+                && !(run == "." && is_abbreviation(&text, offset(run_start)))
+        };
+        if at_break && !run.contains("..") {
+            res.push(text[start..offset(idx)].trim().to_string());
+            start = offset(idx);
+        }
+    }
+    res.push(text[start..].trim().to_string());
+    res.retain(|sentence| !sentence.is_empty());
+    res
+}
+
+/// Splits subtitles that contain more than one sentence into a subtitle per sentence. The timespan
+/// is divided in proportion to the length of the sentences. The sentences keep the kind, style
+/// and settings of the subtitle they come from.
+pub fn split_sentences(subs: Vec<Subtitle>) -> Vec<Subtitle> {
+    let count = subs.len();
+    let mut res = Vec::new();
+
+    for sub in subs {
+        let sentences = sub.text().map(sentences_of).unwrap_or_default();
+        if sentences.len() <= 1 {
+            res.push(sub);
+            continue;
+        }
+        // The readings are only kept if they split into the same sentences
+        let furigana = match sub.dialogue() {
+            Dialogue::Ass(ass) => ass.text.furigana().map(sentences_of),
+            _ => None,
+        }
+        .filter(|furigana| furigana.len() == sentences.len());
+
+        let lengths: Vec<i64> = sentences
+            .iter()
+            .map(|sentence| sentence.chars().count() as i64)
+            .collect();
+        let total: i64 = lengths.iter().sum();
+        let span = sub.timespan();
+        let duration = span.duration().as_millis();
+
+        let mut done = 0;
+        for (idx, (sentence, length)) in sentences.into_iter().zip(lengths).enumerate() {
+            let start = span.start() + Duration::from_millis(duration * done / total);
+            done += length;
+            let end = span.start() + Duration::from_millis(duration * done / total);
+            let mut diag = sub.diag.clone();
+            match &mut diag {
+                Dialogue::Text(text) => *text = sentence,
+                Dialogue::Ass(ass) => {
+                    let furigana = furigana.as_ref().map(|furigana| furigana[idx].clone());
+                    ass.text.set_dialogue(sentence, furigana);
+                }
+                Dialogue::Bitmap(_) => {}
+            }
+            res.push(Subtitle {
+                timespan: Timespan::new(start, end),
+                diag,
+                settings: sub.settings.clone(),
+                forced: sub.forced(),
+                parts: Vec::new(),
+            });
+        }
+    }
+
+    trace!("split {} subtitles into {} sentences", count, res.len());
+    res
+}
+
+fn combine_context(mut subs: Vec<Subtitle>) -> Option<Subtitle> {
+    if subs.len() <= 1 {
+        return subs.pop();
+    }
+
+    let span = subs
+        .iter()
+        .map(Subtitle::timespan)
+        .reduce(|span, other| span.union(&other))?;
+    let text = subs
+        .iter()
+        .filter_map(Subtitle::text)
+        .collect::<Vec<_>>()
+        .join("\\N");
+    let mut context = Subtitle::new(span, Dialogue::Text(text));
+    context.set_forced(subs.iter().any(Subtitle::forced));
+    context.parts = subs.iter().map(Subtitle::timespan).collect();
+    Some(context)
+}
+
+/// Groups every `size` consecutive subtitles into a single subtitle spanning all of them, with a
+/// line per subtitle. Subtitles without text (bitmaps) are never grouped and end a group.
+pub fn group_context(subs: Vec<Subtitle>, size: usize) -> Vec<Subtitle> {
+    let count = subs.len();
+    let mut res = Vec::new();
+    let mut group = Vec::new();
+
+    for sub in subs {
+        if sub.text().is_none() {
+            res.extend(combine_context(std::mem::take(&mut group)));
+            res.push(sub);
+            continue;
+        }
+        group.push(sub);
+        if group.len() == size {
+            res.extend(combine_context(std::mem::take(&mut group)));
+        }
+    }
+    res.extend(combine_context(group));
+
+    trace!("grouped {} subtitles into {} contexts", count, res.len());
+    res
+}
+
+fn is_karaoke(sub: &Subtitle) -> bool {
+    matches!(sub.dialogue(), Dialogue::Ass(ass) if ass.text.is_karaoke())
+}
+
+/// Merges karaoke events that were split into one event per syllable back into a single line.
+/// The syllables of a line are shown at the same time, so karaoke events that overlap are taken
+/// to be part of the same line. Lines that follow each other directly are kept apart.
+pub fn merge_karaoke(subs: Vec<Subtitle>) -> Vec<Subtitle> {
+    let count = subs.len();
+    let mut res: Vec<Subtitle> = Vec::new();
+
+    for sub in subs {
+        if let Some(prev) = res.last_mut() {
+            if is_karaoke(prev)
+                && is_karaoke(&sub)
+                && sub.timespan().start() < prev.timespan().end()
+            {
+                if let (Dialogue::Ass(prev_ass), Dialogue::Ass(ass)) =
+                    (&mut prev.diag, sub.dialogue())
+                {
+                    let joined = format!("{}{}", prev_ass.text.text, ass.text.text);
+                    // Both texts were parsed before, so their brackets are balanced
+                    if let Ok(text) = joined.parse() {
+                        prev_ass.text = text;
+                        prev.timespan = Timespan::new(
+                            prev.timespan().start(),
+                            prev.timespan().end().max(sub.timespan().end()),
+                        );
+                        continue;
+                    }
+                }
+            }
+        }
+        res.push(sub);
+    }
+
+    if res.len() != count {
+        trace!(
+            "merged {} karaoke syllables into {} lines",
+            count,
+            res.len()
+        );
+    }
+    res
+}
+
+/// Sorts subtitles that ran backwards (e.g. after a mux error) by their start time and returns
+/// how many of them were out of order
+pub fn reorder_subtitles(subs: &mut [Subtitle]) -> usize {
+    let reordered = subs
+        .windows(2)
+        .filter(|pair| pair[1].timespan().start() < pair[0].timespan().start())
+        .count();
+
+    if reordered > 0 {
+        subs.sort_by_key(|sub| sub.timespan().start());
+        warn!("reordered {} subtitle(s) that ran backwards", reordered);
+    }
+    reordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Timestamp;
+
+    fn sub(start: u32, end: u32) -> Subtitle {
+        Subtitle {
+            timespan: Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end)),
+            diag: Dialogue::Text(format!("{}", start)),
+            settings: None,
+            forced: false,
+            parts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reorder_backwards_subs() {
+        let mut subs = vec![sub(5000, 6000), sub(0, 1000), sub(2000, 3000)];
+        assert_eq!(reorder_subtitles(&mut subs), 1);
+        let starts: Vec<i64> = subs
+            .iter()
+            .map(|sub| sub.timespan().start().as_millis())
+            .collect();
+        assert_eq!(starts, vec![0, 2000, 5000]);
+    }
+
+    fn text_sub(start: u32, end: u32, text: &str) -> Subtitle {
+        Subtitle::new(
+            Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end)),
+            Dialogue::Text(text.to_string()),
+        )
+    }
+
+    #[test]
+    fn sentence_boundaries() {
+        assert_eq!(
+            sentences_of("Hi! \"Wait.\" Pi is 3.14... right?"),
+            vec!["Hi!", "\"Wait.\"", "Pi is 3.14... right?"]
+        );
+        assert_eq!(sentences_of("はい。行こう！"), vec!["はい。", "行こう！"]);
+        assert_eq!(sentences_of("One sentence"), vec!["One sentence"]);
+        assert_eq!(
+            sentences_of("Mr. Smith paid 3.5 dollars. J. R. said no. so what"),
+            vec!["Mr. Smith paid 3.5 dollars.", "J. R. said no. so what"]
+        );
+    }
+
+    #[test]
+    fn split_sentences_keep_style() {
+        let mut sub = karaoke_sub(0, 2000, "Yes. And then we left.");
+        if let Dialogue::Ass(ass) = &mut sub.diag {
+            ass.name = "Alice".to_string();
+            ass.style = "Top".to_string();
+            ass.layer = 2;
+        }
+        let subs = split_sentences(vec![sub]);
+        assert_eq!(subs.len(), 2);
+        for (sub, text) in subs.iter().zip(["Yes.", "And then we left."]) {
+            let Dialogue::Ass(ass) = sub.dialogue() else {
+                panic!("expected an ass dialogue");
+            };
+            assert_eq!(ass.name, "Alice");
+            assert_eq!(ass.style, "Top");
+            assert_eq!(ass.layer, 2);
+            assert_eq!(sub.text(), Some(text));
+        }
+    }
+
+    #[test]
+    fn split_sentences_in_proportion() {
+        let subs = split_sentences(vec![
+            text_sub(0, 3000, "Yes.\\NAnd then we left."),
+            text_sub(4000, 5000, "Only one."),
+        ]);
+        let parts: Vec<_> = subs
+            .iter()
+            .map(|sub| {
+                (
+                    sub.timespan().start().as_millis(),
+                    sub.timespan().end().as_millis(),
+                    sub.text().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                (0, 571, "Yes."),
+                (571, 3000, "And then we left."),
+                (4000, 5000, "Only one."),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_context_lines() {
+        let subs = group_context(
+            vec![
+                text_sub(0, 1000, "Where are you going?"),
+                text_sub(1200, 2000, "Home."),
+                text_sub(2500, 3000, "Why?"),
+                text_sub(3000, 4000, "I'm tired."),
+                text_sub(5000, 6000, "Okay."),
+            ],
+            2,
+        );
+        let parts: Vec<_> = subs
+            .iter()
+            .map(|sub| {
+                (
+                    sub.timespan().start().as_millis(),
+                    sub.timespan().end().as_millis(),
+                    sub.text().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                (0, 2000, "Where are you going?\\NHome."),
+                (2500, 4000, "Why?\\NI'm tired."),
+                (5000, 6000, "Okay."),
+            ]
+        );
+        assert_eq!(subs[1].parts().len(), 2);
+        assert!(subs[2].parts().is_empty());
+    }
+
+    #[test]
+    fn join_sentences_across_subs() {
+        let subs = join_sentences(vec![
+            text_sub(0, 1000, "I wanted to say"),
+            text_sub(1000, 2000, "that I'm sorry..."),
+            text_sub(2000, 3000, "really sorry!\""),
+            text_sub(4000, 5000, "Okay."),
+            text_sub(5000, 6000, "Bye"),
+        ]);
+        let texts: Vec<&str> = subs.iter().filter_map(Subtitle::text).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "I wanted to say that I'm sorry... really sorry!\"",
+                "Okay.",
+                "Bye"
+            ]
+        );
+        assert_eq!(subs[0].timespan().start(), Timestamp::from_millis(0));
+        assert_eq!(subs[0].timespan().end(), Timestamp::from_millis(3000));
+    }
+
+    #[test]
+    fn join_unpunctuated_lines() {
+        // The lines of a song, back to back for 40 seconds and then after a pause
+        let mut lines: Vec<Subtitle> = (0..20)
+            .map(|idx| text_sub(idx * 2000, (idx + 1) * 2000, "la la la"))
+            .collect();
+        lines.push(text_sub(45_000, 46_000, "one more time"));
+        let subs = join_sentences(lines);
+        let spans: Vec<(i64, i64)> = subs
+            .iter()
+            .map(|sub| {
+                (
+                    sub.timespan().start().as_millis(),
+                    sub.timespan().end().as_millis(),
+                )
+            })
+            .collect();
+        assert_eq!(spans, vec![(0, 30_000), (30_000, 40_000), (45_000, 46_000)]);
+
+        let long = "word ".repeat(40);
+        let subs = join_sentences(vec![text_sub(0, 1000, &long), text_sub(1000, 2000, &long)]);
+        assert_eq!(subs.len(), 2);
+    }
+
+    #[test]
+    fn join_cjk_sentences_without_spaces() {
+        let subs = join_sentences(vec![
+            text_sub(0, 1000, "今日は"),
+            text_sub(1000, 2000, "いい天気ですね。"),
+        ]);
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].text(), Some("今日はいい天気ですね。"));
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_text("Hello,\\N  WORLD!"), "hello world");
+        assert_eq!(normalize_text("...hello world"), "hello world");
+        assert_eq!(normalize_text("今日は、いい天気。"), "今日は いい天気");
+    }
+
+    #[test]
+    fn strip_inline_tags() {
+        assert_eq!(
+            strip_tags("<i>Hello</i> <font color=\"#ff0000\">world</font>"),
+            "Hello world"
+        );
+        assert_eq!(strip_tags("a < b and c<d> e"), "a < b and c e");
+        assert_eq!(strip_tags("<b>unclosed"), "unclosed");
+        assert_eq!(strip_tags("<3 <b"), "<3 <b");
+    }
+
+    #[test]
+    fn sdh_annotations() {
+        assert_eq!(strip_sdh("[door slams]"), "");
+        assert_eq!(strip_sdh("JOHN: Hello.\\N(laughs) Hi!"), "Hello.\\NHi!");
+        assert_eq!(
+            strip_sdh("- MAN #2: Run!\\N- [gasps] Where?"),
+            "- Run!\\N- Where?"
+        );
+        assert_eq!(strip_sdh("♪ La la la ♪\nOkay."), "Okay.");
+        assert_eq!(strip_sdh("Note: it's 5:30."), "Note: it's 5:30.");
+        assert_eq!(strip_sdh("AT 5:30 [sighs] WE GO"), "AT 5:30 WE GO");
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            normalize_whitespace("  Hello   there\\N\\N general\r\nKenobi ", "<br>"),
+            "Hello there<br>general<br>Kenobi"
+        );
+        assert_eq!(normalize_whitespace("a\\Nb", "\\N"), "a\\Nb");
+        assert_eq!(normalize_whitespace("a\tb\nc", " / "), "a b / c");
+    }
+
+    #[test]
+    fn newline_modes() {
+        assert_eq!("br".parse::<NewlineMode>().unwrap().separator(), "<br>");
+        assert_eq!(
+            "separator= / ".parse::<NewlineMode>().unwrap(),
+            NewlineMode::Separator(" / ".to_string())
+        );
+        assert!("tab".parse::<NewlineMode>().is_err());
+    }
+
+    #[test]
+    fn text_normalizations() {
+        let apply = |normalization: &str, text: &str| {
+            normalization
+                .parse::<TextNormalization>()
+                .unwrap()
+                .apply(text)
+        };
+        assert_eq!(apply("lowercase", "HeLLo\\NWORLD"), "hello\\Nworld");
+        assert_eq!(
+            apply("strip-punct", "{\\i1}Wait...{\\i0} what?!\\N- ¿Qué?"),
+            "{\\i1}Wait{\\i0} what\\N Qué"
+        );
+        assert_eq!(apply("collapse-whitespace", "  a \t b \\N c  "), "a b\\Nc");
+        assert!("uppercase".parse::<TextNormalization>().is_err());
+        assert_eq!(
+            apply_normalizations(
+                &[
+                    TextNormalization::StripPunct,
+                    TextNormalization::CollapseWhitespace
+                ],
+                "Wait - what?"
+            ),
+            "Wait what"
+        );
+    }
+
+    #[test]
+    fn characters_per_second() {
+        assert_eq!(text_sub(0, 2000, "Hello\\Nthere").cps(&[]), Some(5.5));
+        assert_eq!(text_sub(0, 0, "Hi").cps(&[]), Some(f64::INFINITY));
+        assert_eq!(text_sub(0, 1000, "  ").cps(&[]), Some(0.0));
+        assert_eq!(
+            text_sub(0, 1000, "Hi!!!").cps(&[TextNormalization::StripPunct]),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn join_rtl_sentences() {
+        let subs = join_sentences(vec![
+            text_sub(0, 1000, "\u{200f}إلى أين"),
+            text_sub(1000, 2000, "أنت ذاهب؟\u{200f}"),
+            text_sub(2000, 3000, "שלום"),
+        ]);
+        assert_eq!(subs.len(), 2);
+        assert_eq!(subs[0].text(), Some("إلى أين أنت ذاهب؟"));
+        assert!(is_rtl(subs[0].text().unwrap()));
+    }
+
+    #[test]
+    fn isolate_mixed_directions() {
+        assert_eq!(join_bidi(&["שלום", "עולם"], " "), "שלום עולם");
+        assert_eq!(
+            join_bidi(&["שלום", "OK"], " "),
+            "\u{2068}שלום\u{2069} \u{2068}OK\u{2069}"
+        );
+        assert!(!is_rtl("123 hello שלום"));
+        assert!(is_rtl("123 שלום hello"));
+    }
+
+    fn karaoke_sub(start: u32, end: u32, text: &str) -> Subtitle {
+        Subtitle::new(
+            Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end)),
+            Dialogue::Ass(DialogueEvent {
+                layer: 0,
+                style: String::new(),
+                name: String::new(),
+                text: text.parse().unwrap(),
+            }),
+        )
+    }
+
+    #[test]
+    fn merge_karaoke_syllables() {
+        let subs = merge_karaoke(vec![
+            karaoke_sub(0, 3000, "{\\k50}ka"),
+            karaoke_sub(500, 3000, "{\\k50}ra"),
+            karaoke_sub(1000, 3200, "{\\k50}o"),
+            karaoke_sub(3200, 6000, "{\\k50}next"),
+            text_sub(3500, 4000, "not karaoke"),
+        ]);
+        assert_eq!(subs.len(), 3);
+        assert_eq!(subs[0].text(), Some("karao"));
+        assert_eq!(
+            subs[0].timespan(),
+            Timespan::new(Timestamp::from_millis(0), Timestamp::from_millis(3200))
+        );
+        assert_eq!(subs[1].text(), Some("next"));
+        assert_eq!(subs[2].text(), Some("not karaoke"));
+    }
+
+    #[test]
+    fn join_keeps_forced() {
+        let mut first = text_sub(0, 1000, "Where are");
+        first.set_forced(true);
+        let subs = join_sentences(vec![first, text_sub(1000, 2000, "you going?")]);
+        assert_eq!(subs.len(), 1);
+        assert!(subs[0].forced());
+    }
+
+    #[test]
+    fn decode_shift_jis() {
+        let text = "1\n00:00:01,000 --> 00:00:02,000\n今日はいい天気ですね。\n\n\
+                    2\n00:00:03,000 --> 00:00:04,000\nどこへ行きますか？駅の近くの喫茶店です。\n";
+        let (data, _, _) = encoding_rs::SHIFT_JIS.encode(text);
+        assert_eq!(decode_text(&data, None), text);
+        assert_eq!(detect_encoding("Hello".as_bytes()), None);
+    }
+
+    #[test]
+    fn decode_with_override() {
+        let (data, _, _) = encoding_rs::WINDOWS_1252.encode("café");
+        assert_eq!(decode_text(&data, Some(encoding_rs::WINDOWS_1252)), "café");
+        assert_eq!(decode_text("café".as_bytes(), None), "café");
+    }
+
+    #[test]
+    fn vobsub_pair() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sub = dir.path().join("movie.sub");
+        let idx = dir.path().join("movie.idx");
+        std::fs::write(&sub, b"")?;
+        assert_eq!(vobsub_index(&sub)?, None);
+        assert!(vobsub_index(&idx).is_ok());
+
+        std::fs::write(&idx, b"")?;
+        assert_eq!(vobsub_index(&sub)?, Some(idx.clone()));
+        assert_eq!(vobsub_index(&idx)?, Some(idx.clone()));
+
+        std::fs::remove_file(&sub)?;
+        assert!(vobsub_index(&idx).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reorder_keeps_ordered_subs() {
+        let mut subs = vec![sub(0, 1000), sub(1000, 2000)];
+        assert_eq!(reorder_subtitles(&mut subs), 0);
+    }
 }
@@ -22,6 +22,41 @@ pub struct Timespan {
     end: Timestamp,
 }
 
+/// Clock convention used when formatting timestamps for note fields.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Clock {
+    #[default]
+    H24,
+    H12,
+}
+
+impl FromStr for Clock {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "24h" | "24" => Ok(Clock::H24),
+            "12h" | "12" => Ok(Clock::H12),
+            _ => bail!("\"{}\" is not a valid clock, expected 24h or 12h", s),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TimestampFormat {
+    pub clock: Clock,
+    pub ms_separator: char,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self {
+            clock: Clock::H24,
+            ms_separator: '.',
+        }
+    }
+}
+
 impl Timestamp {
     const TIMEBASE: Rational = Rational(1, 1000);
     pub const MIN: Timestamp = Self(0);
@@ -60,6 +95,31 @@ impl Timestamp {
     pub fn saturating_sub(&self, duration: Duration) -> Self {
         Self(self.0.saturating_sub(duration.as_millis()).max(0))
     }
+
+    /// Formats the timestamp for display in a note field
+    pub fn format(&self, format: &TimestampFormat) -> String {
+        let ts = self.as_millis();
+        let hours = ts / (1000 * 60 * 60);
+        let (hours, suffix) = match format.clock {
+            Clock::H24 => (hours, ""),
+            Clock::H12 => {
+                let hours = hours % 24;
+                (
+                    (hours + 11) % 12 + 1,
+                    if hours < 12 { " AM" } else { " PM" },
+                )
+            }
+        };
+        format!(
+            "{}:{:02}:{:02}{}{:03}{}",
+            hours,
+            (ts / (1000 * 60)) % 60,
+            (ts / (1000)) % 60,
+            format.ms_separator,
+            ts % 1000,
+            suffix
+        )
+    }
 }
 
 impl Add<Duration> for Timestamp {
@@ -92,29 +152,71 @@ impl fmt::Display for Timestamp {
     }
 }
 
+/// Parses the seconds of a timestamp, which may have up to three decimals, into milliseconds
+fn parse_seconds(secs: &str) -> Option<i64> {
+    let (whole, fraction) = match secs.split_once('.') {
+        Some((_, "")) => return None,
+        Some((whole, fraction)) => (whole, fraction),
+        None => (secs, ""),
+    };
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || fraction.len() > 3 || !digits(fraction) {
+        return None;
+    }
+    let millis: i64 = format!("{:0<3}", fraction).parse().ok()?;
+    Some(whole.parse::<i64>().ok()?.checked_mul(1000)? + millis)
+}
+
 impl FromStr for Timestamp {
     type Err = Error;
 
+    /// Parses `[[hh:]mm:]ss[.mmm]` or a number of milliseconds followed by `ms`
     fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::msg(format!(
+                "\"{}\" is not a valid timestamp, expected [[hh:]mm:]ss[.mmm] or a number of milliseconds followed by ms",
+                s
+            ))
+        };
+
+        if let Some(millis) = s.strip_suffix("ms") {
+            return millis
+                .parse()
+                .map(Self)
+                .ok()
+                .filter(|ts| ts.0 >= 0)
+                .ok_or_else(invalid);
+        }
+
         let parts: Vec<&str> = s.split(':').collect();
+        let (secs, larger) = parts.split_last().ok_or_else(invalid)?;
+        if larger.len() > 2 {
+            return Err(invalid());
+        }
+        let mut millis = parse_seconds(secs).ok_or_else(invalid)?;
+        if !larger.is_empty() && millis >= 60_000 {
+            bail!(
+                "\"{}\" is not a valid timestamp, the seconds must be less than 60",
+                s
+            );
+        }
 
-        match parts[..] {
-            [secs] => Ok(Timestamp::from_secs(secs.parse()?)),
-            [mins, secs] => {
-                let mins: u8 = mins.parse()?;
-                let secs: u8 = secs.parse()?;
-                Ok(Timestamp::from_secs(mins as u32 * 60 + secs as u32))
-            }
-            [hours, mins, secs] => {
-                let hours: u8 = hours.parse()?;
-                let mins: u8 = mins.parse()?;
-                let secs: u8 = secs.parse()?; //TODO better errors
-                Ok(Timestamp::from_secs(
-                    60 * (hours as u32 * 60 + mins as u32) + secs as u32,
-                ))
+        // The minutes, then the hours
+        for (idx, unit) in larger.iter().rev().enumerate() {
+            let value = i64::from(unit.parse::<u32>().map_err(|_| invalid())?);
+            if idx == 0 && larger.len() == 2 && value >= 60 {
+                bail!(
+                    "\"{}\" is not a valid timestamp, the minutes must be less than 60",
+                    s
+                );
             }
-            _ => Err(Error::msg("invalid timestamp")),
+            let scale = if idx == 0 { 60_000 } else { 3_600_000 };
+            millis = value
+                .checked_mul(scale)
+                .and_then(|value| value.checked_add(millis))
+                .ok_or_else(invalid)?;
         }
+        Ok(Self(millis))
     }
 }
 
@@ -143,6 +245,89 @@ impl Timespan {
     pub const fn end(&self) -> Timestamp {
         self.end
     }
+
+    pub const fn duration(&self) -> Duration {
+        Duration(self.end.0 - self.start.0)
+    }
+
+    /// Whether `ts` lies in the span. The end is not part of the span.
+    pub fn contains(&self, ts: Timestamp) -> bool {
+        self.start <= ts && ts < self.end
+    }
+
+    /// Whether the spans share any time. Spans that only touch don't.
+    pub fn intersects(&self, other: &Timespan) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// The time between the end of the earlier span and the start of the later one, zero if the
+    /// spans touch or overlap
+    pub fn gap_to(&self, other: &Timespan) -> Duration {
+        let gap = self.start.max(other.start).0 - self.end.min(other.end).0;
+        Duration(gap.max(0))
+    }
+
+    /// The smallest span that covers both spans
+    pub fn union(&self, other: &Timespan) -> Timespan {
+        Timespan {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// A linear stretch and offset of timestamps, given by two timestamps and where they should end
+/// up. Fixes subtitles that drift because they were timed for a different frame rate.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SyncPoints {
+    from: (Timestamp, Timestamp),
+    to: (Timestamp, Timestamp),
+}
+
+impl SyncPoints {
+    /// Moves `ts` to where it ends up on the line through the two points
+    pub fn apply(&self, ts: Timestamp) -> Timestamp {
+        let (from1, from2) = (self.from.0 .0 as f64, self.from.1 .0 as f64);
+        let (to1, to2) = (self.to.0 .0 as f64, self.to.1 .0 as f64);
+        let moved = to1 + (ts.0 as f64 - from1) * (to2 - to1) / (from2 - from1);
+        // Timestamps before the start end up at the start
+        Timestamp(moved.round().max(0.0) as i64)
+    }
+
+    pub fn apply_span(&self, span: Timespan) -> Timespan {
+        Timespan::new(self.apply(span.start()), self.apply(span.end()))
+    }
+}
+
+impl FromStr for SyncPoints {
+    type Err = Error;
+
+    /// Parses `T1=T1',T2=T2'`
+    fn from_str(s: &str) -> Result<Self> {
+        let point = |point: &str| -> Result<(Timestamp, Timestamp)> {
+            let Some((from, to)) = point.split_once('=') else {
+                bail!(
+                    "\"{}\" is not a valid sync point, expected TIMESTAMP=TIMESTAMP",
+                    point
+                );
+            };
+            Ok((from.trim().parse()?, to.trim().parse()?))
+        };
+        let Some((first, second)) = s.split_once(',') else {
+            bail!(
+                "\"{}\" are not valid sync points, expected two points separated by a comma",
+                s
+            );
+        };
+        let (first, second) = (point(first)?, point(second)?);
+        if first.0 == second.0 {
+            bail!("The two sync points must be at different timestamps");
+        }
+        Ok(Self {
+            from: (first.0, second.0),
+            to: (first.1, second.1),
+        })
+    }
 }
 
 impl From<Timespan> for (Timestamp, Timestamp) {
@@ -155,6 +340,60 @@ impl From<Timespan> for (Timestamp, Timestamp) {
 mod tests {
     use super::*;
 
+    fn span(start: u32, end: u32) -> Timespan {
+        Timespan::new(Timestamp::from_millis(start), Timestamp::from_millis(end))
+    }
+
+    /// Every span with a start and end below `max`
+    fn spans(max: u32) -> impl Iterator<Item = Timespan> + Clone {
+        (0..max).flat_map(move |start| (start..max).map(move |end| span(start, end)))
+    }
+
+    #[test]
+    fn span_methods() {
+        assert!(span(1000, 2000).contains(Timestamp::from_millis(1000)));
+        assert!(!span(1000, 2000).contains(Timestamp::from_millis(2000)));
+        assert!(span(0, 10).intersects(&span(9, 20)));
+        assert!(!span(0, 10).intersects(&span(10, 20)));
+        assert_eq!(span(0, 10).gap_to(&span(25, 30)), Duration::from_millis(15));
+        assert_eq!(span(25, 30).gap_to(&span(0, 10)), Duration::from_millis(15));
+        assert_eq!(span(0, 10).union(&span(25, 30)), span(0, 30));
+        assert_eq!(span(5, 25).duration(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn span_properties() {
+        let ts = |millis: u32| Timestamp::from_millis(millis);
+        for a in spans(8) {
+            for b in spans(8) {
+                assert_eq!(a.intersects(&b), b.intersects(&a));
+                assert_eq!(a.gap_to(&b), b.gap_to(&a));
+                assert_eq!(a.union(&b), b.union(&a));
+
+                // Spans intersect exactly when some timestamp lies in both
+                let shared = (0..8).any(|millis| a.contains(ts(millis)) && b.contains(ts(millis)));
+                assert_eq!(a.intersects(&b), shared);
+                if a.intersects(&b) {
+                    assert_eq!(a.gap_to(&b), Duration::default());
+                }
+
+                let union = a.union(&b);
+                for millis in 0..8 {
+                    if a.contains(ts(millis)) || b.contains(ts(millis)) {
+                        assert!(union.contains(ts(millis)));
+                    }
+                }
+                // The union is the two spans plus the gap between them
+                assert!(
+                    union.duration().as_millis()
+                        <= a.duration().as_millis()
+                            + b.duration().as_millis()
+                            + a.gap_to(&b).as_millis()
+                );
+            }
+        }
+    }
+
     #[test]
     fn saturating_add_normal() {
         let ts = Timestamp::from_millis(0);
@@ -196,4 +435,85 @@ mod tests {
         let ts = Timestamp::MAX;
         assert_eq!(ts.saturating_sub(Duration::from_millis(-1)), Timestamp::MAX);
     }
+
+    #[test]
+    fn parse_timestamps() {
+        let ts = |s: &str| s.parse::<Timestamp>().map(|ts| ts.as_millis()).ok();
+        assert_eq!(ts("90"), Some(90_000));
+        assert_eq!(ts("1:30"), Some(90_000));
+        assert_eq!(ts("1:02:03"), Some(3_723_000));
+        assert_eq!(ts("0:01:30.5"), Some(90_500));
+        assert_eq!(ts("12.345"), Some(12_345));
+        assert_eq!(ts("1500ms"), Some(1_500));
+        assert_eq!(ts("90:00"), Some(5_400_000));
+    }
+
+    #[test]
+    fn parse_invalid_timestamps() {
+        for invalid in [
+            "", "1:", ":30", "1:2:3:4", "1.2345", "1:60", "1:60:00", "-5", "-5ms", "ms", "1.", "a",
+        ] {
+            assert!(invalid.parse::<Timestamp>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn format_default() {
+        let ts = Timestamp::from_millis(3_723_004);
+        assert_eq!(ts.format(&Default::default()), ts.to_string());
+        assert_eq!(ts.format(&Default::default()), "1:02:03.004");
+    }
+
+    #[test]
+    fn format_comma_separator() {
+        let format = TimestampFormat {
+            ms_separator: ',',
+            ..Default::default()
+        };
+        assert_eq!(
+            Timestamp::from_millis(3_723_004).format(&format),
+            "1:02:03,004"
+        );
+    }
+
+    #[test]
+    fn format_12h() {
+        let format = TimestampFormat {
+            clock: Clock::H12,
+            ..Default::default()
+        };
+        assert_eq!(Timestamp::from_millis(0).format(&format), "12:00:00.000 AM");
+        assert_eq!(
+            Timestamp::from_secs(13 * 60 * 60 + 5).format(&format),
+            "1:00:05.000 PM"
+        );
+        assert_eq!(
+            Timestamp::from_secs(12 * 60 * 60).format(&format),
+            "12:00:00.000 PM"
+        );
+    }
+
+    #[test]
+    fn sync_points() {
+        let points: SyncPoints = "1:00=1:02.5,20:00=20:50".parse().unwrap();
+        assert_eq!(
+            points.apply(Timestamp::from_secs(60)),
+            Timestamp::from_millis(62_500)
+        );
+        assert_eq!(
+            points.apply(Timestamp::from_secs(20 * 60)),
+            Timestamp::from_secs(20 * 60 + 50)
+        );
+        // 23.976 fps subtitles on 25 fps video
+        let points: SyncPoints = "0=0,1001ms=960ms".parse().unwrap();
+        assert_eq!(
+            points.apply(Timestamp::from_secs(1001)),
+            Timestamp::from_secs(960)
+        );
+        assert_eq!(points.apply(Timestamp::MIN), Timestamp::MIN);
+
+        assert!("1:00=1:02".parse::<SyncPoints>().is_err());
+        assert!("1:00=1:02,1:00=1:05".parse::<SyncPoints>().is_err());
+        assert!("1:00,2:00=2:05".parse::<SyncPoints>().is_err());
+    }
 }
@@ -1,13 +1,208 @@
-use anyhow::{bail, Result};
-use libav::format::context::common::StreamIter;
-use libav::format::stream::Stream;
+use crate::time::{Duration, Timespan, Timestamp};
+use anyhow::{bail, Context, Error, Result};
+use libav::codec::Id;
+use libav::format::context::Input;
+use libav::format::stream::{Disposition, Stream};
 use libav::media;
+use log::{info, trace};
+use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum StreamSelector<'a> {
     Index(usize),
     Language(&'a str),
+    /// Like `Language`, but falls back to the first stream that has no (or an undetermined)
+    /// language tag
+    LanguageOrUndetermined(&'a str),
+    /// Select the first stream of which the title contains the string (case insensitive)
+    Title(&'a str),
+    /// Select the first stream that is marked as forced
+    Forced,
     Best,
+    /// Like `Best`, but if the best stream is a bitmap subtitle stream, a text subtitle stream in
+    /// the same language is used instead
+    BestText,
+    /// Try the selectors in order and use the first one that matches a stream
+    Chain(Vec<StreamSelector<'a>>),
+}
+
+/// A single selector of a `--sub-select` style selector chain, as given on the command line
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SelectorSpec {
+    Index(usize),
+    Language(String),
+    Title(String),
+    Forced,
+    Best,
+}
+
+impl FromStr for SelectorSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some(("index", idx)) => Ok(SelectorSpec::Index(
+                idx.parse()
+                    .with_context(|| format!("\"{}\" is not a valid stream index", idx))?,
+            )),
+            Some(("lang", lang)) => Ok(SelectorSpec::Language(lang.to_string())),
+            Some(("title", title)) => Ok(SelectorSpec::Title(title.to_string())),
+            None if s == "forced" => Ok(SelectorSpec::Forced),
+            None if s == "best" => Ok(SelectorSpec::Best),
+            _ => bail!(
+                "\"{}\" is not a valid stream selector, expected lang=LANGUAGE, title=TITLE, index=INDEX, forced or best",
+                s
+            ),
+        }
+    }
+}
+
+impl SelectorSpec {
+    /// Parses a comma separated list of selectors
+    pub fn parse_chain(s: &str) -> Result<Vec<Self>> {
+        s.split(',').map(str::parse).collect()
+    }
+
+    pub fn as_selector(&self) -> StreamSelector<'_> {
+        match self {
+            SelectorSpec::Index(idx) => StreamSelector::Index(*idx),
+            SelectorSpec::Language(lang) => StreamSelector::Language(lang),
+            SelectorSpec::Title(title) => StreamSelector::Title(title),
+            SelectorSpec::Forced => StreamSelector::Forced,
+            SelectorSpec::Best => StreamSelector::Best,
+        }
+    }
+}
+
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Continues a 64 bit FNV-1a hash over `bytes`. Unlike the hashers of std the result is the same
+/// for every build, so it can be stored. Start with `FNV_OFFSET`.
+pub fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Parses the `MemAvailable` line of /proc/meminfo, in bytes
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// The memory that is available to start new processes without swapping, if the system tells
+pub fn available_memory() -> Option<u64> {
+    parse_meminfo(&std::fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) date in the proleptic
+/// Gregorian calendar
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    // Counts from 0000-03-01, so the leap day is the last day of the year
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = (if month < 10 { month + 3 } else { month - 9 }) as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The current date in UTC as YYYYMMDD
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let (year, month, day) = civil_date(secs.div_euclid(86_400));
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+/// Characters that are not allowed in file names on Windows. Anki refuses to import media
+/// files that contain them, so decks generated on other platforms would break there.
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names that Windows reserves regardless of the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes `name` safe to use as a media file name on every platform Anki runs on.
+pub fn sanitize_filename(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|ch| {
+            if ch.is_control() || RESERVED_CHARS.contains(&ch) {
+                '_'
+            } else {
+                ch
+            }
+        })
+        .collect();
+
+    // Windows silently strips trailing dots and spaces, which would make the name in the note
+    // differ from the name on disk
+    while sanitized.ends_with(['.', ' ']) {
+        sanitized.pop();
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        sanitized.insert(0, '_');
+    }
+
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+    sanitized
+}
+
+/// The name a media file has in the package and in the notes, which is the file name without the
+/// directory it was generated in
+pub fn media_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+/// Expands a file name template. `%f` is replaced with the index of the input file, `%n` with the
+/// name of the input file (without extension) and `%%` with a literal `%`.
+pub fn expand_template(template: &str, file_idx: usize, name: &str) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            result.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('f') => result.push_str(&file_idx.to_string()),
+            Some('n') => result.push_str(name),
+            Some('%') => result.push('%'),
+            Some(other) => {
+                result.push('%');
+                result.push(other);
+            }
+            None => result.push('%'),
+        }
+    }
+    result
 }
 
 pub fn get_medium_name(medium: media::Type) -> &'static str {
@@ -21,11 +216,107 @@ pub fn get_medium_name(medium: media::Type) -> &'static str {
     }
 }
 
+/// Whether subtitles of the codec are images instead of text
+fn is_bitmap_codec(id: Id) -> bool {
+    matches!(
+        id,
+        Id::DVD_SUBTITLE | Id::DVB_SUBTITLE | Id::HDMV_PGS_SUBTITLE | Id::XSUB
+    )
+}
+
+/// Whether the stream is marked as forced, i.e. only has the subtitles of foreign dialogue and signs
+pub fn is_forced(stream: &Stream) -> bool {
+    stream.disposition().contains(Disposition::FORCED)
+}
+
+/// Returns the frame rate of the selected video stream of `file`, if it has one
+pub fn frame_rate<P: AsRef<Path>>(file: P, selector: StreamSelector<'_>) -> Option<f64> {
+    let ictx = libav::format::input(&file).ok()?;
+    let stream = get_stream(&ictx, media::Type::Video, selector).ok()?;
+    [stream.avg_frame_rate(), stream.rate()]
+        .into_iter()
+        .find(|rate| rate.numerator() > 0 && rate.denominator() > 0)
+        .map(f64::from)
+}
+
+/// Returns the duration of `file` as reported by its container, if it has one
+pub fn media_duration<P: AsRef<Path>>(file: P) -> Option<Duration> {
+    let ictx = libav::format::input(&file).ok()?;
+    // The duration is in AV_TIME_BASE (microsecond) units
+    (ictx.duration() > 0).then(|| Duration::from_millis(ictx.duration() / 1000))
+}
+
+/// Checks that `file` can be opened and has the selected stream
+pub fn check_stream<P: AsRef<Path>>(
+    file: P,
+    medium: media::Type,
+    selector: StreamSelector<'_>,
+) -> Result<()> {
+    let ictx = libav::format::input(&file).context("Failed to open file")?;
+    get_stream(&ictx, medium, selector)?;
+    Ok(())
+}
+
+/// Returns the language tag of the selected subtitle stream of `file`, unless it is missing or
+/// undetermined
+pub fn subtitle_language<P: AsRef<Path>>(file: P, selector: StreamSelector<'_>) -> Option<String> {
+    let ictx = libav::format::input(&file).ok()?;
+    let stream = get_stream(&ictx, media::Type::Subtitle, selector).ok()?;
+    let lang = stream.metadata().get("language")?.to_string();
+    (!lang.eq_ignore_ascii_case("und")).then_some(lang)
+}
+
+/// Returns the header of the selected ass subtitle stream of `file`, which has its styles
+pub fn ass_header<P: AsRef<Path>>(file: P, selector: StreamSelector<'_>) -> Option<String> {
+    let ictx = libav::format::input(&file).ok()?;
+    let stream = get_stream(&ictx, media::Type::Subtitle, selector).ok()?;
+    // The header of ass subtitles is stored as the extradata of the stream
+    let data = unsafe {
+        let params = stream.parameters().as_ptr();
+        if (*params).extradata.is_null() {
+            return None;
+        }
+        std::slice::from_raw_parts(
+            (*params).extradata,
+            (*params).extradata_size.try_into().unwrap_or(0),
+        )
+        .to_vec()
+    };
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// A chapter marker of a media file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub title: String,
+    pub span: Timespan,
+}
+
+pub fn read_chapters<P: AsRef<Path>>(file: P) -> Result<Vec<Chapter>> {
+    let ictx = libav::format::input(&file)
+        .with_context(|| format!("{}: Failed to open file", file.as_ref().to_string_lossy()))?;
+    ictx.chapters()
+        .map(|chapter| {
+            let start = Timestamp::from_libav_ts(chapter.start(), chapter.time_base())?;
+            let end = Timestamp::from_libav_ts(chapter.end(), chapter.time_base())?;
+            Ok(Chapter {
+                title: chapter
+                    .metadata()
+                    .get("title")
+                    .unwrap_or_default()
+                    .to_string(),
+                span: Timespan::new(start, end),
+            })
+        })
+        .collect()
+}
+
 pub fn get_stream<'a>(
-    mut streams: StreamIter<'a>,
+    ictx: &'a Input,
     medium: media::Type,
     selector: StreamSelector<'_>,
 ) -> Result<Stream<'a>> {
+    let mut streams = ictx.streams();
     match selector {
         StreamSelector::Index(stream_idx) => match streams.nth(stream_idx) {
             Some(stream) if stream.parameters().medium() == medium => Ok(stream),
@@ -53,6 +344,63 @@ pub fn get_stream<'a>(
                 get_medium_name(medium)
             )
         }
+        StreamSelector::LanguageOrUndetermined(lang) => {
+            let mut fallback = None;
+            for stream in streams {
+                if stream.parameters().medium() != medium {
+                    continue;
+                }
+
+                let stream_lang = stream.metadata().get("language").map(str::to_string);
+                match stream_lang.as_deref() {
+                    Some(stream_lang) if stream_lang.eq_ignore_ascii_case(lang) => {
+                        return Ok(stream)
+                    }
+                    Some(stream_lang) if !stream_lang.eq_ignore_ascii_case("und") => {}
+                    _ => {
+                        if fallback.is_none() {
+                            fallback = Some(stream);
+                        }
+                    }
+                }
+            }
+            match fallback {
+                Some(stream) => Ok(stream),
+                None => bail!(
+                    "File does not have a {} language or undetermined language {} stream",
+                    lang,
+                    get_medium_name(medium)
+                ),
+            }
+        }
+        StreamSelector::Title(title) => {
+            let needle = title.to_lowercase();
+            for stream in streams {
+                if stream.parameters().medium() == medium {
+                    if let Some(stream_title) = stream.metadata().get("title") {
+                        if stream_title.to_lowercase().contains(&needle) {
+                            return Ok(stream);
+                        }
+                    }
+                }
+            }
+            bail!(
+                "File does not have a {} stream with a title containing \"{}\"",
+                get_medium_name(medium),
+                title
+            )
+        }
+        StreamSelector::Forced => {
+            for stream in streams {
+                if stream.parameters().medium() == medium && is_forced(&stream) {
+                    return Ok(stream);
+                }
+            }
+            bail!(
+                "File does not have a forced {} stream",
+                get_medium_name(medium)
+            )
+        }
         StreamSelector::Best => {
             if let Some(stream) = streams.best(medium) {
                 Ok(stream)
@@ -60,5 +408,135 @@ pub fn get_stream<'a>(
                 bail!("File does not have a {} stream", get_medium_name(medium))
             }
         }
+        StreamSelector::BestText => {
+            let best = get_stream(ictx, medium, StreamSelector::Best)?;
+            if medium != media::Type::Subtitle || !is_bitmap_codec(best.parameters().id()) {
+                return Ok(best);
+            }
+            let lang = best.metadata().get("language").map(str::to_string);
+            let text = ictx.streams().find(|stream| {
+                stream.parameters().medium() == medium
+                    && !is_bitmap_codec(stream.parameters().id())
+                    && stream.metadata().get("language").map(str::to_string) == lang
+            });
+            match text {
+                Some(stream) => {
+                    info!(
+                        "using the {} subtitle stream at index {} instead of the {} stream at index {}, see --prefer-bitmap-subs",
+                        stream.parameters().id().name(),
+                        stream.index(),
+                        best.parameters().id().name(),
+                        best.index()
+                    );
+                    Ok(stream)
+                }
+                None => Ok(best),
+            }
+        }
+        StreamSelector::Chain(selectors) => {
+            for selector in selectors {
+                match get_stream(ictx, medium, selector) {
+                    Ok(stream) => return Ok(stream),
+                    Err(err) => trace!("Stream selector did not match: {}", err),
+                }
+            }
+            bail!(
+                "None of the stream selectors matched a {} stream",
+                get_medium_name(medium)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meminfo() {
+        assert_eq!(
+            parse_meminfo("MemTotal:       16303244 kB\nMemFree:         1021304 kB\nMemAvailable:    8000000 kB\n"),
+            Some(8000000 * 1024)
+        );
+        assert_eq!(parse_meminfo("MemTotal: 16303244 kB\n"), None);
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(civil_date(20_742), (2026, 10, 16));
+    }
+
+    #[test]
+    fn parse_selector_chain() {
+        assert_eq!(
+            SelectorSpec::parse_chain("lang=jpn,title=Full,index=2,forced,best").unwrap(),
+            vec![
+                SelectorSpec::Language("jpn".to_string()),
+                SelectorSpec::Title("Full".to_string()),
+                SelectorSpec::Index(2),
+                SelectorSpec::Forced,
+                SelectorSpec::Best,
+            ]
+        );
+        assert!(SelectorSpec::parse_chain("index=two").is_err());
+        assert!(SelectorSpec::parse_chain("lang=jpn,").is_err());
+        assert!(SelectorSpec::parse_chain("codec=ass").is_err());
+    }
+
+    #[test]
+    fn sanitize_keeps_valid_names() {
+        assert_eq!(sanitize_filename("audio_0_12.mka"), "audio_0_12.mka");
+    }
+
+    #[test]
+    fn sanitize_reserved_chars() {
+        assert_eq!(
+            sanitize_filename("image_0:01:02.500*.jpg"),
+            "image_0_01_02.500_.jpg"
+        );
+        assert_eq!(sanitize_filename("a<b>c\"d/e\\f|g?h"), "a_b_c_d_e_f_g_h");
+    }
+
+    #[test]
+    fn sanitize_control_chars() {
+        assert_eq!(sanitize_filename("line\nbreak\t.jpg"), "line_break_.jpg");
+    }
+
+    #[test]
+    fn sanitize_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_filename("name. . "), "name");
+    }
+
+    #[test]
+    fn sanitize_reserved_names() {
+        assert_eq!(sanitize_filename("con.jpg"), "_con.jpg");
+        assert_eq!(sanitize_filename("LPT1"), "_LPT1");
+        assert_eq!(sanitize_filename("console.jpg"), "console.jpg");
+    }
+
+    #[test]
+    fn expand_template_placeholders() {
+        assert_eq!(
+            expand_template("%n.apkg", 3, "episode 01"),
+            "episode 01.apkg"
+        );
+        assert_eq!(expand_template("deck_%f.apkg", 3, "ep"), "deck_3.apkg");
+        assert_eq!(expand_template("100%%_%x%", 0, "ep"), "100%_%x%");
+    }
+
+    #[test]
+    fn sanitize_empty() {
+        assert_eq!(sanitize_filename(""), "_");
+        assert_eq!(sanitize_filename("..."), "_");
+    }
+
+    #[test]
+    fn media_names() {
+        assert_eq!(media_name("audio_0_12.mka"), "audio_0_12.mka");
+        assert_eq!(media_name("/tmp/.tmpAbc/image_1_2.jpg"), "image_1_2.jpg");
     }
 }
@@ -0,0 +1,341 @@
+use crate::subtitle::{is_cjk, Dialogue, Subtitle};
+use crate::time::{Duration, Timespan, Timestamp};
+use anyhow::{bail, Context, Result};
+use log::trace;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Normalizes a word so that words in the word list match words in the alignment data regardless
+/// of case and surrounding punctuation
+fn normalize(word: &str) -> String {
+    word.trim_matches(|ch: char| !ch.is_alphanumeric())
+        .to_lowercase()
+}
+
+/// Reads a word list with one word per line. Empty lines and lines starting with `#` are ignored.
+pub fn read_word_list<P: AsRef<Path>>(path: P) -> Result<HashSet<String>> {
+    let data = std::fs::read_to_string(&path).context("Failed to read word list")?;
+    Ok(data
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(normalize)
+        .collect())
+}
+
+/// Splits `text` into its normalized words. Apostrophes and hyphens inside a word, like in
+/// "don't" or "well-known", are part of the word.
+fn words_of(text: &str) -> Vec<String> {
+    text.replace("\\N", " ")
+        .split(|ch: char| !ch.is_alphanumeric() && !matches!(ch, '\'' | '’' | '-'))
+        .map(normalize)
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Returns the words of `words` that occur in `text`, in the order they first occur. Words are
+/// matched as whole words. Text in scripts that don't separate words with spaces is split into
+/// the longest words of the list like [`lookup_words`] does, so a word isn't found inside a longer
+/// one that is also listed.
+pub fn target_words_in(text: &str, words: &HashSet<String>) -> Vec<String> {
+    let longest = words.iter().map(|word| word.chars().count()).max();
+    let mut seen = HashSet::new();
+    lookup_words(text, longest.unwrap_or_default(), |word| words.get(word))
+        .into_iter()
+        .flatten()
+        .filter(|word| seen.insert(*word))
+        .cloned()
+        .collect()
+}
+
+/// Splits `text` into words and looks each of them up with `lookup`. Numbers are skipped. Text in
+/// scripts that don't separate words with spaces is split into the longest words, of at most
+/// `longest` characters, that `lookup` finds. Every character between them counts as a word of
+/// its own, which is never found.
+fn lookup_words<T>(
+    text: &str,
+    longest: usize,
+    lookup: impl Fn(&str) -> Option<T>,
+) -> Vec<Option<T>> {
+    let mut found = Vec::new();
+    for token in words_of(text) {
+        if token.chars().all(char::is_numeric) {
+            continue;
+        }
+        if !token.chars().any(is_cjk) {
+            found.push(lookup(&token));
+            continue;
+        }
+
+        let chars: Vec<char> = token.chars().collect();
+        let mut idx = 0;
+        while idx < chars.len() {
+            let word = (1..=longest.min(chars.len() - idx)).rev().find_map(|len| {
+                let word: String = chars[idx..idx + len].iter().collect();
+                lookup(&word).map(|value| (len, value))
+            });
+            match word {
+                Some((len, value)) => {
+                    found.push(Some(value));
+                    idx += len;
+                }
+                None => {
+                    found.push(None);
+                    idx += 1;
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Words the user already knows, for `--known-words`
+#[derive(Debug, Default)]
+pub struct KnownWords {
+    words: HashSet<String>,
+    /// The length in characters of the longest word
+    longest: usize,
+}
+
+impl KnownWords {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let words = read_word_list(path)?;
+        let longest = words.iter().map(|word| word.chars().count()).max();
+        Ok(Self {
+            longest: longest.unwrap_or_default(),
+            words,
+        })
+    }
+
+    /// The number of words of `text` that are not known
+    pub fn unknown_count(&self, text: &str) -> usize {
+        lookup_words(text, self.longest, |word| self.words.get(word))
+            .iter()
+            .filter(|word| word.is_none())
+            .count()
+    }
+}
+
+/// The difficulty buckets of `FrequencyList::difficulty`, by the rank of the rarest word
+const FREQUENCY_BUCKETS: &[(usize, &str)] = &[
+    (1_000, "top1k"),
+    (5_000, "top5k"),
+    (10_000, "top10k"),
+    (20_000, "top20k"),
+];
+const RARE_BUCKET: &str = "rare";
+
+/// Words ranked by how common they are
+#[derive(Debug, Default)]
+pub struct FrequencyList {
+    ranks: HashMap<String, usize>,
+    /// The length in characters of the longest word
+    longest: usize,
+}
+
+impl FrequencyList {
+    /// Parses a frequency list with the most common word first, one word per line. Anything after
+    /// the word (like a count) is ignored, as are empty lines and lines starting with `#`.
+    fn parse(data: &str) -> Self {
+        let mut list = Self::default();
+        for word in data
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_whitespace().next())
+            .map(normalize)
+        {
+            let rank = list.ranks.len();
+            list.longest = list.longest.max(word.chars().count());
+            list.ranks.entry(word).or_insert(rank);
+        }
+        list
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read_to_string(&path).context("Failed to read frequency list")?;
+        Ok(Self::parse(&data))
+    }
+
+    /// Returns the ranks of the words of `text`, `None` for words that are not in the list
+    fn ranks(&self, text: &str) -> Vec<Option<usize>> {
+        lookup_words(text, self.longest, |word| self.ranks.get(word).copied())
+    }
+
+    /// The difficulty bucket of `text` by its rarest word: top1k, top5k, top10k, top20k or rare.
+    /// `None` if `text` has no words.
+    pub fn difficulty(&self, text: &str) -> Option<&'static str> {
+        let rarest = self
+            .ranks(text)
+            .into_iter()
+            .map(|rank| rank.unwrap_or(usize::MAX))
+            .max()?;
+        Some(
+            FREQUENCY_BUCKETS
+                .iter()
+                .find(|(limit, _)| rarest < *limit)
+                .map_or(RARE_BUCKET, |(_, bucket)| bucket),
+        )
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Timestamp> {
+    let secs: f64 = s
+        .parse()
+        .with_context(|| format!("\"{}\" is not a valid amount of seconds", s))?;
+    if !secs.is_finite() || secs < 0.0 {
+        bail!("\"{}\" is not a valid amount of seconds", s);
+    }
+    Ok(Timestamp::MIN + Duration::from_millis((secs * 1000.0).round() as i64))
+}
+
+/// Splits the first field off `text`, and returns it with the rest of `text` after the
+/// whitespace that separates them. Fields may be separated by any amount of whitespace.
+fn split_field(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    let (field, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    (!field.is_empty()).then(|| (field, rest.trim_start()))
+}
+
+/// Parses forced alignment data in the `START END WORD` format (seconds, separated by tabs or
+/// spaces) that aeneas and Audacity labels use
+fn parse_alignment(data: &str) -> Result<Vec<(Timespan, &str)>> {
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            let fields =
+                split_field(line).and_then(|(start, rest)| Some((start, split_field(rest)?)));
+            let Some((start, (end, word))) = fields else {
+                bail!("line {}: expected START END WORD", idx + 1);
+            };
+            let word = word.trim();
+            let span = Timespan::new(parse_seconds(start)?, parse_seconds(end)?);
+            Ok((span, word))
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to parse alignment data")
+}
+
+/// Reads the forced alignment data in `file` and returns a subtitle for the first occurrence of
+/// every word in `words`
+pub fn read_word_clips<P: AsRef<Path>>(file: P, words: &HashSet<String>) -> Result<Vec<Subtitle>> {
+    let data = std::fs::read_to_string(&file).context("Failed to read alignment data")?;
+    Ok(word_clips(&parse_alignment(&data)?, words))
+}
+
+fn word_clips(alignment: &[(Timespan, &str)], words: &HashSet<String>) -> Vec<Subtitle> {
+    let mut seen = HashSet::new();
+    let clips: Vec<Subtitle> = alignment
+        .iter()
+        .filter(|(_, word)| {
+            let word = normalize(word);
+            words.contains(&word) && seen.insert(word)
+        })
+        .map(|(span, word)| Subtitle::new(*span, Dialogue::Text(word.to_string())))
+        .collect();
+    trace!(
+        "found {} of {} words in {} aligned words",
+        clips.len(),
+        words.len(),
+        alignment.len()
+    );
+    clips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aeneas_tsv() {
+        let alignment = parse_alignment("0.000\t0.480\tHello,\n0.480\t1.250\tworld\n\n").unwrap();
+        assert_eq!(alignment.len(), 2);
+        assert_eq!(
+            alignment[1].0,
+            Timespan::new(Timestamp::from_millis(480), Timestamp::from_millis(1250))
+        );
+        assert_eq!(alignment[0].1, "Hello,");
+    }
+
+    #[test]
+    fn alignment_with_wide_separators() {
+        let alignment = parse_alignment("1.0  2.0 word\n2.0\t\t3.0 \t two words\n").unwrap();
+        assert_eq!(alignment[0].1, "word");
+        assert_eq!(
+            alignment[0].0,
+            Timespan::new(Timestamp::from_secs(1), Timestamp::from_secs(2))
+        );
+        assert_eq!(alignment[1].1, "two words");
+    }
+
+    #[test]
+    fn parse_invalid_alignment() {
+        assert!(parse_alignment("0.5 word").is_err());
+        assert!(parse_alignment("-1 2 word").is_err());
+    }
+
+    #[test]
+    fn whole_target_words() {
+        let words: HashSet<String> = ["cat", "don't", "天気", "rain"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            target_words_in("Don't let the cat out!", &words),
+            vec!["don't", "cat"]
+        );
+        assert!(target_words_in("Concatenate the raining catalogue", &words).is_empty());
+        assert_eq!(target_words_in("いい天気ですね。", &words), vec!["天気"]);
+
+        let words: HashSet<String> = ["天", "天気", "気"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(target_words_in("天気は天だ", &words), vec!["天気", "天"]);
+    }
+
+    #[test]
+    fn count_unknown_words() {
+        let known = KnownWords {
+            words: ["i", "see", "a", "今日", "は"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            longest: 2,
+        };
+        assert_eq!(known.unknown_count("I see a cat."), 1);
+        assert_eq!(known.unknown_count("I see 3 big cats"), 2);
+        // The characters of an unknown run count one by one, since they can be several words
+        assert_eq!(known.unknown_count("今日はいい天気"), 4);
+        assert_eq!(known.unknown_count("天気は今日"), 2);
+        assert_eq!(known.unknown_count("今日は雨が降る"), 4);
+    }
+
+    #[test]
+    fn difficulty_of_rarest_word() {
+        let mut data = String::from("# most common first\nthe 1000\ncat 20\n");
+        for idx in 0..1500 {
+            data.push_str(&format!("filler{}\n", idx));
+        }
+        data.push_str("dog\n今日\nは\n");
+        let list = FrequencyList::parse(&data);
+
+        assert_eq!(list.difficulty("The cat!"), Some("top1k"));
+        assert_eq!(list.difficulty("The cat, the dog"), Some("top5k"));
+        assert_eq!(list.difficulty("The aardvark"), Some("rare"));
+        assert_eq!(list.difficulty("42 cats"), Some("rare"));
+        assert_eq!(list.difficulty("今日は"), Some("top5k"));
+        assert_eq!(list.difficulty("1999..."), None);
+    }
+
+    #[test]
+    fn first_occurrence_of_listed_words() {
+        let alignment = parse_alignment("0 1 The\n1 2 cat\n2 3 the\n3 4 dog").unwrap();
+        let words = HashSet::from(["the".to_string(), "bird".to_string()]);
+        let clips = word_clips(&alignment, &words);
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].text(), Some("The"));
+        assert_eq!(clips[0].timespan().start(), Timestamp::from_secs(0));
+    }
+}
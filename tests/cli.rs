@@ -38,6 +38,24 @@ fn no_audio() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn preflight_reports_all_files() -> TestResult {
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("tests/media/sub.srt")
+        .arg("-a")
+        .arg("-i")
+        .arg("-m")
+        .arg("tests/media/only_video.mp4")
+        .arg("tests/media/1000hz.mp3")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("2 problem(s)"))
+        .stderr(predicate::str::contains("only_video.mp4"))
+        .stderr(predicate::str::contains("1000hz.mp3"));
+    Ok(())
+}
+
 #[test]
 fn no_audio_at_index() -> TestResult {
     Command::cargo_bin("stos")?
@@ -96,6 +114,105 @@ fn no_deck() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn deck_per_file() -> TestResult {
+    let dir = tempdir()?;
+    let mut file = dir.path().to_path_buf();
+    file.push("deck.apkg");
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("tests/media/mergable_sub.srt")
+        .arg("-o")
+        .arg(&file)
+        .arg("--deck-per-file")
+        .assert()
+        .success();
+    assert!(!file.exists());
+    assert!(dir.path().join("sub.apkg").exists());
+    assert!(dir.path().join("mergable_sub.apkg").exists());
+    Ok(())
+}
+
+#[test]
+fn append() -> TestResult {
+    let dir = tempdir()?;
+    let mut file = dir.path().to_path_buf();
+    file.push("deck.apkg");
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("-o")
+        .arg(&file)
+        .assert()
+        .success();
+    let size = std::fs::metadata(&file)?.len();
+
+    Command::cargo_bin("stos")?
+        .arg("tests/media/mergable_sub.srt")
+        .arg("--append")
+        .arg(&file)
+        .assert()
+        .success();
+    assert!(std::fs::metadata(&file)?.len() > size);
+    Ok(())
+}
+
+#[test]
+fn append_same_file_twice_fails() -> TestResult {
+    let dir = tempdir()?;
+    let file = dir.path().join("deck.apkg");
+    let media = std::env::current_dir()?.join("tests/media/1000hz.mp3");
+    let sub = std::env::current_dir()?.join("tests/media/sub.srt");
+    Command::cargo_bin("stos")?
+        .current_dir(dir.path())
+        .arg(&sub)
+        .arg("-m")
+        .arg(&media)
+        .arg("-a")
+        .arg("-o")
+        .arg(&file)
+        .assert()
+        .success();
+    Command::cargo_bin("stos")?
+        .current_dir(dir.path())
+        .arg(&sub)
+        .arg("-m")
+        .arg(&media)
+        .arg("-a")
+        .arg("--append")
+        .arg(&file)
+        .assert()
+        .success();
+    let size = std::fs::metadata(&file)?.len();
+
+    Command::cargo_bin("stos")?
+        .current_dir(dir.path())
+        .arg(&sub)
+        .arg("-m")
+        .arg(&media)
+        .arg("-a")
+        .arg("--append")
+        .arg(&file)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "already contains \"1000hz_audio_0_0.mka\"",
+        ));
+    assert_eq!(std::fs::metadata(&file)?.len(), size);
+    Ok(())
+}
+
+#[test]
+fn append_missing_package() -> TestResult {
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--append")
+        .arg("tests/media/doesnt_exist.apkg")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to read package"));
+    Ok(())
+}
+
 #[test]
 fn lang_and_index_fail() -> TestResult {
     Command::cargo_bin("stos")?
@@ -124,3 +241,159 @@ fn subs_and_video() -> TestResult {
     assert!(file.exists());
     Ok(())
 }*/
+
+#[test]
+fn from_recipe() -> TestResult {
+    let dir = tempdir()?;
+    let recipe = dir.path().join("stos.lock");
+    let deck = dir.path().join("deck.apkg");
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("-o")
+        .arg(&deck)
+        .arg("--recipe")
+        .arg(&recipe)
+        .assert()
+        .success();
+    std::fs::remove_file(&deck)?;
+
+    Command::cargo_bin("stos")?
+        .arg("--from-recipe")
+        .arg(&recipe)
+        .assert()
+        .success();
+    assert!(deck.exists());
+    Ok(())
+}
+
+#[test]
+fn from_recipe_with_files() -> TestResult {
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--from-recipe")
+        .arg("stos.lock")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used together"));
+    Ok(())
+}
+
+#[test]
+fn split_deck_by_and_append_fail() -> TestResult {
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--split-deck-by")
+        .arg("(\\w+):")
+        .arg("--append")
+        .arg("deck.apkg")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used together"));
+    Ok(())
+}
+
+#[test]
+fn skip_chapters_without_chapters() -> TestResult {
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--no-deck")
+        .arg("-v")
+        .arg("--skip-chapters")
+        .arg("OP|ED")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("has no chapters"));
+    Ok(())
+}
+
+#[test]
+fn playlist() -> TestResult {
+    let dir = tempdir()?;
+    let playlist = dir.path().join("review.edl");
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--no-deck")
+        .arg("--playlist")
+        .arg(&playlist)
+        .assert()
+        .success();
+    let contents = std::fs::read_to_string(&playlist)?;
+    assert!(contents.starts_with("# mpv EDL v0\n"));
+    assert!(contents.contains("sub.srt,"));
+    Ok(())
+}
+
+#[test]
+fn manifest() -> TestResult {
+    let dir = tempdir()?;
+    let manifest = dir.path().join("manifest.csv");
+    let deck = dir.path().join("deck.apkg");
+    let sub = std::fs::canonicalize("tests/media/sub.srt")?;
+    std::fs::write(
+        &manifest,
+        format!(
+            "subtitle,delay,deck\n\"{}\",500,Show::Episode 1\n",
+            sub.display()
+        ),
+    )?;
+    Command::cargo_bin("stos")?
+        .arg("--manifest")
+        .arg(&manifest)
+        .arg("-o")
+        .arg(&deck)
+        .assert()
+        .success();
+    assert!(deck.exists());
+
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--manifest")
+        .arg(&manifest)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used together"));
+    Ok(())
+}
+
+#[test]
+fn note_lang() -> TestResult {
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--no-deck")
+        .arg("--write-json")
+        .arg("--note-lang=ja")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"lang\":\"ja\""));
+
+    Command::cargo_bin("stos")?
+        .arg("tests/media/sub.srt")
+        .arg("--note-lang=ja jp")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not a valid language code"));
+    Ok(())
+}
+
+#[test]
+fn media_only_for_kept_cards() -> TestResult {
+    let dir = tempdir()?;
+    let media = std::env::current_dir()?.join("tests/media");
+    Command::cargo_bin("stos")?
+        .current_dir(dir.path())
+        .arg(media.join("mergable_sub.srt"))
+        .arg("-m")
+        .arg(media.join("1000hz.mp3"))
+        .arg("-a")
+        .arg("--no-deck")
+        .arg("--max-cards=1")
+        .arg("--card-selection=first")
+        .assert()
+        .success();
+    let clips = std::fs::read_dir(dir.path())?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "mka"))
+        .count();
+    assert_eq!(clips, 1);
+    Ok(())
+}